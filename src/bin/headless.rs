@@ -0,0 +1,85 @@
+//! Run a pattern on a square board without opening a window and print how the population
+//! changes, for CI or machines without a GPU.
+//!
+//! Usage: `headless <pattern.rle> [generations] [board size]`
+//!
+//! Build it with `cargo build --bin headless --no-default-features` to leave out the renderer.
+
+use std::process::ExitCode;
+
+use bevy::math::{IVec2, Vec2};
+use game_of_life::{
+    patterns::place_rle,
+    simulation::SimulationState,
+    tiling::{Tiling, TilingKind, DEFAULT_GRID_SIZE},
+};
+
+const DEFAULT_GENERATIONS: u32 = 100;
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let path = match args.first() {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: headless <pattern.rle> [generations] [board size]");
+            return ExitCode::FAILURE;
+        }
+    };
+    let generations = match args.get(1).map(|arg| arg.parse::<u32>()) {
+        None => DEFAULT_GENERATIONS,
+        Some(Ok(generations)) => generations,
+        Some(Err(err)) => {
+            eprintln!("Bad generation count {}: {}", args[1], err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let size = match args.get(2).map(|arg| arg.parse::<i32>()) {
+        None => DEFAULT_GRID_SIZE,
+        Some(Ok(size)) if size > 0 => size,
+        _ => {
+            eprintln!("Bad board size {}", args[2]);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("Could not read {}: {}", path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut sim_state = SimulationState::new(Tiling {
+        kind: TilingKind::Square,
+        max_index: IVec2::splat(size),
+        offset: Vec2::ZERO,
+    });
+    if let Err(err) = place_rle(&mut sim_state, &text, IVec2::splat(size / 2)) {
+        eprintln!("Could not load {}: {}", path, err);
+        return ExitCode::FAILURE;
+    }
+    sim_state.process();
+
+    let live = |sim_state: &SimulationState| sim_state.population()[1..].iter().sum::<usize>();
+    let start = live(&sim_state);
+    let (mut min, mut max, mut total) = (start, start, start);
+    println!("generation 0: {:?}", sim_state.population());
+    for generation in 1..=generations {
+        sim_state.run_generation();
+        let population = sim_state.population();
+        println!("generation {}: {:?}", generation, population);
+        let count = live(&sim_state);
+        min = min.min(count);
+        max = max.max(count);
+        total += count;
+    }
+    println!(
+        "live cells: start {}, end {}, min {}, max {}, mean {:.1}",
+        start,
+        live(&sim_state),
+        min,
+        max,
+        total as f32 / (generations + 1) as f32
+    );
+    ExitCode::SUCCESS
+}
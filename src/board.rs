@@ -0,0 +1,762 @@
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+    ops::Range,
+    path::Path,
+};
+
+use bevy::{
+    math::{IVec2, Vec2},
+    prelude::{Color, Component},
+    utils::HashMap,
+};
+
+use crate::{
+    simulation::{BoundaryMode, SimulationState},
+    tiling::{Tiling, TilingKind},
+};
+
+// Compact binary board format:
+//   magic "GOLB", version byte
+//   tiling kind byte, max index x and y, wrap byte (1 for toroidal boards and 0 for boards
+//   with dead edges), number of states
+//   number of cells followed by (index delta, state) pairs for every cell not in state 0
+// All integers after the version byte are LEB128 varints. Cells are sorted by their
+// linear index (y * max_index.x + x) so that the deltas stay small for sparse boards.
+const BOARD_MAGIC: &[u8; 4] = b"GOLB";
+const BOARD_VERSION: u8 = 1;
+// The most states a board file may ask for. Every shape gets this many states with their own
+// rules, so the limit keeps a corrupt header from building an unbounded rule set.
+const MAX_BOARD_STATES: usize = 256;
+
+#[derive(Debug)]
+pub enum BoardLoadError {
+    Io(std::io::Error),
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    InvalidTilingKind(u8),
+    InvalidHeader,
+    CellOutOfBounds(u64),
+    InvalidState(u32),
+}
+
+impl Display for BoardLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BoardLoadError::Io(err) => write!(f, "{}", err),
+            BoardLoadError::InvalidMagic => write!(f, "Not a board file"),
+            BoardLoadError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported board version {}", version)
+            }
+            BoardLoadError::InvalidTilingKind(kind) => write!(f, "Invalid tiling kind {}", kind),
+            BoardLoadError::InvalidHeader => write!(f, "Invalid board header"),
+            BoardLoadError::CellOutOfBounds(index) => write!(f, "Cell {} is out of bounds", index),
+            BoardLoadError::InvalidState(state) => write!(f, "Invalid state {}", state),
+        }
+    }
+}
+
+impl From<std::io::Error> for BoardLoadError {
+    fn from(err: std::io::Error) -> Self {
+        BoardLoadError::Io(err)
+    }
+}
+
+fn tiling_kind_to_byte(kind: TilingKind) -> u8 {
+    match kind {
+        TilingKind::Square => 0,
+        TilingKind::Hexagonal => 1,
+        TilingKind::OctagonAndSquare => 2,
+        TilingKind::EquilateralTriangular => 3,
+        TilingKind::RightTriangular => 4,
+    }
+}
+
+fn tiling_kind_from_byte(byte: u8) -> Result<TilingKind, BoardLoadError> {
+    match byte {
+        0 => Ok(TilingKind::Square),
+        1 => Ok(TilingKind::Hexagonal),
+        2 => Ok(TilingKind::OctagonAndSquare),
+        3 => Ok(TilingKind::EquilateralTriangular),
+        4 => Ok(TilingKind::RightTriangular),
+        _ => Err(BoardLoadError::InvalidTilingKind(byte)),
+    }
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> std::io::Result<()> {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> std::io::Result<u8> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<u64, BoardLoadError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_byte(reader)?;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(BoardLoadError::InvalidHeader)
+}
+
+pub fn save_board_binary<W: Write>(sim_state: &SimulationState, writer: &mut W) -> std::io::Result<()> {
+    let max_index = sim_state.tiling.max_index;
+    let mut cells = Vec::new();
+    for y in 0..max_index.y {
+        for x in 0..max_index.x {
+            let state = sim_state.get_at(IVec2::new(x, y));
+            if state != 0 {
+                cells.push(((y as u64) * (max_index.x as u64) + x as u64, state));
+            }
+        }
+    }
+
+    writer.write_all(BOARD_MAGIC)?;
+    writer.write_all(&[BOARD_VERSION, tiling_kind_to_byte(sim_state.tiling.kind)])?;
+    write_varint(writer, max_index.x as u64)?;
+    write_varint(writer, max_index.y as u64)?;
+    let wrap = match sim_state.boundary() {
+        BoundaryMode::Toroidal => 1,
+        BoundaryMode::Dead => 0,
+    };
+    writer.write_all(&[wrap])?;
+    write_varint(writer, sim_state.num_states as u64)?;
+    write_varint(writer, cells.len() as u64)?;
+    let mut last_index = 0;
+    for (index, state) in cells {
+        write_varint(writer, index - last_index)?;
+        write_varint(writer, state as u64)?;
+        last_index = index;
+    }
+    Ok(())
+}
+
+/// Load a board written by `save_board_binary`. The cells are queued as manual sets so
+/// they are applied (and reported as changes) on the next call to `process`.
+pub fn load_board_binary<R: Read>(reader: &mut R) -> Result<SimulationState, BoardLoadError> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != BOARD_MAGIC {
+        return Err(BoardLoadError::InvalidMagic);
+    }
+    let version = read_byte(reader)?;
+    if version != BOARD_VERSION {
+        return Err(BoardLoadError::UnsupportedVersion(version));
+    }
+    let kind = tiling_kind_from_byte(read_byte(reader)?)?;
+    let max_x = read_varint(reader)?;
+    let max_y = read_varint(reader)?;
+    // Cell indices are computed as `x + y * width` in i32, so the whole board must fit.
+    let fits = max_x.checked_mul(max_y).is_some_and(|cells| cells <= i32::MAX as u64);
+    if max_x == 0 || max_y == 0 || !fits {
+        return Err(BoardLoadError::InvalidHeader);
+    }
+    let boundary = match read_byte(reader)? {
+        0 => BoundaryMode::Dead,
+        1 => BoundaryMode::Toroidal,
+        _ => return Err(BoardLoadError::InvalidHeader),
+    };
+    let num_states = read_varint(reader)?;
+    if num_states == 0 || num_states > MAX_BOARD_STATES as u64 {
+        return Err(BoardLoadError::InvalidHeader);
+    }
+    let num_states = num_states as usize;
+    let num_cells = read_varint(reader)?;
+
+    let mut sim_state = SimulationState::new(Tiling {
+        kind,
+        max_index: IVec2::new(max_x as i32, max_y as i32),
+        offset: Vec2::ZERO,
+    });
+    sim_state.set_boundary(boundary);
+    for shape in sim_state.get_shapes() {
+        while (sim_state.get_num_states_for_shape(shape) as usize) < num_states {
+            sim_state.add_state(shape);
+        }
+    }
+
+    let mut index = 0u64;
+    for _ in 0..num_cells {
+        index = index
+            .checked_add(read_varint(reader)?)
+            .ok_or(BoardLoadError::CellOutOfBounds(u64::MAX))?;
+        let state = u32::try_from(read_varint(reader)?)
+            .map_err(|_| BoardLoadError::InvalidState(u32::MAX))?;
+        if index >= max_x * max_y {
+            return Err(BoardLoadError::CellOutOfBounds(index));
+        }
+        if state as usize >= num_states {
+            return Err(BoardLoadError::InvalidState(state));
+        }
+        sim_state.set_at(
+            IVec2::new((index % max_x) as i32, (index / max_x) as i32),
+            state,
+        );
+    }
+
+    Ok(sim_state)
+}
+
+fn color_bytes(color: Color) -> [u8; 4] {
+    color
+        .as_rgba_f32()
+        .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+// Whether the point is inside the convex polygon, or on its edge. The corners may wind either way.
+fn in_convex_polygon(point: Vec2, corners: &[Vec2]) -> bool {
+    let mut sign = 0.0;
+    for (i, corner) in corners.iter().enumerate() {
+        let next = corners[(i + 1) % corners.len()];
+        let side = (next - *corner).perp_dot(point - *corner);
+        if side * sign < 0.0 {
+            return false;
+        }
+        if side != 0.0 {
+            sign = side;
+        }
+    }
+    true
+}
+
+fn svg_color(color: Color) -> String {
+    let [r, g, b, _] = color_bytes(color);
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Load a board saved with `save_board_binary` from the file at the given path.
+pub fn load_board_file(path: impl AsRef<Path>) -> Result<SimulationState, BoardLoadError> {
+    let file = std::fs::File::open(path)?;
+    load_board_binary(&mut std::io::BufReader::new(file))
+}
+
+/// Sent to outline the cells that differ between two boards, each with its state before and
+/// after. Sending no changes clears the outlines.
+#[derive(Component)]
+pub struct ShowBoardDiff {
+    pub changes: Vec<(IVec2, u32, u32)>,
+}
+
+/// Sent to draw the board with each tile's shape and write it to a PNG at `path`, with
+/// `pixels_per_unit` pixels across a unit square tile.
+#[derive(Component)]
+pub struct ExportGridPng {
+    pub path: String,
+    pub pixels_per_unit: f32,
+}
+
+/// Put an image drawn by `BoardExport::render_image` on the system clipboard. Fails if the
+/// clipboard is not available or does not take images.
+pub fn copy_image_to_clipboard(image: &image::RgbaImage) -> Result<(), arboard::Error> {
+    let mut clipboard = arboard::Clipboard::new()?;
+    clipboard.set_image(arboard::ImageData {
+        width: image.width() as usize,
+        height: image.height() as usize,
+        bytes: std::borrow::Cow::Borrowed(image.as_raw()),
+    })
+}
+
+/// Comparing boards and drawing them to images, for the exports and the clipboard.
+pub trait BoardExport {
+    /// Every cell whose state here differs from its state in `other`, along with both states.
+    /// Both boards are expected to share a tiling.
+    fn diff(&self, other: &SimulationState) -> Vec<(IVec2, u32, u32)>;
+
+    /// Draw every tile with an index in `region` as a polygon filled with the color of its
+    /// state. States without a color are drawn gray.
+    fn write_svg<W: Write>(
+        &self,
+        region: Range<IVec2>,
+        colors: &HashMap<u32, Color>,
+        writer: &mut W,
+    ) -> std::io::Result<()>;
+
+    /// Draw every tile as its shape filled with the color of its state, with `pixels_per_unit`
+    /// pixels across a unit square tile. Pixels are filled when their center lies inside a tile,
+    /// so the image matches the board however it is zoomed. States without a color are drawn
+    /// gray and pixels outside every tile are left transparent.
+    fn render_image(&self, colors: &HashMap<u32, Color>, pixels_per_unit: f32) -> image::RgbaImage;
+
+    /// Write the board drawn by `render_image` as a PNG.
+    fn write_png<W: Write>(
+        &self,
+        colors: &HashMap<u32, Color>,
+        pixels_per_unit: f32,
+        writer: &mut W,
+    ) -> image::ImageResult<()>;
+
+    /// Write the board to a PNG file at `path`. See `render_image`.
+    fn export_png(
+        &self,
+        colors: &HashMap<u32, Color>,
+        pixels_per_unit: f32,
+        path: impl AsRef<Path>,
+    ) -> image::ImageResult<()>;
+
+    /// Write the tiles in `region` to an SVG file at `path`. See `write_svg`.
+    fn export_svg(
+        &self,
+        region: Range<IVec2>,
+        colors: &HashMap<u32, Color>,
+        path: impl AsRef<Path>,
+    ) -> std::io::Result<()>;
+}
+
+impl BoardExport for SimulationState {
+    fn diff(&self, other: &SimulationState) -> Vec<(IVec2, u32, u32)> {
+        self.tiling
+            .iter_indices()
+            .filter_map(|index| {
+                let before = self.get_at(index);
+                let after = other.get_at(index);
+                (before != after).then_some((index, before, after))
+            })
+            .collect()
+    }
+
+    fn write_svg<W: Write>(
+        &self,
+        region: Range<IVec2>,
+        colors: &HashMap<u32, Color>,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        let mut polygons = Vec::new();
+        let mut min = Vec2::splat(f32::MAX);
+        let mut max = Vec2::splat(f32::MIN);
+        for y in region.start.y..region.end.y {
+            for x in region.start.x..region.end.x {
+                let index = IVec2::new(x, y);
+                let tile = self.tiling.get_tile_at_index(index);
+                // SVG has y going down, so flip the board over.
+                let corners = tile
+                    .shape
+                    .get_corner_positions()
+                    .into_iter()
+                    .map(|corner| (tile.position + corner) * Vec2::new(1.0, -1.0))
+                    .collect::<Vec<_>>();
+                for corner in &corners {
+                    min = min.min(*corner);
+                    max = max.max(*corner);
+                }
+                let color = colors
+                    .get(&self.get_at(self.tiling.adjust_index(index)))
+                    .cloned()
+                    .unwrap_or(Color::GRAY);
+                polygons.push((corners, color));
+            }
+        }
+        if polygons.is_empty() {
+            min = Vec2::ZERO;
+            max = Vec2::ZERO;
+        }
+
+        let size = max - min;
+        writeln!(
+            writer,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+            min.x, min.y, size.x, size.y
+        )?;
+        for (corners, color) in polygons {
+            let points = corners
+                .iter()
+                .map(|corner| format!("{},{}", corner.x, corner.y))
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(
+                writer,
+                r#"  <polygon points="{}" fill="{}" stroke="black" stroke-width="0.02"/>"#,
+                points,
+                svg_color(color)
+            )?;
+        }
+        writeln!(writer, "</svg>")
+    }
+
+    fn render_image(&self, colors: &HashMap<u32, Color>, pixels_per_unit: f32) -> image::RgbaImage {
+        let tiles = self
+            .tiling
+            .iter_indices()
+            .map(|index| {
+                let tile = self.tiling.get_tile_at_index(index);
+                let corners = tile
+                    .shape
+                    .get_corner_positions()
+                    .into_iter()
+                    .map(|corner| tile.position + corner)
+                    .collect::<Vec<_>>();
+                (corners, self.get_at(index))
+            })
+            .collect::<Vec<_>>();
+        let (min, max) = tiles
+            .iter()
+            .flat_map(|(corners, _)| corners)
+            .fold((Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)), |(min, max), corner| {
+                (min.min(*corner), max.max(*corner))
+            });
+        if tiles.is_empty() {
+            return image::RgbaImage::new(0, 0);
+        }
+
+        let width = ((max.x - min.x) * pixels_per_unit).ceil() as u32;
+        let height = ((max.y - min.y) * pixels_per_unit).ceil() as u32;
+        let mut image = image::RgbaImage::new(width, height);
+        for (corners, state) in tiles {
+            // Image rows go down, so flip the board over.
+            let corners = corners
+                .into_iter()
+                .map(|corner| Vec2::new(corner.x - min.x, max.y - corner.y) * pixels_per_unit)
+                .collect::<Vec<_>>();
+            let color = image::Rgba(color_bytes(
+                colors.get(&state).cloned().unwrap_or(Color::GRAY),
+            ));
+            let (low, high) = corners.iter().fold(
+                (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+                |(low, high), corner| (low.min(*corner), high.max(*corner)),
+            );
+            let rows = low.y.floor().max(0.0) as u32..(high.y.ceil() as u32).min(height);
+            for y in rows {
+                let columns = low.x.floor().max(0.0) as u32..(high.x.ceil() as u32).min(width);
+                for x in columns {
+                    let center = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    if in_convex_polygon(center, &corners) {
+                        image.put_pixel(x, y, color);
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    fn write_png<W: Write>(
+        &self,
+        colors: &HashMap<u32, Color>,
+        pixels_per_unit: f32,
+        writer: &mut W,
+    ) -> image::ImageResult<()> {
+        let image = self.render_image(colors, pixels_per_unit);
+        image::png::PngEncoder::new(writer).encode(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            image::ColorType::Rgba8,
+        )
+    }
+
+    fn export_png(
+        &self,
+        colors: &HashMap<u32, Color>,
+        pixels_per_unit: f32,
+        path: impl AsRef<Path>,
+    ) -> image::ImageResult<()> {
+        let file = std::fs::File::create(path)?;
+        let mut writer = std::io::BufWriter::new(file);
+        self.write_png(colors, pixels_per_unit, &mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn export_svg(
+        &self,
+        region: Range<IVec2>,
+        colors: &HashMap<u32, Color>,
+        path: impl AsRef<Path>,
+    ) -> std::io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        let mut writer = std::io::BufWriter::new(file);
+        self.write_svg(region, colors, &mut writer)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        math::{IVec2, Vec2},
+        prelude::Color,
+        utils::HashMap,
+    };
+
+    use crate::{
+        simulation::{BoundaryMode, SimulationState},
+        tiling::{TileShape, Tiling, TilingKind},
+    };
+
+    use super::{
+        load_board_binary, save_board_binary, write_varint, BoardExport,
+        BoardLoadError, BOARD_MAGIC, BOARD_VERSION, MAX_BOARD_STATES,
+    };
+
+    fn board(size: i32, cells: &[(IVec2, u32)]) -> SimulationState {
+        let mut sim_state = SimulationState::new(Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(size, size),
+            offset: Vec2::ZERO,
+        });
+        sim_state.add_state(TileShape::Square);
+        for (index, state) in cells {
+            sim_state.set_at(*index, *state);
+        }
+        sim_state.process();
+        sim_state
+    }
+
+    #[test]
+    fn round_trip() {
+        let cells = [
+            (IVec2::new(0, 0), 1),
+            (IVec2::new(5, 3), 2),
+            (IVec2::new(19, 19), 1),
+            (IVec2::new(7, 12), 2),
+        ];
+        let sim_state = board(20, &cells);
+
+        let mut bytes = Vec::new();
+        save_board_binary(&sim_state, &mut bytes).unwrap();
+        let mut loaded = load_board_binary(&mut bytes.as_slice()).unwrap();
+        loaded.process();
+
+        assert_eq!(loaded.tiling.kind, TilingKind::Square);
+        assert_eq!(loaded.tiling.max_index, IVec2::new(20, 20));
+        assert_eq!(loaded.num_states, 3);
+        for y in 0..20 {
+            for x in 0..20 {
+                let index = IVec2::new(x, y);
+                assert_eq!(loaded.get_at(index), sim_state.get_at(index));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_keeps_dead_boundaries() {
+        let mut sim_state = board(6, &[]);
+        sim_state.set_boundary(BoundaryMode::Dead);
+        // Cells along the edges would be neighbors if the board wrapped around.
+        for index in [IVec2::new(0, 0), IVec2::new(5, 0), IVec2::new(0, 5)] {
+            sim_state.set_at(index, 1);
+        }
+        sim_state.process();
+
+        let mut bytes = Vec::new();
+        save_board_binary(&sim_state, &mut bytes).unwrap();
+        let mut loaded = load_board_binary(&mut bytes.as_slice()).unwrap();
+        loaded.process();
+
+        assert_eq!(loaded.boundary(), BoundaryMode::Dead);
+        assert_eq!(loaded.get_at(IVec2::new(5, 0)), 1);
+        assert_eq!(loaded.get_neighbor_count(IVec2::new(0, 0), 1), 0);
+
+        // Toroidal boards still come back wrapping around.
+        let sim_state = board(6, &[(IVec2::new(0, 0), 1), (IVec2::new(5, 0), 1)]);
+        let mut bytes = Vec::new();
+        save_board_binary(&sim_state, &mut bytes).unwrap();
+        let mut loaded = load_board_binary(&mut bytes.as_slice()).unwrap();
+        loaded.process();
+        assert_eq!(loaded.boundary(), BoundaryMode::Toroidal);
+        assert_eq!(loaded.get_neighbor_count(IVec2::new(0, 0), 1), 1);
+    }
+
+    #[test]
+    fn sparse_boards_are_small() {
+        let cells = [
+            (IVec2::new(10, 10), 1),
+            (IVec2::new(11, 10), 1),
+            (IVec2::new(12, 10), 1),
+            (IVec2::new(150, 180), 2),
+        ];
+        let sim_state = board(200, &cells);
+
+        let mut bytes = Vec::new();
+        save_board_binary(&sim_state, &mut bytes).unwrap();
+        // Even a dense encoding using a single byte per cell would be 40000 bytes.
+        assert!(bytes.len() < 40);
+    }
+
+    #[test]
+    fn diff_lists_changed_cells() {
+        let before = board(
+            10,
+            &[(IVec2::new(1, 1), 1), (IVec2::new(2, 5), 2), (IVec2::new(9, 0), 1)],
+        );
+        let after = board(
+            10,
+            &[(IVec2::new(1, 1), 1), (IVec2::new(2, 5), 1), (IVec2::new(4, 4), 2)],
+        );
+
+        let mut changes = before.diff(&after);
+        changes.sort_unstable_by_key(|(index, _, _)| (index.x, index.y));
+        assert_eq!(
+            changes,
+            vec![
+                (IVec2::new(2, 5), 2, 1),
+                (IVec2::new(4, 4), 0, 2),
+                (IVec2::new(9, 0), 1, 0),
+            ]
+        );
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let bytes = b"NOPE\x01\x00";
+        assert!(matches!(
+            load_board_binary(&mut bytes.as_slice()),
+            Err(BoardLoadError::InvalidMagic)
+        ));
+    }
+
+    // The bytes of a square 4x4 board with the given header values and no cells.
+    fn header(wrap: u8, num_states: u64) -> Vec<u8> {
+        let mut bytes = BOARD_MAGIC.to_vec();
+        bytes.extend([BOARD_VERSION, 0, 4, 4, wrap]);
+        write_varint(&mut bytes, num_states).unwrap();
+        write_varint(&mut bytes, 0).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn rejects_boards_too_large_to_index() {
+        let mut bytes = BOARD_MAGIC.to_vec();
+        bytes.extend([BOARD_VERSION, 0]);
+        write_varint(&mut bytes, i32::MAX as u64).unwrap();
+        let mut wide = bytes.clone();
+        write_varint(&mut wide, 2).unwrap();
+        let mut huge = bytes.clone();
+        write_varint(&mut huge, u64::MAX).unwrap();
+        for mut bytes in [wide, huge] {
+            bytes.extend([1, 2, 0]);
+            assert!(matches!(
+                load_board_binary(&mut bytes.as_slice()),
+                Err(BoardLoadError::InvalidHeader)
+            ));
+        }
+    }
+
+    // `header` followed by the given (index delta, state) cells.
+    fn with_cells(cells: &[(u64, u64)]) -> Vec<u8> {
+        let mut bytes = header(1, 3);
+        bytes.pop();
+        write_varint(&mut bytes, cells.len() as u64).unwrap();
+        for &(delta, state) in cells {
+            write_varint(&mut bytes, delta).unwrap();
+            write_varint(&mut bytes, state).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn rejects_overflowing_cells() {
+        assert!(load_board_binary(&mut with_cells(&[(1, 2), (3, 1)]).as_slice()).is_ok());
+        assert!(matches!(
+            load_board_binary(&mut with_cells(&[(1, 1), (u64::MAX, 1)]).as_slice()),
+            Err(BoardLoadError::CellOutOfBounds(_))
+        ));
+        assert!(matches!(
+            load_board_binary(&mut with_cells(&[(1, u32::MAX as u64 + 1)]).as_slice()),
+            Err(BoardLoadError::InvalidState(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_header_values() {
+        assert!(load_board_binary(&mut header(1, 3).as_slice()).is_ok());
+        assert!(load_board_binary(&mut header(1, MAX_BOARD_STATES as u64).as_slice()).is_ok());
+        for bytes in [
+            header(1, 0),
+            header(1, MAX_BOARD_STATES as u64 + 1),
+            header(1, u64::MAX),
+            header(2, 3),
+        ] {
+            assert!(matches!(
+                load_board_binary(&mut bytes.as_slice()),
+                Err(BoardLoadError::InvalidHeader)
+            ));
+        }
+    }
+
+    fn svg_polygons(sim_state: &SimulationState, region: std::ops::Range<IVec2>) -> Vec<String> {
+        let mut colors = HashMap::default();
+        colors.insert(0, Color::WHITE);
+        colors.insert(1, Color::BLACK);
+        colors.insert(2, Color::RED);
+        let mut bytes = Vec::new();
+        sim_state.write_svg(region, &colors, &mut bytes).unwrap();
+        String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .filter(|line| line.trim_start().starts_with("<polygon"))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn svg_draws_region_with_state_colors() {
+        let sim_state = board(10, &[(IVec2::new(1, 1), 1), (IVec2::new(2, 0), 2)]);
+        let polygons = svg_polygons(&sim_state, IVec2::ZERO..IVec2::new(3, 2));
+
+        assert_eq!(polygons.len(), 6);
+        let count_fill = |fill: &str| {
+            polygons
+                .iter()
+                .filter(|polygon| polygon.contains(&format!("fill=\"{}\"", fill)))
+                .count()
+        };
+        assert_eq!(count_fill("#ffffff"), 4);
+        assert_eq!(count_fill("#000000"), 1);
+        assert_eq!(count_fill("#ff0000"), 1);
+    }
+
+    #[test]
+    fn svg_draws_each_tile_shape() {
+        let sim_state = SimulationState::new(Tiling {
+            kind: TilingKind::OctagonAndSquare,
+            max_index: IVec2::new(4, 4),
+            offset: Vec2::ZERO,
+        });
+        let polygons = svg_polygons(&sim_state, IVec2::ZERO..IVec2::new(2, 2));
+
+        let corner_counts = polygons
+            .iter()
+            .map(|polygon| {
+                let points = polygon.split('"').nth(1).unwrap();
+                points.split(' ').count()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(corner_counts.iter().filter(|count| **count == 4).count(), 2);
+        assert_eq!(corner_counts.iter().filter(|count| **count == 8).count(), 2);
+    }
+
+    #[test]
+    fn png_has_state_colors_in_corners() {
+        let sim_state = board(2, &[(IVec2::new(0, 0), 1), (IVec2::new(1, 1), 1)]);
+        let mut colors = HashMap::default();
+        colors.insert(0, Color::WHITE);
+        colors.insert(1, Color::BLACK);
+        let mut bytes = Vec::new();
+        sim_state.write_png(&colors, 4.0, &mut bytes).unwrap();
+
+        let image = image::load_from_memory_with_format(&bytes, image::ImageFormat::Png)
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(image.dimensions(), (8, 8));
+        // Cell (0, 0) is in the bottom left of the image.
+        let white = image::Rgba([255, 255, 255, 255]);
+        let black = image::Rgba([0, 0, 0, 255]);
+        assert_eq!(*image.get_pixel(0, 0), white);
+        assert_eq!(*image.get_pixel(7, 0), black);
+        assert_eq!(*image.get_pixel(0, 7), black);
+        assert_eq!(*image.get_pixel(7, 7), white);
+    }
+}
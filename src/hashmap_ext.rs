@@ -1,6 +1,6 @@
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash};
 
-use bevy::utils::HashMap;
+use bevy::utils::hashbrown::HashMap;
 
 pub trait HashMultiMapExt<K, T> {
     fn add_element(&mut self, key: K, value: T);
@@ -11,7 +11,7 @@ pub trait HashMultiMapExt<K, T> {
     }
 }
 
-impl<K: Eq + Hash, T> HashMultiMapExt<K, T> for HashMap<K, Vec<T>> {
+impl<K: Eq + Hash, T, S: BuildHasher> HashMultiMapExt<K, T> for HashMap<K, Vec<T>, S> {
     fn add_element(&mut self, key: K, value: T) {
         if let Some(list) = self.get_mut(&key) {
             list.push(value);
@@ -0,0 +1,8 @@
+//! The simulation and tilings without any of the rendering, so boards can be run headless
+//! (see `src/bin/headless.rs`) or from other crates.
+
+pub mod patterns;
+pub mod random;
+pub mod simulation;
+pub mod tiling;
+pub mod worker;
@@ -1,5 +1,6 @@
 use bevy::{
-    hierarchy::{BuildChildren, Children, Parent},
+    app::AppExit,
+    hierarchy::{BuildChildren, Children, DespawnRecursiveExt, Parent},
     input::{
         mouse::{MouseMotion, MouseWheel},
         Input,
@@ -10,10 +11,10 @@ use bevy::{
         StandardMaterial,
     },
     prelude::{
-        App, AssetServer, Assets, Camera, Changed, Color, Commands, Component, CoreStage,
-        Entity, EventReader, EventWriter, GlobalTransform, Handle, Image, KeyCode, Mesh,
+        App, AssetServer, Assets, Camera, Changed, Color, Commands, Component, CoreStage, DetectChanges,
+        Entity, EventReader, EventWriter, GlobalTransform, Handle, Image, KeyCode, Local, Mesh,
         MouseButton, OrthographicCameraBundle, ParallelSystemDescriptorCoercion,
-        PerspectiveCameraBundle, Query, Res, ResMut, Transform, Visibility, With, Without,
+        PerspectiveCameraBundle, Query, Res, ResMut, Time, Transform, Visibility, With, Without,
     },
     render::{
         camera::Camera3d,
@@ -21,22 +22,27 @@ use bevy::{
     },
     sprite::{ColorMaterial, MaterialMesh2dBundle, Mesh2dHandle},
     text::{Font, Text, Text2dBundle, TextAlignment, TextSection, TextStyle},
-    utils::HashMap,
-    window::Windows,
+    utils::{HashMap, HashSet},
+    window::{WindowResized, Windows},
     DefaultPlugins,
 };
 
-use menus::{DebugTileEvent, MenuState};
+use board::{BoardExport, ExportGridPng, ShowBoardDiff};
+use game_of_life::{patterns, random, simulation, tiling, worker::SimulationWorker};
+use serde::{Deserialize, Serialize};
+use hashmap_ext::HashMultiMapExt;
+use menus::{DebugTileEvent, MenuState, PaintMode, PlacePattern};
 use simulation::SimulationState;
 use tiling::{
-    EquilateralDirection, RightTriangleRotation, TileShape, Tiling, TilingKind,
-    OCTAGON_SQUARE_DIFFERENCE_OF_CENTER,
+    EquilateralDirection, RightTriangleRotation, Tile, TileShape, Tiling, TilingKind,
+    DEFAULT_GRID_SIZE, OCTAGON_SQUARE_DIFFERENCE_OF_CENTER,
 };
 use visuals::{
     collapse::{
-        collapse_visuals, rebuild_visuals, CollapseState,
-        SimulationStateChanged,
+        collapse_visuals, rebuild_visuals, update_collapse_placement, CollapseEntry,
+        CollapseProgress, CollapseState, SimulationStateChanged,
     },
+    export::{export_terrain_obj, ExportTerrainObj},
     render::{
         instanced_mesh::InstanceMeshRenderPlugin, instanced_mesh_material::InstancedMaterialPlugin,
         instanced_pbr::InstancedStandardMaterial,
@@ -49,10 +55,9 @@ extern crate bitflags;
 extern crate bytemuck;
 extern crate enum_flags;
 
+mod board;
 mod hashmap_ext;
 mod menus;
-mod simulation;
-mod tiling;
 mod ui;
 mod visuals;
 
@@ -65,15 +70,88 @@ struct VisualState {
     camera_offset: Vec3,
     camera_angle: Vec2,
     last_click_pos: Option<Vec3>,
+    // Where the last sample of a Ctrl-drag paint stroke landed on the board.
+    last_paint_pos: Option<Vec2>,
     visual_grid_count: IVec2,
     scale: f32,
     min_scale: f32,
     max_scale: f32,
     add_debug: bool,
-    hide: bool,
+    // Tint the tiles by how long their cells have been in their state instead of showing the
+    // state's color as is. Toggled with A.
+    show_age: bool,
 }
 
+/// The file in the working directory the camera and view are kept in between launches.
+const VIEW_STATE_FILE: &str = "view_state.ron";
+
+/// The part of `VisualState` that is kept between launches.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+struct SavedView {
+    cur_offset: Vec2,
+    camera_offset: Vec3,
+    camera_angle: Vec2,
+    scale: f32,
+    // Views saved before the view mode was kept open in the default one.
+    #[serde(default)]
+    view_mode: ViewMode,
+}
+
+impl VisualState {
+    fn saved_view(&self, view_mode: ViewMode) -> SavedView {
+        SavedView {
+            cur_offset: self.cur_offset,
+            camera_offset: self.camera_offset,
+            camera_angle: self.camera_angle,
+            scale: self.scale,
+            view_mode,
+        }
+    }
+
+    /// Take on a saved view, clamped to the limits the inputs keep the view in. Returns the
+    /// view mode it was saved in.
+    fn restore(&mut self, saved: SavedView) -> ViewMode {
+        self.cur_offset = saved.cur_offset;
+        self.camera_offset = saved.camera_offset;
+        self.camera_angle = Vec2::new(
+            saved.camera_angle.x % 360.0,
+            saved.camera_angle.y.clamp(5.0, 85.0),
+        );
+        self.scale = saved.scale.clamp(self.min_scale, self.max_scale);
+        saved.view_mode
+    }
+
+    fn save(&self, view_mode: ViewMode, path: &std::path::Path) -> std::io::Result<()> {
+        let source = ron::ser::to_string_pretty(&self.saved_view(view_mode), Default::default())
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        std::fs::write(path, source)
+    }
+
+    /// Restore the view saved at the path and return the view mode it was saved in.
+    fn load(&mut self, path: &std::path::Path) -> std::io::Result<ViewMode> {
+        let source = std::fs::read_to_string(path)?;
+        let saved = ron::from_str(&source)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        Ok(self.restore(saved))
+    }
+}
+
+/// Which view of the board is shown. Only the 3D camera and the collapse entries are active in
+/// the terrain view, while the 2D tiles are only shown in the flat view. The 2D camera is always
+/// active since it also draws the menus.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+enum ViewMode {
+    Flat2D,
+    #[default]
+    Terrain3D,
+}
+
+/// Marks the camera that renders the terrain. It only has `Camera3d` while the terrain view is
+/// shown so it is not rendered in the flat view.
 #[derive(Component)]
+struct TerrainCamera;
+
+#[derive(Component, Default)]
 pub struct VisualsCache {
     meshes: HashMap<TileShape, Mesh2dHandle>,
     states: HashMap<u32, Handle<ColorMaterial>>,
@@ -81,6 +159,66 @@ pub struct VisualsCache {
     font: Handle<Font>,
     debug_vis_material: Handle<StandardMaterial>,
     debug_vis_outline_material: Handle<StandardMaterial>,
+    hover_outline_meshes: HashMap<TileShape, Mesh2dHandle>,
+    hover_outline_material: Handle<ColorMaterial>,
+    diff_gained_material: Handle<ColorMaterial>,
+    diff_lost_material: Handle<ColorMaterial>,
+    // Drawn over by cells the next generation changes while previewing it.
+    preview_material: Handle<ColorMaterial>,
+    // The color of each state dimmed for each age level, while showing cell ages.
+    age_materials: HashMap<(u32, u32), Handle<ColorMaterial>>,
+}
+
+/// How many shades cell ages are shown in, from just changed to the dimmest.
+const AGE_LEVELS: u32 = 8;
+
+/// How many generations a cell spends at each age level before dimming to the next.
+const AGE_LEVEL_GENERATIONS: u64 = 4;
+
+// The age level the cell at `index` is shown with, or 0 when ages aren't shown.
+fn tile_age_level(index: IVec2, sim_state: &SimulationState, vis_state: &VisualState) -> u32 {
+    if !vis_state.show_age {
+        return 0;
+    }
+    (sim_state.get_age(index) / AGE_LEVEL_GENERATIONS).min(AGE_LEVELS as u64 - 1) as u32
+}
+
+// A state's color for the age level. Cells that just changed are washed halfway to white, which
+// fades out as they age until the oldest are shown at half the state's brightness.
+fn age_color(color: Color, level: u32) -> Color {
+    let age = level as f32 / (AGE_LEVELS - 1) as f32;
+    let highlight = 0.5 * (1.0 - age);
+    let brightness = 1.0 - 0.5 * age;
+    let [r, g, b, a] = color.as_rgba_f32();
+    let shade = |channel: f32| (channel + (1.0 - channel) * highlight) * brightness;
+    Color::rgba(shade(r), shade(g), shade(b), a)
+}
+
+/// How many pixels across a unit square tile is in grid screenshots.
+const GRID_PNG_PIXELS_PER_UNIT: f32 = 16.0;
+
+/// How far the hover outline reaches in from the edges of the tile, relative to its size.
+const HOVER_OUTLINE_WIDTH: f32 = 0.12;
+
+/// Marks the outline drawn around the tile under the cursor in the 2D view.
+#[derive(Component)]
+struct HoverOutline;
+
+/// The edges of every visible tile in the 2D view, drawn as one line mesh. Toggled with G.
+#[derive(Component, Default)]
+struct GridLines {
+    shown: bool,
+    // The tiling kind, board size, center tile and grid count the mesh was last built for.
+    built_for: Option<(TilingKind, IVec2, IVec2, IVec2)>,
+}
+
+/// How far above the tiles the grid lines are drawn, below the hover outline.
+const GRID_LINES_DEPTH: f32 = 0.25;
+
+/// Outlines a cell that differs between two boards being compared.
+#[derive(Component)]
+struct DiffMarker {
+    index: IVec2,
 }
 
 #[derive(Component)]
@@ -92,6 +230,45 @@ struct TileState {
     alive_count: u32,
     dead_count: u32,
     next: u32,
+    age_level: u32,
+}
+
+/// Index of the tile entities currently spawned, keyed by the simulation index they display.
+/// When the view is larger than the board the same index can be shown by several tiles.
+#[derive(Default)]
+pub struct VisibleTiles {
+    tiles: HashMap<IVec2, Vec<Entity>>,
+}
+
+impl VisibleTiles {
+    /// The tile entities currently displaying the given (wrapped) simulation index.
+    pub fn tiles_at(&self, index: IVec2) -> &[Entity] {
+        self.tiles.get(&index).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// All of the visible tiles along with the index they display.
+    pub fn iter(&self) -> impl Iterator<Item = (IVec2, Entity)> + '_ {
+        self.tiles
+            .iter()
+            .flat_map(|(index, entities)| entities.iter().map(move |entity| (*index, *entity)))
+    }
+
+    fn insert(&mut self, index: IVec2, entity: Entity) {
+        self.tiles.add_element(index, entity);
+    }
+
+    fn remove(&mut self, index: IVec2, entity: Entity) {
+        if let Some(entities) = self.tiles.get_mut(&index) {
+            entities.retain(|other| *other != entity);
+            if entities.is_empty() {
+                self.tiles.remove(&index);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.tiles.clear();
+    }
 }
 
 fn setup_world(
@@ -101,10 +278,19 @@ fn setup_world(
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut standard_materials: ResMut<Assets<StandardMaterial>>,
     mut visuals_cache: ResMut<VisualsCache>,
+    mut visible_tiles: ResMut<VisibleTiles>,
     sim_state: Res<SimulationState>,
-    vis_state: Res<VisualState>,
+    mut vis_state: ResMut<VisualState>,
+    mut view_mode: ResMut<ViewMode>,
     menu_state: Res<MenuState>,
 ) {
+    // Pick up where the last session left the view before anything is placed with it.
+    match vis_state.load(std::path::Path::new(VIEW_STATE_FILE)) {
+        Ok(saved_mode) => *view_mode = saved_mode,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => bevy::log::warn!("Failed to restore the view from {}: {}", VIEW_STATE_FILE, err),
+    }
+
     for shape in [TileShape::Square, TileShape::Hexagon, TileShape::Octagon] {
         let mut verticies = vec![[0.0, 0.0, 0.0]];
         let mut normals = vec![[0.0, 0.0, 1.0]];
@@ -204,9 +390,54 @@ fn setup_world(
         visuals_cache.meshes.insert(shape, handle.into());
     }
 
+    let shapes = visuals_cache.meshes.keys().cloned().collect::<Vec<_>>();
+    for shape in shapes {
+        let handle = meshes.add(build_hover_outline_mesh(shape));
+        visuals_cache
+            .hover_outline_meshes
+            .insert(shape, handle.into());
+    }
+    visuals_cache.hover_outline_material = materials.add(ColorMaterial {
+        color: Color::YELLOW,
+        texture: None,
+    });
+    visuals_cache.diff_gained_material = materials.add(ColorMaterial {
+        color: Color::GREEN,
+        texture: None,
+    });
+    visuals_cache.diff_lost_material = materials.add(ColorMaterial {
+        color: Color::RED,
+        texture: None,
+    });
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            // Rebuilt for the visible grid the first time the lines are shown.
+            mesh: meshes
+                .add(build_grid_lines_mesh(&sim_state.tiling, IVec2::ZERO, IVec2::ONE))
+                .into(),
+            material: materials.add(ColorMaterial {
+                color: Color::BLACK,
+                texture: None,
+            }),
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(GridLines::default());
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            material: visuals_cache.hover_outline_material.clone(),
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(HoverOutline);
+
     let outline_img = asset_server.load("Outline.png");
 
     visuals_cache.outline_image = outline_img.clone();
+    visuals_cache.preview_material = materials.add(ColorMaterial {
+        color: Color::ORANGE,
+        texture: Some(outline_img.clone()),
+    });
     visuals_cache.states.insert(
         0,
         materials.add(ColorMaterial {
@@ -233,12 +464,6 @@ fn setup_world(
     visuals_cache.font = asset_server
         .load("fonts/brass-mono-font-freeware-peter-fonseca/BrassMonoCozyRegular-g146.otf");
 
-    let default_color = visuals_cache
-        .states
-        .get(&0)
-        .expect("Failed to get material just created!")
-        .clone();
-
     commands.insert_resource(AmbientLight {
         color: Color::ORANGE_RED,
         brightness: 0.02,
@@ -257,47 +482,13 @@ fn setup_world(
         ..Default::default()
     });
 
-    let half_size = Vec2::ZERO; //sim_state.tiling.size() / 2.0;
-    for x in -vis_state.visual_grid_count.x / 2..(vis_state.visual_grid_count.x + 1) / 2 {
-        for y in -vis_state.visual_grid_count.y / 2..(vis_state.visual_grid_count.y + 1) / 2 {
-            let tile = sim_state.tiling.get_tile_at_index(IVec2::new(x, y));
-
-            let mut entity = commands.spawn_bundle(MaterialMesh2dBundle {
-                mesh: visuals_cache
-                    .meshes
-                    .get(&tile.shape)
-                    .expect("Failed to get mesh we just inserted!")
-                    .clone(),
-                material: default_color.clone(),
-                transform: Transform::from_translation((tile.position - half_size).extend(0.0)),
-                ..Default::default()
-            });
-            entity.insert(TileState {
-                offset_from_center: IVec2::new(x, y),
-                computed_index: sim_state.tiling.adjust_index(IVec2::new(x, y)),
-                current_state: if x == 25 { 1 } else { 0 },
-                previous_shape: tile.shape,
-                alive_count: 0,
-                dead_count: sim_state.tiling.get_neighbors(IVec2::new(x, y)).len() as u32,
-                next: 0,
-            });
-            if vis_state.add_debug {
-                entity.with_children(|child_builder| {
-                    child_builder.spawn_bundle(Text2dBundle {
-                        text: Text {
-                            sections: Vec::new(),
-                            alignment: TextAlignment {
-                                vertical: bevy::text::VerticalAlign::Center,
-                                horizontal: bevy::text::HorizontalAlign::Center,
-                            },
-                        },
-                        transform: Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
-                        ..Default::default()
-                    });
-                });
-            }
-        }
-    }
+    spawn_tiles(
+        &mut commands,
+        &mut visible_tiles,
+        &visuals_cache,
+        &sim_state,
+        &vis_state,
+    );
 
     visuals_cache.debug_vis_material = standard_materials.add(StandardMaterial {
         cull_mode: None,
@@ -312,34 +503,267 @@ fn setup_world(
     let mut camera = PerspectiveCameraBundle::new_3d();
     camera.transform =
         Transform::from_xyz(-20.0, 20.0, -20.0).looking_at(Vec3::new(25.0, 0.0, 25.0), Vec3::Y);
-    commands.spawn_bundle(camera);
+    commands.spawn_bundle(camera).insert(TerrainCamera);
     commands.spawn_bundle(OrthographicCameraBundle::new_2d());
 }
 
+// A band running around the inside of the edges of the shape, built from its corners.
+fn build_hover_outline_mesh(shape: TileShape) -> Mesh {
+    let corners = shape.get_corner_positions();
+    let center =
+        corners.iter().fold(Vec2::ZERO, |sum, corner| sum + *corner) / corners.len() as f32;
+    let mut verticies = Vec::new();
+    let mut indicies = Vec::new();
+    for (i, corner) in corners.iter().enumerate() {
+        let inner = center + (*corner - center) * (1.0 - HOVER_OUTLINE_WIDTH);
+        verticies.push([corner.x, corner.y, 0.0]);
+        verticies.push([inner.x, inner.y, 0.0]);
+        let next = 2 * ((i + 1) % corners.len()) as u32;
+        let cur = 2 * i as u32;
+        indicies.extend_from_slice(&[cur, next, cur + 1, cur + 1, next, next + 1]);
+    }
+    let normals = vec![[0.0, 0.0, 1.0]; verticies.len()];
+    let uvs = vec![[0.0, 0.0]; verticies.len()];
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, verticies);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_indices(Some(Indices::U32(indicies)));
+    mesh
+}
+
+// Add the edges of a tile centered at `center` to a line list, two verticies for each edge.
+fn push_tile_outline(shape: TileShape, center: Vec2, verticies: &mut Vec<[f32; 3]>) {
+    let corners = shape.get_corner_positions();
+    for (i, corner) in corners.iter().enumerate() {
+        let next = corners[(i + 1) % corners.len()];
+        for point in [*corner, next] {
+            verticies.push([center.x + point.x, center.y + point.y, 0.0]);
+        }
+    }
+}
+
+/// A single line mesh outlining every tile in a grid of `count` tiles around `central_index`,
+/// positioned relative to the central tile.
+fn build_grid_lines_mesh(tiling: &Tiling, central_index: IVec2, count: IVec2) -> Mesh {
+    let mut verticies = Vec::new();
+    for offset in grid_offsets(count) {
+        let index = central_index + offset;
+        let shape = tiling.get_tile_at_index(tiling.adjust_index(index)).shape;
+        let center = tiling.compute_offset_between_indicies(central_index, index);
+        push_tile_outline(shape, center, &mut verticies);
+    }
+    let normals = vec![[0.0, 0.0, 1.0]; verticies.len()];
+    let uvs = vec![[0.0, 0.0]; verticies.len()];
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, verticies);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh
+}
+
+// The offsets from the center tile of every tile in a visual grid of the given count.
+fn grid_offsets(count: IVec2) -> impl Iterator<Item = IVec2> {
+    (-count.x / 2..(count.x + 1) / 2)
+        .flat_map(move |x| (-count.y / 2..(count.y + 1) / 2).map(move |y| IVec2::new(x, y)))
+}
+
+/// Spawn the grid of tiles used to display the simulation, centered on the current view.
+fn spawn_tiles(
+    commands: &mut Commands,
+    visible_tiles: &mut VisibleTiles,
+    visuals_cache: &VisualsCache,
+    sim_state: &SimulationState,
+    vis_state: &VisualState,
+) {
+    for offset_from_center in grid_offsets(vis_state.visual_grid_count) {
+        spawn_tile(
+            commands,
+            visible_tiles,
+            visuals_cache,
+            sim_state,
+            vis_state,
+            offset_from_center,
+        );
+    }
+}
+
+/// Spawn the tile `offset_from_center` tiles away from the tile at the center of the view.
+fn spawn_tile(
+    commands: &mut Commands,
+    visible_tiles: &mut VisibleTiles,
+    visuals_cache: &VisualsCache,
+    sim_state: &SimulationState,
+    vis_state: &VisualState,
+    offset_from_center: IVec2,
+) {
+    let default_color = visuals_cache
+        .states
+        .get(&0)
+        .expect("Failed to get material that should be registered!")
+        .clone();
+    let central_tile = sim_state.tiling.get_tile_containing(vis_state.cur_offset);
+    let index = sim_state
+        .tiling
+        .adjust_index(central_tile.index + offset_from_center);
+    let tile = sim_state.tiling.get_tile_at_index(index);
+
+    let mut entity = commands.spawn_bundle(MaterialMesh2dBundle {
+        mesh: visuals_cache
+            .meshes
+            .get(&tile.shape)
+            .expect("Failed to get mesh that should be registered!")
+            .clone(),
+        material: default_color,
+        transform: Transform::from_translation(
+            (vis_state.scale
+                * sim_state.tiling.compute_offset_between_indicies(
+                    central_tile.index,
+                    central_tile.index + offset_from_center,
+                ))
+            .extend(0.0),
+        ),
+        ..Default::default()
+    });
+    entity.insert(TileState {
+        offset_from_center,
+        computed_index: index,
+        current_state: sim_state.get_at(index),
+        previous_shape: tile.shape,
+        alive_count: sim_state.get_neighbor_count(index, 1),
+        dead_count: sim_state.get_neighbor_count(index, 0),
+        next: sim_state.get_pending(index),
+        age_level: tile_age_level(index, sim_state, vis_state),
+    });
+    visible_tiles.insert(index, entity.id());
+    if vis_state.add_debug {
+        entity.with_children(|child_builder| {
+            child_builder.spawn_bundle(Text2dBundle {
+                text: Text {
+                    sections: Vec::new(),
+                    alignment: TextAlignment {
+                        vertical: bevy::text::VerticalAlign::Center,
+                        horizontal: bevy::text::HorizontalAlign::Center,
+                    },
+                },
+                transform: Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
+                ..Default::default()
+            });
+        });
+    }
+}
+
+/// How many tiles along each axis we need to cover a window of the given size at the given
+/// scale, with a tile of margin on every side. Never more than the board has, since those would
+/// just show the same tiles twice.
+fn visual_grid_count_for(window_size: Vec2, scale: f32, tiling: &Tiling) -> IVec2 {
+    let tile_size = tiling.size() / tiling.max_index.max(IVec2::ONE).as_vec2();
+    let covered = (window_size / (scale * tile_size)).ceil().as_ivec2() + IVec2::splat(2);
+    covered.min(tiling.max_index).max(IVec2::ONE)
+}
+
+fn fit_visual_grid(
+    mut window_resized: EventReader<WindowResized>,
+    windows: Res<Windows>,
+    sim_state: Res<SimulationState>,
+    mut vis_state: ResMut<VisualState>,
+) {
+    // Zooming and new boards change how many tiles we need as well as resizing the window.
+    let resized = window_resized.iter().count() > 0;
+    if !resized && !vis_state.is_changed() && !sim_state.is_changed() {
+        return;
+    }
+    if let Some(window) = windows.get_primary() {
+        let count = visual_grid_count_for(
+            Vec2::new(window.width(), window.height()),
+            vis_state.scale,
+            &sim_state.tiling,
+        );
+        if count != vis_state.visual_grid_count {
+            vis_state.visual_grid_count = count;
+        }
+    }
+}
+
+/// When the simulation switches to a new tiling, throw away the tiles built before and spawn a
+/// fresh set so their shapes and indices match the tiling. When we only need a different number
+/// of tiles to cover the window, spawn or despawn the tiles around the edge of the grid instead.
+fn rebuild_tiles(
+    mut commands: Commands,
+    mut events: EventReader<SimulationStateChanged>,
+    tile_query: Query<(Entity, &TileState)>,
+    // The grid count the current tiles were built with.
+    (mut visible_tiles, mut built_count): (ResMut<VisibleTiles>, Local<IVec2>),
+    visuals_cache: Res<VisualsCache>,
+    sim_state: Res<SimulationState>,
+    vis_state: Res<VisualState>,
+) {
+    let new_tiling = events
+        .iter()
+        .any(|event| {
+            matches!(
+                event,
+                SimulationStateChanged::NewTiling | SimulationStateChanged::Resized { .. }
+            )
+        });
+    if !new_tiling && *built_count == vis_state.visual_grid_count {
+        return;
+    }
+    *built_count = vis_state.visual_grid_count;
+    if !new_tiling {
+        let offsets = grid_offsets(vis_state.visual_grid_count).collect::<HashSet<_>>();
+        let mut existing = HashSet::default();
+        for (entity, state) in tile_query.iter() {
+            if offsets.contains(&state.offset_from_center) {
+                existing.insert(state.offset_from_center);
+            } else {
+                visible_tiles.remove(state.computed_index, entity);
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        for offset_from_center in offsets.difference(&existing) {
+            spawn_tile(
+                &mut commands,
+                &mut visible_tiles,
+                &visuals_cache,
+                &sim_state,
+                &vis_state,
+                *offset_from_center,
+            );
+        }
+        return;
+    }
+    for (entity, _) in tile_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    visible_tiles.clear();
+    spawn_tiles(
+        &mut commands,
+        &mut visible_tiles,
+        &visuals_cache,
+        &sim_state,
+        &vis_state,
+    );
+}
+
 fn update_tile(
-    mut tile_query: Query<(&mut Transform, &mut TileState, &mut Visibility)>,
+    mut tile_query: Query<(Entity, &mut Transform, &mut TileState)>,
+    mut visible_tiles: ResMut<VisibleTiles>,
     vis_state: Res<VisualState>,
     sim_state: Res<SimulationState>,
 ) {
     let central_tile = sim_state.tiling.get_tile_containing(vis_state.cur_offset);
-    let mut offset = central_tile.position - vis_state.cur_offset;
-    // This is super hacky way to make sure we wrap smoothly but whatever...
-    let tiling_size = sim_state.tiling.size();
-    if offset.x > tiling_size.x / 2.0 {
-        offset.x -= tiling_size.x;
-    } else if offset.x < tiling_size.x / -2.0 {
-        offset.x += tiling_size.x;
-    }
-    if offset.y > tiling_size.y / 2.0 {
-        offset.y -= tiling_size.y;
-    } else if offset.y < tiling_size.y / -2.0 {
-        offset.y += tiling_size.y;
-    }
-    tile_query.for_each_mut(|(mut transform, mut state, mut vis)| {
+    let offset = wrap_offset(
+        central_tile.position - vis_state.cur_offset,
+        &sim_state.tiling,
+    );
+    tile_query.for_each_mut(|(entity, mut transform, mut state)| {
         let new_index = sim_state
             .tiling
             .adjust_index(central_tile.index + state.offset_from_center);
         if state.computed_index != new_index {
+            visible_tiles.remove(state.computed_index, entity);
+            visible_tiles.insert(new_index, entity);
             state.computed_index = new_index;
         }
         let new_state = sim_state.get_at(new_index);
@@ -362,9 +786,9 @@ fn update_tile(
         if pending != state.next {
             state.next = pending;
         }
-
-        if vis.is_visible == vis_state.hide {
-            vis.is_visible = !vis_state.hide;
+        let age_level = tile_age_level(new_index, &sim_state, &vis_state);
+        if age_level != state.age_level {
+            state.age_level = age_level;
         }
 
         transform.translation = vis_state.scale
@@ -378,6 +802,324 @@ fn update_tile(
     });
 }
 
+// Move an offset between two positions on the board across the edges of the tiling when that
+// gets the two positions closer together.
+fn wrap_offset(mut offset: Vec2, tiling: &Tiling) -> Vec2 {
+    // This is super hacky way to make sure we wrap smoothly but whatever...
+    let tiling_size = tiling.size();
+    if offset.x > tiling_size.x / 2.0 {
+        offset.x -= tiling_size.x;
+    } else if offset.x < tiling_size.x / -2.0 {
+        offset.x += tiling_size.x;
+    }
+    if offset.y > tiling_size.y / 2.0 {
+        offset.y -= tiling_size.y;
+    } else if offset.y < tiling_size.y / -2.0 {
+        offset.y += tiling_size.y;
+    }
+    offset
+}
+
+// Where the ray from the 3D camera through the cursor meets the ground plane, if it does.
+fn cursor_to_ground(
+    cursor: Vec2,
+    window_size: Vec2,
+    transform: &GlobalTransform,
+    camera: &Camera,
+) -> Option<Vec3> {
+    let camera_transform: Mat4 = transform.compute_matrix();
+    let camera_matrix: Mat4 = camera.projection_matrix.inverse();
+    let view_matrix = camera_transform * camera_matrix;
+
+    let x = 2.0 * (cursor.x / window_size.x) - 1.0;
+    let y = 2.0 * (cursor.y / window_size.y) - 1.0;
+
+    let near = view_matrix.project_point3(Vec3::new(x, y, -1.0));
+    let far = view_matrix.project_point3(Vec3::new(x, y, 1.0));
+
+    let dir = (far - near).normalize();
+
+    if dir.y.signum() != near.y.signum() {
+        let time_to_plane = near.y / -dir.y;
+        Some(near + dir * time_to_plane)
+    } else {
+        None
+    }
+}
+
+// The position on the board under the cursor in the 2D view.
+fn cursor_to_board_position(cursor: Vec2, window_size: Vec2, vis_state: &VisualState) -> Vec2 {
+    (cursor - window_size / 2.0) / vis_state.scale + vis_state.cur_offset
+}
+
+// The tile under the given board position and where its outline goes on screen so it lines up
+// with the tile displayed there.
+fn hover_outline_placement(
+    board_position: Vec2,
+    vis_state: &VisualState,
+    tiling: &Tiling,
+) -> (Tile, Vec3) {
+    let tile = tiling.get_tile_containing(board_position);
+    let to_tile = wrap_offset(tile.position - board_position, tiling);
+    let translation = vis_state.scale * (board_position - vis_state.cur_offset + to_tile);
+    (tile, translation.extend(0.5))
+}
+
+fn update_hover_outline(
+    mut outline_query: Query<
+        (&mut Transform, &mut Mesh2dHandle, &mut Visibility),
+        With<HoverOutline>,
+    >,
+    windows: Res<Windows>,
+    visuals_cache: Res<VisualsCache>,
+    vis_state: Res<VisualState>,
+    view_mode: Res<ViewMode>,
+    sim_state: Res<SimulationState>,
+) {
+    let primary_window = windows.primary();
+    let board_position = primary_window
+        .cursor_position()
+        .filter(|_| *view_mode == ViewMode::Flat2D)
+        .map(|cursor| {
+            cursor_to_board_position(
+                cursor,
+                Vec2::new(primary_window.width(), primary_window.height()),
+                &vis_state,
+            )
+        });
+    outline_query.for_each_mut(|(mut transform, mut mesh, mut vis)| {
+        let board_position = match board_position {
+            Some(board_position) => board_position,
+            None => {
+                vis.is_visible = false;
+                return;
+            }
+        };
+        let (tile, translation) =
+            hover_outline_placement(board_position, &vis_state, &sim_state.tiling);
+        if let Some(outline_mesh) = visuals_cache.hover_outline_meshes.get(&tile.shape) {
+            *mesh = outline_mesh.clone();
+        }
+        transform.translation = translation;
+        transform.scale = vis_state.scale * Vec3::ONE;
+        vis.is_visible = true;
+    });
+}
+
+fn update_grid_lines(
+    keyboard: Res<Input<KeyCode>>,
+    input_state: Res<ui::InputState>,
+    mut lines_query: Query<(&mut GridLines, &mut Transform, &Mesh2dHandle, &mut Visibility)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    vis_state: Res<VisualState>,
+    view_mode: Res<ViewMode>,
+    sim_state: Res<SimulationState>,
+) {
+    let toggle = keyboard.just_pressed(KeyCode::G) && !input_state.has_selection();
+    let tiling = &sim_state.tiling;
+    let central_tile = tiling.get_tile_containing(vis_state.cur_offset);
+    lines_query.for_each_mut(|(mut lines, mut transform, mesh, mut vis)| {
+        if toggle {
+            lines.shown = !lines.shown;
+        }
+        let visible = lines.shown && *view_mode == ViewMode::Flat2D;
+        if vis.is_visible != visible {
+            vis.is_visible = visible;
+        }
+        if !visible {
+            return;
+        }
+        // Only rebuild the mesh once the view moves onto a different center tile.
+        let built_for = Some((
+            tiling.kind,
+            tiling.max_index,
+            central_tile.index,
+            vis_state.visual_grid_count,
+        ));
+        if lines.built_for != built_for {
+            if let Some(mesh) = meshes.get_mut(&mesh.0) {
+                *mesh = build_grid_lines_mesh(
+                    tiling,
+                    central_tile.index,
+                    vis_state.visual_grid_count,
+                );
+            }
+            lines.built_for = built_for;
+        }
+        let offset = wrap_offset(central_tile.position - vis_state.cur_offset, tiling);
+        transform.translation = (vis_state.scale * offset).extend(GRID_LINES_DEPTH);
+        transform.scale = vis_state.scale * Vec3::ONE;
+    });
+}
+
+// Draw the board to a PNG with the colors the menus show each state in.
+fn export_grid_png(
+    mut events: EventReader<ExportGridPng>,
+    sim_state: Res<SimulationState>,
+    menu_state: Res<MenuState>,
+) {
+    for event in events.iter() {
+        match sim_state.export_png(&menu_state.state_to_color, event.pixels_per_unit, &event.path) {
+            Ok(()) => bevy::log::info!("Saved the grid to {}", event.path),
+            Err(err) => bevy::log::warn!("Failed to save the grid to {}: {}", event.path, err),
+        }
+    }
+}
+
+// Replace the diff outlines with ones for the newly compared boards.
+fn show_board_diff(
+    mut commands: Commands,
+    mut events: EventReader<ShowBoardDiff>,
+    marker_query: Query<Entity, With<DiffMarker>>,
+    visuals_cache: Res<VisualsCache>,
+    sim_state: Res<SimulationState>,
+) {
+    let changes = match events.iter().last() {
+        Some(event) => &event.changes,
+        None => return,
+    };
+    for entity in marker_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    for (index, before, after) in changes {
+        let shape = sim_state.tiling.get_tile_at_index(*index).shape;
+        let material = if after > before {
+            &visuals_cache.diff_gained_material
+        } else {
+            &visuals_cache.diff_lost_material
+        };
+        commands
+            .spawn_bundle(MaterialMesh2dBundle {
+                mesh: visuals_cache
+                    .hover_outline_meshes
+                    .get(&shape)
+                    .cloned()
+                    .unwrap_or_default(),
+                material: material.clone(),
+                ..Default::default()
+            })
+            .insert(DiffMarker { index: *index });
+    }
+}
+
+// Keep the diff outlines on top of the tiles they mark as the view moves. Outlines of cells that
+// no tile is showing are hidden.
+fn update_diff_markers(
+    mut marker_query: Query<(&DiffMarker, &mut Transform, &mut Visibility), Without<TileState>>,
+    tile_query: Query<&Transform, With<TileState>>,
+    visible_tiles: Res<VisibleTiles>,
+    view_mode: Res<ViewMode>,
+) {
+    marker_query.for_each_mut(|(marker, mut transform, mut vis)| {
+        let tile_transform = visible_tiles
+            .tiles_at(marker.index)
+            .first()
+            .and_then(|tile| tile_query.get(*tile).ok());
+        match tile_transform {
+            Some(tile_transform) => {
+                transform.translation = tile_transform.translation.truncate().extend(0.25);
+                transform.scale = tile_transform.scale;
+                vis.is_visible = *view_mode == ViewMode::Flat2D;
+            }
+            None => vis.is_visible = false,
+        }
+    });
+}
+
+// Show the camera and board entities of the current view and hide the rest. Entities spawned
+// since the last frame are caught here as well.
+fn apply_view_mode(
+    mut commands: Commands,
+    view_mode: Res<ViewMode>,
+    camera_query: Query<(Entity, Option<&Camera3d>), With<TerrainCamera>>,
+    mut tile_query: Query<&mut Visibility, With<TileState>>,
+    mut entry_query: Query<&mut Visibility, (With<CollapseEntry>, Without<TileState>)>,
+) {
+    let terrain = *view_mode == ViewMode::Terrain3D;
+    for (entity, camera_3d) in camera_query.iter() {
+        if terrain && camera_3d.is_none() {
+            commands.entity(entity).insert(Camera3d);
+        } else if !terrain && camera_3d.is_some() {
+            commands.entity(entity).remove::<Camera3d>();
+        }
+    }
+    tile_query.for_each_mut(|mut vis| {
+        if vis.is_visible == terrain {
+            vis.is_visible = !terrain;
+        }
+    });
+    entry_query.for_each_mut(|mut vis| {
+        if vis.is_visible != terrain {
+            vis.is_visible = terrain;
+        }
+    });
+}
+
+// Keep a material for each state at each age level in the state's current color, while ages are
+// shown.
+fn update_age_materials(
+    vis_state: Res<VisualState>,
+    menu_state: Res<MenuState>,
+    mut shown: Local<bool>,
+    mut visuals_cache: ResMut<VisualsCache>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let turned_on = vis_state.show_age && !*shown;
+    *shown = vis_state.show_age;
+    if !vis_state.show_age || !(turned_on || menu_state.is_changed()) {
+        return;
+    }
+    let visuals_cache = &mut *visuals_cache;
+    for (state, color) in menu_state.state_to_color.iter() {
+        for level in 0..AGE_LEVELS {
+            let color = age_color(*color, level);
+            match visuals_cache.age_materials.get(&(*state, level)) {
+                Some(handle) => {
+                    // Only touch the materials whose color changed so the rest aren't re-uploaded.
+                    let stale = materials
+                        .get(handle)
+                        .is_some_and(|material| material.color != color);
+                    if stale {
+                        materials.get_mut(handle).unwrap().color = color;
+                    }
+                }
+                None => {
+                    let material = materials.add(ColorMaterial {
+                        color,
+                        texture: Some(visuals_cache.outline_image.clone()),
+                    });
+                    visuals_cache.age_materials.insert((*state, level), material);
+                }
+            }
+        }
+    }
+}
+
+// Redraw every tile when the preview of the next generation is turned on or off.
+fn refresh_tile_preview(
+    menu_state: Res<MenuState>,
+    mut shown: Local<bool>,
+    mut tile_query: Query<&mut TileState>,
+) {
+    if menu_state.preview_next != *shown {
+        *shown = menu_state.preview_next;
+        tile_query.for_each_mut(|mut state| state.set_changed());
+    }
+}
+
+// Redraw every tile when ages are shown or hidden, since tiles whose age level did not change
+// would otherwise keep their old material.
+fn refresh_tile_age(
+    vis_state: Res<VisualState>,
+    mut shown: Local<bool>,
+    mut tile_query: Query<&mut TileState>,
+) {
+    if vis_state.show_age != *shown {
+        *shown = vis_state.show_age;
+        tile_query.for_each_mut(|mut state| state.set_changed());
+    }
+}
+
 fn update_tile_visual(
     mut tile_query: Query<
         (
@@ -392,9 +1134,12 @@ fn update_tile_visual(
     visuals_cache: Res<VisualsCache>,
     vis_state: Res<VisualState>,
     sim_state: Res<SimulationState>,
+    menu_state: Res<MenuState>,
 ) {
     tile_query.for_each_mut(|(mut mesh, mut material, state, children)| {
-        *mesh = visuals_cache
+        // Scrolling moves most tiles onto a new index without changing what they look like, so
+        // only touch the handles when they differ to keep them out of change detection.
+        let new_mesh = &visuals_cache
             .meshes
             .get(
                 &sim_state
@@ -403,12 +1148,31 @@ fn update_tile_visual(
                     .shape,
             )
             .expect("Failed to get mesh that should be registered!")
-            .clone();
-        *material = visuals_cache
-            .states
-            .get(&state.current_state)
-            .expect("Failed to get material that should be registered!")
-            .clone();
+            .0;
+        if mesh.0 != *new_mesh {
+            mesh.0 = new_mesh.clone();
+        }
+        let aged = vis_state
+            .show_age
+            .then(|| {
+                visuals_cache
+                    .age_materials
+                    .get(&(state.current_state, state.age_level))
+            })
+            .flatten();
+        let new_material = if menu_state.preview_next && state.next != state.current_state {
+            &visuals_cache.preview_material
+        } else if let Some(aged) = aged {
+            aged
+        } else {
+            visuals_cache
+                .states
+                .get(&state.current_state)
+                .expect("Failed to get material that should be registered!")
+        };
+        if *material != *new_material {
+            *material = new_material.clone();
+        }
         if let Some(children) = children {
             for child in children.iter() {
                 if let Ok((mut transform, mut text)) = text_query.get_mut(*child) {
@@ -434,7 +1198,8 @@ fn update_tile_visual(
 fn input_system(
     mut vis_state: ResMut<VisualState>,
     mut sim_state: ResMut<SimulationState>,
-    collapse_state: ResMut<CollapseState>,
+    mut collapse_state: ResMut<CollapseState>,
+    menu_state: Res<MenuState>,
 
     keyboard: Res<Input<KeyCode>>,
     mut input_state: ResMut<ui::InputState>,
@@ -446,8 +1211,18 @@ fn input_system(
     ui_element_query: Query<(&Transform, &mut ui::UiElement, Option<&Children>)>,
     camera: Query<(&GlobalTransform, &Camera), With<Camera3d>>,
     mut inspect_events: EventWriter<DebugTileEvent>,
+    mut collapse_events: EventWriter<SimulationStateChanged>,
+    (mut key_repeat, time, mut png_events, mut view_mode, mut rules_events): (
+        ResMut<ui::KeyRepeat>,
+        Res<Time>,
+        EventWriter<ExportGridPng>,
+        ResMut<ViewMode>,
+        EventWriter<menus::ShowRulesFor>,
+    ),
 ) {
     let shift_down = keyboard.pressed(KeyCode::LShift) || keyboard.pressed(KeyCode::RShift);
+    let ctrl_down = keyboard.pressed(KeyCode::LControl) || keyboard.pressed(KeyCode::RControl);
+    let alt_down = keyboard.pressed(KeyCode::LAlt) || keyboard.pressed(KeyCode::RAlt);
     let processed_input = input_state.process_inputs(
         shift_down,
         &mouse_input,
@@ -460,8 +1235,36 @@ fn input_system(
 
     if !input_state.has_selection() {
         if keyboard.just_pressed(KeyCode::H) && !vis_state.mouse_down {
-            vis_state.hide = !vis_state.hide;
+            *view_mode = match *view_mode {
+                ViewMode::Flat2D => ViewMode::Terrain3D,
+                ViewMode::Terrain3D => ViewMode::Flat2D,
+            };
+        }
+        if keyboard.just_pressed(KeyCode::A) {
+            vis_state.show_age = !vis_state.show_age;
         }
+        if keyboard.just_pressed(KeyCode::F12) {
+            png_events.send(ExportGridPng {
+                path: format!("grid_{}.png", sim_state.step),
+                pixels_per_unit: GRID_PNG_PIXELS_PER_UNIT,
+            });
+        }
+    }
+
+    // Holding an arrow key pans the 2D view a tile at a time.
+    let mut pan = Vec2::ZERO;
+    for (key, direction) in [
+        (KeyCode::Left, Vec2::new(-1.0, 0.0)),
+        (KeyCode::Right, Vec2::new(1.0, 0.0)),
+        (KeyCode::Down, Vec2::new(0.0, -1.0)),
+        (KeyCode::Up, Vec2::new(0.0, 1.0)),
+    ] {
+        if key_repeat.triggered(key, &keyboard, time.delta()) {
+            pan += direction;
+        }
+    }
+    if !input_state.has_selection() && *view_mode == ViewMode::Flat2D && pan != Vec2::ZERO {
+        vis_state.cur_offset = sim_state.tiling.adjust_position(vis_state.cur_offset + pan);
     }
 
     if processed_input.over_some_ui {
@@ -472,37 +1275,62 @@ fn input_system(
         vis_state.mouse_down = true;
         vis_state.mouse_moved = false;
         vis_state.last_click_pos = None;
+        vis_state.last_paint_pos = None;
     }
 
     vis_state.scale = (vis_state.scale + processed_input.scroll.y)
         .max(vis_state.min_scale)
         .min(vis_state.max_scale);
 
+    // Right-click erases the tile under the cursor, or with shift fills the region of same-state
+    // tiles around it with the active state. Middle-click picks up its state.
+    let erase = mouse_input.just_pressed(MouseButton::Right);
+    let pick = mouse_input.just_pressed(MouseButton::Middle);
+    if erase || pick {
+        let primary_window = windows.primary();
+        let window_size = Vec2::new(primary_window.width(), primary_window.height());
+        let position = primary_window.cursor_position().and_then(|mouse_pos| {
+            if *view_mode == ViewMode::Terrain3D {
+                let (transform, camera) = camera.get_single().ok()?;
+                cursor_to_ground(mouse_pos, window_size, transform, camera)
+                    .map(|pos| Vec2::new(pos.x, pos.z))
+            } else {
+                Some(cursor_to_board_position(mouse_pos, window_size, &vis_state))
+            }
+        });
+        if let Some(position) = position {
+            if erase && shift_down {
+                fill_tiles(position, &menu_state, &mut sim_state);
+            } else if erase {
+                erase_tile(position, &mut sim_state);
+            } else {
+                rules_events.send(pick_tile_state(position, &sim_state));
+            }
+        }
+    }
+
     if vis_state.mouse_down {
         let primary_window = windows.primary();
         if let Some(mouse_pos) = windows.primary().cursor_position() {
-            if vis_state.hide {
+            if *view_mode == ViewMode::Terrain3D {
                 if let Ok((transform, camera)) = camera.get_single() {
-                    let camera_transform: Mat4 = transform.compute_matrix();
-                    let camera_matrix: Mat4 = camera.projection_matrix.inverse();
-                    let view_matrix = camera_transform * camera_matrix;
-
-                    let x = 2.0 * (mouse_pos.x / primary_window.width() as f32) - 1.0;
-                    let y = 2.0 * (mouse_pos.y / primary_window.height() as f32) - 1.0;
-
-                    let near = view_matrix.project_point3(Vec3::new(x, y, -1.0));
-                    let far = view_matrix.project_point3(Vec3::new(x, y, 1.0));
-
-                    let dir = (far - near).normalize();
-
-                    let new_pos = if dir.y.signum() != near.y.signum() {
-                        let time_to_plane = near.y / -dir.y;
-                        Some(near + dir * time_to_plane)
-                    } else {
-                        None
-                    };
+                    let new_pos = cursor_to_ground(
+                        mouse_pos,
+                        Vec2::new(primary_window.width(), primary_window.height()),
+                        transform,
+                        camera,
+                    );
 
-                    if shift_down {
+                    if ctrl_down {
+                        if let Some(pos) = new_pos {
+                            paint_stroke(
+                                Vec2::new(pos.x, pos.z),
+                                &menu_state,
+                                &mut sim_state,
+                                &mut vis_state,
+                            );
+                        }
+                    } else if shift_down {
                         if processed_input.movement.length_squared() > 0.01 || vis_state.mouse_moved
                         {
                             vis_state.mouse_moved = true;
@@ -534,19 +1362,28 @@ fn input_system(
                                         .get_tile_containing(Vec2::new(pos.x, pos.z));
                                     inspect_events.send(DebugTileEvent(tile.index));
                                 } else {
-                                    let tile = sim_state
-                                        .tiling
-                                        .get_tile_containing(Vec2::new(pos.x, pos.z));
-                                    let target_state = (sim_state.get_at(tile.index) + 1)
-                                        % sim_state.get_num_states_for_shape(tile.shape);
-                                    sim_state.set_at(tile.index, target_state);
+                                    click_tile(
+                                        Vec2::new(pos.x, pos.z),
+                                        alt_down,
+                                        &menu_state,
+                                        &mut sim_state,
+                                        &mut collapse_state,
+                                        &mut collapse_events,
+                                    );
                                 }
                             }
                         }
                     }
                 }
             } else {
-                if processed_input.movement.length_squared() > 0.001 {
+                if ctrl_down {
+                    let board_position = cursor_to_board_position(
+                        mouse_pos,
+                        Vec2::new(primary_window.width(), primary_window.height()),
+                        &vis_state,
+                    );
+                    paint_stroke(board_position, &menu_state, &mut sim_state, &mut vis_state);
+                } else if processed_input.movement.length_squared() > 0.001 {
                     vis_state.mouse_moved = true;
                     vis_state.cur_offset = sim_state.tiling.adjust_position(
                         processed_input.movement * Vec2::new(-1.0, 1.0) / vis_state.scale
@@ -557,13 +1394,19 @@ fn input_system(
                 if mouse_input.just_released(MouseButton::Left) {
                     vis_state.mouse_down = false;
                     if !vis_state.mouse_moved {
-                        let mouse_pos = mouse_pos
-                            - Vec2::new(primary_window.width(), primary_window.height()) / 2.0;
-                        let adjusted_position = mouse_pos / vis_state.scale + vis_state.cur_offset;
-                        let tile = sim_state.tiling.get_tile_containing(adjusted_position);
-                        let target_state = (sim_state.get_at(tile.index) + 1)
-                            % sim_state.get_num_states_for_shape(tile.shape);
-                        sim_state.set_at(tile.index, target_state);
+                        let adjusted_position = cursor_to_board_position(
+                            mouse_pos,
+                            Vec2::new(primary_window.width(), primary_window.height()),
+                            &vis_state,
+                        );
+                        click_tile(
+                            adjusted_position,
+                            alt_down,
+                            &menu_state,
+                            &mut sim_state,
+                            &mut collapse_state,
+                            &mut collapse_events,
+                        );
                     }
                 }
             }
@@ -571,12 +1414,170 @@ fn input_system(
     }
 }
 
+// Paint the tile under the given position, cycling it to the next state instead when `cycle` is
+// set, or, when marking the collapse region, use the collapse tile under it as a corner of the
+// region.
+fn click_tile(
+    position: Vec2,
+    cycle: bool,
+    menu_state: &MenuState,
+    sim_state: &mut SimulationState,
+    collapse_state: &mut CollapseState,
+    collapse_events: &mut EventWriter<SimulationStateChanged>,
+) {
+    if menu_state.paint_mode == PaintMode::Region {
+        let tile = collapse_state.dual_tiling.get_tile_containing(position);
+        if collapse_state.mark_region_corner(tile.index) {
+            collapse_events.send(SimulationStateChanged::NewTiling);
+        }
+    } else {
+        let tile = sim_state.tiling.get_tile_containing(position);
+        let mode = if cycle {
+            PaintMode::Cycle
+        } else {
+            menu_state.paint_mode
+        };
+        menu_state.paint(sim_state, tile.index, mode);
+    }
+}
+
+// Set the tile under the given position back to the empty state.
+fn erase_tile(position: Vec2, sim_state: &mut SimulationState) {
+    let tile = sim_state.tiling.get_tile_containing(position);
+    sim_state.set_at(tile.index, 0);
+}
+
+// Set the connected tiles in the same state as the one under the given position to the active
+// state.
+fn fill_tiles(position: Vec2, menu_state: &MenuState, sim_state: &mut SimulationState) {
+    let tile = sim_state.tiling.get_tile_containing(position);
+    sim_state.flood_fill(tile.index, menu_state.active_state);
+}
+
+// Make the state of the tile under the given position the active one, showing the rules for it.
+fn pick_tile_state(position: Vec2, sim_state: &SimulationState) -> menus::ShowRulesFor {
+    let tile = sim_state.tiling.get_tile_containing(position);
+    menus::ShowRulesFor {
+        shape: tile.shape,
+        state: sim_state.get_at(tile.index),
+    }
+}
+
+// Set every tile between the previous sample of the stroke and this one to the active state, so
+// dragging quickly still paints an unbroken line.
+fn paint_stroke(
+    position: Vec2,
+    menu_state: &MenuState,
+    sim_state: &mut SimulationState,
+    vis_state: &mut VisualState,
+) {
+    let from = vis_state.last_paint_pos.unwrap_or(position);
+    for crossed in tiles_along_line(&sim_state.tiling, from, position) {
+        for index in menu_state.mirror_targets(&sim_state.tiling, crossed) {
+            let shape = sim_state.tiling.get_tile_at_index(index).shape;
+            if menu_state.active_state < sim_state.get_num_states_for_shape(shape) {
+                sim_state.set_at(index, menu_state.active_state);
+            }
+        }
+    }
+    vis_state.last_paint_pos = Some(position);
+    // A stroke is never also a click.
+    vis_state.mouse_moved = true;
+}
+
+// The tiles crossed going from one board position to the other, taking the short way around the
+// edges of the tiling, in order and without repeats of the same tile in a row.
+fn tiles_along_line(tiling: &Tiling, from: Vec2, to: Vec2) -> Vec<IVec2> {
+    // Small enough that the line cannot step over the corner of even the thinnest triangle.
+    const SAMPLE_SPACING: f32 = 0.05;
+    let delta = wrap_offset(to - from, tiling);
+    let samples = (delta.length() / SAMPLE_SPACING).ceil().max(1.0) as usize;
+    let mut indices: Vec<IVec2> = Vec::new();
+    for sample in 0..=samples {
+        let position = tiling.adjust_position(from + delta * (sample as f32 / samples as f32));
+        let index = tiling.adjust_index(tiling.get_index_for_position(position));
+        if indices.last() != Some(&index) {
+            indices.push(index);
+        }
+    }
+    indices
+}
+
+fn place_pattern(
+    mut events: EventReader<PlacePattern>,
+    vis_state: Res<VisualState>,
+    view_mode: Res<ViewMode>,
+    mut sim_state: ResMut<SimulationState>,
+) {
+    for event in events.iter() {
+        // Place the pattern under whatever the active view is centered on.
+        let center = if *view_mode == ViewMode::Terrain3D {
+            Vec2::new(vis_state.camera_offset.x, vis_state.camera_offset.z)
+        } else {
+            vis_state.cur_offset
+        };
+        let center_index = sim_state.tiling.get_tile_containing(center).index;
+        if let Err(err) = patterns::place_named(&mut sim_state, event.0, center_index) {
+            bevy::log::warn!("{}", err);
+        }
+    }
+}
+
+// Boards with more cells than this run their generations on a `SimulationWorker` so drawing
+// doesn't wait on them.
+const WORKER_CELL_COUNT: i32 = 128 * 128;
+
+#[derive(Default)]
+struct BackgroundSimulation {
+    worker: Option<SimulationWorker>,
+    // The board was edited since the worker last got a copy of it.
+    stale: bool,
+    // The edit epoch of the board the last time it was looked at.
+    edit_epoch: u64,
+}
+
 fn process_simulation(
     mut sim_state: ResMut<SimulationState>,
+    time: Res<Time>,
     mut events: EventWriter<SimulationStateChanged>,
+    mut background: Local<BackgroundSimulation>,
 ) {
-    let changes = sim_state.process();
-    if changes.len() > 0 {
+    let background = &mut *background;
+    let max_index = sim_state.tiling.max_index;
+    if max_index.x * max_index.y <= WORKER_CELL_COUNT {
+        background.worker = None;
+        let changes = sim_state.advance(time.delta());
+        if changes.len() > 0 {
+            events.send(SimulationStateChanged::StatesChanged(changes));
+        }
+        return;
+    }
+
+    // Other systems changing the board (painting, rules, new boards) make whatever the worker
+    // is running out of date. Changing the speed does not.
+    let edited = sim_state.edit_epoch() != background.edit_epoch;
+    background.edit_epoch = sim_state.edit_epoch();
+    let due = sim_state.due_generations(time.delta());
+    let mut changes = sim_state.apply_edits();
+    if edited || !changes.is_empty() {
+        if let Some(worker) = &mut background.worker {
+            worker.discard();
+        }
+        background.stale = true;
+    }
+    if due > 0 {
+        match &mut background.worker {
+            Some(worker) if background.stale => worker.replace(sim_state.clone()),
+            Some(_) => {}
+            None => background.worker = Some(SimulationWorker::spawn(sim_state.clone())),
+        }
+        background.stale = false;
+        background.worker.as_mut().unwrap().request(due);
+    }
+    if let Some(batch) = background.worker.as_mut().and_then(|worker| worker.poll()) {
+        changes.extend(sim_state.apply_generations(batch.generations, &batch.changes));
+    }
+    if !changes.is_empty() {
         events.send(SimulationStateChanged::StatesChanged(changes));
     }
 }
@@ -602,7 +1603,7 @@ fn main() {
     let mut app = App::new();
     let tiling = Tiling {
         kind: TilingKind::Square,
-        max_index: IVec2::new(50, 50),
+        max_index: IVec2::splat(DEFAULT_GRID_SIZE),
         offset: Vec2::ZERO,
     };
     app.add_plugins(DefaultPlugins);
@@ -611,9 +1612,12 @@ fn main() {
         ui::UIPlugin::new()
             .register_event::<menus::ChangeViewTo>()
             .register_event::<menus::ShowRulesFor>()
-            .register_event::<menus::TogglePlay>()
+            .register_number_event_generator::<menus::PlaySpeedEventGenerator>()
             .register_event::<menus::DebugTileEvent>()
+            .register_event::<menus::PlacePattern>()
             .register_number_event_generator::<menus::RuleUpdateEventGenerator>()
+            .register_number_event_generator::<menus::EditSettingsEventGenerator>()
+            .register_color_event_generator::<menus::StateColorEventGenerator>()
             .register_text_event_generator::<menus::CommandEventGenerator>(),
     );
     app.add_plugin(menus::MenusPlugin);
@@ -626,8 +1630,15 @@ fn main() {
         font: Handle::default(),
         debug_vis_material: Handle::default(),
         debug_vis_outline_material: Handle::default(),
+        hover_outline_meshes: Default::default(),
+        hover_outline_material: Handle::default(),
+        diff_gained_material: Handle::default(),
+        diff_lost_material: Handle::default(),
+        preview_material: Handle::default(),
+        age_materials: Default::default(),
     })
     .insert_resource(SimulationState::new(tiling))
+    .insert_resource(VisibleTiles::default())
     .insert_resource(VisualState {
         mouse_down: false,
         mouse_moved: false,
@@ -636,26 +1647,611 @@ fn main() {
         camera_offset: Vec3::ZERO,
         camera_angle: Vec2::new(0.0, 20.0),
         last_click_pos: None,
+        last_paint_pos: None,
         visual_grid_count: IVec2::new(26, 26),
         scale: 50.0,
         min_scale: 5.0,
         max_scale: 100.0,
         add_debug: false,
-        hide: true,
+        show_age: false,
     })
+    .insert_resource(ViewMode::Terrain3D)
     .insert_resource(CollapseState::default())
     .add_event::<SimulationStateChanged>()
+    .add_event::<CollapseProgress>()
+    .add_event::<ExportTerrainObj>()
+    .add_event::<ShowBoardDiff>()
+    .add_event::<ExportGridPng>()
     .insert_resource(visuals::geom::GeometryStorage::new())
     .add_startup_system(setup_world.after(menus::setup_menus))
     .add_system_to_stage(CoreStage::PreUpdate, input_system)
     .add_startup_system(visuals::geom::load_geometry)
     .add_system(visuals::geom::log_geometry)
+    .add_system(fit_visual_grid.before(rebuild_tiles))
+    .add_system(rebuild_tiles.before(update_tile))
     .add_system(update_tile)
-    .add_system(update_tile_visual.after(update_tile))
+    .add_system(refresh_tile_preview.after(update_tile))
+    .add_system(update_age_materials.before(update_tile_visual))
+    .add_system(refresh_tile_age.after(update_tile))
+    .add_system(
+        update_tile_visual
+            .after(update_tile)
+            .after(refresh_tile_preview)
+            .after(refresh_tile_age),
+    )
+    .add_system(update_hover_outline.after(update_tile))
+    .add_system(update_grid_lines.after(update_tile))
+    .add_system(show_board_diff)
+    .add_system(update_diff_markers.after(show_board_diff).after(update_tile))
+    .add_system(apply_view_mode)
+    .add_system(place_pattern.before(process_simulation))
     .add_system(process_simulation)
     .add_system(collapse_visuals)
     .add_system(rebuild_visuals)
+    .add_system(update_collapse_placement)
+    .add_system(export_terrain_obj)
+    .add_system(export_grid_png)
     .add_system(move_camera)
     .add_system(visuals::geom::geometry_input)
+    .add_system_to_stage(CoreStage::Last, save_view_state)
     .run()
 }
+
+/// Save the view when the app is closing so the next launch starts from it.
+fn save_view_state(
+    mut exit_events: EventReader<AppExit>,
+    vis_state: Res<VisualState>,
+    view_mode: Res<ViewMode>,
+) {
+    if exit_events.iter().next().is_none() {
+        return;
+    }
+    if let Err(err) = vis_state.save(*view_mode, std::path::Path::new(VIEW_STATE_FILE)) {
+        bevy::log::warn!("Failed to save the view to {}: {}", VIEW_STATE_FILE, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{
+        ecs::event::Events,
+        math::{IVec2, Vec2, Vec3},
+        asset::HandleId,
+        prelude::{
+            Changed, Entity, Handle, Mesh, ParallelSystemDescriptorCoercion, Stage, SystemStage,
+            Transform, Visibility, World,
+        },
+        render::camera::Camera3d,
+        sprite::{ColorMaterial, Mesh2dHandle},
+        utils::HashMap,
+    };
+
+    use crate::{
+        apply_view_mode, cursor_to_board_position, erase_tile, hover_outline_placement,
+        pick_tile_state, rebuild_tiles, refresh_tile_age, update_diff_markers, DiffMarker,
+        simulation::SimulationState,
+        tiling::{EquilateralDirection, RightTriangleRotation, TileShape, Tiling, TilingKind},
+        build_grid_lines_mesh, grid_offsets, push_tile_outline, update_tile, update_tile_visual, visual_grid_count_for,
+        menus::MenuState,
+        visuals::{
+            collapse::{CollapseEntry, SimulationStateChanged},
+            geom::GeometryStorage,
+        },
+        tiles_along_line, SavedView, TerrainCamera, TileState, ViewMode, VisibleTiles, VisualState, VisualsCache,
+    };
+
+    fn visuals_cache() -> VisualsCache {
+        let mut meshes = HashMap::default();
+        for shape in [
+            TileShape::Square,
+            TileShape::Hexagon,
+            TileShape::Octagon,
+            TileShape::EquilateralTriangle(EquilateralDirection::Up),
+            TileShape::EquilateralTriangle(EquilateralDirection::Down),
+            TileShape::RightTriangle(RightTriangleRotation::Zero),
+            TileShape::RightTriangle(RightTriangleRotation::One),
+            TileShape::RightTriangle(RightTriangleRotation::Two),
+            TileShape::RightTriangle(RightTriangleRotation::Three),
+        ] {
+            meshes.insert(shape, Handle::<Mesh>::default().into());
+        }
+        let mut states = HashMap::default();
+        states.insert(0, Handle::default());
+        VisualsCache {
+            meshes,
+            states,
+            outline_image: Handle::default(),
+            font: Handle::default(),
+            debug_vis_material: Handle::default(),
+            debug_vis_outline_material: Handle::default(),
+            hover_outline_meshes: HashMap::default(),
+            hover_outline_material: Handle::default(),
+            diff_gained_material: Handle::default(),
+            diff_lost_material: Handle::default(),
+            preview_material: Handle::default(),
+            age_materials: HashMap::default(),
+        }
+    }
+
+    fn visual_state() -> VisualState {
+        VisualState {
+            mouse_down: false,
+            mouse_moved: false,
+            cur_offset: Vec2::ZERO,
+            camera_offset: Vec3::ZERO,
+            camera_angle: Vec2::ZERO,
+            last_click_pos: None,
+            last_paint_pos: None,
+            visual_grid_count: IVec2::new(6, 6),
+            scale: 1.0,
+            min_scale: 1.0,
+            max_scale: 1.0,
+            add_debug: false,
+            show_age: false,
+        }
+    }
+
+    fn simulation(kind: TilingKind) -> SimulationState {
+        SimulationState::new(Tiling {
+            kind,
+            max_index: IVec2::new(10, 10),
+            offset: Vec2::ZERO,
+        })
+    }
+
+    fn change_tiling(world: &mut World, kind: TilingKind) {
+        world.insert_resource(simulation(kind));
+        world
+            .get_resource_mut::<Events<SimulationStateChanged>>()
+            .unwrap()
+            .send(SimulationStateChanged::NewTiling);
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(rebuild_tiles);
+        stage.run(world);
+    }
+
+    // A world with the tiles spawned for the given tiling.
+    fn tile_world(kind: TilingKind) -> World {
+        let mut world = World::new();
+        world.insert_resource(visuals_cache());
+        world.insert_resource(visual_state());
+        world.insert_resource(VisibleTiles::default());
+        world.insert_resource(Events::<SimulationStateChanged>::default());
+        change_tiling(&mut world, kind);
+        world
+    }
+
+    #[test]
+    fn tiles_match_new_tiling_after_view_change() {
+        let mut world = tile_world(TilingKind::Hexagonal);
+        change_tiling(&mut world, TilingKind::OctagonAndSquare);
+
+        let tiles = world
+            .query::<&TileState>()
+            .iter(&world)
+            .map(|state| (state.computed_index, state.previous_shape))
+            .collect::<Vec<_>>();
+        let sim_state = world.get_resource::<SimulationState>().unwrap();
+        assert_eq!(tiles.len(), 36);
+        assert!(tiles.iter().any(|(_, shape)| *shape == TileShape::Octagon));
+        for (index, shape) in tiles {
+            assert_eq!(shape, sim_state.tiling.get_tile_at_index(index).shape);
+        }
+    }
+
+    #[test]
+    fn visible_tiles_follow_pan() {
+        let mut world = tile_world(TilingKind::Square);
+        let center = world
+            .get_resource::<SimulationState>()
+            .unwrap()
+            .tiling
+            .get_tile_at_index(IVec2::new(3, 2))
+            .position;
+        world.get_resource_mut::<VisualState>().unwrap().cur_offset = center;
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(update_tile);
+        stage.run(&mut world);
+
+        let tiles = world
+            .query::<(Entity, &TileState)>()
+            .iter(&world)
+            .map(|(entity, state)| (entity, state.offset_from_center, state.computed_index))
+            .collect::<Vec<_>>();
+        let visible_tiles = world.get_resource::<VisibleTiles>().unwrap();
+        assert_eq!(visible_tiles.iter().count(), tiles.len());
+        for (entity, offset, index) in tiles {
+            assert_eq!(visible_tiles.tiles_at(index), &[entity]);
+            if offset == IVec2::ZERO {
+                assert_eq!(index, IVec2::new(3, 2));
+            }
+        }
+    }
+
+    #[test]
+    fn diff_markers_sit_on_the_tiles_showing_their_cell() {
+        let mut world = tile_world(TilingKind::Square);
+        world.insert_resource(ViewMode::Flat2D);
+        let center = world
+            .get_resource::<SimulationState>()
+            .unwrap()
+            .tiling
+            .get_tile_at_index(IVec2::new(3, 2))
+            .position;
+        world.get_resource_mut::<VisualState>().unwrap().cur_offset = center;
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(update_tile);
+        stage.run(&mut world);
+
+        let visible_tiles = world.get_resource::<VisibleTiles>().unwrap();
+        let shown = IVec2::new(4, 2);
+        let tile = visible_tiles.tiles_at(shown)[0];
+        let hidden = (0..10)
+            .flat_map(|y| (0..10).map(move |x| IVec2::new(x, y)))
+            .find(|index| visible_tiles.tiles_at(*index).is_empty())
+            .unwrap();
+        let mut spawn_marker = |index| {
+            world
+                .spawn()
+                .insert_bundle((DiffMarker { index }, Transform::default(), Visibility::default()))
+                .id()
+        };
+        let shown_marker = spawn_marker(shown);
+        let hidden_marker = spawn_marker(hidden);
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(update_diff_markers);
+        stage.run(&mut world);
+
+        let tile_translation = world.get::<Transform>(tile).unwrap().translation;
+        let marker_translation = world.get::<Transform>(shown_marker).unwrap().translation;
+        assert_eq!(marker_translation.truncate(), tile_translation.truncate());
+        assert!(world.get::<Visibility>(shown_marker).unwrap().is_visible);
+        assert!(!world.get::<Visibility>(hidden_marker).unwrap().is_visible);
+    }
+
+    #[test]
+    fn visual_grid_covers_the_window_with_a_margin() {
+        let tiling = Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(100, 100),
+            offset: Vec2::ZERO,
+        };
+        let window = Vec2::new(400.0, 300.0);
+        assert_eq!(visual_grid_count_for(window, 20.0, &tiling), IVec2::new(22, 17));
+        // Zooming in needs fewer tiles, and partly covered tiles still count.
+        assert_eq!(visual_grid_count_for(window, 40.0, &tiling), IVec2::new(12, 10));
+        assert_eq!(visual_grid_count_for(Vec2::ZERO, 20.0, &tiling), IVec2::new(2, 2));
+        // Never more tiles than the board has.
+        let small = Tiling {
+            max_index: IVec2::new(10, 6),
+            ..tiling
+        };
+        assert_eq!(visual_grid_count_for(window, 20.0, &small), IVec2::new(10, 6));
+    }
+
+    #[test]
+    fn resizing_the_grid_only_spawns_and_despawns_the_edges() {
+        let mut world = tile_world(TilingKind::Square);
+        world
+            .get_resource_mut::<Events<SimulationStateChanged>>()
+            .unwrap()
+            .clear();
+        let tiles = |world: &mut World| {
+            world
+                .query::<(Entity, &TileState)>()
+                .iter(world)
+                .map(|(entity, state)| (state.offset_from_center, (entity, state.computed_index)))
+                .collect::<HashMap<_, _>>()
+        };
+        let before = tiles(&mut world);
+
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(rebuild_tiles);
+        for count in [IVec2::new(8, 7), IVec2::new(4, 4)] {
+            world.get_resource_mut::<VisualState>().unwrap().visual_grid_count = count;
+            stage.run(&mut world);
+
+            let after = tiles(&mut world);
+            assert_eq!(after.len() as i32, count.x * count.y);
+            let sim_state = world.get_resource::<SimulationState>().unwrap();
+            let visible_tiles = world.get_resource::<VisibleTiles>().unwrap();
+            assert_eq!(visible_tiles.iter().count(), after.len());
+            for offset in grid_offsets(count) {
+                let (entity, index) = after[&offset];
+                assert_eq!(index, sim_state.tiling.adjust_index(offset));
+                assert!(visible_tiles.tiles_at(index).contains(&entity));
+                // Tiles that were already there are kept.
+                if let Some((kept, _)) = before.get(&offset) {
+                    assert_eq!(*kept, entity);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hexagon_outline_traces_every_edge() {
+        let center = Vec2::new(2.0, -1.0);
+        let mut verticies = Vec::new();
+        push_tile_outline(TileShape::Hexagon, center, &mut verticies);
+        assert_eq!(verticies.len(), 12);
+        let corners = TileShape::Hexagon.get_corner_positions();
+        let radius = TileShape::Hexagon.get_radius();
+        for (i, edge) in verticies.chunks(2).enumerate() {
+            let start = Vec2::new(edge[0][0], edge[0][1]) - center;
+            let end = Vec2::new(edge[1][0], edge[1][1]) - center;
+            assert!(start.distance(corners[i]) < 1e-5);
+            assert!(end.distance(corners[(i + 1) % 6]) < 1e-5);
+            // The sides of a regular hexagon are as long as its radius.
+            assert!((start.distance(end) - radius).abs() < 1e-5);
+        }
+        // Hexagons are drawn point up.
+        assert!(corners.iter().any(|corner| corner.distance(Vec2::new(0.0, radius)) < 1e-5));
+
+        // The batched mesh has an outline for every tile in the grid.
+        let hexagons = simulation(TilingKind::Hexagonal).tiling;
+        let mesh = build_grid_lines_mesh(&hexagons, IVec2::new(9, 9), IVec2::new(3, 2));
+        assert_eq!(mesh.count_vertices(), 6 * 12);
+    }
+
+    #[test]
+    fn scrolling_onto_matching_tiles_keeps_their_handles() {
+        let mut world = tile_world(TilingKind::Square);
+        world.insert_resource(MenuState::default());
+        let alive = Handle::weak(HandleId::random::<ColorMaterial>());
+        world
+            .get_resource_mut::<VisualsCache>()
+            .unwrap()
+            .states
+            .insert(1, alive.clone());
+        // A full column of live cells looks the same after scrolling up a tile.
+        {
+            let mut sim_state = world.get_resource_mut::<SimulationState>().unwrap();
+            for y in 0..10 {
+                sim_state.set_at(IVec2::new(1, y), 1);
+            }
+            sim_state.advance(Duration::ZERO);
+        }
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(update_tile);
+        stage.add_system(update_tile_visual.after(update_tile));
+        stage.run(&mut world);
+        assert!(world
+            .query::<&Handle<ColorMaterial>>()
+            .iter(&world)
+            .any(|material| *material == alive));
+        world.clear_trackers();
+
+        world.get_resource_mut::<VisualState>().unwrap().cur_offset.y += 1.0;
+        stage.run(&mut world);
+        assert!(world.query_filtered::<(), Changed<TileState>>().iter(&world).count() > 0);
+        assert_eq!(
+            world
+                .query_filtered::<(), Changed<Handle<ColorMaterial>>>()
+                .iter(&world)
+                .count(),
+            0
+        );
+        assert_eq!(
+            world.query_filtered::<(), Changed<Mesh2dHandle>>().iter(&world).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn showing_ages_redraws_unchanged_tiles() {
+        let mut world = tile_world(TilingKind::Square);
+        world.insert_resource(MenuState::default());
+        let fresh = Handle::weak(HandleId::random::<ColorMaterial>());
+        world
+            .get_resource_mut::<VisualsCache>()
+            .unwrap()
+            .age_materials
+            .insert((0, 0), fresh.clone());
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(update_tile);
+        stage.add_system(refresh_tile_age.after(update_tile));
+        stage.add_system(update_tile_visual.after(refresh_tile_age));
+        stage.run(&mut world);
+        world.clear_trackers();
+
+        // Nothing on the empty board changes, but every tile still picks up its age material.
+        world.get_resource_mut::<VisualState>().unwrap().show_age = true;
+        stage.run(&mut world);
+        let mut materials = world.query::<&Handle<ColorMaterial>>();
+        assert!(materials.iter(&world).count() > 0);
+        assert!(materials.iter(&world).all(|material| *material == fresh));
+    }
+
+    #[test]
+    fn hover_outline_sits_on_tile_under_cursor() {
+        let sim_state = simulation(TilingKind::Square);
+        let mut vis_state = visual_state();
+        vis_state.scale = 20.0;
+        vis_state.cur_offset = Vec2::new(1.0, 1.0);
+        let window_size = Vec2::new(400.0, 300.0);
+
+        // Two tiles right and one up from the middle of the window.
+        let cursor = window_size / 2.0 + Vec2::new(41.0, 18.0);
+        let board_position = cursor_to_board_position(cursor, window_size, &vis_state);
+        let (tile, translation) =
+            hover_outline_placement(board_position, &vis_state, &sim_state.tiling);
+        assert_eq!(tile.index, IVec2::new(3, 2));
+        assert!((translation - Vec3::new(40.0, 20.0, 0.5)).length() < 1e-4);
+
+        // Past the edge of the board the tile wraps around but the outline stays under the cursor.
+        vis_state.cur_offset = Vec2::ZERO;
+        let cursor = window_size / 2.0 + Vec2::new(-22.0, 3.0);
+        let board_position = cursor_to_board_position(cursor, window_size, &vis_state);
+        let (tile, translation) =
+            hover_outline_placement(board_position, &vis_state, &sim_state.tiling);
+        assert_eq!(tile.index, IVec2::new(9, 0));
+        assert!((translation - Vec3::new(-20.0, 0.0, 0.5)).length() < 1e-4);
+    }
+
+    #[test]
+    fn view_mode_shows_only_its_entities() {
+        let mut world = tile_world(TilingKind::Square);
+        let sim_state = simulation(TilingKind::Square);
+        let entry = world
+            .spawn()
+            .insert(CollapseEntry::new(
+                &sim_state.tiling.get_dual(),
+                &sim_state,
+                &GeometryStorage::new(),
+                IVec2::ZERO,
+                0,
+                false,
+            ))
+            .insert(Visibility::default())
+            .id();
+        let camera = world.spawn().insert(TerrainCamera).insert(Camera3d).id();
+
+        let apply = |world: &mut World, view_mode: ViewMode| {
+            world.insert_resource(view_mode);
+            let mut stage = SystemStage::single_threaded();
+            stage.add_system(apply_view_mode);
+            stage.run(world);
+            let tiles_visible = world
+                .query::<(&Visibility, &TileState)>()
+                .iter(world)
+                .map(|(vis, _)| vis.is_visible)
+                .collect::<Vec<_>>();
+            assert!(!tiles_visible.is_empty());
+            (
+                tiles_visible,
+                world.get::<Visibility>(entry).unwrap().is_visible,
+                world.get::<Camera3d>(camera).is_some(),
+            )
+        };
+
+        let (tiles_visible, entry_visible, camera_active) = apply(&mut world, ViewMode::Flat2D);
+        assert!(tiles_visible.iter().all(|visible| *visible));
+        assert!(!entry_visible);
+        assert!(!camera_active);
+
+        let (tiles_visible, entry_visible, camera_active) = apply(&mut world, ViewMode::Terrain3D);
+        assert!(tiles_visible.iter().all(|visible| !*visible));
+        assert!(entry_visible);
+        assert!(camera_active);
+    }
+
+    #[test]
+    fn picking_and_erasing_use_the_tile_under_the_click() {
+        let mut sim_state = simulation(TilingKind::Square);
+        let vis_state = visual_state();
+        let click = cursor_to_board_position(
+            Vec2::new(53.4, 52.2),
+            Vec2::new(100.0, 100.0),
+            &vis_state,
+        );
+        let index = sim_state.tiling.get_tile_containing(click).index;
+        sim_state.set_at(index, 1);
+        sim_state.advance(Duration::ZERO);
+
+        let picked = pick_tile_state(click, &sim_state);
+        assert_eq!(picked.shape, TileShape::Square);
+        assert_eq!(picked.state, 1);
+
+        erase_tile(click, &mut sim_state);
+        // The erase goes out as a change like any other set.
+        assert_eq!(sim_state.advance(Duration::ZERO), vec![(index, 0)]);
+        assert_eq!(sim_state.get_at(index), 0);
+        assert_eq!(pick_tile_state(click, &sim_state).state, 0);
+    }
+
+    #[test]
+    fn line_fill_crosses_contiguous_tiles() {
+        for kind in [TilingKind::Square, TilingKind::Hexagonal] {
+            let tiling = Tiling {
+                kind,
+                max_index: IVec2::new(10, 10),
+                offset: Vec2::ZERO,
+            };
+            // A long diagonal in one step, and one that is shorter going around the edge.
+            for (from, to) in [
+                (Vec2::new(0.2, 0.3), Vec2::new(6.7, 4.1)),
+                (Vec2::new(0.4, 1.2), Vec2::new(-2.6, 1.9)),
+            ] {
+                let indices = tiles_along_line(&tiling, from, to);
+                assert_eq!(indices.first(), Some(&tiling.get_index_for_position(from)));
+                assert_eq!(
+                    indices.last(),
+                    Some(&tiling.adjust_index(
+                        tiling.get_index_for_position(tiling.adjust_position(to))
+                    ))
+                );
+                for pair in indices.windows(2) {
+                    assert!(
+                        tiling.get_neighbors(pair[0]).iter().any(|(x, y)| {
+                            tiling.adjust_index(pair[0] + IVec2::new(*x, *y)) == pair[1]
+                        }),
+                        "{:?}: {:?} and {:?} are not neighbors",
+                        kind,
+                        pair[0],
+                        pair[1]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn view_state_round_trips_and_clamps() {
+        let path = std::env::temp_dir().join(format!("view_state_{}.ron", std::process::id()));
+        let mut saved = VisualState {
+            cur_offset: Vec2::new(3.5, -2.0),
+            camera_offset: Vec3::new(1.0, 0.0, -4.0),
+            camera_angle: Vec2::new(45.0, 30.0),
+            scale: 20.0,
+            min_scale: 5.0,
+            max_scale: 100.0,
+            ..visual_state()
+        };
+        saved.save(ViewMode::Flat2D, &path).unwrap();
+        let mut restored = VisualState {
+            min_scale: 5.0,
+            max_scale: 100.0,
+            ..visual_state()
+        };
+        assert_eq!(restored.load(&path).unwrap(), ViewMode::Flat2D);
+        assert_eq!(
+            restored.saved_view(ViewMode::Flat2D),
+            saved.saved_view(ViewMode::Flat2D)
+        );
+
+        // Views saved before the view mode was kept open in the terrain view.
+        let source = std::fs::read_to_string(&path).unwrap();
+        assert!(source.contains("view_mode"));
+        let source = source
+            .lines()
+            .filter(|line| !line.contains("view_mode"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        std::fs::write(&path, source).unwrap();
+        assert_eq!(restored.load(&path).unwrap(), ViewMode::Terrain3D);
+        assert_eq!(restored.scale, saved.scale);
+
+        // Views saved outside the limits we allow now are pulled back inside them.
+        saved.scale = 500.0;
+        saved.camera_angle = Vec2::new(400.0, 90.0);
+        saved.save(ViewMode::Terrain3D, &path).unwrap();
+        restored.load(&path).unwrap();
+        assert_eq!(restored.scale, 100.0);
+        assert_eq!(restored.camera_angle, Vec2::new(40.0, 85.0));
+        restored.restore(SavedView {
+            scale: 0.5,
+            ..restored.saved_view(ViewMode::Terrain3D)
+        });
+        assert_eq!(restored.scale, 5.0);
+
+        std::fs::write(&path, "not a view").unwrap();
+        assert_eq!(
+            restored.load(&path).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.load(&path).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,64 @@
+use bevy::{
+    math::Size,
+    prelude::{Color, Commands, Component, EventReader, Query, With},
+    text::Text,
+};
+
+use crate::{
+    ui::{AnchoredUi, UiElement},
+    visuals::collapse::CollapseProgress,
+};
+
+use super::{MenuState, REGULAR_FONT_SIZE, REGULAR_HEIGHT_STEP};
+
+const PROGRESS_WIDTH: f32 = 150.0;
+
+#[derive(Component)]
+pub struct CollapseProgressText;
+
+fn progress_text(progress: &CollapseProgress) -> String {
+    if progress.total == 0 {
+        return "Terrain: -".to_string();
+    }
+    format!(
+        "Terrain: {}/{} ({}%)",
+        progress.collapsed,
+        progress.total,
+        100 * progress.collapsed / progress.total
+    )
+}
+
+pub(super) fn spawn_collapse_progress(commands: &mut Commands, menu_data: &MenuState) {
+    commands
+        .spawn_bundle(menu_data.get_text_bundle(
+            progress_text(&CollapseProgress { collapsed: 0, total: 0 }),
+            REGULAR_FONT_SIZE,
+            Color::BLACK,
+        ))
+        .insert(UiElement {
+            size: Size::new(PROGRESS_WIDTH, REGULAR_HEIGHT_STEP),
+            ..Default::default()
+        })
+        .insert(AnchoredUi {
+            x_percent: 1.0,
+            y_percent: 0.5,
+            width_grow: None,
+            height_grow: None,
+            ..Default::default()
+        })
+        .insert(CollapseProgressText);
+}
+
+pub(super) fn update_collapse_progress(
+    mut events: EventReader<CollapseProgress>,
+    mut text_query: Query<&mut Text, With<CollapseProgressText>>,
+) {
+    if let Some(progress) = events.iter().last() {
+        let value = progress_text(progress);
+        text_query.for_each_mut(|mut text| {
+            if let Some(section) = text.sections.first_mut() {
+                section.value = value.clone();
+            }
+        });
+    }
+}
@@ -0,0 +1,667 @@
+use bevy::{
+    hierarchy::{BuildChildren, DespawnRecursiveExt},
+    math::{IVec2, Size},
+    prelude::{
+        Changed, Color, Commands, Component, DetectChanges, Entity, EventReader, EventWriter,
+        Query, Res, ResMut, With,
+    },
+    sprite::{Sprite, SpriteBundle},
+    text::Text,
+    utils::HashSet,
+};
+
+use crate::{
+    simulation::{BoundaryMode, SimulationState},
+    tiling::Tiling,
+    ui::*,
+    visuals::collapse::SimulationStateChanged,
+};
+
+use super::{events::ShowRulesFor, MenuState};
+
+const EDITING_PANEL_WIDTH: f32 = 250.0;
+const MAX_BRUSH_RADIUS: u32 = 10;
+const MIN_GRID_SIZE: i32 = 2;
+const MAX_GRID_SIZE: i32 = 200;
+
+/// What clicking on a tile does to the tiles under the brush.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PaintMode {
+    /// Advance each tile to the next state.
+    Cycle,
+    /// Set each tile to the active state.
+    Paint,
+    /// Mark the corners of the rectangle the terrain is collapsed in instead of painting.
+    Region,
+}
+
+/// The panel holding all the settings used when editing the board by clicking on it.
+#[derive(Component)]
+pub struct EditingPanel {
+    pub collapsed: bool,
+}
+
+/// Marker for sprites that display the color of the active state.
+#[derive(Component)]
+pub struct ActiveStateSwatch;
+
+#[derive(Component, Clone, Copy)]
+pub enum EditSettingsEvent {
+    SetActiveState(u32),
+    SetBrushRadius(u32),
+    SetPaintMode(PaintMode),
+    SetGridWidth(u32),
+    SetGridHeight(u32),
+    ResizeGrid,
+    SetBoundary(BoundaryMode),
+    SetRandomStart(bool),
+    ToggleMirrorX,
+    ToggleMirrorY,
+    ToggleCollapsed,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EditSettingsTarget {
+    ActiveState,
+    BrushRadius,
+    GridWidth,
+    GridHeight,
+}
+
+#[derive(Component)]
+pub struct EditSettingsEventGenerator(pub EditSettingsTarget);
+
+impl NumberedEventGenerator for EditSettingsEventGenerator {
+    type Event = EditSettingsEvent;
+    type Value = u32;
+
+    fn create_event(&self, value: u32) -> Self::Event {
+        match self.0 {
+            EditSettingsTarget::ActiveState => EditSettingsEvent::SetActiveState(value),
+            EditSettingsTarget::BrushRadius => EditSettingsEvent::SetBrushRadius(value),
+            EditSettingsTarget::GridWidth => EditSettingsEvent::SetGridWidth(value),
+            EditSettingsTarget::GridHeight => EditSettingsEvent::SetGridHeight(value),
+        }
+    }
+}
+
+impl MenuState {
+    /// Get every index covered by the brush when centered on the given index. This includes
+    /// the mirrored copies of the brush if symmetry is enabled.
+    pub fn brush_targets(&self, tiling: &Tiling, center: IVec2) -> Vec<IVec2> {
+        // Grow the brush outwards one ring of neighbors at a time so it follows the tiling.
+        let center = tiling.adjust_index(center);
+        let mut covered = HashSet::default();
+        covered.insert(center);
+        let mut frontier = vec![center];
+        for _ in 0..self.brush_radius {
+            let mut next_frontier = Vec::new();
+            for index in frontier {
+                for (x, y) in tiling.get_neighbors(index) {
+                    let neighbor = tiling.adjust_index(index + IVec2::new(*x, *y));
+                    if covered.insert(neighbor) {
+                        next_frontier.push(neighbor);
+                    }
+                }
+            }
+            frontier = next_frontier;
+        }
+
+        let mut targets = covered
+            .into_iter()
+            .flat_map(|index| self.mirror_targets(tiling, index))
+            .collect::<Vec<_>>();
+        targets.sort_unstable_by_key(|index| (index.y, index.x));
+        targets.dedup();
+        targets
+    }
+
+    /// The index along with its copies mirrored across the center of the board by the enabled
+    /// symmetry. Cells on a mirror line map onto themselves and are only listed once.
+    pub fn mirror_targets(&self, tiling: &Tiling, index: IVec2) -> Vec<IVec2> {
+        let index = tiling.adjust_index(index);
+        let max_index = tiling.max_index;
+        let mirrored = IVec2::new(max_index.x - 1 - index.x, max_index.y - 1 - index.y);
+        let mut targets = vec![index];
+        if self.mirror_x {
+            targets.push(IVec2::new(mirrored.x, index.y));
+        }
+        if self.mirror_y {
+            targets.push(IVec2::new(index.x, mirrored.y));
+        }
+        if self.mirror_x && self.mirror_y {
+            targets.push(mirrored);
+        }
+        targets.sort_unstable_by_key(|index| (index.y, index.x));
+        targets.dedup();
+        targets
+    }
+
+    /// Apply the brush to the simulation centered on the given index, in the given mode rather
+    /// than `paint_mode` so a modifier key can switch it for a single click.
+    pub fn paint(&self, sim_state: &mut SimulationState, center: IVec2, mode: PaintMode) {
+        for index in self.brush_targets(&sim_state.tiling, center) {
+            let num_states = sim_state.get_num_states_for_shape(sim_state.tiling.get_tile_at_index(index).shape);
+            let target_state = match mode {
+                PaintMode::Cycle => (sim_state.get_at(index) + 1) % num_states,
+                PaintMode::Paint => {
+                    if self.active_state >= num_states {
+                        continue;
+                    }
+                    self.active_state
+                }
+                PaintMode::Region => return,
+            };
+            sim_state.set_at(index, target_state);
+        }
+    }
+}
+
+pub(super) fn on_edit_settings(
+    mut events: EventReader<EditSettingsEvent>,
+    mut menu_data: ResMut<MenuState>,
+    mut panel_query: Query<&mut EditingPanel>,
+    mut show_rules_events: EventWriter<ShowRulesFor>,
+    mut out_vis_events: EventWriter<SimulationStateChanged>,
+    mut sim_state: ResMut<SimulationState>,
+) {
+    for event in events.iter() {
+        // Number fields update themselves, and rebuilding would lose their selection.
+        let mut rebuild = true;
+        match *event {
+            EditSettingsEvent::SetActiveState(state) => {
+                menu_data.active_state = state;
+                // Keep the rules panel showing the state we are painting with.
+                show_rules_events.send(ShowRulesFor {
+                    shape: menu_data.active_shape,
+                    state,
+                });
+                rebuild = false;
+            }
+            EditSettingsEvent::SetBrushRadius(radius) => {
+                menu_data.brush_radius = radius;
+                rebuild = false;
+            }
+            EditSettingsEvent::SetPaintMode(mode) => menu_data.paint_mode = mode,
+            EditSettingsEvent::SetGridWidth(width) => {
+                menu_data.grid_size.x = width as i32;
+                rebuild = false;
+            }
+            EditSettingsEvent::SetGridHeight(height) => {
+                menu_data.grid_size.y = height as i32;
+                rebuild = false;
+            }
+            // Rebuilding shows the size we clamped to.
+            EditSettingsEvent::ResizeGrid => {
+                let size = menu_data
+                    .grid_size
+                    .clamp(IVec2::splat(MIN_GRID_SIZE), IVec2::splat(MAX_GRID_SIZE));
+                menu_data.grid_size = size;
+                if size != sim_state.tiling.max_index {
+                    let retained = sim_state.resize(size);
+                    out_vis_events.send(SimulationStateChanged::Resized { retained });
+                }
+            }
+            EditSettingsEvent::SetBoundary(boundary) => sim_state.set_boundary(boundary),
+            EditSettingsEvent::SetRandomStart(random_start) => menu_data.random_start = random_start,
+            EditSettingsEvent::ToggleMirrorX => menu_data.mirror_x = !menu_data.mirror_x,
+            EditSettingsEvent::ToggleMirrorY => menu_data.mirror_y = !menu_data.mirror_y,
+            EditSettingsEvent::ToggleCollapsed => {
+                panel_query.for_each_mut(|mut panel| panel.collapsed = !panel.collapsed);
+            }
+        }
+        if rebuild {
+            panel_query.for_each_mut(|mut panel| panel.set_changed());
+        }
+    }
+}
+
+pub(super) fn rebuild_editing_panel(
+    mut panel_query: Query<
+        (Entity, &EditingPanel, &mut UiElement, &mut AnchoredUi),
+        Changed<EditingPanel>,
+    >,
+    mut commands: Commands,
+    menu_data: Res<MenuState>,
+    sim_state: Res<SimulationState>,
+) {
+    panel_query.for_each_mut(|(entity, panel, mut element, mut anchor)| {
+        let mut entity = commands.entity(entity);
+        entity.despawn_descendants();
+
+        let step_size = Size::new(EDITING_PANEL_WIDTH, super::REGULAR_HEIGHT_STEP);
+        let toggle_color = |enabled| if enabled { Color::GRAY } else { Color::WHITE };
+        entity.with_children(|child_builder| {
+            menu_data.build_button_group(
+                &mut child_builder.spawn(),
+                Color::WHITE,
+                vec![(
+                    if panel.collapsed { "Editing +" } else { "Editing -" }.to_string(),
+                    Color::WHITE,
+                    EditSettingsEvent::ToggleCollapsed,
+                )],
+                EDITING_PANEL_WIDTH,
+                super::HEADER_HEIGHT,
+                super::HEADER_FONT_SIZE,
+                Color::BLACK,
+                super::HEADER_MARGIN,
+            );
+            if panel.collapsed {
+                return;
+            }
+
+            menu_data.spawn_labeled(
+                &mut child_builder.spawn(),
+                step_size,
+                "State:".into(),
+                Color::BLACK,
+                |data, state_builder| {
+                    let swatch_size = super::REGULAR_HEIGHT_STEP - super::REGULAR_MARGIN;
+                    state_builder
+                        .spawn_bundle(SpriteBundle {
+                            sprite: Sprite {
+                                color: data.active_state_color(),
+                                ..Default::default()
+                            },
+                            texture: data.button.clone(),
+                            ..Default::default()
+                        })
+                        .insert(UiElement {
+                            size: Size::new(swatch_size, swatch_size),
+                            ..Default::default()
+                        })
+                        .insert(ActiveStateSwatch);
+                    let mut number_bundle = data.get_text_bundle(
+                        data.active_state.to_string(),
+                        super::REGULAR_FONT_SIZE,
+                        Color::BLACK,
+                    );
+                    number_bundle.text.alignment.horizontal = bevy::text::HorizontalAlign::Right;
+                    state_builder
+                        .spawn_bundle(number_bundle)
+                        .insert(UiElement {
+                            size: Size::new(
+                                step_size.width - 100.0 - swatch_size,
+                                swatch_size,
+                            ),
+                            selected_state: UiStateDetails {
+                                accepts_state: true,
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        })
+                        .insert(NumberField {
+                            event_generator: EditSettingsEventGenerator(
+                                EditSettingsTarget::ActiveState,
+                            ),
+                            current_value: data.active_state,
+                            max_value: sim_state
+                                .get_num_states_for_shape(data.active_shape)
+                                .max(1)
+                                - 1,
+                            min_value: 0,
+                            typed: None,
+                        });
+                },
+            );
+
+            menu_data.spawn_labeled_number_field(
+                &mut child_builder.spawn(),
+                step_size,
+                "Brush:".into(),
+                Color::BLACK,
+                NumberField {
+                    event_generator: EditSettingsEventGenerator(EditSettingsTarget::BrushRadius),
+                    current_value: menu_data.brush_radius,
+                    max_value: MAX_BRUSH_RADIUS,
+                    min_value: 0,
+                    typed: None,
+                },
+            );
+
+            menu_data.spawn_labeled(
+                &mut child_builder.spawn(),
+                step_size,
+                "Mode:".into(),
+                Color::BLACK,
+                |data, mode_builder| {
+                    data.build_button_group(
+                        &mut mode_builder.spawn(),
+                        Color::WHITE,
+                        [
+                            ("Cycle", PaintMode::Cycle),
+                            ("Paint", PaintMode::Paint),
+                            ("Region", PaintMode::Region),
+                        ]
+                            .into_iter()
+                            .map(|(name, mode)| {
+                                (
+                                    name.to_string(),
+                                    toggle_color(data.paint_mode == mode),
+                                    EditSettingsEvent::SetPaintMode(mode),
+                                )
+                            })
+                            .collect(),
+                        step_size.width - 100.0,
+                        super::REGULAR_HEIGHT_STEP,
+                        super::REGULAR_FONT_SIZE,
+                        Color::BLACK,
+                        super::REGULAR_MARGIN,
+                    );
+                },
+            );
+
+            for (label, target, value) in [
+                ("Width:", EditSettingsTarget::GridWidth, menu_data.grid_size.x),
+                ("Height:", EditSettingsTarget::GridHeight, menu_data.grid_size.y),
+            ] {
+                menu_data.spawn_labeled_number_field(
+                    &mut child_builder.spawn(),
+                    step_size,
+                    label.into(),
+                    Color::BLACK,
+                    NumberField {
+                        event_generator: EditSettingsEventGenerator(target),
+                        current_value: value.max(0) as u32,
+                        max_value: MAX_GRID_SIZE as u32,
+                        min_value: 0,
+                        typed: None,
+                    },
+                );
+            }
+
+            menu_data.spawn_labeled(
+                &mut child_builder.spawn(),
+                step_size,
+                "Grid:".into(),
+                Color::BLACK,
+                |data, grid_builder| {
+                    data.build_button_group(
+                        &mut grid_builder.spawn(),
+                        Color::WHITE,
+                        vec![(
+                            "Resize".to_string(),
+                            Color::WHITE,
+                            EditSettingsEvent::ResizeGrid,
+                        )],
+                        step_size.width - 100.0,
+                        super::REGULAR_HEIGHT_STEP,
+                        super::REGULAR_FONT_SIZE,
+                        Color::BLACK,
+                        super::REGULAR_MARGIN,
+                    );
+                },
+            );
+
+            menu_data.spawn_labeled(
+                &mut child_builder.spawn(),
+                step_size,
+                "Edges:".into(),
+                Color::BLACK,
+                |data, edges_builder| {
+                    data.build_button_group(
+                        &mut edges_builder.spawn(),
+                        Color::WHITE,
+                        [("Wrap", BoundaryMode::Toroidal), ("Dead", BoundaryMode::Dead)]
+                            .into_iter()
+                            .map(|(name, boundary)| {
+                                (
+                                    name.to_string(),
+                                    toggle_color(sim_state.boundary() == boundary),
+                                    EditSettingsEvent::SetBoundary(boundary),
+                                )
+                            })
+                            .collect(),
+                        step_size.width - 100.0,
+                        super::REGULAR_HEIGHT_STEP,
+                        super::REGULAR_FONT_SIZE,
+                        Color::BLACK,
+                        super::REGULAR_MARGIN,
+                    );
+                },
+            );
+
+            menu_data.spawn_labeled(
+                &mut child_builder.spawn(),
+                step_size,
+                "Start:".into(),
+                Color::BLACK,
+                |data, start_builder| {
+                    data.build_button_group(
+                        &mut start_builder.spawn(),
+                        Color::WHITE,
+                        [("Empty", false), ("Random", true)]
+                            .into_iter()
+                            .map(|(name, random_start)| {
+                                (
+                                    name.to_string(),
+                                    toggle_color(data.random_start == random_start),
+                                    EditSettingsEvent::SetRandomStart(random_start),
+                                )
+                            })
+                            .collect(),
+                        step_size.width - 100.0,
+                        super::REGULAR_HEIGHT_STEP,
+                        super::REGULAR_FONT_SIZE,
+                        Color::BLACK,
+                        super::REGULAR_MARGIN,
+                    );
+                },
+            );
+
+            menu_data.spawn_labeled(
+                &mut child_builder.spawn(),
+                step_size,
+                "Mirror:".into(),
+                Color::BLACK,
+                |data, mirror_builder| {
+                    data.build_button_group(
+                        &mut mirror_builder.spawn(),
+                        Color::WHITE,
+                        vec![
+                            (
+                                "X".to_string(),
+                                toggle_color(data.mirror_x),
+                                EditSettingsEvent::ToggleMirrorX,
+                            ),
+                            (
+                                "Y".to_string(),
+                                toggle_color(data.mirror_y),
+                                EditSettingsEvent::ToggleMirrorY,
+                            ),
+                        ],
+                        step_size.width - 100.0,
+                        super::REGULAR_HEIGHT_STEP,
+                        super::REGULAR_FONT_SIZE,
+                        Color::BLACK,
+                        super::REGULAR_MARGIN,
+                    );
+                },
+            );
+        });
+
+        // Resize the panel to fit its contents and re-anchor it at the new size.
+        element.size = Size::new(
+            EDITING_PANEL_WIDTH,
+            if panel.collapsed {
+                super::HEADER_HEIGHT
+            } else {
+                super::HEADER_HEIGHT + 9.0 * super::REGULAR_HEIGHT_STEP
+            },
+        );
+        anchor.set_changed();
+    });
+}
+
+/// Keep the swatch and active state field in sync when the active state is changed elsewhere,
+/// such as from the rules panel.
+pub(super) fn update_active_state_display(
+    menu_data: Res<MenuState>,
+    sim_state: Res<SimulationState>,
+    mut swatch_query: Query<&mut Sprite, With<ActiveStateSwatch>>,
+    mut field_query: Query<(&mut Text, &mut NumberField<EditSettingsEventGenerator>, &UiElement)>,
+) {
+    if !menu_data.is_changed() {
+        return;
+    }
+    let color = menu_data.active_state_color();
+    swatch_query.for_each_mut(|mut sprite| {
+        if sprite.color != color {
+            sprite.color = color;
+        }
+    });
+    field_query.for_each_mut(|(mut text, mut number_field, element)| {
+        if number_field.event_generator.0 != EditSettingsTarget::ActiveState {
+            return;
+        }
+        number_field.max_value = sim_state
+            .get_num_states_for_shape(menu_data.active_shape)
+            .max(1)
+            - 1;
+        if !element.selected_state.current && number_field.current_value != menu_data.active_state {
+            number_field.current_value = menu_data.active_state;
+            if let Some(section) = text.sections.first_mut() {
+                section.value = menu_data.active_state.to_string();
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::{event::Events, schedule::SystemStage},
+        math::{IVec2, Vec2},
+        prelude::{Color, ParallelSystemDescriptorCoercion, Stage, World},
+    };
+
+    use crate::tiling::{TileShape, TilingKind};
+
+    use super::*;
+
+    fn square_simulation(size: i32) -> SimulationState {
+        SimulationState::new(Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(size, size),
+            offset: Vec2::ZERO,
+        })
+    }
+
+    #[test]
+    fn active_state_field_updates_menu_state_and_swatch() {
+        let mut world = World::new();
+        let mut menu_data = MenuState::default();
+        menu_data.state_to_color.insert(0, Color::WHITE);
+        menu_data.state_to_color.insert(1, Color::BLACK);
+        menu_data.state_to_color.insert(2, Color::RED);
+        world.insert_resource(menu_data);
+        let mut sim_state = square_simulation(10);
+        sim_state.add_state(TileShape::Square);
+        world.insert_resource(sim_state);
+        world.insert_resource(Events::<EditSettingsEvent>::default());
+        world.insert_resource(Events::<ShowRulesFor>::default());
+        world.insert_resource(Events::<SimulationStateChanged>::default());
+        let swatch = world
+            .spawn()
+            .insert(Sprite {
+                color: Color::WHITE,
+                ..Default::default()
+            })
+            .insert(ActiveStateSwatch)
+            .id();
+
+        let generator = EditSettingsEventGenerator(EditSettingsTarget::ActiveState);
+        world
+            .get_resource_mut::<Events<EditSettingsEvent>>()
+            .unwrap()
+            .send(generator.create_event(2));
+
+        let mut stage = SystemStage::single_threaded()
+            .with_system(on_edit_settings)
+            .with_system(update_active_state_display.after(on_edit_settings));
+        stage.run(&mut world);
+
+        let menu_data = world.get_resource::<MenuState>().unwrap();
+        assert_eq!(menu_data.active_state, 2);
+        assert_eq!(
+            world.get::<Sprite>(swatch).unwrap().color,
+            menu_data.state_to_color[&2]
+        );
+    }
+
+    #[test]
+    fn clicking_sets_the_active_state_whatever_the_tile_held() {
+        let menu_data = MenuState {
+            active_state: 2,
+            ..Default::default()
+        };
+        let mut sim_state = square_simulation(10);
+        sim_state.add_state(TileShape::Square);
+        assert_eq!(sim_state.get_num_states_for_shape(TileShape::Square), 3);
+        let index = IVec2::new(3, 3);
+        for prior in 0..3 {
+            sim_state.set_at(index, prior);
+            sim_state.advance(std::time::Duration::ZERO);
+            menu_data.paint(&mut sim_state, index, menu_data.paint_mode);
+            sim_state.advance(std::time::Duration::ZERO);
+            assert_eq!(sim_state.get_at(index), 2);
+        }
+
+        // Cycling is still there for clicks made with the modifier held.
+        menu_data.paint(&mut sim_state, index, PaintMode::Cycle);
+        sim_state.advance(std::time::Duration::ZERO);
+        assert_eq!(sim_state.get_at(index), 0);
+    }
+
+    #[test]
+    fn mirrored_brush_covers_both_halves() {
+        let mut menu_data = MenuState {
+            mirror_x: true,
+            ..Default::default()
+        };
+        let sim_state = square_simulation(10);
+        assert_eq!(
+            menu_data.brush_targets(&sim_state.tiling, IVec2::new(1, 4)),
+            vec![IVec2::new(1, 4), IVec2::new(8, 4)]
+        );
+
+        menu_data.brush_radius = 1;
+        menu_data.mirror_x = false;
+        assert_eq!(
+            menu_data
+                .brush_targets(&sim_state.tiling, IVec2::new(1, 4))
+                .len(),
+            9
+        );
+    }
+
+    #[test]
+    fn four_fold_symmetry_sets_each_quadrant_once() {
+        let menu_data = MenuState {
+            active_state: 1,
+            mirror_x: true,
+            mirror_y: true,
+            ..Default::default()
+        };
+        let mut sim_state = square_simulation(10);
+        menu_data.paint(&mut sim_state, IVec2::new(2, 7), PaintMode::Paint);
+        let mut changed = sim_state
+            .advance(std::time::Duration::ZERO)
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        changed.sort_unstable_by_key(|index| (index.y, index.x));
+        assert_eq!(
+            changed,
+            [(2, 2), (7, 2), (2, 7), (7, 7)].map(|(x, y)| IVec2::new(x, y))
+        );
+
+        // On an odd board the middle row, column and cell are their own mirrors.
+        let tiling = square_simulation(9).tiling;
+        assert_eq!(menu_data.mirror_targets(&tiling, IVec2::new(4, 4)), vec![IVec2::new(4, 4)]);
+        assert_eq!(
+            menu_data.mirror_targets(&tiling, IVec2::new(4, 1)),
+            vec![IVec2::new(4, 1), IVec2::new(4, 7)]
+        );
+    }
+}
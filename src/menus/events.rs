@@ -1,34 +1,72 @@
 use bevy::{
-    math::{IVec2, Vec2},
-    prelude::{Assets, Color, Component, EventReader, EventWriter, ResMut},
-    sprite::ColorMaterial,
+    hierarchy::Children,
+    math::Vec2,
+    prelude::{
+        info, warn, Assets, Color, Component, EventReader, EventWriter, Query, Res, ResMut,
+        Transform, With,
+    },
+    sprite::{ColorMaterial, Sprite},
+    text::Text,
 };
 
 use crate::{
     simulation::{RuleUpdateTarget, SimulationState},
     tiling::{EquilateralDirection, RightTriangleRotation, TileShape, Tiling, TilingKind},
-    ui::NumberedEventGenerator,
+    ui::{
+        slider::show_slider_value, ColorEventGenerator, NumberedEventGenerator, Slider, SliderFill,
+        SliderValueText, UiElement,
+    },
     visuals::collapse::SimulationStateChanged,
     VisualsCache,
 };
 
 use super::MenuState;
 
+// The board a random start makes when changing the tiling.
+const RANDOM_START_MAX_STATE: u32 = 3;
+const RANDOM_START_DENSITY: f32 = 0.5;
+const RANDOM_START_SEED: u64 = 0x5EED;
+
 #[derive(Component, Clone, Copy)]
 pub enum TogglePlay {
     Toggle,
     Step,
+    // Empty the board, keeping the rules.
+    NewBoard,
+    // Undo the last generation.
+    StepBack,
+    // Run a generation every this many frames, pausing at 0.
+    SetSpeed(u32),
+    // Show or hide the tint on cells the next generation changes.
+    Preview,
 }
 
 #[derive(Component, Clone)]
 pub struct ChangeViewTo(pub TilingKind);
 
+/// Place the named pattern from the pattern library near the center of the screen.
+#[derive(Component, Clone, Copy)]
+pub struct PlacePattern(pub &'static str);
+
 #[derive(Component, Clone, Copy)]
 pub struct ShowRulesFor {
     pub shape: TileShape,
     pub state: u32,
 }
 
+/// Makes the play speed slider set how many frames pass between generations.
+#[derive(Component)]
+pub struct PlaySpeedEventGenerator;
+
+impl NumberedEventGenerator for PlaySpeedEventGenerator {
+    type Event = TogglePlay;
+    type Value = u32;
+
+    fn create_event(&self, value: u32) -> Self::Event {
+        TogglePlay::SetSpeed(value)
+    }
+}
+
 #[derive(Component)]
 pub struct RuleUpdateEventGenerator {
     pub tile: TileShape,
@@ -39,6 +77,7 @@ pub struct RuleUpdateEventGenerator {
 
 impl NumberedEventGenerator for RuleUpdateEventGenerator {
     type Event = RuleUpdateEvent;
+    type Value = u32;
 
     fn create_event(&self, value: u32) -> Self::Event {
         RuleUpdateEvent::ModifyRule {
@@ -51,6 +90,29 @@ impl NumberedEventGenerator for RuleUpdateEventGenerator {
     }
 }
 
+/// Sent when the color used to display a state is picked.
+#[derive(Component, Clone, Copy)]
+pub struct StateColorChanged {
+    pub state: u32,
+    pub color: Color,
+}
+
+#[derive(Component)]
+pub struct StateColorEventGenerator {
+    pub state: u32,
+}
+
+impl ColorEventGenerator for StateColorEventGenerator {
+    type Event = StateColorChanged;
+
+    fn create_event(&self, color: Color) -> Self::Event {
+        StateColorChanged {
+            state: self.state,
+            color,
+        }
+    }
+}
+
 #[derive(Component, Clone, Copy)]
 pub enum RuleUpdateEvent {
     ModifyRule {
@@ -63,10 +125,24 @@ pub enum RuleUpdateEvent {
     AddState {
         shape: TileShape,
     },
+    RemoveState {
+        state: u32,
+    },
     AddRule {
         shape: TileShape,
         state: u32,
     },
+    MoveRule {
+        shape: TileShape,
+        state: u32,
+        from: usize,
+        to: usize,
+    },
+    DeleteRule {
+        shape: TileShape,
+        state: u32,
+        rule_number: usize,
+    },
     ShowRulesFor {
         shape: TileShape,
         state: u32,
@@ -78,18 +154,30 @@ pub(super) fn change_view_to(
     mut change_rules_view_events: EventWriter<ShowRulesFor>,
     mut out_vis_events: EventWriter<SimulationStateChanged>,
     mut sim_state: ResMut<SimulationState>,
+    mut menu_state: ResMut<MenuState>,
+    (mut vis_cache, mut materials): (ResMut<VisualsCache>, ResMut<Assets<ColorMaterial>>),
 ) {
     for event in events.iter() {
-        let grid_size = if event.0 == TilingKind::Square {
-            52
-        } else {
-            52
-        };
-        *sim_state = SimulationState::new(Tiling {
+        let tiling = Tiling {
             kind: event.0,
-            max_index: IVec2::new(grid_size, grid_size),
+            max_index: sim_state.tiling.max_index,
             offset: Vec2::ZERO,
-        });
+        };
+        let boundary = sim_state.boundary();
+        *sim_state = if menu_state.random_start {
+            SimulationState::new_with_random(
+                tiling,
+                RANDOM_START_MAX_STATE,
+                RANDOM_START_DENSITY,
+                RANDOM_START_SEED,
+            )
+        } else {
+            SimulationState::new(tiling)
+        };
+        sim_state.set_boundary(boundary);
+        for state in 0..sim_state.num_states as u32 {
+            register_state_color(state, &mut menu_state, &mut vis_cache, &mut materials);
+        }
 
         change_rules_view_events.send(ShowRulesFor {
             shape: match sim_state.tiling.kind {
@@ -112,16 +200,92 @@ pub(super) fn change_view_to(
 
 pub(super) fn toggle_play_event(
     mut events: EventReader<TogglePlay>,
+    mut out_vis_events: EventWriter<SimulationStateChanged>,
     mut sim_state: ResMut<SimulationState>,
+    mut menu_state: ResMut<MenuState>,
 ) {
     for event in events.iter() {
         match event {
-            TogglePlay::Toggle => {
-                sim_state.run_every = if sim_state.run_every == 0 { 5 } else { 0 }
-            }
+            TogglePlay::Toggle => sim_state.toggle_play(),
+            TogglePlay::SetSpeed(frames) => sim_state.set_run_every(*frames),
             TogglePlay::Step => {
                 sim_state.step += 1;
             }
+            TogglePlay::NewBoard => {
+                sim_state.reset_keep_rules();
+                out_vis_events.send(SimulationStateChanged::NewTiling);
+            }
+            TogglePlay::StepBack => {
+                if let Some(changes) = sim_state.step_back() {
+                    out_vis_events.send(SimulationStateChanged::StatesChanged(changes));
+                }
+            }
+            TogglePlay::Preview => menu_state.preview_next = !menu_state.preview_next,
+        }
+    }
+}
+
+/// Move the play speed slider to the speed the simulation runs at when something other than
+/// the slider changes it, like pausing, resuming or a new board. A slider being dragged is left
+/// alone.
+pub(super) fn sync_play_speed_slider(
+    sim_state: Res<SimulationState>,
+    mut slider_query: Query<(&UiElement, &mut Slider<PlaySpeedEventGenerator>, &Children)>,
+    mut fill_query: Query<(&mut Transform, &mut Sprite), With<SliderFill>>,
+    mut text_query: Query<&mut Text, With<SliderValueText>>,
+) {
+    if !sim_state.is_changed() {
+        return;
+    }
+    slider_query.for_each_mut(|(element, mut slider, children)| {
+        if element.click_state.current || slider.current == sim_state.run_every {
+            return;
+        }
+        slider.current = sim_state.run_every;
+        show_slider_value(&slider, element.size, children, &mut fill_query, &mut text_query);
+    });
+}
+
+/// Make sure we have a color and material to display the given state with. Returns true if
+/// a new color had to be created.
+pub(super) fn register_state_color(
+    state: u32,
+    menu_state: &mut MenuState,
+    vis_cache: &mut VisualsCache,
+    materials: &mut Assets<ColorMaterial>,
+) -> bool {
+    if menu_state.state_to_color.contains_key(&state) {
+        return false;
+    }
+    let color = Color::hsl(((state * 37) % 360) as f32, 1.0, 0.75);
+    menu_state.state_to_color.insert(state, color);
+    let image = vis_cache.outline_image.clone();
+    vis_cache.states.insert(
+        state,
+        materials.add(ColorMaterial {
+            color,
+            texture: Some(image),
+        }),
+    );
+    true
+}
+
+/// Store the picked color and change the state's material to match. Every tile shares the
+/// material of its state, so tiles already showing the state change color with it.
+pub(super) fn on_state_color_changed(
+    mut events: EventReader<StateColorChanged>,
+    mut menu_state: ResMut<MenuState>,
+    vis_cache: Res<VisualsCache>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for event in events.iter() {
+        menu_state.state_to_color.insert(event.state, event.color);
+        if let Some(material) = vis_cache
+            .states
+            .get(&event.state)
+            .and_then(|handle| materials.get_mut(handle))
+        {
+            material.color = event.color;
         }
     }
 }
@@ -149,31 +313,74 @@ pub(super) fn on_rule_update(
                 value,
                 target,
             } => {
-                update_view = target == RuleUpdateTarget::ToggleCount;
+                update_view = matches!(
+                    target,
+                    RuleUpdateTarget::ToggleCount
+                        | RuleUpdateTarget::ToggleIncludeSelf
+                        | RuleUpdateTarget::EvaluationMode
+                        | RuleUpdateTarget::AddConstraint
+                        | RuleUpdateTarget::RemoveConstraint(_)
+                        | RuleUpdateTarget::ToggleConstraintCount(_)
+                );
                 sim_state.set_rule_value(shape, state, rule_number, value, target);
             }
             RuleUpdateEvent::AddState { shape: tile } => {
                 sim_state.add_state(tile);
                 let new_state = sim_state.num_states as u32 - 1;
-                if !menu_state.state_to_color.contains_key(&new_state) {
-                    let color = Color::hsl(((new_state * 37) % 360) as f32, 1.0, 0.75);
-                    menu_state.state_to_color.insert(new_state, color);
-                    let image = vis_cache.outline_image.clone();
-                    vis_cache.states.insert(
-                        new_state,
-                        materials.add(ColorMaterial {
-                            color,
-                            texture: Some(image),
-                        }),
-                    );
+                if register_state_color(new_state, &mut menu_state, &mut vis_cache, &mut materials) {
                     out_vis_events.send(SimulationStateChanged::NewTiling);
                 }
                 update_view = true;
             }
+            RuleUpdateEvent::RemoveState { state } => {
+                match sim_state.remove_state(state) {
+                    Ok(removed) => {
+                        for reference in removed.clamped {
+                            info!(
+                                "Clamped {:?} of {:?} state {} rule {:?} from {} to {}",
+                                reference.target,
+                                reference.shape,
+                                reference.state,
+                                reference.rule,
+                                reference.from,
+                                reference.to
+                            );
+                        }
+                        if !removed.changed.is_empty() {
+                            out_vis_events
+                                .send(SimulationStateChanged::StatesChanged(removed.changed));
+                        }
+                        // Stay on the state that took the removed one's place.
+                        let last_state = sim_state
+                            .get_num_states_for_shape(show_rule_event.shape)
+                            .saturating_sub(1);
+                        show_rule_event.state = show_rule_event.state.min(last_state);
+                    }
+                    Err(err) => warn!("Failed to remove state {}: {}", state, err),
+                }
+                update_view = true;
+            }
             RuleUpdateEvent::AddRule { shape: tile, state } => {
                 sim_state.add_rule(tile, state);
                 update_view = true;
             }
+            RuleUpdateEvent::MoveRule {
+                shape,
+                state,
+                from,
+                to,
+            } => {
+                sim_state.move_rule(shape, state, from, to);
+                update_view = true;
+            }
+            RuleUpdateEvent::DeleteRule {
+                shape,
+                state,
+                rule_number,
+            } => {
+                sim_state.delete_rule(shape, state, rule_number);
+                update_view = true;
+            }
             RuleUpdateEvent::ShowRulesFor { shape, state } => {
                 show_rule_event = ShowRulesFor { shape, state };
                 update_view = true;
@@ -185,3 +392,176 @@ pub(super) fn on_rule_update(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        asset::{AddAsset, AssetPlugin, Assets},
+        core::CorePlugin,
+        ecs::{event::Events, schedule::SystemStage},
+        hierarchy::BuildWorldChildren,
+        math::Size,
+        prelude::{App, Color, ParallelSystemDescriptorCoercion, Stage, Transform},
+        sprite::{ColorMaterial, Sprite},
+        text::{Text, TextSection},
+    };
+
+    use bevy::math::{IVec2, Vec2};
+
+    use crate::{
+        simulation::{SimulationState, DEFAULT_RUN_EVERY},
+        tiling::{Tiling, TilingKind},
+        ui::{
+            ColorEventGenerator, NumberedEventGenerator, Slider, SliderFill, SliderValueText,
+            UiElement,
+        },
+        visuals::collapse::SimulationStateChanged,
+        VisualsCache,
+    };
+
+    use super::{
+        on_state_color_changed, sync_play_speed_slider, toggle_play_event, MenuState,
+        PlaySpeedEventGenerator, StateColorChanged, StateColorEventGenerator, TogglePlay,
+    };
+
+    #[test]
+    fn resuming_play_keeps_the_speed_set() {
+        let mut app = App::new();
+        app.insert_resource(SimulationState::new(Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(4, 4),
+            offset: Vec2::ZERO,
+        }));
+        app.insert_resource(MenuState::default());
+        app.insert_resource(Events::<TogglePlay>::default());
+        app.insert_resource(Events::<SimulationStateChanged>::default());
+        let mut stage = SystemStage::single_threaded().with_system(toggle_play_event);
+        let mut send = |app: &mut App, event: TogglePlay| {
+            app.world
+                .get_resource_mut::<Events<TogglePlay>>()
+                .unwrap()
+                .send(event);
+            stage.run(&mut app.world);
+            app.world.get_resource::<SimulationState>().unwrap().run_every
+        };
+
+        assert_eq!(send(&mut app, PlaySpeedEventGenerator.create_event(30)), 30);
+        assert_eq!(send(&mut app, TogglePlay::Toggle), 0);
+        assert_eq!(send(&mut app, TogglePlay::Toggle), 30);
+        // Dragging the speed to 0 pauses without forgetting the speed to resume at.
+        assert_eq!(send(&mut app, TogglePlay::SetSpeed(0)), 0);
+        assert_eq!(send(&mut app, TogglePlay::Toggle), 30);
+        assert_eq!(send(&mut app, TogglePlay::SetSpeed(120)), 120);
+        assert_eq!(send(&mut app, TogglePlay::Toggle), 0);
+        assert_eq!(send(&mut app, TogglePlay::Toggle), 120);
+    }
+
+    #[test]
+    fn speed_slider_follows_play_and_pause() {
+        let mut app = App::new();
+        app.insert_resource(SimulationState::new(Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(4, 4),
+            offset: Vec2::ZERO,
+        }));
+        app.insert_resource(MenuState::default());
+        app.insert_resource(Events::<TogglePlay>::default());
+        app.insert_resource(Events::<SimulationStateChanged>::default());
+        let text = app
+            .world
+            .spawn()
+            .insert(Text {
+                sections: vec![TextSection::default()],
+                ..Default::default()
+            })
+            .insert(SliderValueText)
+            .id();
+        let fill = app
+            .world
+            .spawn()
+            .insert(Transform::default())
+            .insert(Sprite::default())
+            .insert(SliderFill)
+            .id();
+        let slider = app
+            .world
+            .spawn()
+            .insert(UiElement {
+                size: Size::new(100.0, 20.0),
+                ..Default::default()
+            })
+            .insert(Slider {
+                event_generator: PlaySpeedEventGenerator,
+                current: 0,
+                min: 0,
+                max: 100,
+            })
+            .push_children(&[text, fill])
+            .id();
+        let mut stage = SystemStage::single_threaded()
+            .with_system(toggle_play_event)
+            .with_system(sync_play_speed_slider.after(toggle_play_event));
+        let mut send = |app: &mut App, event: TogglePlay| {
+            app.world
+                .get_resource_mut::<Events<TogglePlay>>()
+                .unwrap()
+                .send(event);
+            stage.run(&mut app.world);
+            let current = app
+                .world
+                .get::<Slider<PlaySpeedEventGenerator>>(slider)
+                .unwrap()
+                .current;
+            assert_eq!(app.world.get::<Text>(text).unwrap().sections[0].value, current.to_string());
+            current
+        };
+
+        // Playing from the start resumes at the default speed.
+        assert_eq!(send(&mut app, TogglePlay::Toggle), DEFAULT_RUN_EVERY);
+        assert_eq!(app.world.get::<Sprite>(fill).unwrap().custom_size.unwrap().x, 5.0);
+        assert_eq!(send(&mut app, TogglePlay::Toggle), 0);
+        assert_eq!(send(&mut app, TogglePlay::SetSpeed(40)), 40);
+        assert_eq!(send(&mut app, TogglePlay::Toggle), 0);
+        assert_eq!(send(&mut app, TogglePlay::Toggle), 40);
+    }
+
+    #[test]
+    fn picked_color_updates_color_map_and_material() {
+        let mut app = App::new();
+        app.add_plugin(CorePlugin)
+            .add_plugin(AssetPlugin)
+            .add_asset::<ColorMaterial>();
+        let material = app
+            .world
+            .get_resource_mut::<Assets<ColorMaterial>>()
+            .unwrap()
+            .add(ColorMaterial::from(Color::BLACK));
+        let mut vis_cache = VisualsCache::default();
+        vis_cache.states.insert(1, material.clone());
+        app.insert_resource(vis_cache);
+        let mut menu_state = MenuState::default();
+        menu_state.state_to_color.insert(0, Color::WHITE);
+        menu_state.state_to_color.insert(1, Color::BLACK);
+        app.insert_resource(menu_state);
+        app.insert_resource(Events::<StateColorChanged>::default());
+
+        let picked = Color::rgb(0.2, 0.6, 0.4);
+        let mut events = app
+            .world
+            .get_resource_mut::<Events<StateColorChanged>>()
+            .unwrap();
+        events.send(StateColorEventGenerator { state: 1 }.create_event(picked));
+        // A state we have no material for yet still remembers its color.
+        events.send(StateColorEventGenerator { state: 4 }.create_event(Color::RED));
+        SystemStage::single_threaded()
+            .with_system(on_state_color_changed)
+            .run(&mut app.world);
+
+        let menu_state = app.world.get_resource::<MenuState>().unwrap();
+        assert_eq!(menu_state.state_to_color[&0], Color::WHITE);
+        assert_eq!(menu_state.state_to_color[&1], picked);
+        assert_eq!(menu_state.state_to_color[&4], Color::RED);
+        let materials = app.world.get_resource::<Assets<ColorMaterial>>().unwrap();
+        assert_eq!(materials.get(&material).unwrap().color, picked);
+    }
+}
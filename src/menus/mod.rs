@@ -1,11 +1,15 @@
+mod collapse_progress;
+mod editing;
 mod events;
 mod rules_container;
 mod state;
 mod tile_inspect;
 
 use bevy::prelude::{Plugin, ParallelSystemDescriptorCoercion};
+pub use editing::{EditSettingsEventGenerator, PaintMode};
 pub use events::{
-    ChangeViewTo, RuleUpdateEvent, RuleUpdateEventGenerator, ShowRulesFor, TogglePlay,
+    ChangeViewTo, PlacePattern, PlaySpeedEventGenerator, RuleUpdateEventGenerator, ShowRulesFor,
+    StateColorEventGenerator,
 };
 pub use rules_container::RulesContainer;
 pub use state::{setup_menus, MenuState};
@@ -27,8 +31,14 @@ impl Plugin for MenusPlugin {
             .add_startup_system(state::setup_menus)
             .add_system(events::change_view_to)
             .add_system(events::on_rule_update)
+            .add_system(events::on_state_color_changed)
             .add_system(events::toggle_play_event)
+            .add_system(events::sync_play_speed_slider.after(events::toggle_play_event))
             .add_system(rules_container::change_rules_event)
+            .add_system(editing::on_edit_settings)
+            .add_system(editing::rebuild_editing_panel.after(editing::on_edit_settings))
+            .add_system(editing::update_active_state_display)
+            .add_system(collapse_progress::update_collapse_progress)
             .add_system(tile_inspect::inspect)
             .add_system(tile_inspect::adjust_child_sizes.before(crate::ui::scroll_view::linear_scroll_handler))
             .add_system(tile_inspect::process_debug_inserts)
@@ -2,15 +2,15 @@ use bevy::{
     hierarchy::{BuildChildren, DespawnRecursiveExt},
     math::Size,
     prelude::{Color, Commands, Component, Entity, EventReader, Query, Res, ResMut, With},
-    sprite::SpriteBundle,
+    sprite::{Sprite, SpriteBundle},
 };
 
 use crate::{
-    simulation::{RuleUpdateTarget, SimulationState},
+    simulation::{find_unreachable_states, RuleEvaluation, RuleUpdateTarget, SimulationState},
     ui::*,
 };
 
-use super::{events::*, MenuState};
+use super::{editing::ActiveStateSwatch, events::*, MenuState};
 
 #[derive(Component)]
 pub struct RulesContainer {}
@@ -36,6 +36,7 @@ pub(super) fn change_rules_event(
             let valid_shapes = sim_state.get_shapes();
             let states = sim_state.clone_rules_for_shape(menu_data.active_shape);
             let num_states = states.len();
+            let unreachable_states = find_unreachable_states(&states);
 
             entity.with_children(|child_builder| {
                 // If we have multiple shapes allow the user to select a different
@@ -77,7 +78,11 @@ pub(super) fn change_rules_event(
                     (0..num_states)
                         .map(|index| {
                             (
-                                format!("{}", index),
+                                if unreachable_states.contains(&(index as u32)) {
+                                    format!("{}!", index)
+                                } else {
+                                    format!("{}", index)
+                                },
                                 if index as u32 == menu_data.active_state {
                                     Color::GRAY
                                 } else {
@@ -89,13 +94,22 @@ pub(super) fn change_rules_event(
                                 },
                             )
                         })
-                        .chain([(
-                            "+".to_string(),
-                            Color::WHITE,
-                            RuleUpdateEvent::AddState {
-                                shape: menu_data.active_shape,
-                            },
-                        )])
+                        .chain([
+                            (
+                                "+".to_string(),
+                                Color::WHITE,
+                                RuleUpdateEvent::AddState {
+                                    shape: menu_data.active_shape,
+                                },
+                            ),
+                            (
+                                "-".to_string(),
+                                Color::WHITE,
+                                RuleUpdateEvent::RemoveState {
+                                    state: menu_data.active_state,
+                                },
+                            ),
+                        ])
                         .collect(),
                     element.size.width,
                     super::HEADER_HEIGHT,
@@ -104,9 +118,31 @@ pub(super) fn change_rules_event(
                     super::HEADER_MARGIN,
                 );
 
+                // States no rule ever produces were most likely never wired up.
+                if !unreachable_states.is_empty() {
+                    child_builder
+                        .spawn_bundle(menu_data.get_text_bundle(
+                            format!(
+                                "Unreachable states: {}",
+                                unreachable_states
+                                    .iter()
+                                    .map(|state| state.to_string())
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ),
+                            super::REGULAR_FONT_SIZE,
+                            Color::RED,
+                        ))
+                        .insert(UiElement {
+                            size: Size::new(element.size.width, super::REGULAR_HEIGHT_STEP),
+                            ..Default::default()
+                        });
+                }
+
                 let step_size = Size::new(element.size.width, super::REGULAR_HEIGHT_STEP);
                 let num_rules = states.len() as u32;
                 let rule_set = &states[menu_data.active_state as usize];
+                let max_neighbors = sim_state.tiling.max_neighbor_count(menu_data.active_shape);
 
                 menu_data.spawn_labeled_number_field(
                     &mut child_builder.spawn(),
@@ -122,21 +158,172 @@ pub(super) fn change_rules_event(
                         },
                         current_value: rule_set.default_state,
                         min_value: 0,
+                        typed: None,
                         max_value: num_rules - 1,
                     },
                 );
 
+                menu_data.spawn_labeled(
+                    &mut child_builder.spawn(),
+                    step_size,
+                    "Color:".into(),
+                    Color::BLACK,
+                    |data, color_builder| {
+                        let swatch_size = super::REGULAR_HEIGHT_STEP - super::REGULAR_MARGIN;
+                        color_builder
+                            .spawn_bundle(SpriteBundle {
+                                sprite: Sprite {
+                                    color: data.active_state_color(),
+                                    ..Default::default()
+                                },
+                                texture: data.button.clone(),
+                                ..Default::default()
+                            })
+                            .insert(UiElement {
+                                size: Size::new(swatch_size, swatch_size),
+                                ..Default::default()
+                            })
+                            .insert(ActiveStateSwatch);
+                        let picker = ColorPicker::new(
+                            StateColorEventGenerator {
+                                state: data.active_state,
+                            },
+                            data.active_state_color(),
+                        );
+                        let mut picker_bundle = data.get_text_bundle(
+                            picker.label(),
+                            super::REGULAR_FONT_SIZE,
+                            Color::BLACK,
+                        );
+                        picker_bundle.text.alignment.horizontal =
+                            bevy::text::HorizontalAlign::Right;
+                        color_builder
+                            .spawn_bundle(picker_bundle)
+                            .insert(UiElement {
+                                size: Size::new(
+                                    step_size.width - 100.0 - swatch_size,
+                                    swatch_size,
+                                ),
+                                selected_state: UiStateDetails {
+                                    accepts_state: true,
+                                    ..Default::default()
+                                },
+                                ..Default::default()
+                            })
+                            .insert(picker);
+                    },
+                );
+
+                menu_data.spawn_labeled(
+                    &mut child_builder.spawn(),
+                    step_size,
+                    "Order:".into(),
+                    Color::BLACK,
+                    |data, order_builder| {
+                        data.build_button_group(
+                            &mut order_builder.spawn(),
+                            Color::WHITE,
+                            [
+                                ("First Match", RuleEvaluation::FirstMatch),
+                                ("Priority", RuleEvaluation::Priority),
+                            ]
+                            .into_iter()
+                            .map(|(label, evaluation)| {
+                                (
+                                    label.to_string(),
+                                    if rule_set.evaluation == evaluation {
+                                        Color::GRAY
+                                    } else {
+                                        Color::WHITE
+                                    },
+                                    RuleUpdateEvent::ModifyRule {
+                                        shape: menu_data.active_shape,
+                                        state: menu_data.active_state,
+                                        rule_number: 0,
+                                        value: evaluation.value(),
+                                        target: RuleUpdateTarget::EvaluationMode,
+                                    },
+                                )
+                            })
+                            .collect(),
+                            element.size.width - 100.0,
+                            super::REGULAR_HEIGHT_STEP,
+                            super::REGULAR_FONT_SIZE,
+                            Color::GRAY,
+                            super::REGULAR_MARGIN,
+                        );
+                    },
+                );
+
+                // Rules that can never fire because of other rules get a red header.
+                let unreachable_rules = rule_set.find_unreachable_rules();
                 for (i, rule) in rule_set.rules.iter().enumerate() {
                     child_builder
                         .spawn_bundle(menu_data.get_text_bundle(
                             format!("Rule {}", i),
                             super::HEADER_FONT_SIZE,
-                            Color::BLACK,
+                            if unreachable_rules.contains(&i) {
+                                Color::RED
+                            } else {
+                                Color::BLACK
+                            },
                         ))
                         .insert(UiElement {
                             size: Size::new(element.size.width, super::HEADER_HEIGHT),
                             ..Default::default()
                         });
+                    // Rule order decides which rule wins under first match evaluation.
+                    let move_to = |to: usize| RuleUpdateEvent::MoveRule {
+                        shape: menu_data.active_shape,
+                        state: menu_data.active_state,
+                        from: i,
+                        to,
+                    };
+                    let mut edits = Vec::new();
+                    if i > 0 {
+                        edits.push(("Up".to_string(), Color::WHITE, move_to(i - 1)));
+                    }
+                    if i + 1 < rule_set.rules.len() {
+                        edits.push(("Down".to_string(), Color::WHITE, move_to(i + 1)));
+                    }
+                    edits.push((
+                        "+ Count".to_string(),
+                        Color::WHITE,
+                        RuleUpdateEvent::ModifyRule {
+                            shape: menu_data.active_shape,
+                            state: menu_data.active_state,
+                            rule_number: i,
+                            value: 0,
+                            target: RuleUpdateTarget::AddConstraint,
+                        },
+                    ));
+                    edits.push((
+                        "Delete".to_string(),
+                        Color::WHITE,
+                        RuleUpdateEvent::DeleteRule {
+                            shape: menu_data.active_shape,
+                            state: menu_data.active_state,
+                            rule_number: i,
+                        },
+                    ));
+                    menu_data.spawn_labeled(
+                        &mut child_builder.spawn(),
+                        step_size,
+                        "Edit:".into(),
+                        Color::BLACK,
+                        |data, edit_builder| {
+                            data.build_button_group(
+                                &mut edit_builder.spawn(),
+                                Color::WHITE,
+                                edits,
+                                element.size.width - 100.0,
+                                super::REGULAR_HEIGHT_STEP,
+                                super::REGULAR_FONT_SIZE,
+                                Color::GRAY,
+                                super::REGULAR_MARGIN,
+                            );
+                        },
+                    );
                     menu_data.spawn_labeled(
                         &mut child_builder.spawn(),
                         step_size,
@@ -167,6 +354,21 @@ pub(super) fn change_rules_event(
                                             },
                                         )
                                     })
+                                    .chain([(
+                                        "Self".to_string(),
+                                        if rule.include_self {
+                                            Color::BLACK
+                                        } else {
+                                            Color::WHITE
+                                        },
+                                        RuleUpdateEvent::ModifyRule {
+                                            shape: menu_data.active_shape,
+                                            state: menu_data.active_state,
+                                            rule_number: i,
+                                            value: 0,
+                                            target: RuleUpdateTarget::ToggleIncludeSelf,
+                                        },
+                                    )])
                                     .collect(),
                                 element.size.width - 100.0,
                                 super::REGULAR_HEIGHT_STEP,
@@ -190,8 +392,9 @@ pub(super) fn change_rules_event(
                                 target: RuleUpdateTarget::MinValue,
                             },
                             current_value: rule.min,
-                            max_value: 8,
+                            max_value: rule.max_count(max_neighbors),
                             min_value: 0,
+                            typed: None,
                         },
                     );
 
@@ -208,11 +411,86 @@ pub(super) fn change_rules_event(
                                 target: RuleUpdateTarget::MaxValue,
                             },
                             current_value: rule.max,
-                            max_value: 8,
+                            max_value: rule.max_count(max_neighbors),
                             min_value: 0,
+                            typed: None,
                         },
                     );
 
+                    // Extra counts which must also be in range for the rule to fire.
+                    for (j, extra) in rule.extra_counts.iter().enumerate() {
+                        menu_data.spawn_labeled(
+                            &mut child_builder.spawn(),
+                            step_size,
+                            "And Count:".into(),
+                            Color::BLACK,
+                            |data, count_builder| {
+                                data.build_button_group(
+                                    &mut count_builder.spawn(),
+                                    Color::WHITE,
+                                    (0..sim_state.num_states)
+                                        .map(|index| {
+                                            (
+                                                format!("{}", index),
+                                                if extra.states.contains(&(index as u32)) {
+                                                    Color::BLACK
+                                                } else {
+                                                    Color::WHITE
+                                                },
+                                                RuleUpdateEvent::ModifyRule {
+                                                    shape: menu_data.active_shape,
+                                                    state: menu_data.active_state,
+                                                    rule_number: i,
+                                                    value: index as u32,
+                                                    target: RuleUpdateTarget::ToggleConstraintCount(j),
+                                                },
+                                            )
+                                        })
+                                        .chain([(
+                                            "X".to_string(),
+                                            Color::WHITE,
+                                            RuleUpdateEvent::ModifyRule {
+                                                shape: menu_data.active_shape,
+                                                state: menu_data.active_state,
+                                                rule_number: i,
+                                                value: 0,
+                                                target: RuleUpdateTarget::RemoveConstraint(j),
+                                            },
+                                        )])
+                                        .collect(),
+                                    element.size.width - 100.0,
+                                    super::REGULAR_HEIGHT_STEP,
+                                    super::REGULAR_FONT_SIZE,
+                                    Color::GRAY,
+                                    super::REGULAR_MARGIN,
+                                );
+                            },
+                        );
+                        for (label, target, current_value) in [
+                            ("And Min:", RuleUpdateTarget::ConstraintMin(j), extra.min),
+                            ("And Max:", RuleUpdateTarget::ConstraintMax(j), extra.max),
+                        ] {
+                            menu_data.spawn_labeled_number_field(
+                                &mut child_builder.spawn(),
+                                step_size,
+                                label.into(),
+                                Color::BLACK,
+                                NumberField {
+                                    event_generator: RuleUpdateEventGenerator {
+                                        tile: menu_data.active_shape,
+                                        state: menu_data.active_state,
+                                        rule_number: i,
+                                        target,
+                                    },
+                                    current_value,
+                                    max_value: rule.max_count(max_neighbors),
+                                    min_value: 0,
+                                    typed: None,
+                                },
+                            );
+                        }
+                    }
+
                     menu_data.spawn_labeled_number_field(
                         &mut child_builder.spawn(),
                         step_size,
@@ -228,8 +506,48 @@ pub(super) fn change_rules_event(
                             current_value: rule.output,
                             max_value: num_rules - 1,
                             min_value: 0,
+                            typed: None,
                         },
                     );
+
+                    menu_data.spawn_labeled_slider(
+                        &mut child_builder.spawn(),
+                        step_size,
+                        "Chance %:".into(),
+                        Color::BLACK,
+                        Slider {
+                            event_generator: RuleUpdateEventGenerator {
+                                tile: menu_data.active_shape,
+                                state: menu_data.active_state,
+                                rule_number: i,
+                                target: RuleUpdateTarget::Probability,
+                            },
+                            current: (rule.probability * 100.0).round() as u32,
+                            min: 0,
+                            max: 100,
+                        },
+                    );
+
+                    if rule_set.evaluation == RuleEvaluation::Priority {
+                        menu_data.spawn_labeled_number_field(
+                            &mut child_builder.spawn(),
+                            step_size,
+                            "Priority:".into(),
+                            Color::BLACK,
+                            NumberField {
+                                event_generator: RuleUpdateEventGenerator {
+                                    tile: menu_data.active_shape,
+                                    state: menu_data.active_state,
+                                    rule_number: i,
+                                    target: RuleUpdateTarget::Priority,
+                                },
+                                current_value: rule.priority,
+                                max_value: 99,
+                                min_value: 0,
+                                typed: None,
+                            },
+                        );
+                    }
                 }
 
                 child_builder
@@ -1,7 +1,7 @@
 use bevy::{
     ecs::system::EntityCommands,
     hierarchy::{BuildChildren, ChildBuilder},
-    math::{Size, Vec3},
+    math::{IVec2, Size, Vec2, Vec3},
     prelude::{
         AssetServer, Color, Commands, Component, EventWriter, Handle, Image, Res, ResMut, Transform, Bundle,
     },
@@ -11,9 +11,12 @@ use bevy::{
     utils::HashMap,
 };
 
-use crate::{tiling::*, ui::*};
+use crate::{patterns::pattern_names, simulation::SimulationState, tiling::*, ui::*};
 
-use super::{events::*, tile_inspect::DebugRoot, RulesContainer, REGULAR_FONT_SIZE, REGULAR_HEIGHT_STEP, CommandEventGenerator};
+use super::{collapse_progress::spawn_collapse_progress, editing::{EditingPanel, PaintMode}, events::*, tile_inspect::DebugRoot, RulesContainer, REGULAR_FONT_SIZE, REGULAR_HEIGHT_STEP, CommandEventGenerator};
+
+// The most frames between generations the speed slider goes up to.
+const MAX_RUN_EVERY: u32 = 120;
 
 pub struct MenuState {
     pub button: Handle<Image>,
@@ -21,6 +24,16 @@ pub struct MenuState {
     pub active_shape: TileShape,
     pub active_state: u32,
     pub state_to_color: HashMap<u32, Color>,
+    pub brush_radius: u32,
+    pub paint_mode: PaintMode,
+    pub mirror_x: bool,
+    pub mirror_y: bool,
+    // The board size typed into the editing panel, applied when resizing.
+    pub grid_size: IVec2,
+    // Whether cells the next generation changes are tinted in the 2D view.
+    pub preview_next: bool,
+    // Whether changing the tiling starts from a random board instead of an empty one.
+    pub random_start: bool,
 }
 
 impl Default for MenuState {
@@ -29,8 +42,16 @@ impl Default for MenuState {
             button: Default::default(),
             font: Default::default(),
             active_shape: TileShape::Square,
-            active_state: 0u32,
+            // Start on the first live state so clicking the board paints something.
+            active_state: 1u32,
             state_to_color: Default::default(),
+            brush_radius: 0,
+            paint_mode: PaintMode::Paint,
+            mirror_x: false,
+            mirror_y: false,
+            grid_size: IVec2::splat(DEFAULT_GRID_SIZE),
+            preview_next: false,
+            random_start: false,
         }
     }
 }
@@ -43,6 +64,14 @@ pub struct Text2dUiBundle {
 }
 
 impl MenuState {
+    /// The color used to display the active state.
+    pub fn active_state_color(&self) -> Color {
+        self.state_to_color
+            .get(&self.active_state)
+            .cloned()
+            .unwrap_or(Color::GRAY)
+    }
+
     /// Create a text bundle using the default font of the given string in the given
     /// color and size, center aligned, positioned closer to the camera by 1 unit.
     pub fn get_text_bundle(&self, text: String, size: f32, color: Color) -> Text2dBundle {
@@ -97,7 +126,35 @@ impl MenuState {
         &self,
         root: &mut EntityCommands,
         background: Color,
-        mut data: Vec<(String, Color, Event)>,
+        data: Vec<(String, Color, Event)>,
+        width: f32,
+        height: f32,
+        font_size: f32,
+        font_color: Color,
+        margin: f32,
+    ) {
+        self.build_button_group_with_tooltips(
+            root,
+            background,
+            data.into_iter()
+                .map(|(text, color, event)| (text, None, color, event))
+                .collect(),
+            width,
+            height,
+            font_size,
+            font_color,
+            margin,
+        );
+    }
+
+    /// Build a group of buttons like `build_button_group` where each
+    /// button may also show a tooltip when hovered.
+    #[allow(clippy::too_many_arguments)]
+    pub fn build_button_group_with_tooltips<Event: Component + Clone>(
+        &self,
+        root: &mut EntityCommands,
+        background: Color,
+        mut data: Vec<(String, Option<String>, Color, Event)>,
         width: f32,
         height: f32,
         font_size: f32,
@@ -119,7 +176,7 @@ impl MenuState {
         .with_children(|choice_builder| {
             let num = data.len() as f32;
             let width = width / num;
-            for (i, (text, color, event)) in data.drain(..).enumerate() {
+            for (i, (text, tooltip, color, event)) in data.drain(..).enumerate() {
                 choice_builder
                     .spawn_bundle(SpriteBundle {
                         sprite: Sprite {
@@ -144,6 +201,7 @@ impl MenuState {
                             accepts_state: true,
                             ..Default::default()
                         },
+                        tooltip,
                         ..Default::default()
                     })
                     .insert(Button::new(self.button.clone(), event))
@@ -189,6 +247,58 @@ impl MenuState {
             });
     }
 
+    /// Spawn a slider with the given label in-front of it.
+    pub fn spawn_labeled_slider<Generator: Component + NumberedEventGenerator<Value = u32>>(
+        &self,
+        builder: &mut EntityCommands,
+        size: Size,
+        label: String,
+        label_color: Color,
+        slider: Slider<Generator>,
+    ) {
+        self.spawn_labeled(builder, size, label, label_color, |data, child_builder| {
+            let slider_size = Size::new(size.width - 100.0, size.height - super::REGULAR_MARGIN);
+            let (fill_x, fill_size) = slider::fill_layout(slider.fraction(), slider_size);
+            let value = slider.current.to_string();
+            child_builder
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::WHITE,
+                        ..Default::default()
+                    },
+                    texture: data.button.clone(),
+                    ..Default::default()
+                })
+                .insert(UiElement {
+                    size: slider_size,
+                    click_state: UiStateDetails {
+                        accepts_state: true,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(slider)
+                .with_children(|slider_builder| {
+                    slider_builder
+                        .spawn_bundle(SpriteBundle {
+                            sprite: Sprite {
+                                color: Color::GRAY,
+                                custom_size: Some(Vec2::new(fill_size.width, fill_size.height)),
+                                ..Default::default()
+                            },
+                            texture: data.button.clone(),
+                            transform: Transform::from_translation(Vec3::new(fill_x, 0.0, 1.0)),
+                            ..Default::default()
+                        })
+                        .insert(SliderFill);
+                    let mut value_bundle =
+                        data.get_text_bundle(value, super::REGULAR_FONT_SIZE, Color::BLACK);
+                    value_bundle.transform.translation.z = 2.0;
+                    slider_builder.spawn_bundle(value_bundle).insert(SliderValueText);
+                });
+        });
+    }
+
     /// Spawn a number field with the given label in-front of it.
     pub fn spawn_labeled_number_field<Generator: Component + NumberedEventGenerator>(
         &self,
@@ -222,13 +332,16 @@ impl MenuState {
 
 pub fn setup_menus(
     mut menu_data: ResMut<MenuState>,
+    mut tooltip_style: ResMut<TooltipStyle>,
     asset_server: Res<AssetServer>,
     mut commands: Commands,
     mut events: EventWriter<ChangeViewTo>,
+    sim_state: Res<SimulationState>,
 ) {
     menu_data.button = asset_server.load("button.png");
     menu_data.font =
         asset_server.load("fonts/brass-mono-font-freeware-peter-fonseca/BrassMonoRegular-o2Yz.otf");
+    tooltip_style.font = menu_data.font.clone();
     menu_data.state_to_color.insert(0, Color::WHITE);
     menu_data.state_to_color.insert(1, Color::BLACK);
 
@@ -240,6 +353,7 @@ pub fn setup_menus(
         y_percent: 1.0,
         width_grow: None,
         height_grow: None,
+        ..Default::default()
     });
     menu_data.build_button_group(
         &mut tiling_button_group,
@@ -302,31 +416,167 @@ pub fn setup_menus(
             y_percent: 0.5,
             width_grow: None,
             height_grow: Some(1.0),
+            ..Default::default()
         })
         .insert(RulesContainer {})
         .insert(UiLinearScroll::default());
 
-    let mut play_step = commands.spawn();
-    play_step.insert(AnchoredUi {
-        x_percent: 0.5,
-        y_percent: 0.0,
-        width_grow: None,
-        height_grow: None,
-    });
-    menu_data.build_button_group(
-        &mut play_step,
-        Color::WHITE,
-        vec![
-            ("P".into(), Color::rgb(0.25, 0.5, 0.25), TogglePlay::Toggle),
-            ("S".into(), Color::rgb(0.5, 0.25, 0.25), TogglePlay::Step),
-        ],
-        2.0 * super::HEADER_HEIGHT,
-        super::HEADER_HEIGHT,
-        super::HEADER_FONT_SIZE,
-        Color::WHITE,
-        super::HEADER_MARGIN,
-    );
-    play_step.insert(Transform::from_translation(Vec3::new(0.0, 0.0, 10.0))); // Move it up.
+    // The play buttons with the speed slider above them.
+    let play_width = 5.0 * super::HEADER_HEIGHT;
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(1.0, 1.0, 1.0, 0.5),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 10.0)), // Move it up.
+            ..Default::default()
+        })
+        .insert(UiElement {
+            size: Size::new(play_width, super::HEADER_HEIGHT + REGULAR_HEIGHT_STEP),
+            ..Default::default()
+        })
+        .insert(UiLinearScroll::default())
+        .insert(AnchoredUi {
+            x_percent: 0.5,
+            y_percent: 0.0,
+            width_grow: None,
+            height_grow: None,
+            ..Default::default()
+        })
+        .with_children(|play_controls| {
+            menu_data.spawn_labeled_slider(
+                &mut play_controls.spawn(),
+                Size::new(play_width, REGULAR_HEIGHT_STEP),
+                "Speed:".into(),
+                Color::BLACK,
+                Slider {
+                    event_generator: PlaySpeedEventGenerator,
+                    current: sim_state.run_every,
+                    min: 0,
+                    max: MAX_RUN_EVERY,
+                },
+            );
+            menu_data.build_button_group_with_tooltips(
+                &mut play_controls.spawn(),
+                Color::WHITE,
+                vec![
+                    (
+                        "P".into(),
+                        Some("Play / Pause".into()),
+                        Color::rgb(0.25, 0.5, 0.25),
+                        TogglePlay::Toggle,
+                    ),
+                    (
+                        "B".into(),
+                        Some("Step back one generation".into()),
+                        Color::rgb(0.4, 0.2, 0.35),
+                        TogglePlay::StepBack,
+                    ),
+                    (
+                        "S".into(),
+                        Some("Step once".into()),
+                        Color::rgb(0.5, 0.25, 0.25),
+                        TogglePlay::Step,
+                    ),
+                    (
+                        "N".into(),
+                        Some("Empty the board".into()),
+                        Color::rgb(0.25, 0.25, 0.5),
+                        TogglePlay::NewBoard,
+                    ),
+                    (
+                        "V".into(),
+                        Some("Tint cells the next step changes".into()),
+                        Color::rgb(0.5, 0.4, 0.1),
+                        TogglePlay::Preview,
+                    ),
+                ],
+                play_width,
+                super::HEADER_HEIGHT,
+                super::HEADER_FONT_SIZE,
+                Color::WHITE,
+                super::HEADER_MARGIN,
+            );
+        });
+
+    // A list of the built-in patterns which places the pattern when clicked.
+    let pattern_names = pattern_names().collect::<Vec<_>>();
+    commands
+        .spawn()
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(1.0, 1.0, 1.0, 0.5),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
+            ..Default::default()
+        })
+        .insert(UiElement {
+            size: Size::new(150.0, 250.0),
+            scroll_state: UiStateDetails {
+                accepts_state: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(AnchoredUi {
+            x_percent: 1.0,
+            y_percent: 1.0,
+            width_grow: None,
+            height_grow: None,
+            ..Default::default()
+        })
+        .insert(UiLinearScroll::default())
+        .with_children(|child_builder| {
+            child_builder
+                .spawn_bundle(menu_data.get_text_bundle(
+                    "Patterns".into(),
+                    super::HEADER_FONT_SIZE,
+                    Color::BLACK,
+                ))
+                .insert(UiElement {
+                    size: Size::new(150.0, super::HEADER_HEIGHT),
+                    ..Default::default()
+                });
+            for name in pattern_names {
+                menu_data.build_button_group(
+                    &mut child_builder.spawn(),
+                    Color::WHITE,
+                    vec![(name.to_string(), Color::WHITE, PlacePattern(name))],
+                    150.0,
+                    REGULAR_HEIGHT_STEP + super::REGULAR_MARGIN,
+                    REGULAR_FONT_SIZE,
+                    Color::BLACK,
+                    super::REGULAR_MARGIN,
+                );
+            }
+        });
+
+    // The editing panel builds its contents once it is added.
+    commands
+        .spawn()
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(1.0, 1.0, 1.0, 0.5),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
+            ..Default::default()
+        })
+        .insert(UiElement::default())
+        .insert(AnchoredUi {
+            x_percent: 1.0,
+            y_percent: 0.0,
+            width_grow: None,
+            height_grow: None,
+            ..Default::default()
+        })
+        .insert(UiLinearScroll::default())
+        .insert(EditingPanel { collapsed: false });
+
+    // Shows how much of the terrain has finished collapsing.
+    spawn_collapse_progress(&mut commands, &menu_data);
 
     let scroll_id = commands.spawn_bundle(TransformBundle::default()).insert(UiElement {
         size: Size::new(300.0, 400.0),
@@ -346,10 +596,7 @@ pub fn setup_menus(
         },
         ..Default::default()
     })
-    .insert(TextField {
-        event_generator: CommandEventGenerator,
-        current_value: "help (click + enter)".to_string(),
-    }).id();
+    .insert(TextField::new(CommandEventGenerator, "help (click + enter)".to_string())).id();
 
     commands
         .spawn()
@@ -366,10 +613,12 @@ pub fn setup_menus(
             ..Default::default()
         })
         .insert(AnchoredUi {
-            x_percent: 2.0,
+            x_percent: 1.0,
             y_percent: 0.5,
             width_grow: None,
             height_grow: Some(1.0),
+            visible: false,
+            ..Default::default()
         })
         .insert(DebugRoot {
             log_panel: scroll_id,
@@ -380,3 +629,4 @@ pub fn setup_menus(
 
     events.send(ChangeViewTo(TilingKind::Square));
 }
+
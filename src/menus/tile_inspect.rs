@@ -1,21 +1,35 @@
-use std::fmt::Debug;
+use std::{collections::BTreeMap, fmt::Debug, time::Duration};
 
 use bevy::{
     hierarchy::{BuildChildren, DespawnRecursiveExt, Children},
-    math::{IVec2, Vec3, Quat},
-    prelude::{Color, Commands, Component, Entity, EventReader, Query, Res, With, Changed, KeyCode, ResMut, ParamSet, Visibility, Transform},
-    utils::{HashSet, HashMap}, text::{Text, TextSection, TextStyle}, input::Input, pbr::MaterialMeshBundle,
+    math::{IVec2, Vec2, Vec3, Quat},
+    prelude::{Assets, Color, Commands, Component, Entity, EventReader, EventWriter, Query, Res, With, Changed, KeyCode, ResMut, ParamSet, Visibility, Transform},
+    utils::{HashSet, HashMap}, text::Text, input::Input, sprite::ColorMaterial,
 };
 
 use crate::{
-    ui::{UiElement, UiLinearScroll, text_field::{TextEventGenerator, TextField}, AnchoredUi, InputState},
+    board::{
+        copy_image_to_clipboard, load_board_file, save_board_binary, BoardExport, ShowBoardDiff,
+    },
+    simulation::SimulationState,
+    VisualsCache,
+    ui::{UiElement, UiLinearScroll, scroll_view::ScrollBar, text_field::{TextEventGenerator, TextField}, AnchoredUi, InputState},
     visuals::{
-        collapse::{CollapseEntry, CollapseState, CollapseEntryIndex},
+        collapse::{wall_labels, CollapseEntry, CollapseHistory, CollapseState, CollapseEntryIndex, SimulationStateChanged},
+        export::ExportTerrainObj,
         geom::{handles::GeometryHandleSet, GeomOrientation, GeometryStorage, WallProfileIndex, VerticalProfile, GeometryHandle, LayerProfileIndex, geom::DebugGeomDisplay},
+        render::{instanced_mesh::{InstanceColor, MeshInstance}, instanced_pbr::InstancedStandardMaterial, InstancedPbrBundle},
     },
 };
 
-use super::{MenuState, REGULAR_FONT_SIZE, REGULAR_HEIGHT_STEP};
+use super::{events::register_state_color, MenuState, ShowRulesFor, REGULAR_FONT_SIZE, REGULAR_HEIGHT_STEP};
+
+// How many pixels wide a unit square tile is in the image copied to the clipboard.
+const CLIPBOARD_PIXELS_PER_UNIT: f32 = 4.0;
+
+// The most columns and grid lines a dump writes so it still fits in the log.
+const DUMP_MAX_COLUMNS: usize = 48;
+const DUMP_MAX_LINES: usize = 60;
 
 #[derive(Component, Clone, Debug)]
 pub struct DebugTileEvent(pub IVec2);
@@ -36,6 +50,8 @@ pub struct DebugState {
     pub remove_displays: Vec<HashMap<GeometryHandle, Vec<Entity>>>,
     pub wall_names: HashMap<WallProfileIndex, String>,
     pub layer_names: HashMap<LayerProfileIndex, String>,
+    // The most lines the debug log keeps. The oldest lines are removed to make room.
+    pub max_log_lines: usize,
 }
 
 impl Default for DebugState {
@@ -48,7 +64,8 @@ impl Default for DebugState {
             display_options_for: Default::default(),
             remove_displays: Default::default(),
             wall_names: Default::default(),
-            layer_names: Default::default()
+            layer_names: Default::default(),
+            max_log_lines: 100,
         }
     }
 }
@@ -62,7 +79,7 @@ pub fn update_debugger_panel(
     if !input_state.has_selection() && keyboard.just_pressed(KeyCode::D) {
         debug_state.debugging = !debug_state.debugging;
         for mut anchor in debug_panel.iter_mut() {
-            anchor.x_percent = if debug_state.debugging { 1.0 } else { 2.0 };
+            anchor.visible = debug_state.debugging;
         }
     }
 }
@@ -129,13 +146,37 @@ enum DebugNameTarget {
     Layer,
 }
 
+enum CollapseBudget {
+    Iterations(usize),
+    Millis(u64),
+}
+
 enum DebugCommand {
     ToggleBreak { tile: CollapseEntryIndex },
     Continue,
     Step,
     DebugTile { tile: CollapseEntryIndex, debug_op: DebugTileOps },
+    History { tile: CollapseEntryIndex },
     NameProfile { target: DebugNameTarget, index: usize, name: String },
     PrintMesh { mesh: GeometryHandle },
+    PlaceGround { height: f32, origin: Option<Vec2> },
+    Seed { seed: u64 },
+    SaveBoard { path: String },
+    LoadBoard { path: String },
+    DiffBoards { paths: Option<(String, String)> },
+    ExportSvg { path: String },
+    CopyImage,
+    ClearRegion,
+    FlatBorder { enabled: bool },
+    ExportObj { path: String },
+    NeighborCounts,
+    DumpCollapse,
+    Distance { from: IVec2, to: IVec2 },
+    ClearLog,
+    IntersectionCache { capacity: Option<usize> },
+    Budget { budget: Option<CollapseBudget> },
+    Pool { enabled: bool },
+    Echo { message: String },
     Empty,
     Help,
 }
@@ -184,6 +225,11 @@ fn parse_command(
                 debug_op,
             })
         }
+        "history" => {
+            Ok(DebugCommand::History {
+                tile: parse_tile_index(&mut position, &tokens, true)?
+            })
+        }
         "name" | "n" => {
             if position >= tokens.len() {
                 return Err(ParseError::MissingTokens { num_tokens: tokens.len(), expected: position });
@@ -229,23 +275,180 @@ fn parse_command(
                 value: handle_split[0].to_string(),
                 error: "Expected index@orientation but could not parse usize from index".to_string(),
             })?;
-            let reverse = handle_split[1].chars().next() == Some('r');
-            let rotation = handle_split[1][if reverse { 1 } else { 0 }..].parse().map_err(|_| ParseError::InvalidToken {
+            let reflection = handle_split[1].chars().next().filter(|c| *c == 'r' || *c == 'm');
+            let rotation = handle_split[1][if reflection.is_some() { 1 } else { 0 }..].parse().map_err(|_| ParseError::InvalidToken {
                 position,
                 value:handle_split[1].to_string(),
-                error: "Expected index@orientation to be usize or r followed by a usize".to_string(),
+                error: "Expected index@orientation to be usize or r or m followed by a usize".to_string(),
             })?;
 
             let handle = GeometryHandle {
                 index,
-                orientation: if reverse {
-                    GeomOrientation::Flipped { rotations: rotation }
-                } else {
-                    GeomOrientation::Standard { rotations: rotation }
+                orientation: match reflection {
+                    Some('r') => GeomOrientation::Flipped { rotations: rotation },
+                    Some(_) => GeomOrientation::Mirrored { rotations: rotation },
+                    None => GeomOrientation::Standard { rotations: rotation },
                 },
             };
             Ok(DebugCommand::PrintMesh { mesh: handle })
         }
+        "ground" | "g" => {
+            if position >= tokens.len() {
+                return Err(ParseError::MissingTokens { num_tokens: tokens.len(), expected: position + 1 });
+            }
+            let mut values = Vec::new();
+            for (offset, token) in tokens[position..].iter().enumerate() {
+                values.push(token.parse::<f32>().map_err(|err| ParseError::InvalidToken {
+                    position: position + offset,
+                    value: token.to_string(),
+                    error: format!("Failed to parse f32: {:?}", err),
+                })?);
+            }
+            match values.len() {
+                1 => Ok(DebugCommand::PlaceGround { height: values[0], origin: None }),
+                3 => Ok(DebugCommand::PlaceGround { height: values[0], origin: Some(Vec2::new(values[1], values[2])) }),
+                _ => Err(ParseError::MissingTokens { num_tokens: tokens.len(), expected: position + 3 }),
+            }
+        }
+        "seed" | "e" => {
+            if position >= tokens.len() {
+                return Err(ParseError::MissingTokens { num_tokens: tokens.len(), expected: position + 1 });
+            }
+            let seed = tokens[position].parse().map_err(|err| ParseError::InvalidToken {
+                position,
+                value: tokens[position].to_string(),
+                error: format!("Failed to parse seed: {:?}", err),
+            })?;
+            Ok(DebugCommand::Seed { seed })
+        }
+        "save" | "load" | "svg" => {
+            if position >= tokens.len() {
+                return Err(ParseError::MissingTokens { num_tokens: tokens.len(), expected: position + 1 });
+            }
+            let path = tokens[position..].join(" ");
+            match tokens[0] {
+                "save" => Ok(DebugCommand::SaveBoard { path }),
+                "load" => Ok(DebugCommand::LoadBoard { path }),
+                _ => Ok(DebugCommand::ExportSvg { path }),
+            }
+        }
+        "region" | "r" => {
+            match tokens.get(position) {
+                Some(&"clear") => Ok(DebugCommand::ClearRegion),
+                Some(token) => Err(ParseError::InvalidToken {
+                    position,
+                    value: token.to_string(),
+                    error: "Expected clear".to_string(),
+                }),
+                None => Err(ParseError::MissingTokens { num_tokens: tokens.len(), expected: position + 1 }),
+            }
+        }
+        "clear" => {
+            Ok(DebugCommand::ClearLog)
+        }
+        "border" => {
+            match tokens.get(position) {
+                Some(&"flat") => Ok(DebugCommand::FlatBorder { enabled: true }),
+                Some(&"off") => Ok(DebugCommand::FlatBorder { enabled: false }),
+                Some(token) => Err(ParseError::InvalidToken {
+                    position,
+                    value: token.to_string(),
+                    error: "Expected flat or off".to_string(),
+                }),
+                None => Err(ParseError::MissingTokens { num_tokens: tokens.len(), expected: position + 1 }),
+            }
+        }
+        "copy" => {
+            Ok(DebugCommand::CopyImage)
+        }
+        "diff" => {
+            match (tokens.get(position), tokens.get(position + 1)) {
+                (Some(&"clear"), None) => Ok(DebugCommand::DiffBoards { paths: None }),
+                (Some(before), Some(after)) => Ok(DebugCommand::DiffBoards {
+                    paths: Some((before.to_string(), after.to_string())),
+                }),
+                _ => Err(ParseError::MissingTokens { num_tokens: tokens.len(), expected: position + 2 }),
+            }
+        }
+        "cache" => {
+            let capacity = match tokens.get(position) {
+                Some(token) => Some(token.parse().map_err(|err| ParseError::InvalidToken {
+                    position,
+                    value: token.to_string(),
+                    error: format!("Failed to parse capacity: {:?}", err),
+                })?),
+                None => None,
+            };
+            Ok(DebugCommand::IntersectionCache { capacity })
+        }
+        "pool" => {
+            match tokens.get(position) {
+                Some(&"on") => Ok(DebugCommand::Pool { enabled: true }),
+                Some(&"off") => Ok(DebugCommand::Pool { enabled: false }),
+                Some(token) => Err(ParseError::InvalidToken {
+                    position,
+                    value: token.to_string(),
+                    error: "Expected on or off".to_string(),
+                }),
+                None => Err(ParseError::MissingTokens { num_tokens: tokens.len(), expected: position + 1 }),
+            }
+        }
+        "budget" => {
+            let budget = match tokens.get(position) {
+                Some(token) => {
+                    let parse_error = |err| ParseError::InvalidToken {
+                        position,
+                        value: token.to_string(),
+                        error: format!("Failed to parse budget: {:?}", err),
+                    };
+                    Some(match token.strip_suffix("ms") {
+                        Some(millis) => CollapseBudget::Millis(millis.parse().map_err(parse_error)?),
+                        None => CollapseBudget::Iterations(token.parse().map_err(parse_error)?),
+                    })
+                }
+                None => None,
+            };
+            Ok(DebugCommand::Budget { budget })
+        }
+        "distance" | "dist" => {
+            if position + 4 > tokens.len() {
+                return Err(ParseError::MissingTokens { num_tokens: tokens.len(), expected: position + 4 });
+            }
+            let mut values = Vec::new();
+            for (offset, token) in tokens[position..position + 4].iter().enumerate() {
+                values.push(token.parse::<i32>().map_err(|err| ParseError::InvalidToken {
+                    position: position + offset,
+                    value: token.to_string(),
+                    error: format!("Failed to parse index: {:?}", err),
+                })?);
+            }
+            Ok(DebugCommand::Distance {
+                from: IVec2::new(values[0], values[1]),
+                to: IVec2::new(values[2], values[3]),
+            })
+        }
+        "neighbors" | "nb" => {
+            Ok(DebugCommand::NeighborCounts)
+        }
+        "dump" => {
+            Ok(DebugCommand::DumpCollapse)
+        }
+        "export" => {
+            if position + 1 >= tokens.len() {
+                return Err(ParseError::MissingTokens { num_tokens: tokens.len(), expected: position + 2 });
+            }
+            if tokens[position] != "obj" {
+                return Err(ParseError::InvalidToken {
+                    position,
+                    value: tokens[position].to_string(),
+                    error: "Expected obj".to_string(),
+                });
+            }
+            Ok(DebugCommand::ExportObj { path: tokens[position + 1..].join(" ") })
+        }
+        "echo" => {
+            Ok(DebugCommand::Echo { message: tokens[position..].join(" ") })
+        }
         "help" | "h" => {
             Ok(DebugCommand::Help)
         }
@@ -255,16 +458,26 @@ fn parse_command(
 
 pub fn inspect(
     mut events: EventReader<CommandEvent>,
-    menu_data: Res<MenuState>,
+    mut menu_data: ResMut<MenuState>,
     geom_data: Res<GeometryStorage>,
     mut debug_state: ResMut<DebugState>,
-    collapse_state: Res<CollapseState>,
-    collapse_query: Query<&CollapseEntry>,
+    mut collapse_state: ResMut<CollapseState>,
+    mut sim_state: ResMut<SimulationState>,
+    mut collapse_query: Query<&mut CollapseEntry>,
+    (mut vis_cache, mut materials): (ResMut<VisualsCache>, ResMut<Assets<ColorMaterial>>),
+    (mut out_vis_events, mut show_rules_events, mut export_events, mut diff_events): (
+        EventWriter<SimulationStateChanged>,
+        EventWriter<ShowRulesFor>,
+        EventWriter<ExportTerrainObj>,
+        EventWriter<ShowBoardDiff>,
+    ),
     inspector_query: Query<&DebugRoot>,
     mut inspector_text_query: Query<&mut Children, With<UiLinearScroll>>,
+    scroll_bars: Query<(), With<ScrollBar>>,
     mut commands: Commands,
 ) {
     let mut new_text = Vec::new();
+    let mut clear_log = false;
     for event in events.iter() {
         let command = match parse_command(event.0.clone()) {
             Ok(command) => command,
@@ -343,25 +556,40 @@ pub fn inspect(
                         } else {
                             new_text.push(format!("Edges restrictions for {} at height {}", tile.index, tile.height));
                             for restriction in &collapse_entry.edge_restrictions {
-                                let mut walls = String::new();
-                                for wall in WallProfileIndex::from_bits(restriction.restruction.unwrap_or(0)) {
-                                    match debug_state.wall_names.get(&wall) {
-                                        Some(name) => {
-                                            walls.push_str(name);
-                                            walls.push(' ');
-                                        },
-                                        None => {
-                                            walls.push_str(&wall.index().to_string());
-                                            walls.push(' ');
-                                        },
-                                    }
-                                }
+                                let walls = wall_labels(restriction.restruction.unwrap_or(0), &debug_state.wall_names);
                                 new_text.push(format!("  Edge {}: {}", restriction.edge, walls));
                             }
                         }
                     },
                 }
             },
+            DebugCommand::History { tile } => {
+                let mut collapse_entry = match collapse_state
+                    .position_to_entry
+                    .get(&tile)
+                    .and_then(|entity| collapse_query.get_mut(*entity).ok())
+                {
+                    Some(entry) => entry,
+                    None => {
+                        new_text.push(format!("Invalid tile {} at height {}", tile.index, tile.height));
+                        continue;
+                    }
+                };
+                if collapse_entry.enable_history() {
+                    new_text.push(format!("Recording history for {} at height {}", tile.index, tile.height));
+                }
+                new_text.push(format!("History for {} at height {}", tile.index, tile.height));
+                for history in collapse_entry.history() {
+                    match history {
+                        CollapseHistory::Contradiction(report) => {
+                            for line in report.describe(&debug_state.wall_names) {
+                                new_text.push(format!("  {}", line));
+                            }
+                        }
+                        _ => new_text.push(format!("  {}", history)),
+                    }
+                }
+            },
             DebugCommand::NameProfile { target, index, name } => {
                 match target {
                     DebugNameTarget::Wall => {
@@ -392,37 +620,336 @@ pub fn inspect(
                 }
                 new_text.push(format!("Mesh {} has walls {}", mesh, data));
             },
+            DebugCommand::PlaceGround { height, origin } => {
+                collapse_state.set_ground_height(height);
+                if let Some(origin) = origin {
+                    collapse_state.set_origin(origin);
+                }
+                new_text.push(format!("Ground placed at height {} with origin {}", height, origin.unwrap_or(collapse_state.origin())));
+            }
+            DebugCommand::Seed { seed } => {
+                sim_state.set_seed(seed);
+                // Rebuild the terrain so it is collapsed again from the new seed.
+                collapse_state.set_seed(seed);
+                out_vis_events.send(SimulationStateChanged::NewTiling);
+                new_text.push(format!("Simulation and terrain seeded with {}", seed));
+            }
+            DebugCommand::SaveBoard { path } => {
+                let result = std::fs::File::create(&path).and_then(|file| {
+                    let mut writer = std::io::BufWriter::new(file);
+                    save_board_binary(&sim_state, &mut writer)
+                });
+                match result {
+                    Ok(()) => new_text.push(format!("Saved board to {}", path)),
+                    Err(err) => new_text.push(format!("Failed to save board to {}: {}", path, err)),
+                }
+            }
+            DebugCommand::ExportSvg { path } => {
+                let region = IVec2::ZERO..sim_state.tiling.max_index;
+                match sim_state.export_svg(region, &menu_data.state_to_color, &path) {
+                    Ok(()) => new_text.push(format!("Exported board to {}", path)),
+                    Err(err) => new_text.push(format!("Failed to export board to {}: {}", path, err)),
+                }
+            }
+            DebugCommand::CopyImage => {
+                let image = sim_state.render_image(&menu_data.state_to_color, CLIPBOARD_PIXELS_PER_UNIT);
+                match copy_image_to_clipboard(&image) {
+                    Ok(()) => new_text.push(format!("Copied a {}x{} image of the board", image.width(), image.height())),
+                    Err(err) => new_text.push(format!("Could not copy the board as an image: {}", err)),
+                }
+            }
+            DebugCommand::ClearRegion => {
+                collapse_state.set_region(None);
+                out_vis_events.send(SimulationStateChanged::NewTiling);
+                new_text.push("Collapsing the whole board".to_string());
+            }
+            DebugCommand::FlatBorder { enabled } => {
+                collapse_state.set_flat_border(enabled);
+                out_vis_events.send(SimulationStateChanged::NewTiling);
+                if enabled {
+                    new_text.push("Collapsing with a flat border".to_string());
+                } else {
+                    new_text.push("Collapsing with a free border".to_string());
+                }
+            }
+            DebugCommand::ExportObj { path } => {
+                new_text.push(format!("Exporting terrain to {}", path));
+                export_events.send(ExportTerrainObj { path });
+            }
+            DebugCommand::Distance { from, to } => {
+                match sim_state.tiling.grid_distance(from, to) {
+                    Some(distance) => {
+                        new_text.push(format!("{} is {} steps from {}", from, distance, to))
+                    }
+                    None => new_text.push("Distances are only known on square and hexagonal boards".to_string()),
+                }
+            }
+            DebugCommand::NeighborCounts => {
+                for (state, counts) in sim_state.neighbor_count_distribution().iter().enumerate() {
+                    let counts = counts
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, cells)| **cells > 0)
+                        .map(|(live, cells)| format!("{} have {}", cells, live))
+                        .collect::<Vec<_>>();
+                    if counts.is_empty() {
+                        continue;
+                    }
+                    new_text.push(format!("Of state-{} cells by live neighbors: {}", state, counts.join(", ")));
+                }
+            }
+            DebugCommand::DumpCollapse => {
+                let (height_updates, neighbor_updates) = collapse_state.pending_updates();
+                new_text.push(format!(
+                    "Pending updates: {} heights and {} neighbors",
+                    height_updates, neighbor_updates
+                ));
+                let entries = collapse_state
+                    .position_to_entry
+                    .iter()
+                    .filter_map(|(index, entity)| {
+                        let entry = collapse_query.get(*entity).ok()?;
+                        Some((*index, entry.options, entry.current_mesh.is_some()))
+                    })
+                    .collect::<Vec<_>>();
+                new_text.extend(dump_collapse_grid(&entries));
+            }
+            DebugCommand::LoadBoard { path } => {
+                match load_board_file(&path) {
+                    Ok(loaded) => {
+                        *sim_state = loaded;
+                        for state in 0..sim_state.num_states as u32 {
+                            register_state_color(state, &mut menu_data, &mut vis_cache, &mut materials);
+                        }
+                        out_vis_events.send(SimulationStateChanged::NewTiling);
+                        if let Some(shape) = sim_state.get_shapes().into_iter().next() {
+                            show_rules_events.send(ShowRulesFor { shape, state: 0 });
+                        }
+                        new_text.push(format!("Loaded board from {}", path));
+                    }
+                    Err(err) => new_text.push(format!("Failed to load board from {}: {}", path, err)),
+                }
+            }
+            DebugCommand::DiffBoards { paths: None } => {
+                diff_events.send(ShowBoardDiff { changes: Vec::new() });
+                new_text.push("Cleared board diff".to_string());
+            }
+            DebugCommand::DiffBoards { paths: Some((before_path, after_path)) } => {
+                let (mut before, mut after) = match (load_board_file(&before_path), load_board_file(&after_path)) {
+                    (Ok(before), Ok(after)) => (before, after),
+                    (Err(err), _) => {
+                        new_text.push(format!("Failed to load board from {}: {}", before_path, err));
+                        continue;
+                    }
+                    (_, Err(err)) => {
+                        new_text.push(format!("Failed to load board from {}: {}", after_path, err));
+                        continue;
+                    }
+                };
+                if before.tiling.kind != after.tiling.kind || before.tiling.max_index != after.tiling.max_index {
+                    new_text.push("Can only diff boards with the same tiling".to_string());
+                    continue;
+                }
+                // Loaded cells are only set once the board is advanced. Advancing by no time
+                // sets them without running a generation.
+                before.advance(Duration::ZERO);
+                after.advance(Duration::ZERO);
+                let changes = before.diff(&after);
+                let gained = changes.iter().filter(|(_, before, after)| after > before).count();
+                new_text.push(format!(
+                    "{} cells differ, {} gained and {} lost state",
+                    changes.len(),
+                    gained,
+                    changes.len() - gained
+                ));
+
+                // Show the later board with the changes outlined on it.
+                *sim_state = after;
+                for state in 0..sim_state.num_states as u32 {
+                    register_state_color(state, &mut menu_data, &mut vis_cache, &mut materials);
+                }
+                out_vis_events.send(SimulationStateChanged::NewTiling);
+                diff_events.send(ShowBoardDiff { changes });
+            }
             DebugCommand::Help => {
                 new_text.push("Commands are".to_string());
                 new_text.push("break(b) x y height".to_string());
                 new_text.push("continue(c)        ".to_string());
                 new_text.push("step(s)            ".to_string());
                 new_text.push("info(i) x y height display(d)|meshes(m)|restrictions(r)".to_string());
+                new_text.push("history x y height".to_string());
                 new_text.push("name(n) wall(w)|layer(l) index <value>".to_string());
                 new_text.push("print(p) index@orientation".to_string());
+                new_text.push("ground(g) height [x z]".to_string());
+                new_text.push("seed(e) value".to_string());
+                new_text.push("save|load path".to_string());
+                new_text.push("svg path".to_string());
+                new_text.push("copy".to_string());
+                new_text.push("diff before after|clear".to_string());
+                new_text.push("region(r) clear".to_string());
+                new_text.push("border flat|off".to_string());
+                new_text.push("export obj path".to_string());
+                new_text.push("neighbors(nb)".to_string());
+                new_text.push("dump".to_string());
+                new_text.push("distance(dist) x y x y".to_string());
+                new_text.push("clear".to_string());
+                new_text.push("cache [capacity]".to_string());
+                new_text.push("budget [iterations|<millis>ms]".to_string());
+                new_text.push("pool on|off".to_string());
+                new_text.push("echo message".to_string());
+            }
+            DebugCommand::Echo { message } => new_text.push(message),
+            DebugCommand::ClearLog => {
+                // Anything logged before the clear goes with it.
+                new_text.clear();
+                clear_log = true;
+            }
+            DebugCommand::IntersectionCache { capacity } => {
+                if let Some(capacity) = capacity {
+                    collapse_state.set_intersection_cache_capacity(capacity);
+                }
+                let cache = collapse_state.intersection_cache();
+                new_text.push(format!(
+                    "Intersection cache holds {}/{} with {} hits",
+                    cache.len(),
+                    cache.capacity(),
+                    cache.hits()
+                ));
+            }
+            DebugCommand::Budget { budget } => {
+                match budget {
+                    Some(CollapseBudget::Iterations(iterations)) => {
+                        collapse_state.set_iterations_per_frame(iterations)
+                    }
+                    Some(CollapseBudget::Millis(millis)) => {
+                        collapse_state.set_time_budget(Duration::from_millis(millis))
+                    }
+                    None => (),
+                }
+                match collapse_state.time_budget() {
+                    Some(time_budget) => new_text.push(format!(
+                        "Collapsing for {}ms each frame",
+                        time_budget.as_millis()
+                    )),
+                    None => new_text.push(format!(
+                        "Collapsing {} iterations each frame",
+                        collapse_state.iterations_per_frame()
+                    )),
+                }
+                new_text.push(format!(
+                    "Ran {} iterations last frame",
+                    collapse_state.iterations_last_frame()
+                ));
+            }
+            DebugCommand::Pool { enabled } => {
+                collapse_state.set_pool_entities(enabled);
+                if enabled {
+                    new_text.push("Reusing terrain entities on rebuild".to_string());
+                } else {
+                    new_text.push("Respawning terrain entities on rebuild".to_string());
+                }
             }
             DebugCommand::Empty => (),
         }
     }
 
     // Early out
-    if new_text.is_empty() { return; }
+    if new_text.is_empty() && !clear_log { return; }
 
     let root: &DebugRoot = inspector_query.single();
-    match inspector_text_query.get_mut(root.log_panel) {
-        Ok(scroll_children) => {
-            let next_total_children = scroll_children.len() + new_text.len();
-            if next_total_children >= 100 {
-                for i in 0..next_total_children - 100 {
-                    commands.entity(scroll_children[i]).despawn_recursive();
+    // The scrollbar is a child of the log too, but it is not one of its lines.
+    let existing = match inspector_text_query.get_mut(root.log_panel) {
+        Ok(scroll_children) => scroll_children
+            .iter()
+            .copied()
+            .filter(|child| scroll_bars.get(*child).is_err())
+            .collect(),
+        _ => Vec::new(),
+    };
+    write_to_log(
+        &mut commands,
+        root.log_panel,
+        &existing,
+        new_text,
+        clear_log,
+        debug_state.max_log_lines,
+        &menu_data,
+    );
+}
+
+// Lay out the entries at each height as a grid, one character per entry: `#` when it has a mesh,
+// otherwise its number of options (`+` past 9). Indices without an entry are left blank. Rows
+// are cut off past DUMP_MAX_COLUMNS and once DUMP_MAX_LINES have been written.
+fn dump_collapse_grid(entries: &[(CollapseEntryIndex, usize, bool)]) -> Vec<String> {
+    let mut heights = BTreeMap::<u32, HashMap<IVec2, (usize, bool)>>::new();
+    for (index, options, has_mesh) in entries {
+        heights
+            .entry(index.height)
+            .or_default()
+            .insert(index.index, (*options, *has_mesh));
+    }
+
+    let mut lines = Vec::new();
+    let mut grid_lines = 0;
+    for (height, entries) in &heights {
+        let min = entries.keys().fold(IVec2::splat(i32::MAX), |min, index| min.min(*index));
+        let max = entries.keys().fold(IVec2::splat(i32::MIN), |max, index| max.max(*index));
+        let collapsed = entries.values().filter(|(_, has_mesh)| *has_mesh).count();
+        lines.push(format!(
+            "Height {}: {}/{} have a mesh, {} to {}",
+            height,
+            collapsed,
+            entries.len(),
+            min,
+            max
+        ));
+        for y in min.y..=max.y {
+            if grid_lines == DUMP_MAX_LINES {
+                lines.push("  ...".to_string());
+                return lines;
+            }
+            let mut row = "  ".to_string();
+            for x in min.x..=max.x {
+                if (x - min.x) as usize == DUMP_MAX_COLUMNS {
+                    row.push_str("...");
+                    break;
                 }
+                row.push(match entries.get(&IVec2::new(x, y)) {
+                    Some((_, true)) => '#',
+                    Some((options, false)) if *options <= 9 => {
+                        char::from_digit(*options as u32, 10).unwrap()
+                    }
+                    Some(_) => '+',
+                    None => ' ',
+                });
             }
-        },
-        _ => ()
-    };
+            lines.push(row);
+            grid_lines += 1;
+        }
+    }
+    lines
+}
 
-    commands.entity(root.log_panel).with_children(|spawner| {
-        for text in new_text {
+// Add the lines to the end of the log, removing the oldest lines (or all of them when clearing)
+// so that we keep at most `max_lines`.
+fn write_to_log(
+    commands: &mut Commands,
+    log_panel: Entity,
+    existing: &[Entity],
+    new_text: Vec<String>,
+    clear: bool,
+    max_lines: usize,
+    menu_data: &MenuState,
+) {
+    let keep = if clear { 0 } else { max_lines.saturating_sub(new_text.len()) };
+    for child in &existing[..existing.len().saturating_sub(keep)] {
+        commands.entity(*child).despawn_recursive();
+    }
+
+    // If more lines came in at once than fit, only the newest are shown.
+    let skip = new_text.len().saturating_sub(max_lines);
+    commands.entity(log_panel).with_children(|spawner| {
+        for text in new_text.into_iter().skip(skip) {
             spawner.spawn_bundle(menu_data.get_ui_text_bundle(text, REGULAR_FONT_SIZE, 300.0, REGULAR_HEIGHT_STEP, Color::BLACK));
         }
     });
@@ -475,20 +1002,8 @@ pub fn process_debug_inserts(
             if !element.selected_state.current {
                 return;
             }
-            text_field.current_value.push_str(&format!(" {} {}", event.0.x, event.0.y));
-
-            if text.sections.len() == 0 {
-                text.sections.push(TextSection {
-                    value: text_field.current_value.clone(),
-                    style: TextStyle {
-                        font: Default::default(),
-                        font_size: 14.0,
-                        color: Color::BLACK,
-                    },
-                });
-            } else {
-                text.sections[0].value = text_field.current_value.clone();
-            }
+            text_field.insert_str(&format!(" {} {}", event.0.x, event.0.y));
+            text_field.update_text(&mut text, true);
         });
     }
 }
@@ -535,39 +1050,252 @@ pub fn display_debug_options(
             }
         });
 
-        let pos = collapse_state.dual_tiling.get_tile_at_index(tile.index).position;
+        let pos = collapse_state.get_entry_translation(*tile);
 
         for (y, handle) in available_profiles.into_iter().enumerate() {
             if !displays.contains_key(&handle) {
-                let sides = geom_data.profiles[handle.index].sides;
-                
-                let base_transform = handle.orientation.get_transform(sides);
-                let mut entities = Vec::new();
-                let offset = Vec3::new(pos.x as f32, 1.5 + y as f32, pos.y as f32);
-                entities.push(commands
-                    .spawn_bundle(MaterialMeshBundle {
-                        mesh: (&geom_data.mesh_handles[handle.index]).as_ref().map(|x| x.clone()).unwrap_or_default(),
-                        material: geom_data.base_material.clone(),
-                        transform: base_transform.with_translation(offset),
-                        visibility: Visibility { is_visible: is_debugging },
-                        ..Default::default()
-                    })
-                    .insert(DebugGeomDisplay).id());
-                for side in 0..sides {
-                    let angle = std::f32::consts::FRAC_PI_2 - std::f32::consts::TAU * side as f32 / sides as f32;
-                    let transform = Transform::from_rotation(Quat::from_rotation_y(-std::f32::consts::TAU * (0.5 - side as f32 / sides as f32)))
-                        .with_translation(offset + 0.5 * Vec3::new(angle.cos(), 0.0, angle.sin()));
-                    let index = geom_data.get_wall(&geom_data.profiles[handle.index], side, &handle.orientation);
-                    entities.push(commands.spawn_bundle(MaterialMeshBundle {
-                        mesh: geom_data.profile_2d_meshes[index.index()].clone(),
-                        material: geom_data.side_materials[index.index()].clone(),
-                        visibility: Visibility { is_visible: is_debugging },
-                        transform,
-                        ..Default::default()
-                    }).insert(DebugGeomDisplay).id());
-                }
+                let offset = pos + Vec3::new(0.0, 1.5 + y as f32, 0.0);
+                let entities = debug_display_instances(&geom_data, handle, offset, is_debugging)
+                    .into_iter()
+                    .map(|bundle| commands.spawn_bundle(bundle).insert(DebugGeomDisplay).id())
+                    .collect();
                 displays.insert(handle, entities);
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// The instances previewing a geometry handle at `offset`: its mesh, with the profile of each of
+/// its walls standing around it. Previews of the same mesh or wall share a mesh and material so
+/// the instanced renderer draws all of them at once.
+fn debug_display_instances(
+    geom_data: &GeometryStorage,
+    handle: GeometryHandle,
+    offset: Vec3,
+    is_visible: bool,
+) -> Vec<InstancedPbrBundle<InstancedStandardMaterial>> {
+    let sides = geom_data.profiles[handle.index].sides;
+    let base_transform = handle.orientation.get_transform(sides);
+    let mut instances = vec![InstancedPbrBundle {
+        mesh: MeshInstance {
+            mesh: geom_data.mesh_handles[handle.index].clone().unwrap_or_default(),
+            lods: Vec::new(),
+        },
+        material: geom_data.material(handle).unwrap_or(&geom_data.base_material).clone(),
+        transform: base_transform.with_translation(offset),
+        visibility: Visibility { is_visible },
+        ..Default::default()
+    }];
+    for side in 0..sides {
+        let angle = std::f32::consts::FRAC_PI_2 - std::f32::consts::TAU * side as f32 / sides as f32;
+        let transform = Transform::from_rotation(Quat::from_rotation_y(-std::f32::consts::TAU * (0.5 - side as f32 / sides as f32)))
+            .with_translation(offset + 0.5 * Vec3::new(angle.cos(), 0.0, angle.sin()));
+        let index = geom_data.get_wall(&geom_data.profiles[handle.index], side, &handle.orientation);
+        instances.push(InstancedPbrBundle {
+            mesh: MeshInstance {
+                mesh: geom_data.profile_2d_meshes[index.index()].clone(),
+                lods: Vec::new(),
+            },
+            material: geom_data.side_material.clone(),
+            color: InstanceColor(geom_data.side_colors[index.index()]),
+            visibility: Visibility { is_visible },
+            transform,
+            ..Default::default()
+        });
+    }
+    instances
+}
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        asset::HandleId,
+        ecs::system::CommandQueue,
+        hierarchy::Children,
+        math::{IVec2, Vec3},
+        prelude::{Color, Commands, Entity, Handle, Mesh, World},
+        text::Text,
+        utils::HashSet,
+    };
+
+    use crate::{
+        menus::MenuState,
+        visuals::collapse::CollapseEntryIndex,
+        visuals::geom::{
+            build_profiles::{MeshProfile, ProfileDefinition, WallProfileDefinition},
+            GeomOrientation, GeometryHandle, GeometryStorage, LayerProfileIndex, VerticalProfile,
+            WallProfileIndex,
+        },
+    };
+
+    use super::{
+        debug_display_instances, dump_collapse_grid, parse_command, write_to_log, DebugCommand,
+        DUMP_MAX_COLUMNS, DUMP_MAX_LINES,
+    };
+
+    fn log_lines(world: &World, log_panel: Entity) -> Vec<String> {
+        world
+            .get::<Children>(log_panel)
+            .map(|children| {
+                children
+                    .iter()
+                    .map(|child| world.get::<Text>(*child).unwrap().sections[0].value.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn write(world: &mut World, log_panel: Entity, lines: &[&str], clear: bool, max_lines: usize) {
+        let existing = world
+            .get::<Children>(log_panel)
+            .map(|children| children.to_vec())
+            .unwrap_or_default();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, world);
+        write_to_log(
+            &mut commands,
+            log_panel,
+            &existing,
+            lines.iter().map(|line| line.to_string()).collect(),
+            clear,
+            max_lines,
+            &MenuState::default(),
+        );
+        queue.apply(world);
+    }
+
+    #[test]
+    fn log_keeps_configured_number_of_lines() {
+        let mut world = World::new();
+        let log_panel = world.spawn().id();
+        write(&mut world, log_panel, &["a", "b", "c"], false, 4);
+        write(&mut world, log_panel, &["d", "e"], false, 4);
+        assert_eq!(log_lines(&world, log_panel), vec!["b", "c", "d", "e"]);
+
+        // More lines than fit only keeps the newest.
+        write(&mut world, log_panel, &["f", "g", "h", "i", "j"], false, 4);
+        assert_eq!(log_lines(&world, log_panel), vec!["g", "h", "i", "j"]);
+    }
+
+    #[test]
+    fn debug_displays_group_by_mesh() {
+        let mesh = || Handle::<Mesh>::weak(HandleId::random::<Mesh>());
+        let mut geom_data = GeometryStorage::new();
+        for index in 0..2 {
+            geom_data.wall_profiles.push(WallProfileDefinition {
+                definition: ProfileDefinition {
+                    verticies: Vec::new(),
+                    edges: Vec::new(),
+                },
+                reverse_profile: WallProfileIndex::new(index),
+            });
+            geom_data.profile_2d_meshes.push(mesh());
+            geom_data.side_colors.push(Color::hsl(180.0 * index as f32, 1.0, 0.5));
+        }
+        let orientations = (0..4)
+            .map(|rotations| GeomOrientation::Standard { rotations })
+            .collect::<Vec<_>>();
+        let vertical = VerticalProfile::parse_from("ffff".to_string()).unwrap();
+        geom_data.store(
+            MeshProfile {
+                sides: 4,
+                walls: vec![
+                    WallProfileIndex::new(0),
+                    WallProfileIndex::new(0),
+                    WallProfileIndex::new(1),
+                    WallProfileIndex::new(1),
+                ],
+                top: LayerProfileIndex::new(0),
+                bottom: LayerProfileIndex::new(0),
+                orientations: orientations.clone(),
+                weight: 1.0,
+            },
+            &vertical,
+            &vertical,
+            Some(mesh()),
+        );
+
+        // Every orientation previewed on a few tiles.
+        let geom_data = &geom_data;
+        let instances = orientations
+            .iter()
+            .flat_map(|orientation| {
+                let handle = GeometryHandle {
+                    index: 0,
+                    orientation: *orientation,
+                };
+                (0..3).flat_map(move |tile| {
+                    debug_display_instances(geom_data, handle, Vec3::X * tile as f32, false)
+                })
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(instances.len(), 4 * 3 * 5);
+        assert!(instances.iter().all(|instance| !instance.visibility.is_visible));
+
+        // One group for the mesh itself and one for each wall profile.
+        let groups = instances
+            .iter()
+            .map(|instance| (instance.mesh.mesh.clone(), instance.material.clone()))
+            .collect::<HashSet<_>>();
+        assert_eq!(groups.len(), 3);
+
+        // The walls share the side material and are told apart by the color they are tinted with.
+        let mut walls_seen = HashSet::new();
+        for instance in &instances {
+            let wall = geom_data
+                .profile_2d_meshes
+                .iter()
+                .position(|mesh| *mesh == instance.mesh.mesh);
+            if let Some(wall) = wall {
+                assert_eq!(instance.material, geom_data.side_material);
+                assert_eq!(instance.color.0, geom_data.side_colors[wall]);
+                walls_seen.insert(wall);
+            }
+        }
+        assert_eq!(walls_seen.len(), 2);
+        assert_ne!(geom_data.side_colors[0], geom_data.side_colors[1]);
+    }
+
+    #[test]
+    fn dump_lays_out_each_height_as_a_grid() {
+        assert!(matches!(parse_command("dump".to_string()), Ok(DebugCommand::DumpCollapse)));
+
+        let entries = [
+            (CollapseEntryIndex::new(IVec2::new(0, 0), 0), 1, true),
+            (CollapseEntryIndex::new(IVec2::new(1, 0), 0), 3, false),
+            (CollapseEntryIndex::new(IVec2::new(2, 1), 0), 12, false),
+            (CollapseEntryIndex::new(IVec2::new(1, 1), 3), 0, true),
+        ];
+        assert_eq!(
+            dump_collapse_grid(&entries),
+            vec![
+                "Height 0: 1/3 have a mesh, [0, 0] to [2, 1]",
+                "  #3 ",
+                "    +",
+                "Height 3: 1/1 have a mesh, [1, 1] to [1, 1]",
+                "  #",
+            ]
+        );
+
+        // Large grids are cut off.
+        let entries = (0..DUMP_MAX_COLUMNS as i32 * 2)
+            .flat_map(|x| (0..DUMP_MAX_LINES as i32 * 2).map(move |y| IVec2::new(x, y)))
+            .map(|index| (CollapseEntryIndex::new(index, 0), 2, false))
+            .collect::<Vec<_>>();
+        let lines = dump_collapse_grid(&entries);
+        assert_eq!(lines.len(), DUMP_MAX_LINES + 2);
+        assert_eq!(lines[1], format!("  {}...", "2".repeat(DUMP_MAX_COLUMNS)));
+        assert_eq!(lines.last().unwrap(), "  ...");
+    }
+
+    #[test]
+    fn clear_empties_log() {
+        assert!(matches!(parse_command("clear".to_string()), Ok(DebugCommand::ClearLog)));
+
+        let mut world = World::new();
+        let log_panel = world.spawn().id();
+        write(&mut world, log_panel, &["a", "b", "c"], false, 100);
+        write(&mut world, log_panel, &[], true, 100);
+        assert!(log_lines(&world, log_panel).is_empty());
+        assert_eq!(world.query::<&Text>().iter(&world).count(), 0);
+    }
+}
@@ -0,0 +1,346 @@
+use std::fmt::Display;
+
+use bevy::math::IVec2;
+
+use crate::simulation::{SetError, SimulationState};
+
+/// Built-in patterns drawn row by row from the top, where `#` marks a live (state 1)
+/// cell. The bottom left of the drawing is placed at the requested origin.
+const PATTERNS: &[(&str, &[&str])] = &[
+    ("Glider", &[".#.", "..#", "###"]),
+    ("Blinker", &["###"]),
+    ("Toad", &[".###", "###."]),
+    ("Beacon", &["##..", "##..", "..##", "..##"]),
+    ("LWSS", &[".#..#", "#....", "#...#", "####."]),
+    (
+        "Pulsar",
+        &[
+            "..###...###..",
+            ".............",
+            "#....#.#....#",
+            "#....#.#....#",
+            "#....#.#....#",
+            "..###...###..",
+            ".............",
+            "..###...###..",
+            "#....#.#....#",
+            "#....#.#....#",
+            "#....#.#....#",
+            ".............",
+            "..###...###..",
+        ],
+    ),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownPattern(pub String);
+
+impl Display for UnknownPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown pattern {}", self.0)
+    }
+}
+
+/// Why an RLE pattern could not be read.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RleError {
+    /// A character that is not a run count, a cell or a line end turned up in the pattern.
+    UnexpectedCharacter(char),
+    /// The pattern ended without the closing `!`.
+    MissingEnd,
+    /// A run count has too many digits to be read.
+    RunOverflow,
+    /// A run went past the width or height of the pattern, given here.
+    OutsideDeclaredSize(IVec2),
+    /// A cell of the pattern could not be placed on the board.
+    InvalidCell(SetError),
+}
+
+impl Display for RleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RleError::UnexpectedCharacter(c) => write!(f, "Unexpected character {:?} in RLE pattern", c),
+            RleError::MissingEnd => write!(f, "RLE pattern is missing its closing '!'"),
+            RleError::RunOverflow => write!(f, "Run count in RLE pattern is too large"),
+            RleError::OutsideDeclaredSize(size) => {
+                write!(f, "RLE pattern runs past its size of {}x{}", size.x, size.y)
+            }
+            RleError::InvalidCell(err) => write!(f, "Could not place RLE pattern: {}", err),
+        }
+    }
+}
+
+/// Names of all the patterns that can be passed to `place_named`.
+pub fn pattern_names() -> impl Iterator<Item = &'static str> {
+    PATTERNS.iter().map(|(name, _)| *name)
+}
+
+/// Get the live cells of the named pattern relative to its bottom left corner.
+fn pattern_cells(name: &str) -> Result<Vec<IVec2>, UnknownPattern> {
+    let (_, rows) = PATTERNS
+        .iter()
+        .find(|(pattern_name, _)| pattern_name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| UnknownPattern(name.to_string()))?;
+    let height = rows.len() as i32;
+    let mut cells = Vec::new();
+    for (row, line) in rows.iter().enumerate() {
+        for (column, cell) in line.chars().enumerate() {
+            if cell == '#' {
+                cells.push(IVec2::new(column as i32, height - 1 - row as i32));
+            }
+        }
+    }
+    Ok(cells)
+}
+
+// The largest width and height an RLE pattern is read with, used for patterns that do not
+// declare their size. Keeps a huge run count from queueing cells without bound.
+const MAX_RLE_SIZE: i32 = 4096;
+
+// The width and height given by the `x = 3, y = 3, rule = B3/S23` header of an RLE pattern,
+// capped at `MAX_RLE_SIZE`. Sizes that are missing or can not be read are left at the cap.
+fn rle_declared_size(text: &str) -> IVec2 {
+    let mut size = IVec2::splat(MAX_RLE_SIZE);
+    let header = text.lines().map(str::trim).find(|line| line.starts_with('x'));
+    for field in header.into_iter().flat_map(|header| header.split(',')) {
+        let mut parts = field.splitn(2, '=').map(str::trim);
+        let key = parts.next();
+        let value = parts.next().and_then(|value| value.parse::<i32>().ok());
+        match (key, value) {
+            (Some("x"), Some(width)) if width >= 0 => size.x = width.min(MAX_RLE_SIZE),
+            (Some("y"), Some(height)) if height >= 0 => size.y = height.min(MAX_RLE_SIZE),
+            _ => {}
+        }
+    }
+    size
+}
+
+/// Read the cells of a pattern in the run length encoded format used by Golly and LifeWiki,
+/// relative to its bottom left corner. `b` and `.` are empty cells, `o` is state 1 and the
+/// multi-state letters `A` to `X` are states 1 to 24. Comment lines are skipped, as is the rule
+/// in the `x = ..` header. Runs may not go past the size the header declares.
+pub fn parse_rle(text: &str) -> Result<Vec<(IVec2, u32)>, RleError> {
+    let size = rle_declared_size(text);
+    let mut cells = Vec::new();
+    let mut position = IVec2::ZERO;
+    let mut run: Option<i32> = None;
+    let mut ended = false;
+    let body = text.lines().map(str::trim).filter(|line| {
+        !line.starts_with('#') && !line.starts_with('x') && !line.is_empty()
+    });
+    'lines: for line in body {
+        for c in line.chars() {
+            let count = run.unwrap_or(1);
+            let state = match c {
+                '0'..='9' => {
+                    let digit = c.to_digit(10).unwrap() as i32;
+                    let longer = run
+                        .unwrap_or(0)
+                        .checked_mul(10)
+                        .and_then(|run| run.checked_add(digit));
+                    run = Some(longer.ok_or(RleError::RunOverflow)?);
+                    continue;
+                }
+                c if c.is_whitespace() => continue,
+                'b' | '.' => 0,
+                'o' => 1,
+                'A'..='X' => c as u32 - 'A' as u32 + 1,
+                '$' => {
+                    if count > size.y - position.y {
+                        return Err(RleError::OutsideDeclaredSize(size));
+                    }
+                    position = IVec2::new(0, position.y + count);
+                    run = None;
+                    continue;
+                }
+                '!' => {
+                    ended = true;
+                    break 'lines;
+                }
+                _ => return Err(RleError::UnexpectedCharacter(c)),
+            };
+            if count > size.x - position.x || position.y >= size.y {
+                return Err(RleError::OutsideDeclaredSize(size));
+            }
+            if state != 0 {
+                for x in position.x..position.x + count {
+                    cells.push((IVec2::new(x, position.y), state));
+                }
+            }
+            position.x += count;
+            run = None;
+        }
+    }
+    if !ended {
+        return Err(RleError::MissingEnd);
+    }
+
+    // Rows are listed from the top, so flip them to put the bottom left corner at the origin.
+    let height = cells.iter().map(|(cell, _)| cell.y).max().map_or(0, |y| y + 1);
+    Ok(cells
+        .into_iter()
+        .map(|(cell, state)| (IVec2::new(cell.x, height - 1 - cell.y), state))
+        .collect())
+}
+
+/// Set the cells of an RLE pattern with its bottom left corner at `origin`. Like `place_named`
+/// the cells are queued as manual sets and the (wrapped) indices set are returned. Nothing is
+/// queued if any cell is in a state its tile does not have, or off the edge of a board that does
+/// not wrap.
+pub fn place_rle(
+    sim: &mut SimulationState,
+    text: &str,
+    origin: IVec2,
+) -> Result<Vec<(IVec2, u32)>, RleError> {
+    let cells = parse_rle(text)?;
+    let changes = cells
+        .into_iter()
+        .map(|(cell, state)| Ok((sim.check_set(origin + cell, state)?, state)))
+        .collect::<Result<Vec<_>, SetError>>()
+        .map_err(RleError::InvalidCell)?;
+    for (index, state) in &changes {
+        sim.set_at(*index, *state);
+    }
+    Ok(changes)
+}
+
+/// Set the cells of the named pattern to state 1 with its bottom left corner at `origin`.
+/// The cells are queued as manual sets and the (wrapped) indices that were set are returned.
+pub fn place_named(
+    sim: &mut SimulationState,
+    name: &str,
+    origin: IVec2,
+) -> Result<Vec<(IVec2, u32)>, UnknownPattern> {
+    let cells = pattern_cells(name)?;
+    let mut changes = Vec::with_capacity(cells.len());
+    for cell in cells {
+        let index = sim.tiling.adjust_index(origin + cell);
+        sim.set_at(index, 1);
+        changes.push((index, 1));
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{IVec2, Vec2};
+
+    use crate::{
+        simulation::{SetError, SimulationState},
+        tiling::{Tiling, TilingKind},
+    };
+
+    use super::{
+        parse_rle, pattern_cells, place_named, place_rle, RleError, UnknownPattern, MAX_RLE_SIZE,
+    };
+
+    fn square_simulation(size: i32) -> SimulationState {
+        SimulationState::new(Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(size, size),
+            offset: Vec2::ZERO,
+        })
+    }
+
+    fn live_cells(sim: &SimulationState, size: i32) -> Vec<IVec2> {
+        let mut cells = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                if sim.get_at(IVec2::new(x, y)) == 1 {
+                    cells.push(IVec2::new(x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn glider_places_five_cells() {
+        let mut sim = square_simulation(10);
+        let changes = place_named(&mut sim, "glider", IVec2::new(4, 4)).unwrap();
+        sim.process();
+
+        assert_eq!(changes.len(), 5);
+        assert!(changes.iter().all(|(_, state)| *state == 1));
+        let mut expected = vec![
+            IVec2::new(5, 6),
+            IVec2::new(6, 5),
+            IVec2::new(4, 4),
+            IVec2::new(5, 4),
+            IVec2::new(6, 4),
+        ];
+        let mut placed = live_cells(&sim, 10);
+        expected.sort_unstable_by_key(|index| (index.y, index.x));
+        placed.sort_unstable_by_key(|index| (index.y, index.x));
+        assert_eq!(placed, expected);
+    }
+
+    #[test]
+    fn patterns_wrap_around_the_board() {
+        let mut sim = square_simulation(10);
+        let changes = place_named(&mut sim, "Blinker", IVec2::new(8, 0)).unwrap();
+        sim.process();
+
+        assert!(changes.contains(&(IVec2::new(0, 0), 1)));
+        assert_eq!(live_cells(&sim, 10).len(), 3);
+    }
+
+    #[test]
+    fn rle_matches_the_built_in_patterns() {
+        let rle = "#N Glider\nx = 3, y = 3, rule = B3/S23\nbo$2bo$3o!";
+        let mut cells = parse_rle(rle).unwrap();
+        assert!(cells.iter().all(|(_, state)| *state == 1));
+        let mut expected = pattern_cells("Glider").unwrap();
+        cells.sort_unstable_by_key(|(index, _)| (index.y, index.x));
+        expected.sort_unstable_by_key(|index| (index.y, index.x));
+        assert_eq!(cells.into_iter().map(|(index, _)| index).collect::<Vec<_>>(), expected);
+
+        // Runs of rows and multi-state cells, split across lines.
+        assert_eq!(
+            parse_rle("2A$\n2$.B!").unwrap(),
+            vec![
+                (IVec2::new(0, 3), 1),
+                (IVec2::new(1, 3), 1),
+                (IVec2::new(1, 0), 2)
+            ]
+        );
+        assert_eq!(parse_rle("3o$"), Err(RleError::MissingEnd));
+        let mut sim = square_simulation(10);
+        assert!(matches!(
+            place_rle(&mut sim, "oB!", IVec2::ZERO),
+            Err(RleError::InvalidCell(SetError::NoSuchState { state: 2, .. }))
+        ));
+        // The valid cell before the bad one is not left queued.
+        sim.process();
+        assert!(live_cells(&sim, 10).is_empty());
+        assert_eq!(parse_rle("3z!"), Err(RleError::UnexpectedCharacter('z')));
+    }
+
+    #[test]
+    fn rle_runs_are_bounded() {
+        assert_eq!(parse_rle("99999999999o!"), Err(RleError::RunOverflow));
+        assert_eq!(
+            parse_rle(&format!("{}o!", MAX_RLE_SIZE + 1)),
+            Err(RleError::OutsideDeclaredSize(IVec2::splat(MAX_RLE_SIZE)))
+        );
+        // Runs of cells and of rows stop at the size in the header.
+        let size = IVec2::new(3, 2);
+        assert_eq!(parse_rle("x = 3, y = 2\n4o!"), Err(RleError::OutsideDeclaredSize(size)));
+        assert_eq!(parse_rle("x = 3, y = 2\nbo$2$o!"), Err(RleError::OutsideDeclaredSize(size)));
+        assert_eq!(parse_rle("x = 3, y = 2\n3o$o$!").map(|cells| cells.len()), Ok(4));
+        // Declared sizes are capped too.
+        assert_eq!(
+            parse_rle("x = 100000, y = 1\n5000o!"),
+            Err(RleError::OutsideDeclaredSize(IVec2::new(MAX_RLE_SIZE, 1)))
+        );
+    }
+
+    #[test]
+    fn unknown_pattern_is_rejected() {
+        let mut sim = square_simulation(10);
+        assert_eq!(
+            place_named(&mut sim, "spaceship", IVec2::ZERO),
+            Err(UnknownPattern("spaceship".to_string()))
+        );
+    }
+}
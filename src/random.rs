@@ -0,0 +1,59 @@
+/// Small seedable pseudo random number generator (SplitMix64). We only need
+/// reproducible streams of numbers, not anything cryptographically secure.
+#[derive(Clone, Debug)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Returns a value in [0, bound). `bound` must be positive.
+    pub fn next_below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    /// Pick one of the items with probability proportional to its weight. If no item has a
+    /// positive weight they are all treated as equally likely.
+    pub fn choose_weighted<T>(&mut self, items: impl IntoIterator<Item = (T, f32)>) -> Option<T> {
+        let mut items = items.into_iter().collect::<Vec<_>>();
+        if items.is_empty() {
+            return None;
+        }
+        let total: f32 = items.iter().map(|(_, weight)| weight.max(0.0)).sum();
+        if total <= 0.0 {
+            let index = self.next_below(items.len());
+            return Some(items.swap_remove(index).0);
+        }
+        let mut draw = self.next_f32() * total;
+        // Rounding can leave us just past the end, so fall back to the last item we could pick.
+        let mut chosen = items
+            .iter()
+            .rposition(|(_, weight)| *weight > 0.0)
+            .unwrap_or(items.len() - 1);
+        for (index, (_, weight)) in items.iter().enumerate() {
+            let weight = weight.max(0.0);
+            if draw < weight {
+                chosen = index;
+                break;
+            }
+            draw -= weight;
+        }
+        Some(items.swap_remove(chosen).0)
+    }
+}
@@ -1,27 +1,106 @@
-use bevy::{math::IVec2, prelude::Component, utils::HashMap};
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
-use crate::tiling::{EquilateralDirection, RightTriangleRotation, TileShape, Tiling, TilingKind};
+use bevy::{
+    math::IVec2,
+    prelude::Component,
+    utils::{HashMap, HashSet},
+};
 
-#[derive(Component)]
+use crate::{
+    random::SeededRng,
+    tiling::{EquilateralDirection, RightTriangleRotation, TileShape, Tiling, TilingKind},
+};
+
+const DEFAULT_SEED: u64 = 0x5EED;
+
+/// How many frames pass between generations when play is first started.
+pub const DEFAULT_RUN_EVERY: u32 = 5;
+
+/// How many generations can be stepped back through by default.
+pub const DEFAULT_HISTORY_DEPTH: usize = 64;
+
+// Hands out edit epochs. It is shared by every simulation so a board that replaces another one
+// never starts out with the epoch of the board it replaced.
+static NEXT_EDIT_EPOCH: AtomicU64 = AtomicU64::new(1);
+
+fn next_edit_epoch() -> u64 {
+    NEXT_EDIT_EPOCH.fetch_add(1, Ordering::Relaxed)
+}
+
+// How long one of the frames counted by `run_every` lasts.
+const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+#[derive(Component, Clone)]
 pub struct SimulationState {
     pub tiling: Tiling,
+    // How many frames pass between generations while running, or 0 while paused. Set it through
+    // `set_run_every` so resuming goes back to the same speed.
     pub run_every: u32,
+    // What `run_every` goes back to when play is resumed.
+    resume_every: u32,
     pub step: u32,
-    time_since_last_update: u32,
+    time_until_update: Duration,
     pub num_states: usize,
+    boundary: BoundaryMode,
     states: HashMap<TileShape, Vec<StateRules>>,
     index_to_state: HashMap<IVec2, SimulationCellState>,
     manual_sets: HashMap<IVec2, u32>,
-    pending_sets: HashMap<IVec2, u32>,
+    // Sets for the next generation along with the chance that they get applied.
+    pending_sets: HashMap<IVec2, (u32, f32)>,
+    rng: SeededRng,
+    // How many generations have run since the board was made or emptied.
+    generation: u64,
+    // What each recent generation changed, newest last. Holds at most `history_depth` entries.
+    history: VecDeque<HistoryEntry>,
+    pub history_depth: usize,
+    // Changes whenever the board or the rules are edited, but not when generations run or the
+    // speed changes.
+    edit_epoch: u64,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+// The cells a generation changed along with the state they were in before it. Generations run
+// elsewhere and taken in together by `apply_generations` share one entry.
+#[derive(Clone)]
+struct HistoryEntry {
+    generations: u32,
+    previous: Vec<(IVec2, u32)>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum RuleUpdateTarget {
     DefaultValue,
     MinValue,
     MaxValue,
     ToggleCount,
+    ToggleIncludeSelf,
     ResultValue,
+    Probability,
+    Priority,
+    EvaluationMode,
+    // Toggles counting the neighbor in the direction given by the value. Rules counting every
+    // direction start counting only the toggled one, and rules left with no directions go back
+    // to counting every direction.
+    ToggleDirection,
+    AddConstraint,
+    RemoveConstraint(usize),
+    // Toggles counting the state given by the value in one of the rule's extra counts.
+    ToggleConstraintCount(usize),
+    ConstraintMin(usize),
+    ConstraintMax(usize),
+}
+
+/// A further count of neighbors in some states that has to fall within `[min, max]` for the
+/// rule holding it to fire.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CountConstraint {
+    pub states: Vec<u32>,
+    pub min: u32,
+    pub max: u32,
 }
 
 #[derive(Clone)]
@@ -29,18 +108,282 @@ pub struct StateRule {
     pub min: u32,
     pub max: u32,
     pub neighbor_states_to_count: Vec<u32>,
+    // Whether the cell itself is counted alongside its neighbors if it is in
+    // one of the counted states. This allows for totalistic rules.
+    pub include_self: bool,
+    // When set, only the neighbors in the directions whose bits are set are counted. Directions
+    // index into `Tiling::get_neighbors` for the counting cell.
+    pub direction_mask: Option<u32>,
+    // Further counts that must all be in range, along with the one above, for the rule to fire.
+    // They are counted the same way as the rule's own states.
+    pub extra_counts: Vec<CountConstraint>,
     pub output: u32,
+    // Chance in [0, 1] that the output is applied when this rule matches. If it isn't
+    // applied the cell keeps its state and the rule is tried again next generation.
+    pub probability: f32,
+    // When the state uses priority evaluation, the matching rule with the highest priority wins.
+    pub priority: u32,
+}
+
+impl StateRule {
+    /// The highest count the rule can see on a tile with `neighbors` neighbors, which is one
+    /// more when the cell counts itself.
+    pub fn max_count(&self, neighbors: u32) -> u32 {
+        let counted = match self.direction_mask {
+            Some(mask) => mask.count_ones().min(neighbors),
+            None => neighbors,
+        };
+        counted + self.include_self as u32
+    }
+
+    // Whether the rule counts neighbors in `state`, in its own count or any of its extra ones.
+    fn counts_state(&self, state: u32) -> bool {
+        self.neighbor_states_to_count.contains(&state)
+            || self.extra_counts.iter().any(|extra| extra.states.contains(&state))
+    }
+
+    // Keep every range within what can be counted after how the rule counts has changed.
+    fn clamp_counts(&mut self, neighbors: u32) {
+        let max_count = self.max_count(neighbors);
+        self.min = self.min.min(max_count);
+        self.max = self.max.min(max_count);
+        for extra in self.extra_counts.iter_mut() {
+            extra.min = extra.min.min(max_count);
+            extra.max = extra.max.min(max_count);
+        }
+    }
+}
+
+/// What lies past the edges of the board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoundaryMode {
+    /// The board wraps around, so cells on opposite edges are neighbors.
+    Toroidal,
+    /// Neighbors past the edges do not exist and always count as state 0.
+    Dead,
+}
+
+impl BoundaryMode {
+    // The index of the neighbor at the offset from the index, or None if it is off the board and
+    // the boundary is dead. Cells start out counting every neighbor as state 0, so neighbors we
+    // never tell about changes stay dead.
+    fn neighbor_index(self, tiling: &Tiling, index: IVec2, offset: &(i32, i32)) -> Option<IVec2> {
+        let neighbor = index + IVec2::from(*offset);
+        match self {
+            BoundaryMode::Toroidal => Some(tiling.adjust_index(neighbor)),
+            BoundaryMode::Dead => tiling.in_bounds(neighbor).then_some(neighbor),
+        }
+    }
+}
+
+/// How the rules of a state decide which matching rule is applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleEvaluation {
+    /// The first matching rule in the list wins.
+    FirstMatch,
+    /// The matching rule with the highest priority wins, ties going to the earlier rule.
+    Priority,
+}
+
+impl RuleEvaluation {
+    pub fn from_value(value: u32) -> Self {
+        match value {
+            0 => RuleEvaluation::FirstMatch,
+            _ => RuleEvaluation::Priority,
+        }
+    }
+
+    pub fn value(self) -> u32 {
+        match self {
+            RuleEvaluation::FirstMatch => 0,
+            RuleEvaluation::Priority => 1,
+        }
+    }
+}
+
+/// Why a cell could not be set by `try_set_at`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SetError {
+    /// The index is off the board and the boundary is dead, so there is nothing to wrap it to.
+    OutOfBounds(IVec2),
+    /// The shape of the tile at the index has no such state.
+    NoSuchState { index: IVec2, state: u32, num_states: u32 },
+}
+
+impl Display for SetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SetError::OutOfBounds(index) => write!(f, "{} is off the edge of the board", index),
+            SetError::NoSuchState {
+                index,
+                state,
+                num_states,
+            } => write!(
+                f,
+                "The tile at {} has {} states so it can not be set to state {}",
+                index, num_states, state
+            ),
+        }
+    }
+}
+
+/// What removing a state changed.
+pub struct RemovedState {
+    pub changed: Vec<(IVec2, u32)>,
+    // Anything `validate_and_clamp_rules` still had to fix after the rules were remapped.
+    pub clamped: Vec<ClampedReference>,
+}
+
+/// Why a state could not be removed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RemoveStateError {
+    /// State 0 is what every cell starts in, so it always exists.
+    StateZero,
+    NoSuchState(u32),
+    /// A rule of another state counts neighbors in the state.
+    StillCounted {
+        shape: TileShape,
+        state: u32,
+        rule: usize,
+    },
+}
+
+impl Display for RemoveStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoveStateError::StateZero => write!(f, "State 0 can not be removed"),
+            RemoveStateError::NoSuchState(state) => write!(f, "There is no state {}", state),
+            RemoveStateError::StillCounted { shape, state, rule } => write!(
+                f,
+                "Rule {} of {:?} state {} still counts neighbors in it",
+                rule, shape, state
+            ),
+        }
+    }
+}
+
+/// A rule's reference to a state that no longer exists, along with the state it now points at.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ClampedReference {
+    pub shape: TileShape,
+    pub state: u32,
+    // The rule holding the reference or None for the state's default.
+    pub rule: Option<usize>,
+    pub target: RuleUpdateTarget,
+    pub from: u32,
+    pub to: u32,
 }
 
 #[derive(Clone)]
 pub struct StateRules {
     pub default_state: u32,
+    pub evaluation: RuleEvaluation,
     pub rules: Vec<StateRule>,
 }
 
+impl StateRules {
+    // Whether the rule at `index` wins over the rule at `other` when both match.
+    fn takes_precedence(&self, index: usize, other: usize) -> bool {
+        match self.evaluation {
+            RuleEvaluation::FirstMatch => index < other,
+            RuleEvaluation::Priority => {
+                let (priority, other_priority) =
+                    (self.rules[index].priority, self.rules[other].priority);
+                priority > other_priority || (priority == other_priority && index < other)
+            }
+        }
+    }
+
+    // A rule whose whole [min, max] range is already covered by rules counting the same
+    // states that take precedence over it can never fire.
+    pub fn find_unreachable_rules(&self) -> Vec<usize> {
+        let mut unreachable = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let mut counted = rule.neighbor_states_to_count.clone();
+            counted.sort_unstable();
+            counted.dedup();
+
+            let mut covering: Vec<(u32, u32)> = self
+                .rules
+                .iter()
+                .enumerate()
+                .filter(|(other_index, other)| {
+                    if !self.takes_precedence(*other_index, index) {
+                        return false;
+                    }
+                    let mut other_counted = other.neighbor_states_to_count.clone();
+                    other_counted.sort_unstable();
+                    other_counted.dedup();
+                    // Extra counts keep a rule from firing, so they leave gaps in what it covers.
+                    other.include_self == rule.include_self
+                        && other.extra_counts.is_empty()
+                        && other.direction_mask == rule.direction_mask
+                        && other_counted == counted
+                })
+                .map(|(_, other)| (other.min, other.max))
+                .collect();
+            covering.sort_unstable();
+
+            // Walk the covering ranges in order and see if they reach past our max
+            // without leaving a gap.
+            let mut next_uncovered = rule.min as u64;
+            for (min, max) in covering {
+                if min as u64 > next_uncovered {
+                    break;
+                }
+                next_uncovered = next_uncovered.max(max as u64 + 1);
+            }
+            if next_uncovered > rule.max as u64 {
+                unreachable.push(index);
+            }
+        }
+        unreachable
+    }
+}
+
+// States that no cell can ever move into. Every cell starts in state 0 and can only move to the
+// outputs of rules that can fire or to the default state of its current state.
+pub fn find_unreachable_states(rules: &[StateRules]) -> Vec<u32> {
+    if rules.is_empty() {
+        return Vec::new();
+    }
+    let mut reachable = vec![false; rules.len()];
+    reachable[0] = true;
+    let mut to_visit = vec![0usize];
+    while let Some(state) = to_visit.pop() {
+        let state_rules = &rules[state];
+        let shadowed = state_rules.find_unreachable_rules();
+        let outputs = state_rules
+            .rules
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !shadowed.contains(index))
+            .map(|(_, rule)| rule.output)
+            .chain([state_rules.default_state]);
+        for output in outputs {
+            let output = output as usize;
+            if output < reachable.len() && !reachable[output] {
+                reachable[output] = true;
+                to_visit.push(output);
+            }
+        }
+    }
+    reachable
+        .iter()
+        .enumerate()
+        .filter(|(_, reachable)| !**reachable)
+        .map(|(state, _)| state as u32)
+        .collect()
+}
+
+#[derive(Clone)]
 struct SimulationCellState {
     pub state: u32,
     pub neighbors_in_state: Vec<u32>,
+    // The state of the neighbor in each direction, for rules that only count some directions.
+    pub neighbor_states: Vec<u32>,
+    // The generation the cell last changed state in.
+    pub changed_at: u64,
 }
 
 impl SimulationCellState {
@@ -50,16 +393,22 @@ impl SimulationCellState {
         Self {
             state,
             neighbors_in_state,
+            neighbor_states: vec![0; num_neighbors as usize],
+            changed_at: 0,
         }
     }
 
-    // Apply a change
+    // Apply a change to our neighbor in `direction`, if we know which direction it is in.
+    // `index` is the index of this cell, only used to report neighbor counts falling out of
+    // sync with the board.
     pub fn apply_change(
         &mut self,
+        index: IVec2,
+        direction: Option<usize>,
         replaced_state: u32,
         new_state: u32,
         rules: &Vec<StateRules>,
-    ) -> Option<u32> {
+    ) -> Option<(u32, f32)> {
         if replaced_state as usize >= self.neighbors_in_state.len()
             || new_state as usize >= self.neighbors_in_state.len()
         {
@@ -73,30 +422,89 @@ impl SimulationCellState {
         if self.state as usize >= rules.len() {
             panic!("We do not have a rule registered for this state!");
         }
-        self.neighbors_in_state[replaced_state as usize] -= 1;
+        let replaced_count = &mut self.neighbors_in_state[replaced_state as usize];
+        debug_assert!(
+            *replaced_count > 0,
+            "Cell {} has no neighbors in state {} to replace with {}",
+            index,
+            replaced_state,
+            new_state
+        );
+        match replaced_count.checked_sub(1) {
+            Some(count) => *replaced_count = count,
+            // Keep the count at zero rather than wrapping so this cell can still be evaluated.
+            None => bevy::log::error!(
+                "Cell {} has no neighbors in state {} to replace with {}",
+                index,
+                replaced_state,
+                new_state
+            ),
+        }
         self.neighbors_in_state[new_state as usize] += 1;
+        if let Some(neighbor_state) = direction.and_then(|d| self.neighbor_states.get_mut(d)) {
+            *neighbor_state = new_state;
+        }
 
         self.evaluate(rules)
     }
 
-    fn evaluate(&self, rules: &Vec<StateRules>) -> Option<u32> {
-        let mut final_value = rules[self.state as usize].default_state;
-        for rule in &rules[self.state as usize].rules {
-            let count = rule
-                .neighbor_states_to_count
-                .iter()
-                .fold(0u32, |value, state| {
-                    value + self.neighbors_in_state[*state as usize]
-                });
-            if rule.min <= count && count <= rule.max {
-                final_value = rule.output;
-                break;
+    // Returns the state this cell should move to along with the chance of doing so.
+    fn evaluate(&self, rules: &Vec<StateRules>) -> Option<(u32, f32)> {
+        let state_rules = &rules[self.state as usize];
+        let mut matched: Option<usize> = None;
+        for (index, rule) in state_rules.rules.iter().enumerate() {
+            let count = |states: &[u32]| {
+                let mut count = match rule.direction_mask {
+                    Some(mask) => self
+                        .neighbor_states
+                        .iter()
+                        .enumerate()
+                        .filter(|(direction, state)| {
+                            mask & (1 << direction) != 0 && states.contains(state)
+                        })
+                        .count() as u32,
+                    None => states.iter().fold(0u32, |value, state| {
+                        value + self.neighbors_in_state[*state as usize]
+                    }),
+                };
+                if rule.include_self && states.contains(&self.state) {
+                    count += 1;
+                }
+                count
+            };
+            let in_range = |states: &[u32], min: u32, max: u32| {
+                let count = count(states);
+                min <= count && count <= max
+            };
+            if in_range(&rule.neighbor_states_to_count, rule.min, rule.max)
+                && rule
+                    .extra_counts
+                    .iter()
+                    .all(|extra| in_range(&extra.states, extra.min, extra.max))
+            {
+                let wins = match matched {
+                    Some(best) => state_rules.takes_precedence(index, best),
+                    None => true,
+                };
+                if wins {
+                    matched = Some(index);
+                }
+                if state_rules.evaluation == RuleEvaluation::FirstMatch {
+                    break;
+                }
             }
         }
+        let (final_value, probability) = match matched {
+            Some(index) => (
+                state_rules.rules[index].output,
+                state_rules.rules[index].probability,
+            ),
+            None => (state_rules.default_state, 1.0),
+        };
         if final_value == self.state {
             None
         } else {
-            Some(final_value)
+            Some((final_value, probability))
         }
     }
 }
@@ -110,20 +518,32 @@ fn get_default_rules_for_tiling(kind: TilingKind) -> HashMap<TileShape, Vec<Stat
                 vec![
                     StateRules {
                         default_state: 0,
+                        evaluation: RuleEvaluation::FirstMatch,
                         rules: vec![StateRule {
                             min: 3,
                             max: 3,
                             neighbor_states_to_count: vec![1],
+                            include_self: false,
+                            direction_mask: None,
+                            extra_counts: Vec::new(),
                             output: 1,
+                            probability: 1.0,
+                            priority: 0,
                         }],
                     },
                     StateRules {
                         default_state: 0,
+                        evaluation: RuleEvaluation::FirstMatch,
                         rules: vec![StateRule {
                             min: 2,
                             max: 3,
                             neighbor_states_to_count: vec![1],
+                            include_self: false,
+                            direction_mask: None,
+                            extra_counts: Vec::new(),
                             output: 1,
+                            probability: 1.0,
+                            priority: 0,
                         }],
                     },
                 ],
@@ -135,27 +555,44 @@ fn get_default_rules_for_tiling(kind: TilingKind) -> HashMap<TileShape, Vec<Stat
                 vec![
                     StateRules {
                         default_state: 0,
+                        evaluation: RuleEvaluation::FirstMatch,
                         rules: vec![StateRule {
                             min: 2,
                             max: 2,
                             neighbor_states_to_count: vec![1],
+                            include_self: false,
+                            direction_mask: None,
+                            extra_counts: Vec::new(),
                             output: 1,
+                            probability: 1.0,
+                            priority: 0,
                         }],
                     },
                     StateRules {
                         default_state: 0,
+                        evaluation: RuleEvaluation::FirstMatch,
                         rules: vec![
                             StateRule {
                                 min: 3,
                                 max: 3,
                                 neighbor_states_to_count: vec![1],
+                                include_self: false,
+                                direction_mask: None,
+                                extra_counts: Vec::new(),
                                 output: 1,
+                                probability: 1.0,
+                                priority: 0,
                             },
                             StateRule {
                                 min: 5,
                                 max: 5,
                                 neighbor_states_to_count: vec![1],
+                                include_self: false,
+                                direction_mask: None,
+                                extra_counts: Vec::new(),
                                 output: 1,
+                                probability: 1.0,
+                                priority: 0,
                             },
                         ],
                     },
@@ -168,20 +605,32 @@ fn get_default_rules_for_tiling(kind: TilingKind) -> HashMap<TileShape, Vec<Stat
                 vec![
                     StateRules {
                         default_state: 0,
+                        evaluation: RuleEvaluation::FirstMatch,
                         rules: vec![StateRule {
                             min: 3,
                             max: 3,
                             neighbor_states_to_count: vec![1],
+                            include_self: false,
+                            direction_mask: None,
+                            extra_counts: Vec::new(),
                             output: 1,
+                            probability: 1.0,
+                            priority: 0,
                         }],
                     },
                     StateRules {
                         default_state: 0,
+                        evaluation: RuleEvaluation::FirstMatch,
                         rules: vec![StateRule {
                             min: 3,
                             max: 3,
                             neighbor_states_to_count: vec![1],
+                            include_self: false,
+                            direction_mask: None,
+                            extra_counts: Vec::new(),
                             output: 1,
+                            probability: 1.0,
+                            priority: 0,
                         }],
                     },
                 ],
@@ -191,20 +640,32 @@ fn get_default_rules_for_tiling(kind: TilingKind) -> HashMap<TileShape, Vec<Stat
                 vec![
                     StateRules {
                         default_state: 0,
+                        evaluation: RuleEvaluation::FirstMatch,
                         rules: vec![StateRule {
                             min: 2,
                             max: 3,
                             neighbor_states_to_count: vec![1],
+                            include_self: false,
+                            direction_mask: None,
+                            extra_counts: Vec::new(),
                             output: 1,
+                            probability: 1.0,
+                            priority: 0,
                         }],
                     },
                     StateRules {
                         default_state: 0,
+                        evaluation: RuleEvaluation::FirstMatch,
                         rules: vec![StateRule {
                             min: 1,
                             max: 2,
                             neighbor_states_to_count: vec![1],
+                            include_self: false,
+                            direction_mask: None,
+                            extra_counts: Vec::new(),
                             output: 1,
+                            probability: 1.0,
+                            priority: 0,
                         }],
                     },
                 ],
@@ -217,20 +678,32 @@ fn get_default_rules_for_tiling(kind: TilingKind) -> HashMap<TileShape, Vec<Stat
                     vec![
                         StateRules {
                             default_state: 0,
+                            evaluation: RuleEvaluation::FirstMatch,
                             rules: vec![StateRule {
                                 min: 3,
                                 max: 3,
                                 neighbor_states_to_count: vec![1],
+                                include_self: false,
+                                direction_mask: None,
+                                extra_counts: Vec::new(),
                                 output: 1,
+                                probability: 1.0,
+                                priority: 0,
                             }],
                         },
                         StateRules {
                             default_state: 0,
+                            evaluation: RuleEvaluation::FirstMatch,
                             rules: vec![StateRule {
                                 min: 3,
                                 max: 3,
                                 neighbor_states_to_count: vec![1],
+                                include_self: false,
+                                direction_mask: None,
+                                extra_counts: Vec::new(),
                                 output: 1,
+                                probability: 1.0,
+                                priority: 0,
                             }],
                         },
                     ],
@@ -249,20 +722,32 @@ fn get_default_rules_for_tiling(kind: TilingKind) -> HashMap<TileShape, Vec<Stat
                     vec![
                         StateRules {
                             default_state: 0,
+                            evaluation: RuleEvaluation::FirstMatch,
                             rules: vec![StateRule {
                                 min: 3,
                                 max: 3,
                                 neighbor_states_to_count: vec![1],
+                                include_self: false,
+                                direction_mask: None,
+                                extra_counts: Vec::new(),
                                 output: 1,
+                                probability: 1.0,
+                                priority: 0,
                             }],
                         },
                         StateRules {
                             default_state: 0,
+                            evaluation: RuleEvaluation::FirstMatch,
                             rules: vec![StateRule {
                                 min: 3,
                                 max: 3,
                                 neighbor_states_to_count: vec![1],
+                                include_self: false,
+                                direction_mask: None,
+                                extra_counts: Vec::new(),
                                 output: 1,
+                                probability: 1.0,
+                                priority: 0,
                             }],
                         },
                     ],
@@ -282,112 +767,324 @@ impl SimulationState {
         Self {
             tiling,
             run_every: 0,
+            resume_every: DEFAULT_RUN_EVERY,
             step: 0,
-            time_since_last_update: 0,
+            time_until_update: Duration::ZERO,
             states,
             num_states,
+            boundary: BoundaryMode::Toroidal,
             index_to_state: Default::default(),
             manual_sets: Default::default(),
             pending_sets: Default::default(),
+            rng: SeededRng::new(DEFAULT_SEED),
+            generation: 0,
+            history: VecDeque::new(),
+            history_depth: DEFAULT_HISTORY_DEPTH,
+            edit_epoch: next_edit_epoch(),
         }
     }
 
-    pub fn get_shapes(&self) -> Vec<TileShape> {
-        self.states.keys().cloned().collect()
+    /// A new simulation where each cell has a `density` chance of starting in one of the states
+    /// `1..=max_state`, each half as likely as the state below it. Shapes gain states without
+    /// rules until they have `max_state`. The same seed always gives the same board.
+    pub fn new_with_random(tiling: Tiling, max_state: u32, density: f32, seed: u64) -> Self {
+        let mut sim_state = Self::new(tiling);
+        sim_state.set_seed(seed);
+        for shape in sim_state.get_shapes() {
+            while sim_state.get_num_states_for_shape(shape) <= max_state {
+                sim_state.add_state(shape);
+            }
+        }
+
+        let mut rng = SeededRng::new(seed);
+        for index in sim_state.tiling.iter_indices() {
+            if max_state == 0 || rng.next_f32() >= density {
+                continue;
+            }
+            let state = rng
+                .choose_weighted((1..=max_state).map(|state| (state, 0.5f32.powi(state as i32))))
+                .unwrap_or(1);
+            sim_state.set_at(index, state);
+        }
+        // Set the cells now so the board starts out in them rather than after the first advance.
+        sim_state.apply_manual_sets();
+        sim_state
     }
 
-    pub fn get_num_states_for_shape(&self, shape: TileShape) -> u32 {
-        self.states
-            .get(&shape)
-            .map(|rules| rules.len() as u32)
-            .unwrap_or(0)
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = SeededRng::new(seed);
+        self.mark_edited();
     }
 
-    pub fn clone_rules_for_shape(&self, shape: TileShape) -> Vec<StateRules> {
-        self.states.get(&shape).cloned().unwrap_or_default()
+    /// Identifies the last edit made to the board or the rules. Running generations, changing
+    /// the speed and queueing sets with `set_at` leave it alone, and clones share it.
+    pub fn edit_epoch(&self) -> u64 {
+        self.edit_epoch
     }
 
-    pub fn set_rule_value(
-        &mut self,
-        shape: TileShape,
-        state: u32,
-        rule_number: usize,
-        value: u32,
-        target: RuleUpdateTarget,
-    ) {
-        if let Some(rules) = self.states.get_mut(&shape) {
-            if state as usize >= rules.len() {
-                return;
-            }
+    fn mark_edited(&mut self) {
+        self.edit_epoch = next_edit_epoch();
+    }
 
-            if let Some(rules) = rules.get_mut(state as usize) {
-                if target == RuleUpdateTarget::DefaultValue {
-                    rules.default_state = value;
-                    return;
+    /// Empty the board, dropping any sets waiting for the next generation, while keeping the
+    /// tiling and the rules of every state. Unlike building a new simulation for the tiling this
+    /// does not put the default rules back.
+    pub fn reset_keep_rules(&mut self) {
+        self.index_to_state.clear();
+        self.manual_sets.clear();
+        self.pending_sets.clear();
+        self.history.clear();
+        self.generation = 0;
+        self.step = 0;
+        self.time_until_update = Duration::ZERO;
+        self.mark_edited();
+    }
+
+    /// Run a generation every `run_every` frames, or pause with 0. Play resumes at the last speed
+    /// that was not 0.
+    pub fn set_run_every(&mut self, run_every: u32) {
+        self.run_every = run_every;
+        if run_every != 0 {
+            self.resume_every = run_every;
+        }
+    }
+
+    /// Pause if playing, otherwise resume at the last speed set.
+    pub fn toggle_play(&mut self) {
+        if self.run_every == 0 {
+            self.run_every = self.resume_every;
+        } else {
+            self.run_every = 0;
+        }
+    }
+
+    /// How many generations have run since the board was made or emptied, less the ones stepped
+    /// back through.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Undo the last generation, putting every cell it changed back in its old state right away.
+    /// A batch of generations taken in by `apply_generations` is undone as a whole. Returns the
+    /// cells set, or `None` when there is no history left to step back through.
+    /// Cells set by hand since that generation keep their new state, and the random rolls of
+    /// probabilistic rules are not rewound.
+    pub fn step_back(&mut self) -> Option<Vec<(IVec2, u32)>> {
+        let entry = self.history.pop_back()?;
+        self.generation -= entry.generations as u64;
+        self.manual_sets.extend(entry.previous);
+        self.mark_edited();
+        Some(
+            self.apply_manual_sets()
+                .into_iter()
+                .map(|(index, _, state)| (index, state))
+                .collect(),
+        )
+    }
+
+    pub fn boundary(&self) -> BoundaryMode {
+        self.boundary
+    }
+
+    /// Change what lies past the edges of the board. Every cell is set again so the neighbor
+    /// counts along the edges are rebuilt for the new mode.
+    pub fn set_boundary(&mut self, boundary: BoundaryMode) {
+        if self.boundary != boundary {
+            self.boundary = boundary;
+            self.resize(self.tiling.max_index);
+        }
+    }
+
+    /// Change the size of the board while keeping the rules and the state of every cell that is
+    /// still on the board. The kept cells are set again, so they are back after the next
+    /// `advance` the same way a loaded board's are. Returns the kept cells with their states.
+    pub fn resize(&mut self, max_index: IVec2) -> Vec<(IVec2, u32)> {
+        let in_bounds = |index: &IVec2| index.x < max_index.x && index.y < max_index.y;
+        let mut kept = self
+            .index_to_state
+            .iter()
+            .filter(|(index, state)| state.state != 0 && in_bounds(index))
+            .map(|(index, state)| (*index, state.state))
+            .collect::<HashMap<_, _>>();
+        // Sets that have not been applied yet would have overwritten the cells they target.
+        kept.extend(
+            self.manual_sets
+                .drain()
+                .filter(|(index, _)| in_bounds(index)),
+        );
+
+        self.tiling.max_index = max_index;
+        self.index_to_state.clear();
+        self.pending_sets.clear();
+        let mut retained = kept.iter().map(|(index, state)| (*index, *state)).collect::<Vec<_>>();
+        retained.sort_unstable_by_key(|(index, _)| (index.y, index.x));
+        self.manual_sets = kept;
+        // The cells are all set again, so the old generations can not be put back over them.
+        self.history.clear();
+        self.time_until_update = Duration::ZERO;
+        self.mark_edited();
+        retained
+    }
+
+    /// Remove the state from every shape that has it. Cells in the state go to state 0 and
+    /// every higher state moves down by one, along with the rules that produce or count them.
+    /// Rules producing the removed state produce state 0 instead.
+    pub fn remove_state(&mut self, state: u32) -> Result<RemovedState, RemoveStateError> {
+        if state == 0 {
+            return Err(RemoveStateError::StateZero);
+        }
+        if state as usize >= self.num_states {
+            return Err(RemoveStateError::NoSuchState(state));
+        }
+        // Counting a state that is gone has no stand in, unlike producing one.
+        for (shape, rules) in &self.states {
+            for (owner, state_rules) in rules.iter().enumerate() {
+                if owner as u32 == state {
+                    continue;
                 }
-                if let Some(rule) = rules.rules.get_mut(rule_number) {
-                    match target {
-                        RuleUpdateTarget::MinValue => {
-                            rule.min = value;
-                        }
-                        RuleUpdateTarget::MaxValue => {
-                            rule.max = value;
-                        }
-                        RuleUpdateTarget::ToggleCount => {
-                            if let Some((index, _)) = rule
-                                .neighbor_states_to_count
-                                .iter()
-                                .enumerate()
-                                .filter(|(_, state)| **state == value)
-                                .next()
-                            {
-                                rule.neighbor_states_to_count.remove(index);
-                            } else {
-                                rule.neighbor_states_to_count.push(value);
-                            }
-                        }
-                        RuleUpdateTarget::ResultValue => {
-                            rule.output = value;
+                if let Some(rule) = state_rules
+                    .rules
+                    .iter()
+                    .position(|rule| rule.counts_state(state))
+                {
+                    return Err(RemoveStateError::StillCounted {
+                        shape: *shape,
+                        state: owner as u32,
+                        rule,
+                    });
+                }
+            }
+        }
+
+        let remap = |value: u32| match value.cmp(&state) {
+            std::cmp::Ordering::Less => value,
+            std::cmp::Ordering::Equal => 0,
+            std::cmp::Ordering::Greater => value - 1,
+        };
+        for rules in self.states.values_mut() {
+            if (state as usize) < rules.len() {
+                rules.remove(state as usize);
+            }
+            for state_rules in rules.iter_mut() {
+                state_rules.default_state = remap(state_rules.default_state);
+                for rule in state_rules.rules.iter_mut() {
+                    rule.output = remap(rule.output);
+                    for counted in rule.neighbor_states_to_count.iter_mut() {
+                        *counted = remap(*counted);
+                    }
+                    for extra in rule.extra_counts.iter_mut() {
+                        for counted in extra.states.iter_mut() {
+                            *counted = remap(*counted);
                         }
-                        RuleUpdateTarget::DefaultValue => {}
                     }
                 }
             }
         }
 
+        // Every cell's neighbors move the same way, so the counts can be moved without looking
+        // at the neighbors themselves.
+        let mut changed = Vec::new();
+        for (index, cell) in self.index_to_state.iter_mut() {
+            let counted = cell.neighbors_in_state.remove(state as usize);
+            cell.neighbors_in_state[0] += counted;
+            for neighbor_state in cell.neighbor_states.iter_mut() {
+                if *neighbor_state >= state {
+                    *neighbor_state = remap(*neighbor_state);
+                }
+            }
+            if cell.state >= state {
+                cell.state = remap(cell.state);
+                changed.push((*index, cell.state));
+            }
+        }
+        for value in self.manual_sets.values_mut() {
+            *value = remap(*value);
+        }
+        for entry in self.history.iter_mut() {
+            for (_, value) in entry.previous.iter_mut() {
+                *value = remap(*value);
+            }
+        }
+        self.num_states -= 1;
+        self.mark_edited();
+
+        let clamped = self.validate_and_clamp_rules();
         self.re_evaluate_cells();
+        Ok(RemovedState { changed, clamped })
     }
 
-    pub fn add_state(&mut self, shape: TileShape) {
-        if let Some(rules) = self.states.get_mut(&shape) {
-            rules.push(StateRules {
-                default_state: 0,
-                rules: Vec::new(),
-            });
-            if self.num_states < rules.len() {
-                for _ in self.num_states..rules.len() {
-                    for state in self.index_to_state.values_mut() {
-                        state.neighbors_in_state.push(0);
+    /// Clamp every reference to a state that does not exist to the highest state that does. This
+    /// covers default states and outputs past their shape's states and counted states past
+    /// `num_states`. Counted states that end up listed twice are dropped. Returns what changed.
+    pub fn validate_and_clamp_rules(&mut self) -> Vec<ClampedReference> {
+        let max_counted = self.num_states.max(1) as u32 - 1;
+        let mut clamped = Vec::new();
+        for (shape, rules) in self.states.iter_mut() {
+            let max_state = rules.len().max(1) as u32 - 1;
+            for (state, state_rules) in rules.iter_mut().enumerate() {
+                let mut clamp = |rule: Option<usize>, target, value: &mut u32, max: u32| {
+                    if *value > max {
+                        clamped.push(ClampedReference {
+                            shape: *shape,
+                            state: state as u32,
+                            rule,
+                            target,
+                            from: *value,
+                            to: max,
+                        });
+                        *value = max;
+                    }
+                };
+                clamp(
+                    None,
+                    RuleUpdateTarget::DefaultValue,
+                    &mut state_rules.default_state,
+                    max_state,
+                );
+                for (rule_number, rule) in state_rules.rules.iter_mut().enumerate() {
+                    clamp(
+                        Some(rule_number),
+                        RuleUpdateTarget::ResultValue,
+                        &mut rule.output,
+                        max_state,
+                    );
+                    for counted in rule.neighbor_states_to_count.iter_mut() {
+                        clamp(
+                            Some(rule_number),
+                            RuleUpdateTarget::ToggleCount,
+                            counted,
+                            max_counted,
+                        );
+                    }
+                    let mut seen = Vec::new();
+                    rule.neighbor_states_to_count.retain(|counted| {
+                        let first = !seen.contains(counted);
+                        seen.push(*counted);
+                        first
+                    });
+                    for (index, extra) in rule.extra_counts.iter_mut().enumerate() {
+                        for counted in extra.states.iter_mut() {
+                            clamp(
+                                Some(rule_number),
+                                RuleUpdateTarget::ToggleConstraintCount(index),
+                                counted,
+                                max_counted,
+                            );
+                        }
+                        let mut seen = Vec::new();
+                        extra.states.retain(|counted| {
+                            let first = !seen.contains(counted);
+                            seen.push(*counted);
+                            first
+                        });
                     }
                 }
-                self.num_states = rules.len();
             }
         }
-    }
-
-    pub fn add_rule(&mut self, shape: TileShape, state: u32) {
-        if let Some(rules) = self.states.get_mut(&shape) {
-            if let Some(rule) = rules.get_mut(state as usize) {
-                rule.rules.push(StateRule {
-                    min: 0,
-                    max: 0,
-                    neighbor_states_to_count: Vec::new(),
-                    output: 0,
-                })
-            }
+        if !clamped.is_empty() {
+            self.mark_edited();
         }
-
-        self.re_evaluate_cells();
+        clamped
     }
 
     fn re_evaluate_cells(&mut self) {
@@ -405,11 +1102,89 @@ impl SimulationState {
         }
     }
 
+    /// Queue the cell to be set to the state at the next update. The index is always wrapped
+    /// onto the board, whatever the boundary, and the state is not checked against the states of
+    /// the tile, so use `try_set_at` for input that might be invalid.
     pub fn set_at(&mut self, index: IVec2, new_state: u32) {
         self.manual_sets
             .insert(self.tiling.adjust_index(index), new_state);
     }
 
+    /// Like `set_at`, but indices off the board are only wrapped when the board is toroidal and
+    /// the state must be one the tile has. Returns the (wrapped) cell that was queued.
+    pub fn try_set_at(&mut self, index: IVec2, new_state: u32) -> Result<Vec<(IVec2, u32)>, SetError> {
+        let index = self.check_set(index, new_state)?;
+        self.set_at(index, new_state);
+        Ok(vec![(index, new_state)])
+    }
+
+    /// Whether `try_set_at` would accept the set, without queueing it. Returns the (wrapped) cell
+    /// it would set.
+    pub fn check_set(&self, index: IVec2, new_state: u32) -> Result<IVec2, SetError> {
+        if self.boundary == BoundaryMode::Dead && !self.tiling.in_bounds(index) {
+            return Err(SetError::OutOfBounds(index));
+        }
+        let index = self.tiling.adjust_index(index);
+        let num_states = self.get_num_states_for_shape(self.tiling.get_tile_at_index(index).shape);
+        if new_state >= num_states {
+            return Err(SetError::NoSuchState {
+                index,
+                state: new_state,
+                num_states,
+            });
+        }
+        Ok(index)
+    }
+
+    /// How many generations the cell has been in its current state. Cells that were never set
+    /// have been in state 0 since the board started.
+    pub fn get_age(&self, index: IVec2) -> u64 {
+        let changed_at = self
+            .index_to_state
+            .get(&self.tiling.adjust_index(index))
+            .map_or(0, |state| state.changed_at);
+        self.generation.saturating_sub(changed_at)
+    }
+
+    /// Queue every cell connected to `start` through neighbors in the same state as it to be set
+    /// to `new_state`, like a paint bucket. Cells whose shape has no `new_state` stop the fill.
+    /// Returns the cells queued, which is none when `start` is already in `new_state`.
+    pub fn flood_fill(&mut self, start: IVec2, new_state: u32) -> Vec<(IVec2, u32)> {
+        let start = self.tiling.adjust_index(start);
+        let old_state = self.get_at(start);
+        if old_state == new_state {
+            return Vec::new();
+        }
+        let fills = |index: IVec2| {
+            let shape = self.tiling.get_tile_at_index(index).shape;
+            self.get_at(index) == old_state && new_state < self.get_num_states_for_shape(shape)
+        };
+        let mut filled = Vec::new();
+        if !fills(start) {
+            return filled;
+        }
+        // Each cell is queued at most once, so the fill ends even when the region wraps all the
+        // way around the board.
+        let mut queued = HashSet::default();
+        queued.insert(start);
+        let mut queue = VecDeque::from([start]);
+        while let Some(index) = queue.pop_front() {
+            filled.push((index, new_state));
+            for offset in self.tiling.get_neighbors(index) {
+                if let Some(neighbor) = self.boundary.neighbor_index(&self.tiling, index, offset) {
+                    if fills(neighbor) && queued.insert(neighbor) {
+                        queue.push_back(neighbor);
+                    }
+                }
+            }
+        }
+        for (index, state) in &filled {
+            self.set_at(*index, *state);
+        }
+        filled
+    }
+
+    /// The state of the cell, wrapping the index onto the board. Cells never set are in state 0.
     pub fn get_at(&self, index: IVec2) -> u32 {
         match self.index_to_state.get(&self.tiling.adjust_index(index)) {
             Some(state) => state.state,
@@ -417,59 +1192,223 @@ impl SimulationState {
         }
     }
 
+    /// How many neighbors of the cell are in the given state. Cells keep these counts up to date
+    /// as their neighbors change, so this is just a lookup. States we don't have count 0.
     pub fn get_neighbor_count(&self, index: IVec2, neighbor_state: u32) -> u32 {
-        match self.index_to_state.get(&self.tiling.adjust_index(index)) {
-            Some(state) => state.neighbors_in_state[neighbor_state as usize],
+        let index = self.tiling.adjust_index(index);
+        match self.index_to_state.get(&index) {
+            Some(state) => state
+                .neighbors_in_state
+                .get(neighbor_state as usize)
+                .copied()
+                .unwrap_or(0),
+            // Nothing next to this cell has ever been set, so all of its neighbors are in state 0.
+            None if neighbor_state == 0 => self.tiling.get_neighbors(index).len() as u32,
             None => 0u32,
         }
     }
 
+    /// For every state, how many cells in that state have each number of live neighbors, where
+    /// a live neighbor is any neighbor not in state 0. `distribution[state][count]` is the number
+    /// of cells in `state` with `count` live neighbors.
+    pub fn neighbor_count_distribution(&self) -> Vec<Vec<u32>> {
+        let mut distribution = vec![Vec::new(); self.num_states.max(1)];
+        let mut add_cell = |state: u32, live_neighbors: u32, cells: u32| {
+            let counts = &mut distribution[state as usize];
+            if counts.len() <= live_neighbors as usize {
+                counts.resize(live_neighbors as usize + 1, 0);
+            }
+            counts[live_neighbors as usize] += cells;
+        };
+        for state in self.index_to_state.values() {
+            let live_neighbors = state.neighbors_in_state.iter().skip(1).sum();
+            add_cell(state.state, live_neighbors, 1);
+        }
+        // Cells we have no state for have never been set or been next to a set cell.
+        let num_cells = (self.tiling.max_index.x * self.tiling.max_index.y) as usize;
+        let untouched = num_cells.saturating_sub(self.index_to_state.len());
+        if untouched > 0 {
+            add_cell(0, 0, untouched as u32);
+        }
+        distribution
+    }
+
     pub fn get_pending(&self, index: IVec2) -> u32 {
         match self.manual_sets.get(&self.tiling.adjust_index(index)) {
             Some(value) => *value,
             None => match self.pending_sets.get(&self.tiling.adjust_index(index)) {
-                Some(value) => *value,
+                Some((value, _)) => *value,
                 None => self.get_at(index),
             },
         }
     }
 
-    pub fn process(&mut self) -> Vec<(IVec2, u32)> {
-        let mut sets = Vec::new();
+    /// Every cell the current rules would change at the next generation along with the state it
+    /// would move to, sorted by index. Nothing is changed: the cells are evaluated the same way
+    /// `process` does, ignoring the chance of probabilistic rules and any manual sets that have
+    /// not been applied yet.
+    pub fn preview_changes(&self) -> Vec<(IVec2, u32)> {
+        let default_rules = Vec::new();
+        let mut changes = self
+            .index_to_state
+            .iter()
+            .filter_map(|(index, state)| {
+                let shape = self.tiling.get_tile_at_index(*index).shape;
+                let rules = self.states.get(&shape).unwrap_or(&default_rules);
+                state.evaluate(rules).map(|(next, _)| (*index, next))
+            })
+            .collect::<Vec<_>>();
+        changes.sort_unstable_by_key(|(index, _)| (index.y, index.x));
+        changes
+    }
 
-        // If we are doing a real tick, take in the value from the last process
-        // step along with the usual normal values.
-        if self.step > 0 {
-            self.step -= 1;
-            for (key, value) in self.pending_sets.drain() {
+    // Move the pending sets into our manual sets. Sets from probabilistic rules that don't
+    // happen this generation stay pending so they get another chance next generation.
+    fn apply_pending_sets(&mut self) {
+        // Sort so that the random rolls don't depend on the hash map's iteration order.
+        let mut pending_sets = self.pending_sets.drain().collect::<Vec<_>>();
+        pending_sets.sort_unstable_by_key(|(key, _)| (key.x, key.y));
+        for (key, (value, probability)) in pending_sets {
+            if probability >= 1.0 || self.rng.next_f32() < probability {
                 self.manual_sets.try_insert(key, value).ok();
+            } else {
+                self.pending_sets.insert(key, (value, probability));
             }
-        } else if self.run_every != 0 {
-            if self.time_since_last_update == 0 {
-                for (key, value) in self.pending_sets.drain() {
-                    self.manual_sets.try_insert(key, value).ok();
-                }
-                self.time_since_last_update = self.run_every;
+        }
+    }
+
+    /// Process a single frame's worth of time.
+    pub fn process(&mut self) -> Vec<(IVec2, u32)> {
+        self.advance(FRAME_TIME)
+    }
+
+    /// Run the next generation right away, like the step button does, whether or not the
+    /// simulation is playing. Returns the cells set.
+    pub fn run_generation(&mut self) -> Vec<(IVec2, u32)> {
+        self.step += 1;
+        self.process()
+    }
+
+    /// How many tiles are in each state, indexed by state.
+    pub fn population(&self) -> Vec<usize> {
+        let mut population = vec![0; self.num_states];
+        for state in self.index_to_state.values() {
+            if let Some(count) = population.get_mut(state.state as usize) {
+                *count += 1;
             }
-            self.time_since_last_update -= 1;
         }
+        let tile_count = (self.tiling.max_index.x * self.tiling.max_index.y) as usize;
+        population[0] = tile_count - population[1..].iter().sum::<usize>();
+        population
+    }
 
+    /// Move the simulation forward by `delta` of elapsed time, running the next generation once
+    /// `run_every` frames worth of time has passed since the last one. Returns the cells set.
+    pub fn advance(&mut self, delta: Duration) -> Vec<(IVec2, u32)> {
+        // If we are doing a real tick, take in the value from the last process
+        // step along with the usual normal values.
+        let ran_generation = self.due_generations(delta) > 0;
+        if ran_generation {
+            self.apply_pending_sets();
+            // Count the generation first so the cells it changes are new to it.
+            self.generation += 1;
+        }
+
+        let sets = self.apply_manual_sets();
+        if ran_generation {
+            self.record_history(1, &sets);
+        }
+        sets.into_iter()
+            .map(|(index, _, value)| (index, value))
+            .collect()
+    }
+
+    /// Move the generation timer forward by `delta` without running anything, returning how
+    /// many generations are now due. Used when the generations run on a `SimulationWorker`.
+    pub fn due_generations(&mut self, delta: Duration) -> u32 {
+        if self.step > 0 {
+            self.step -= 1;
+            return 1;
+        }
+        let mut due = 0;
+        if self.run_every != 0 {
+            if self.time_until_update.is_zero() {
+                due = 1;
+                self.time_until_update = FRAME_TIME * self.run_every;
+            }
+            self.time_until_update = self.time_until_update.saturating_sub(delta);
+        }
+        due
+    }
+
+    /// Set the cells waiting from `set_at` and friends outside of any generation. Returns the
+    /// cells set.
+    pub fn apply_edits(&mut self) -> Vec<(IVec2, u32)> {
+        self.apply_manual_sets()
+            .into_iter()
+            .map(|(index, _, value)| (index, value))
+            .collect()
+    }
+
+    /// Take in `generations` worth of changes run elsewhere, as if they had been run here.
+    /// Returns the cells set.
+    pub fn apply_generations(
+        &mut self,
+        generations: u32,
+        changes: &[(IVec2, u32)],
+    ) -> Vec<(IVec2, u32)> {
+        self.manual_sets.extend(changes.iter().cloned());
+        self.generation += generations as u64;
+        let sets = self.apply_manual_sets();
+        self.record_history(generations, &sets);
+        sets.into_iter()
+            .map(|(index, _, value)| (index, value))
+            .collect()
+    }
+
+    // Remember the old states of the cells changed by the last `generations` generations so they
+    // can be stepped back.
+    fn record_history(&mut self, generations: u32, sets: &[(IVec2, u32, u32)]) {
+        if self.history_depth > 0 {
+            while self.history.len() >= self.history_depth {
+                self.history.pop_front();
+            }
+            self.history.push_back(HistoryEntry {
+                generations,
+                previous: sets
+                    .iter()
+                    .filter(|(_, old_value, value)| old_value != value)
+                    .map(|(index, old_value, _)| (*index, *old_value))
+                    .collect(),
+            });
+        }
+    }
+
+    // Set every cell waiting in the manual sets, updating the neighbor counts around it and what
+    // it and its neighbors are pending. Returns each cell set with its old and new state.
+    fn apply_manual_sets(&mut self) -> Vec<(IVec2, u32, u32)> {
+        let mut sets = Vec::new();
         // Iterate all sets that we need to process and update their state
         for (key, value) in self.manual_sets.drain() {
             let neighbors = self.tiling.get_neighbors(key);
             let old_value = if let Some(state) = self.index_to_state.get_mut(&key) {
                 let old_value = state.state;
                 state.state = value;
+                if old_value != value {
+                    state.changed_at = self.generation;
+                }
                 old_value
             } else {
-                self.index_to_state.insert(
-                    key,
-                    SimulationCellState::new(value, neighbors.len() as u32, self.num_states),
-                );
+                let mut state =
+                    SimulationCellState::new(value, neighbors.len() as u32, self.num_states);
+                if value != 0 {
+                    state.changed_at = self.generation;
+                }
+                self.index_to_state.insert(key, state);
                 0u32
             };
 
-            sets.push((key, value));
+            sets.push((key, old_value, value));
 
             // Determine if after updating our state we need to change our state in the next step.
             let default_rules = Vec::new();
@@ -487,11 +1426,26 @@ impl SimulationState {
             // we have replaced the old neighbor value with it's new value. If this results in
             // any sets for the next round, then store them in pending sets.
             for neighbor in neighbors {
-                let neighbor_index = self.tiling.adjust_index(key + IVec2::from(*neighbor));
+                let neighbor_index = match self.boundary.neighbor_index(&self.tiling, key, neighbor) {
+                    Some(neighbor_index) => neighbor_index,
+                    None => continue,
+                };
+                // Where we sit among our neighbor's neighbors, for rules counting directions.
+                let direction = self
+                    .tiling
+                    .get_neighbors(neighbor_index)
+                    .iter()
+                    .position(|offset| *offset == (-neighbor.0, -neighbor.1));
                 let neighbor_shape = self.tiling.get_tile_at_index(neighbor_index).shape;
                 let neighbor_rules = self.states.get(&neighbor_shape).unwrap_or(&default_rules);
                 if let Some(state) = self.index_to_state.get_mut(&neighbor_index) {
-                    if let Some(new_state) = state.apply_change(old_value, value, neighbor_rules) {
+                    if let Some(new_state) = state.apply_change(
+                        neighbor_index,
+                        direction,
+                        old_value,
+                        value,
+                        neighbor_rules,
+                    ) {
                         self.pending_sets.insert(neighbor_index, new_state);
                     } else {
                         self.pending_sets.remove(&neighbor_index);
@@ -502,7 +1456,13 @@ impl SimulationState {
                         self.tiling.get_neighbors(neighbor_index).len() as u32,
                         self.num_states,
                     );
-                    if let Some(new_state) = state.apply_change(old_value, value, neighbor_rules) {
+                    if let Some(new_state) = state.apply_change(
+                        neighbor_index,
+                        direction,
+                        old_value,
+                        value,
+                        neighbor_rules,
+                    ) {
                         self.pending_sets.insert(neighbor_index, new_state);
                     } else {
                         self.pending_sets.remove(&neighbor_index);
@@ -515,3 +1475,1277 @@ impl SimulationState {
         sets
     }
 }
+
+/// Editing the rules. These are what the rules menus use, and are safe to drive from scripts
+/// and tests: every edit keeps the rules valid for the tiling and re-evaluates what each cell
+/// becomes next generation.
+impl SimulationState {
+    /// The shapes with rules in this simulation's tiling.
+    pub fn get_shapes(&self) -> Vec<TileShape> {
+        self.states.keys().cloned().collect()
+    }
+
+    /// How many states the shape has, or 0 for shapes not in the tiling.
+    pub fn get_num_states_for_shape(&self, shape: TileShape) -> u32 {
+        self.states
+            .get(&shape)
+            .map(|rules| rules.len() as u32)
+            .unwrap_or(0)
+    }
+
+    /// A copy of the rules for every state of the shape, indexed by state. Empty for shapes not
+    /// in the tiling.
+    pub fn clone_rules_for_shape(&self, shape: TileShape) -> Vec<StateRules> {
+        self.states.get(&shape).cloned().unwrap_or_default()
+    }
+
+    /// Change one setting of a state's rules. `rule_number` is ignored by the targets that apply
+    /// to the whole state, `DefaultValue` and `EvaluationMode`. Values are clamped to what can
+    /// take effect:
+    /// - counts to the most the rule can see, see `StateRule::max_count`,
+    /// - default states and outputs to the states the shape has,
+    /// - probabilities to 100%.
+    ///
+    /// Toggling a state in or out of a count ignores states that don't exist. Shapes, states,
+    /// rules and extra counts that don't exist are ignored.
+    pub fn set_rule_value(
+        &mut self,
+        shape: TileShape,
+        state: u32,
+        rule_number: usize,
+        value: u32,
+        target: RuleUpdateTarget,
+    ) {
+        let neighbors = self.tiling.max_neighbor_count(shape);
+        let max_state = self.get_num_states_for_shape(shape).max(1) - 1;
+        let counted_exists = (value as usize) < self.num_states;
+        self.mark_edited();
+        if let Some(rules) = self.states.get_mut(&shape) {
+            if state as usize >= rules.len() {
+                return;
+            }
+
+            if let Some(rules) = rules.get_mut(state as usize) {
+                if target == RuleUpdateTarget::DefaultValue {
+                    rules.default_state = value.min(max_state);
+                    self.re_evaluate_cells();
+                    return;
+                }
+                if target == RuleUpdateTarget::EvaluationMode {
+                    rules.evaluation = RuleEvaluation::from_value(value);
+                    self.re_evaluate_cells();
+                    return;
+                }
+                if let Some(rule) = rules.rules.get_mut(rule_number) {
+                    match target {
+                        // Counts past the neighbors of the shape could never match.
+                        RuleUpdateTarget::MinValue => {
+                            rule.min = value.min(rule.max_count(neighbors));
+                        }
+                        RuleUpdateTarget::MaxValue => {
+                            rule.max = value.min(rule.max_count(neighbors));
+                        }
+                        RuleUpdateTarget::ToggleCount if counted_exists => {
+                            if let Some((index, _)) = rule
+                                .neighbor_states_to_count
+                                .iter()
+                                .enumerate()
+                                .filter(|(_, state)| **state == value)
+                                .next()
+                            {
+                                rule.neighbor_states_to_count.remove(index);
+                            } else {
+                                rule.neighbor_states_to_count.push(value);
+                            }
+                        }
+                        RuleUpdateTarget::ToggleIncludeSelf => {
+                            rule.include_self = !rule.include_self;
+                            rule.clamp_counts(neighbors);
+                        }
+                        RuleUpdateTarget::ResultValue => {
+                            rule.output = value.min(max_state);
+                        }
+                        RuleUpdateTarget::Probability => {
+                            rule.probability = value.min(100) as f32 / 100.0;
+                        }
+                        RuleUpdateTarget::Priority => {
+                            rule.priority = value;
+                        }
+                        RuleUpdateTarget::ToggleDirection => {
+                            let mask = rule.direction_mask.unwrap_or(0) ^ (1 << value.min(31));
+                            rule.direction_mask = (mask != 0).then_some(mask);
+                            rule.clamp_counts(neighbors);
+                        }
+                        RuleUpdateTarget::AddConstraint => {
+                            rule.extra_counts.push(CountConstraint {
+                                states: Vec::new(),
+                                min: 0,
+                                max: 0,
+                            });
+                        }
+                        RuleUpdateTarget::RemoveConstraint(index) => {
+                            if index < rule.extra_counts.len() {
+                                rule.extra_counts.remove(index);
+                            }
+                        }
+                        RuleUpdateTarget::ToggleConstraintCount(index) if counted_exists => {
+                            if let Some(extra) = rule.extra_counts.get_mut(index) {
+                                match extra.states.iter().position(|state| *state == value) {
+                                    Some(position) => {
+                                        extra.states.remove(position);
+                                    }
+                                    None => extra.states.push(value),
+                                }
+                            }
+                        }
+                        RuleUpdateTarget::ConstraintMin(index) => {
+                            let max_count = rule.max_count(neighbors);
+                            if let Some(extra) = rule.extra_counts.get_mut(index) {
+                                extra.min = value.min(max_count);
+                            }
+                        }
+                        RuleUpdateTarget::ConstraintMax(index) => {
+                            let max_count = rule.max_count(neighbors);
+                            if let Some(extra) = rule.extra_counts.get_mut(index) {
+                                extra.max = value.min(max_count);
+                            }
+                        }
+                        RuleUpdateTarget::ToggleCount
+                        | RuleUpdateTarget::ToggleConstraintCount(_)
+                        | RuleUpdateTarget::DefaultValue
+                        | RuleUpdateTarget::EvaluationMode => {}
+                    }
+                }
+            }
+        }
+
+        self.re_evaluate_cells();
+    }
+
+    /// Add a state to the shape with no rules, so its cells stay in the default state 0.
+    pub fn add_state(&mut self, shape: TileShape) {
+        if let Some(rules) = self.states.get_mut(&shape) {
+            rules.push(StateRules {
+                default_state: 0,
+                evaluation: RuleEvaluation::FirstMatch,
+                rules: Vec::new(),
+            });
+            if self.num_states < rules.len() {
+                for _ in self.num_states..rules.len() {
+                    for state in self.index_to_state.values_mut() {
+                        state.neighbors_in_state.push(0);
+                    }
+                }
+                self.num_states = rules.len();
+            }
+        }
+        self.mark_edited();
+    }
+
+    /// Move the rule at `from` so it sits at `to` in the state's list, which changes which rule
+    /// wins under first match evaluation. Out of range rules are left where they are.
+    pub fn move_rule(&mut self, shape: TileShape, state: u32, from: usize, to: usize) {
+        if let Some(rules) = self.states.get_mut(&shape) {
+            if let Some(rules) = rules.get_mut(state as usize) {
+                if from >= rules.rules.len() || to >= rules.rules.len() {
+                    return;
+                }
+                let rule = rules.rules.remove(from);
+                rules.rules.insert(to, rule);
+            }
+        }
+
+        self.mark_edited();
+        self.re_evaluate_cells();
+    }
+
+    /// Remove a rule from a state. Rules that no longer exist are ignored, since the menus may
+    /// still be showing a rule that was already removed.
+    pub fn delete_rule(&mut self, shape: TileShape, state: u32, rule_number: usize) {
+        if let Some(rules) = self.states.get_mut(&shape) {
+            if let Some(rules) = rules.get_mut(state as usize) {
+                if rule_number < rules.rules.len() {
+                    rules.rules.remove(rule_number);
+                }
+            }
+        }
+
+        self.mark_edited();
+        self.re_evaluate_cells();
+    }
+
+    /// Append a rule to the state that never fires until it is edited: it counts no states,
+    /// needs a count of exactly 0 and outputs state 0 with certainty.
+    pub fn add_rule(&mut self, shape: TileShape, state: u32) {
+        if let Some(rules) = self.states.get_mut(&shape) {
+            if let Some(rule) = rules.get_mut(state as usize) {
+                rule.rules.push(StateRule {
+                    min: 0,
+                    max: 0,
+                    neighbor_states_to_count: Vec::new(),
+                    include_self: false,
+                    direction_mask: None,
+                    extra_counts: Vec::new(),
+                    output: 0,
+                    probability: 1.0,
+                    priority: 0,
+                })
+            }
+        }
+
+        self.mark_edited();
+        self.re_evaluate_cells();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{
+        math::{IVec2, Vec2},
+        utils::HashMap,
+    };
+
+    use crate::tiling::{TileShape, Tiling, TilingKind};
+
+    use super::{
+        find_unreachable_states, BoundaryMode, CountConstraint, RemoveStateError, FRAME_TIME, RuleEvaluation, RuleUpdateTarget, SetError, SimulationState, StateRule,
+        SimulationCellState, StateRules,
+    };
+
+    fn square_simulation(size: i32) -> SimulationState {
+        SimulationState::new(Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(size, size),
+            offset: Vec2::ZERO,
+        })
+    }
+
+    #[test]
+    fn random_start_is_seeded_and_within_max_state() {
+        let tiling = Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(16, 16),
+            offset: Vec2::ZERO,
+        };
+        let grid = |sim_state: &SimulationState| {
+            sim_state
+                .tiling
+                .iter_indices()
+                .map(|index| sim_state.get_at(index))
+                .collect::<Vec<_>>()
+        };
+
+        // The cells are set straight away, without advancing.
+        let random = SimulationState::new_with_random(tiling.clone(), 3, 0.6, 7);
+        let states = grid(&random);
+        assert_eq!(states, grid(&SimulationState::new_with_random(tiling.clone(), 3, 0.6, 7)));
+        assert_ne!(states, grid(&SimulationState::new_with_random(tiling.clone(), 3, 0.6, 8)));
+        assert!(states.iter().all(|state| *state <= 3));
+        assert!(random.get_num_states_for_shape(TileShape::Square) >= 4);
+
+        // Lower states are more common and every state shows up.
+        let population = random.population();
+        assert!(population[1] > population[2] && population[2] > population[3]);
+        assert!(population[3] > 0);
+
+        assert!(grid(&SimulationState::new_with_random(tiling, 3, 0.0, 7))
+            .iter()
+            .all(|state| *state == 0));
+    }
+
+    #[test]
+    fn reset_keeps_rules_and_empties_board() {
+        let mut sim_state = square_simulation(8);
+        sim_state.add_state(TileShape::Square);
+        sim_state.set_rule_value(TileShape::Square, 1, 0, 3, RuleUpdateTarget::MinValue);
+        for x in 2..5 {
+            sim_state.set_at(IVec2::new(x, 3), 1);
+        }
+        sim_state.set_at(IVec2::new(6, 6), 2);
+        sim_state.process();
+        sim_state.process();
+
+        sim_state.reset_keep_rules();
+        let rules = sim_state.clone_rules_for_shape(TileShape::Square);
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[1].rules[0].min, 3);
+        assert_eq!(sim_state.num_states, 3);
+        for _ in 0..3 {
+            for index in sim_state.tiling.iter_indices() {
+                assert_eq!(sim_state.get_at(index), 0);
+                assert_eq!(sim_state.get_pending(index), 0);
+            }
+            sim_state.process();
+        }
+    }
+
+    #[test]
+    fn stepping_back_restores_the_previous_grids() {
+        let mut sim_state = square_simulation(10);
+        for cell in [(1, 7), (2, 6), (0, 5), (1, 5), (2, 5), (5, 2), (6, 2), (7, 2), (8, 8)] {
+            sim_state.set_at(IVec2::from(cell), 1);
+        }
+        sim_state.process();
+        let grid = |sim_state: &SimulationState| {
+            sim_state
+                .tiling
+                .iter_indices()
+                .map(|index| sim_state.get_at(index))
+                .collect::<Vec<_>>()
+        };
+        let mut grids = vec![grid(&sim_state)];
+        let preview = sim_state.preview_changes();
+        for _ in 0..5 {
+            sim_state.run_generation();
+            grids.push(grid(&sim_state));
+        }
+        assert_eq!(sim_state.generation(), 5);
+
+        grids.pop();
+        while let Some(expected) = grids.pop() {
+            let changes = sim_state.step_back().unwrap();
+            assert!(changes.iter().all(|(index, state)| sim_state.get_at(*index) == *state));
+            assert_eq!(grid(&sim_state), expected);
+        }
+        assert_eq!(sim_state.generation(), 0);
+        assert!(sim_state.step_back().is_none());
+        // What comes next is worked out again for the restored cells.
+        assert_eq!(sim_state.preview_changes(), preview);
+    }
+
+    #[test]
+    fn stepping_back_undoes_a_batch_of_generations() {
+        let mut sim_state = square_simulation(10);
+        for cell in [(1, 7), (2, 6), (0, 5), (1, 5), (2, 5)] {
+            sim_state.set_at(IVec2::from(cell), 1);
+        }
+        sim_state.process();
+        let start = sim_state.clone();
+
+        // Run three generations elsewhere and take them in as one batch.
+        let mut elsewhere = sim_state.clone();
+        let mut changes = HashMap::default();
+        for _ in 0..3 {
+            changes.extend(elsewhere.run_generation());
+        }
+        let changes = changes.into_iter().collect::<Vec<_>>();
+        let generation = sim_state.generation();
+        sim_state.apply_generations(3, &changes);
+        assert_eq!(sim_state.generation(), generation + 3);
+
+        assert!(sim_state.step_back().is_some());
+        assert_eq!(sim_state.generation(), generation);
+        for index in sim_state.tiling.iter_indices() {
+            assert_eq!(sim_state.get_at(index), start.get_at(index), "{:?}", index);
+        }
+    }
+
+    #[test]
+    fn edit_epoch_only_changes_on_edits() {
+        let mut sim_state = square_simulation(10);
+        let epoch = sim_state.edit_epoch();
+        sim_state.set_run_every(1);
+        sim_state.toggle_play();
+        sim_state.run_generation();
+        assert_eq!(sim_state.edit_epoch(), epoch);
+        assert_eq!(sim_state.clone().edit_epoch(), epoch);
+        assert_ne!(square_simulation(10).edit_epoch(), epoch);
+
+        sim_state.set_rule_value(TileShape::Square, 0, 0, 2, RuleUpdateTarget::MinValue);
+        let rule_epoch = sim_state.edit_epoch();
+        assert_ne!(rule_epoch, epoch);
+        sim_state.resize(IVec2::splat(5));
+        assert_ne!(sim_state.edit_epoch(), rule_epoch);
+    }
+
+    #[test]
+    fn history_only_keeps_the_configured_depth() {
+        let mut sim_state = square_simulation(10);
+        sim_state.history_depth = 2;
+        for x in 3..6 {
+            sim_state.set_at(IVec2::new(x, 4), 1);
+        }
+        sim_state.process();
+        for _ in 0..5 {
+            sim_state.run_generation();
+        }
+        assert!(sim_state.step_back().is_some());
+        assert!(sim_state.step_back().is_some());
+        assert!(sim_state.step_back().is_none());
+        assert_eq!(sim_state.generation(), 3);
+    }
+
+    #[test]
+    fn preview_matches_the_next_generation() {
+        let mut sim_state = square_simulation(10);
+        // A glider next to a blinker, so cells are both born and die.
+        for cell in [(1, 7), (2, 6), (0, 5), (1, 5), (2, 5), (5, 2), (6, 2), (7, 2)] {
+            sim_state.set_at(IVec2::from(cell), 1);
+        }
+        sim_state.process();
+        for _ in 0..3 {
+            let preview = sim_state.preview_changes();
+            let population = sim_state.population();
+            assert!(!preview.is_empty());
+            // Looking ahead leaves the board as it was.
+            assert_eq!(sim_state.population(), population);
+
+            let mut changes = sim_state.run_generation();
+            changes.sort_unstable_by_key(|(index, _)| (index.y, index.x));
+            assert_eq!(preview, changes);
+        }
+    }
+
+    #[test]
+    fn rule_counts_are_capped_at_the_neighbors_of_the_shape() {
+        let mut sim_state = SimulationState::new(Tiling {
+            kind: TilingKind::OctagonAndSquare,
+            max_index: IVec2::new(8, 8),
+            offset: Vec2::ZERO,
+        });
+        assert_eq!(sim_state.tiling.max_neighbor_count(TileShape::Square), 4);
+        assert_eq!(sim_state.tiling.max_neighbor_count(TileShape::Octagon), 8);
+        assert_eq!(sim_state.tiling.max_neighbor_count(TileShape::Hexagon), 0);
+
+        let rule = |sim_state: &SimulationState, shape| {
+            sim_state.clone_rules_for_shape(shape)[1].rules[0].clone()
+        };
+        sim_state.set_rule_value(TileShape::Square, 1, 0, 7, RuleUpdateTarget::MinValue);
+        sim_state.set_rule_value(TileShape::Square, 1, 0, 9, RuleUpdateTarget::MaxValue);
+        let square_rule = rule(&sim_state, TileShape::Square);
+        assert_eq!((square_rule.min, square_rule.max), (4, 4));
+
+        // Counting the cell itself allows one more.
+        sim_state.set_rule_value(TileShape::Square, 1, 0, 0, RuleUpdateTarget::ToggleIncludeSelf);
+        sim_state.set_rule_value(TileShape::Square, 1, 0, 9, RuleUpdateTarget::MaxValue);
+        assert_eq!(rule(&sim_state, TileShape::Square).max, 5);
+        sim_state.set_rule_value(TileShape::Square, 1, 0, 0, RuleUpdateTarget::ToggleIncludeSelf);
+        assert_eq!(rule(&sim_state, TileShape::Square).max, 4);
+
+        sim_state.set_rule_value(TileShape::Octagon, 1, 0, 9, RuleUpdateTarget::MaxValue);
+        assert_eq!(rule(&sim_state, TileShape::Octagon).max, 8);
+    }
+
+    #[test]
+    fn adding_states_and_rules_appends_defaults() {
+        let mut sim_state = square_simulation(4);
+        assert_eq!(sim_state.get_shapes(), vec![TileShape::Square]);
+        assert_eq!(sim_state.get_num_states_for_shape(TileShape::Square), 2);
+        assert_eq!(sim_state.get_num_states_for_shape(TileShape::Hexagon), 0);
+        assert!(sim_state.clone_rules_for_shape(TileShape::Hexagon).is_empty());
+
+        sim_state.add_state(TileShape::Square);
+        let rules = sim_state.clone_rules_for_shape(TileShape::Square);
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[2].default_state, 0);
+        assert_eq!(rules[2].evaluation, RuleEvaluation::FirstMatch);
+        assert!(rules[2].rules.is_empty());
+
+        sim_state.add_rule(TileShape::Square, 1);
+        let rules = sim_state.clone_rules_for_shape(TileShape::Square);
+        assert_eq!(rules[1].rules.len(), 2);
+        let added = &rules[1].rules[1];
+        assert_eq!((added.min, added.max, added.output), (0, 0, 0));
+        assert!(added.neighbor_states_to_count.is_empty() && added.extra_counts.is_empty());
+        assert!(!added.include_self && added.direction_mask.is_none());
+        assert_eq!((added.probability, added.priority), (1.0, 0));
+
+        // Shapes and states that don't exist are left alone.
+        sim_state.add_rule(TileShape::Square, 3);
+        sim_state.add_rule(TileShape::Hexagon, 0);
+        sim_state.add_state(TileShape::Hexagon);
+        assert_eq!(sim_state.clone_rules_for_shape(TileShape::Square).len(), 3);
+        assert!(sim_state.clone_rules_for_shape(TileShape::Hexagon).is_empty());
+    }
+
+    #[test]
+    fn set_rule_value_clamps_each_target() {
+        let mut sim_state = square_simulation(4);
+        let mut set = |target, value| {
+            sim_state.set_rule_value(TileShape::Square, 1, 0, value, target);
+            let rules = sim_state.clone_rules_for_shape(TileShape::Square).remove(1);
+            (rules.clone(), rules.rules[0].clone())
+        };
+
+        assert_eq!(set(RuleUpdateTarget::MinValue, 12).1.min, 8);
+        assert_eq!(set(RuleUpdateTarget::MaxValue, 5).1.max, 5);
+        assert_eq!(set(RuleUpdateTarget::ResultValue, 7).1.output, 1);
+        assert_eq!(set(RuleUpdateTarget::ResultValue, 0).1.output, 0);
+        assert_eq!(set(RuleUpdateTarget::DefaultValue, 7).0.default_state, 1);
+        assert_eq!(set(RuleUpdateTarget::Probability, 250).1.probability, 1.0);
+        assert_eq!(set(RuleUpdateTarget::Probability, 25).1.probability, 0.25);
+        assert_eq!(set(RuleUpdateTarget::Priority, 40).1.priority, 40);
+        assert_eq!(
+            set(RuleUpdateTarget::EvaluationMode, RuleEvaluation::Priority.value()).0.evaluation,
+            RuleEvaluation::Priority
+        );
+        let (_, rule) = set(RuleUpdateTarget::ToggleIncludeSelf, 0);
+        assert!(rule.include_self);
+        assert_eq!(set(RuleUpdateTarget::MaxValue, 12).1.max, 9);
+
+        // Counting fewer directions clamps the counts already set.
+        let (_, rule) = set(RuleUpdateTarget::ToggleDirection, 2);
+        assert_eq!(rule.direction_mask, Some(1 << 2));
+        assert_eq!((rule.min, rule.max), (2, 2));
+        assert_eq!(set(RuleUpdateTarget::ToggleDirection, 2).1.direction_mask, None);
+
+        // Rules that don't exist are ignored.
+        let before = sim_state.clone_rules_for_shape(TileShape::Square);
+        sim_state.set_rule_value(TileShape::Square, 1, 5, 3, RuleUpdateTarget::MinValue);
+        sim_state.set_rule_value(TileShape::Square, 4, 0, 3, RuleUpdateTarget::MinValue);
+        sim_state.set_rule_value(TileShape::Hexagon, 0, 0, 3, RuleUpdateTarget::MinValue);
+        let after = sim_state.clone_rules_for_shape(TileShape::Square);
+        assert_eq!(before[1].rules[0].min, after[1].rules[0].min);
+
+        // A lone cell matches none of its rules, so its default state is what comes next.
+        let mut sim_state = square_simulation(4);
+        let lone = IVec2::new(1, 1);
+        sim_state.set_at(lone, 1);
+        sim_state.process();
+        assert_eq!(sim_state.get_pending(lone), 0);
+        sim_state.set_rule_value(TileShape::Square, 1, 0, 1, RuleUpdateTarget::DefaultValue);
+        assert_eq!(sim_state.get_pending(lone), 1);
+    }
+
+    #[test]
+    fn toggling_counts_flips_membership() {
+        let mut sim_state = square_simulation(4);
+        let mut set = |target, value| {
+            sim_state.set_rule_value(TileShape::Square, 1, 0, value, target);
+            sim_state.clone_rules_for_shape(TileShape::Square)[1].rules[0].clone()
+        };
+
+        assert_eq!(set(RuleUpdateTarget::ToggleCount, 0).neighbor_states_to_count, vec![1, 0]);
+        assert_eq!(set(RuleUpdateTarget::ToggleCount, 1).neighbor_states_to_count, vec![0]);
+        assert_eq!(set(RuleUpdateTarget::ToggleCount, 1).neighbor_states_to_count, vec![0, 1]);
+        // There is no state 5 to count.
+        assert_eq!(set(RuleUpdateTarget::ToggleCount, 5).neighbor_states_to_count, vec![0, 1]);
+
+        let expected = |states, min, max| CountConstraint { states, min, max };
+        assert_eq!(
+            set(RuleUpdateTarget::AddConstraint, 0).extra_counts,
+            vec![expected(vec![], 0, 0)]
+        );
+        set(RuleUpdateTarget::ToggleConstraintCount(0), 1);
+        set(RuleUpdateTarget::ToggleConstraintCount(0), 5);
+        set(RuleUpdateTarget::ConstraintMin(0), 1);
+        let rule = set(RuleUpdateTarget::ConstraintMax(0), 20);
+        assert_eq!(rule.extra_counts, vec![expected(vec![1], 1, 8)]);
+        let rule = set(RuleUpdateTarget::ToggleConstraintCount(0), 1);
+        assert!(rule.extra_counts[0].states.is_empty());
+
+        // Extra counts that don't exist are ignored.
+        assert_eq!(set(RuleUpdateTarget::ConstraintMin(3), 2).extra_counts.len(), 1);
+        assert_eq!(set(RuleUpdateTarget::RemoveConstraint(3), 0).extra_counts.len(), 1);
+        assert!(set(RuleUpdateTarget::RemoveConstraint(0), 0).extra_counts.is_empty());
+    }
+
+    #[test]
+    fn try_set_at_rejects_states_the_tile_does_not_have() {
+        let mut sim_state = square_simulation(8);
+        assert_eq!(
+            sim_state.try_set_at(IVec2::new(2, 3), 2),
+            Err(SetError::NoSuchState {
+                index: IVec2::new(2, 3),
+                state: 2,
+                num_states: 2,
+            })
+        );
+        sim_state.process();
+        assert_eq!(sim_state.get_at(IVec2::new(2, 3)), 0);
+
+        sim_state.add_state(TileShape::Square);
+        assert_eq!(sim_state.try_set_at(IVec2::new(2, 3), 2), Ok(vec![(IVec2::new(2, 3), 2)]));
+        sim_state.process();
+        assert_eq!(sim_state.get_at(IVec2::new(2, 3)), 2);
+    }
+
+    #[test]
+    fn age_resets_on_change_and_grows_while_stable() {
+        let mut sim_state = square_simulation(8);
+        // A block is stable and its neighbors stay empty.
+        for (x, y) in [(2, 2), (3, 2), (2, 3), (3, 3)] {
+            sim_state.set_at(IVec2::new(x, y), 1);
+        }
+        sim_state.advance(Duration::ZERO);
+        assert_eq!(sim_state.get_age(IVec2::new(2, 2)), 0);
+        assert_eq!(sim_state.get_age(IVec2::new(5, 5)), 0);
+
+        sim_state.run_generation();
+        sim_state.run_generation();
+        assert_eq!(sim_state.get_age(IVec2::new(2, 2)), 2);
+        assert_eq!(sim_state.get_age(IVec2::new(10, 10)), 2);
+
+        // Changing a cell starts its count again, and setting it to the state it is already in
+        // does not.
+        sim_state.set_at(IVec2::new(2, 2), 0);
+        sim_state.set_at(IVec2::new(3, 3), 1);
+        sim_state.advance(Duration::ZERO);
+        assert_eq!(sim_state.get_age(IVec2::new(2, 2)), 0);
+        assert_eq!(sim_state.get_age(IVec2::new(3, 3)), 2);
+        sim_state.run_generation();
+        // The cell came back to life with the generation, and the one next to it stayed.
+        assert_eq!(sim_state.get_at(IVec2::new(2, 2)), 1);
+        assert_eq!(sim_state.get_age(IVec2::new(2, 2)), 0);
+        assert_eq!(sim_state.get_age(IVec2::new(3, 2)), 3);
+    }
+
+    #[test]
+    fn flood_fill_only_reaches_connected_cells() {
+        let mut sim_state = square_simulation(8);
+        // A blob wrapping across the left edge, and a separate cell it never touches.
+        let blob = [(0, 3), (0, 4), (7, 4), (6, 5), (1, 2)].map(|(x, y)| IVec2::new(x, y));
+        for index in blob {
+            sim_state.set_at(index, 1);
+        }
+        sim_state.set_at(IVec2::new(4, 0), 1);
+        sim_state.advance(Duration::ZERO);
+
+        assert!(sim_state.flood_fill(IVec2::new(0, 3), 1).is_empty());
+        let mut filled = sim_state.flood_fill(IVec2::new(8, 3), 0);
+        filled.sort_unstable_by_key(|(index, _)| (index.x, index.y));
+        let mut expected = blob.map(|index| (index, 0)).to_vec();
+        expected.sort_unstable_by_key(|(index, _)| (index.x, index.y));
+        assert_eq!(filled, expected);
+
+        sim_state.advance(Duration::ZERO);
+        assert_eq!(sim_state.population(), vec![63, 1]);
+        assert_eq!(sim_state.get_at(IVec2::new(4, 0)), 1);
+
+        // Filling the empty cells stops at the ones in other states and covers the rest.
+        let filled = sim_state.flood_fill(IVec2::ZERO, 1);
+        assert_eq!(filled.len(), 63);
+        // There is no state 2 for squares to fill with.
+        assert!(sim_state.flood_fill(IVec2::ZERO, 2).is_empty());
+    }
+
+    #[test]
+    fn try_set_at_only_wraps_toroidal_boards() {
+        let mut sim_state = square_simulation(8);
+        assert_eq!(sim_state.try_set_at(IVec2::new(9, -1), 1), Ok(vec![(IVec2::new(1, 7), 1)]));
+
+        sim_state.set_boundary(BoundaryMode::Dead);
+        for index in [IVec2::new(8, 0), IVec2::new(-1, 3), IVec2::new(2, 8)] {
+            assert_eq!(sim_state.try_set_at(index, 1), Err(SetError::OutOfBounds(index)));
+        }
+        assert_eq!(sim_state.try_set_at(IVec2::new(7, 7), 1), Ok(vec![(IVec2::new(7, 7), 1)]));
+        sim_state.process();
+        assert_eq!(sim_state.get_at(IVec2::new(1, 7)), 1);
+        assert_eq!(sim_state.get_at(IVec2::new(7, 7)), 1);
+        assert_eq!(sim_state.population()[1], 2);
+    }
+
+    #[test]
+    fn removing_a_state_sends_its_cells_and_producers_to_zero() {
+        let mut sim_state = square_simulation(8);
+        sim_state.add_state(TileShape::Square);
+        sim_state.set_rule_value(TileShape::Square, 1, 0, 2, RuleUpdateTarget::ResultValue);
+        sim_state.set_at(IVec2::new(6, 6), 2);
+        sim_state.process();
+
+        let removed = sim_state.remove_state(2).unwrap();
+        assert_eq!(removed.changed, vec![(IVec2::new(6, 6), 0)]);
+        assert!(removed.clamped.is_empty());
+        let rules = sim_state.clone_rules_for_shape(TileShape::Square);
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[1].rules[0].output, 0);
+        assert_eq!(sim_state.num_states, 2);
+        assert_eq!(sim_state.get_at(IVec2::new(6, 6)), 0);
+        assert_eq!(sim_state.get_neighbor_count(IVec2::new(5, 6), 0), 8);
+        // Running on afterwards must not look up the removed state.
+        sim_state.process();
+        sim_state.process();
+        assert!(sim_state.validate_and_clamp_rules().is_empty());
+    }
+
+    #[test]
+    fn removing_a_middle_state_shifts_higher_states_down() {
+        let mut sim_state = square_simulation(8);
+        sim_state.add_state(TileShape::Square);
+        sim_state.add_state(TileShape::Square);
+        sim_state.add_rule(TileShape::Square, 3);
+        sim_state.set_rule_value(TileShape::Square, 3, 0, 3, RuleUpdateTarget::ResultValue);
+        sim_state.set_rule_value(TileShape::Square, 3, 0, 3, RuleUpdateTarget::ToggleCount);
+        sim_state.set_rule_value(TileShape::Square, 2, 0, 3, RuleUpdateTarget::DefaultValue);
+        sim_state.set_at(IVec2::new(2, 2), 3);
+        sim_state.set_at(IVec2::new(3, 2), 2);
+        sim_state.set_at(IVec2::new(4, 2), 1);
+        sim_state.process();
+
+        let mut changed = sim_state.remove_state(2).unwrap().changed;
+        changed.sort_unstable_by_key(|(index, _)| (index.x, index.y));
+        assert_eq!(changed, vec![(IVec2::new(2, 2), 2), (IVec2::new(3, 2), 0)]);
+        let rules = sim_state.clone_rules_for_shape(TileShape::Square);
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[2].rules[0].output, 2);
+        assert_eq!(rules[2].rules[0].neighbor_states_to_count, vec![2]);
+        assert_eq!(sim_state.get_at(IVec2::new(4, 2)), 1);
+        // The cell next to all three now sees one neighbor in each of states 1 and 2.
+        assert_eq!(sim_state.get_neighbor_count(IVec2::new(3, 3), 1), 1);
+        assert_eq!(sim_state.get_neighbor_count(IVec2::new(3, 3), 2), 1);
+        assert_eq!(sim_state.get_neighbor_count(IVec2::new(3, 3), 0), 6);
+    }
+
+    #[test]
+    fn stepping_back_after_removing_a_state_uses_the_remapped_states() {
+        let mut sim_state = square_simulation(8);
+        sim_state.add_state(TileShape::Square);
+        // Surviving cells move on to state 2, which then dies off by default.
+        sim_state.set_rule_value(TileShape::Square, 1, 0, 2, RuleUpdateTarget::ResultValue);
+        let block = [(3, 3), (4, 3), (3, 4), (4, 4)].map(IVec2::from);
+        for index in block {
+            sim_state.set_at(index, 1);
+        }
+        sim_state.process();
+        sim_state.run_generation();
+        assert!(block.iter().all(|index| sim_state.get_at(*index) == 2));
+        sim_state.run_generation();
+        assert!(block.iter().all(|index| sim_state.get_at(*index) == 0));
+
+        sim_state.remove_state(2).unwrap();
+        // The generation that took the block out of state 2 now puts it back in state 0.
+        assert!(sim_state.step_back().is_some());
+        assert!(block.iter().all(|index| sim_state.get_at(*index) == 0));
+        assert!(sim_state.step_back().is_some());
+        assert!(block.iter().all(|index| sim_state.get_at(*index) == 1));
+        assert_eq!(sim_state.get_neighbor_count(IVec2::new(3, 3), 1), 3);
+        assert_eq!(sim_state.population(), vec![60, 4]);
+    }
+
+    #[test]
+    fn removing_a_state_rejects_ones_it_cannot_remap() {
+        let mut sim_state = square_simulation(8);
+        sim_state.add_state(TileShape::Square);
+        assert_eq!(sim_state.remove_state(0).err(), Some(RemoveStateError::StateZero));
+        assert_eq!(
+            sim_state.remove_state(3).err(),
+            Some(RemoveStateError::NoSuchState(3))
+        );
+        // The default rules count state 1 to decide births and deaths.
+        assert_eq!(
+            sim_state.remove_state(1).err(),
+            Some(RemoveStateError::StillCounted {
+                shape: TileShape::Square,
+                state: 0,
+                rule: 0,
+            })
+        );
+        assert_eq!(sim_state.num_states, 3);
+        assert!(sim_state.remove_state(2).is_ok());
+    }
+
+    #[test]
+    fn cached_neighbor_counts_match_recount() {
+        let mut sim_state = square_simulation(12);
+        sim_state.add_state(TileShape::Square);
+        sim_state.set_rule_value(TileShape::Square, 0, 0, 2, RuleUpdateTarget::ResultValue);
+        sim_state.run_every = 1;
+        // An R-pentomino near the edge so it spreads across the wrap, plus a cell in state 2.
+        for (x, y) in [(1, 0), (2, 0), (0, 1), (1, 1), (1, 2)] {
+            sim_state.set_at(IVec2::new(x, y), 1);
+        }
+        sim_state.set_at(IVec2::new(8, 8), 2);
+        for _ in 0..12 {
+            sim_state.process();
+        }
+
+        for index in sim_state.tiling.iter_indices() {
+            for neighbor_state in 0..=sim_state.num_states as u32 {
+                let recount = sim_state
+                    .tiling
+                    .get_neighbors(index)
+                    .iter()
+                    .filter(|offset| {
+                        sim_state.get_at(index + IVec2::from(**offset)) == neighbor_state
+                    })
+                    .count() as u32;
+                assert_eq!(
+                    sim_state.get_neighbor_count(index, neighbor_state),
+                    recount,
+                    "{:?} neighbors in state {}",
+                    index,
+                    neighbor_state
+                );
+            }
+        }
+    }
+
+    // The live cells after a vertical blinker along the left edge takes one step.
+    fn corner_blinker_step(boundary: BoundaryMode) -> Vec<IVec2> {
+        let mut sim_state = square_simulation(8);
+        sim_state.set_boundary(boundary);
+        for y in 0..3 {
+            sim_state.set_at(IVec2::new(0, y), 1);
+        }
+        sim_state.run_every = 1;
+        sim_state.process();
+        sim_state.process();
+        sim_state
+            .tiling
+            .iter_indices()
+            .filter(|index| sim_state.get_at(*index) == 1)
+            .collect()
+    }
+
+    #[test]
+    fn dead_boundary_cuts_off_corner_blinker() {
+        // Wrapping around keeps the blinker whole, with its left cell on the far edge.
+        assert_eq!(
+            corner_blinker_step(BoundaryMode::Toroidal),
+            vec![IVec2::new(0, 1), IVec2::new(1, 1), IVec2::new(7, 1)]
+        );
+        // With nothing past the edge there is no cell on the far side to be born.
+        assert_eq!(
+            corner_blinker_step(BoundaryMode::Dead),
+            vec![IVec2::new(0, 1), IVec2::new(1, 1)]
+        );
+    }
+
+    #[test]
+    fn resize_keeps_cells_inside_new_bounds() {
+        let mut sim_state = square_simulation(20);
+        sim_state.add_state(TileShape::Square);
+        sim_state.set_at(IVec2::new(2, 3), 1);
+        sim_state.set_at(IVec2::new(9, 9), 2);
+        sim_state.set_at(IVec2::new(15, 4), 1);
+        sim_state.set_at(IVec2::new(4, 12), 1);
+        sim_state.process();
+        // Not applied yet, but still on the board once it shrinks.
+        sim_state.set_at(IVec2::new(0, 9), 1);
+
+        sim_state.resize(IVec2::new(10, 10));
+        sim_state.process();
+        assert_eq!(sim_state.tiling.max_index, IVec2::new(10, 10));
+        assert_eq!(sim_state.num_states, 3);
+        let live = sim_state
+            .tiling
+            .iter_indices()
+            .filter(|index| sim_state.get_at(*index) != 0)
+            .map(|index| (index, sim_state.get_at(index)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            live,
+            vec![
+                (IVec2::new(0, 9), 1),
+                (IVec2::new(2, 3), 1),
+                (IVec2::new(9, 9), 2)
+            ]
+        );
+        // Neighbors are counted across the new edges.
+        assert_eq!(sim_state.get_neighbor_count(IVec2::new(0, 0), 2), 1);
+
+        sim_state.resize(IVec2::new(30, 12));
+        sim_state.process();
+        assert_eq!(sim_state.get_at(IVec2::new(9, 9)), 2);
+        assert_eq!(sim_state.get_at(IVec2::new(15, 4)), 0);
+    }
+
+    fn rule(min: u32, max: u32, neighbor_states_to_count: Vec<u32>) -> StateRule {
+        StateRule {
+            min,
+            max,
+            neighbor_states_to_count,
+            include_self: false,
+            direction_mask: None,
+            extra_counts: Vec::new(),
+            output: 1,
+            probability: 1.0,
+            priority: 0,
+        }
+    }
+
+    #[test]
+    fn shadowed_rule_is_unreachable() {
+        let rules = StateRules {
+            default_state: 0,
+            evaluation: RuleEvaluation::FirstMatch,
+            rules: vec![rule(2, 4, vec![1]), rule(3, 3, vec![1])],
+        };
+        assert_eq!(rules.find_unreachable_rules(), vec![1]);
+    }
+
+    #[test]
+    fn disjoint_counts_are_not_shadowed() {
+        let rules = StateRules {
+            default_state: 0,
+            evaluation: RuleEvaluation::FirstMatch,
+            rules: vec![rule(2, 4, vec![1]), rule(3, 3, vec![2])],
+        };
+        assert!(rules.find_unreachable_rules().is_empty());
+    }
+
+    #[test]
+    fn shadowed_by_multiple_rules() {
+        let rules = StateRules {
+            default_state: 0,
+            evaluation: RuleEvaluation::FirstMatch,
+            rules: vec![
+                rule(0, 1, vec![1, 2]),
+                rule(2, 3, vec![2, 1]),
+                rule(1, 3, vec![1, 2]),
+                rule(1, 4, vec![1, 2]),
+            ],
+        };
+        assert_eq!(rules.find_unreachable_rules(), vec![2]);
+    }
+
+    #[test]
+    fn direction_masks_grow_in_one_direction() {
+        let mut sim = square_simulation(8);
+        // Dead cells come alive when the neighbor to their left is alive, and live cells stay
+        // alive whatever is around them.
+        let mut growth = rule(1, 1, vec![1]);
+        growth.output = 1;
+        sim.states.insert(
+            TileShape::Square,
+            vec![state_with_rules(0, vec![growth]), state_with_rules(1, vec![])],
+        );
+        assert_eq!(sim.tiling.get_neighbors(IVec2::ZERO)[1], (-1, 0));
+        sim.set_rule_value(TileShape::Square, 0, 0, 1, RuleUpdateTarget::ToggleDirection);
+        assert_eq!(sim.clone_rules_for_shape(TileShape::Square)[0].rules[0].direction_mask, Some(2));
+
+        sim.set_at(IVec2::new(2, 3), 1);
+        sim.process();
+        for _ in 0..3 {
+            sim.run_generation();
+        }
+        let alive = (0..8)
+            .flat_map(|x| (0..8).map(move |y| IVec2::new(x, y)))
+            .filter(|index| sim.get_at(*index) == 1)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            alive,
+            vec![IVec2::new(2, 3), IVec2::new(3, 3), IVec2::new(4, 3), IVec2::new(5, 3)]
+        );
+
+        // Toggling the only direction off goes back to counting all of them.
+        sim.set_rule_value(TileShape::Square, 0, 0, 1, RuleUpdateTarget::ToggleDirection);
+        assert_eq!(sim.clone_rules_for_shape(TileShape::Square)[0].rules[0].direction_mask, None);
+    }
+
+    #[test]
+    fn moving_a_rule_changes_which_one_matches_first() {
+        let mut sim = square_simulation(6);
+        sim.add_state(TileShape::Square);
+        // A lone live cell matches both "no live neighbors" rules, and the first one wins.
+        let mut to_zero = rule(0, 0, vec![1]);
+        to_zero.output = 0;
+        let mut to_two = rule(0, 0, vec![1]);
+        to_two.output = 2;
+        sim.states.insert(
+            TileShape::Square,
+            vec![
+                state_with_rules(0, vec![]),
+                state_with_rules(1, vec![to_zero, to_two]),
+                state_with_rules(2, vec![]),
+            ],
+        );
+        let cell = IVec2::new(2, 2);
+        sim.set_at(cell, 1);
+        sim.process();
+        assert_eq!(sim.get_pending(cell), 0);
+
+        sim.move_rule(TileShape::Square, 1, 1, 0);
+        assert_eq!(sim.get_pending(cell), 2);
+        assert_eq!(sim.clone_rules_for_shape(TileShape::Square)[1].rules[1].output, 0);
+
+        // Moving a rule that does not exist leaves the order alone.
+        sim.move_rule(TileShape::Square, 1, 0, 2);
+        assert_eq!(sim.get_pending(cell), 2);
+    }
+
+    #[test]
+    fn deleting_the_matching_rule_falls_back_to_the_default_state() {
+        // Lone live cells die under the default rules as no survival rule matches them.
+        let cells = [IVec2::new(2, 2), IVec2::new(2, 3)];
+        let mut sim = square_simulation(6);
+        sim.set_rule_value(TileShape::Square, 1, 0, 0, RuleUpdateTarget::ToggleIncludeSelf);
+        for cell in cells {
+            sim.set_at(cell, 1);
+        }
+        sim.process();
+        for cell in cells {
+            assert_eq!(sim.get_pending(cell), 1);
+        }
+
+        // A stale rule number does nothing.
+        sim.delete_rule(TileShape::Square, 1, 5);
+        assert_eq!(sim.get_pending(cells[0]), 1);
+
+        sim.delete_rule(TileShape::Square, 1, 0);
+        assert!(sim.clone_rules_for_shape(TileShape::Square)[1].rules.is_empty());
+        for cell in cells {
+            assert_eq!(sim.get_pending(cell), 0);
+        }
+    }
+
+    #[test]
+    fn extra_counts_must_all_hold() {
+        let mut sim = square_simulation(8);
+        sim.add_state(TileShape::Square);
+        // Dead cells become state 2 with 2-3 neighbors in state 1 and at most 1 in state 2.
+        let mut birth = rule(2, 3, vec![1]);
+        birth.output = 2;
+        birth.extra_counts.push(CountConstraint {
+            states: vec![2],
+            min: 0,
+            max: 1,
+        });
+        sim.states.insert(
+            TileShape::Square,
+            vec![
+                state_with_rules(0, vec![birth]),
+                state_with_rules(1, vec![]),
+                state_with_rules(2, vec![]),
+            ],
+        );
+        let cell = IVec2::new(3, 3);
+        sim.set_at(IVec2::new(2, 3), 1);
+        sim.set_at(IVec2::new(4, 3), 1);
+        sim.set_at(IVec2::new(3, 2), 2);
+        sim.process();
+        assert_eq!(sim.get_pending(cell), 2);
+
+        // A second neighbor in state 2 breaks the extra count.
+        sim.set_at(IVec2::new(3, 4), 2);
+        sim.process();
+        assert_eq!(sim.get_pending(cell), 0);
+
+        // As does dropping below the first range.
+        sim.set_at(IVec2::new(3, 4), 0);
+        sim.set_at(IVec2::new(4, 3), 0);
+        sim.process();
+        assert_eq!(sim.get_pending(cell), 0);
+    }
+
+    #[test]
+    fn include_self_counts_own_state() {
+        // Two live cells next to each other only have one live neighbor each, so under
+        // the default survival rule (2-3 live neighbors) they die. Counting themselves
+        // brings the total to 2 and they survive.
+        let cells = [IVec2::new(2, 2), IVec2::new(2, 3)];
+
+        let mut sim = square_simulation(6);
+        for cell in cells {
+            sim.set_at(cell, 1);
+        }
+        sim.process();
+        for cell in cells {
+            assert_eq!(sim.get_pending(cell), 0);
+        }
+
+        let mut sim = square_simulation(6);
+        sim.set_rule_value(TileShape::Square, 1, 0, 0, RuleUpdateTarget::ToggleIncludeSelf);
+        for cell in cells {
+            sim.set_at(cell, 1);
+        }
+        sim.process();
+        for cell in cells {
+            assert_eq!(sim.get_pending(cell), 1);
+        }
+    }
+
+    #[test]
+    fn neighbor_count_distribution_matches_recount() {
+        let mut sim = square_simulation(8);
+        // A glider and a lone cell in a second state.
+        for cell in [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)] {
+            sim.set_at(IVec2::new(cell.0, cell.1), 1);
+        }
+        sim.add_state(TileShape::Square);
+        sim.set_at(IVec2::new(5, 5), 2);
+        sim.process();
+
+        let mut expected = vec![Vec::new(); sim.num_states];
+        for x in 0..8 {
+            for y in 0..8 {
+                let index = IVec2::new(x, y);
+                let live = sim
+                    .tiling
+                    .get_neighbors(index)
+                    .iter()
+                    .filter(|(dx, dy)| sim.get_at(index + IVec2::new(*dx, *dy)) != 0)
+                    .count();
+                let counts: &mut Vec<u32> = &mut expected[sim.get_at(index) as usize];
+                if counts.len() <= live {
+                    counts.resize(live + 1, 0);
+                }
+                counts[live] += 1;
+            }
+        }
+
+        let distribution = sim.neighbor_count_distribution();
+        assert_eq!(distribution, expected);
+        assert_eq!(distribution[1], vec![0, 2, 1, 2]);
+        assert_eq!(distribution[2], vec![1]);
+    }
+
+    // Three cells of a block. Each has two live neighbors so they survive and the missing
+    // corner of the block has three so it is born.
+    fn run_partial_block_with_birth_chance(percent: u32, generations: u32) -> SimulationState {
+        let mut sim = square_simulation(6);
+        sim.set_rule_value(TileShape::Square, 0, 0, percent, RuleUpdateTarget::Probability);
+        for cell in [IVec2::new(1, 1), IVec2::new(1, 2), IVec2::new(2, 1)] {
+            sim.set_at(cell, 1);
+        }
+        sim.process();
+        for _ in 0..generations {
+            sim.step = 1;
+            sim.process();
+        }
+        sim
+    }
+
+    #[test]
+    fn zero_probability_never_applies() {
+        let sim = run_partial_block_with_birth_chance(0, 20);
+        assert_eq!(sim.get_at(IVec2::new(2, 2)), 0);
+        assert_eq!(sim.get_pending(IVec2::new(2, 2)), 1);
+    }
+
+    #[test]
+    fn full_probability_always_applies() {
+        let sim = run_partial_block_with_birth_chance(100, 1);
+        assert_eq!(sim.get_at(IVec2::new(2, 2)), 1);
+    }
+
+    // A block of live cells where every cell has three live neighbors. State 1 gets a second
+    // rule, listed after the survival rule, that kills a cell with any number of neighbors.
+    fn block_with_death_rule(evaluation: RuleEvaluation) -> SimulationState {
+        let mut sim = square_simulation(6);
+        sim.add_rule(TileShape::Square, 1);
+        sim.set_rule_value(TileShape::Square, 1, 1, 1, RuleUpdateTarget::ToggleCount);
+        sim.set_rule_value(TileShape::Square, 1, 1, 8, RuleUpdateTarget::MaxValue);
+        sim.set_rule_value(TileShape::Square, 1, 1, 5, RuleUpdateTarget::Priority);
+        sim.set_rule_value(
+            TileShape::Square,
+            1,
+            0,
+            evaluation.value(),
+            RuleUpdateTarget::EvaluationMode,
+        );
+        for cell in [IVec2::new(1, 1), IVec2::new(1, 2), IVec2::new(2, 1), IVec2::new(2, 2)] {
+            sim.set_at(cell, 1);
+        }
+        sim.process();
+        sim
+    }
+
+    #[test]
+    fn higher_priority_rule_overrides_earlier_match() {
+        let sim = block_with_death_rule(RuleEvaluation::FirstMatch);
+        assert_eq!(sim.get_pending(IVec2::new(1, 1)), 1);
+
+        let sim = block_with_death_rule(RuleEvaluation::Priority);
+        assert_eq!(sim.get_pending(IVec2::new(1, 1)), 0);
+    }
+
+    #[test]
+    fn lower_priority_rule_is_shadowed_by_later_rule() {
+        let mut rules = StateRules {
+            default_state: 0,
+            evaluation: RuleEvaluation::Priority,
+            rules: vec![rule(3, 3, vec![1]), rule(2, 4, vec![1])],
+        };
+        rules.rules[1].priority = 1;
+        assert_eq!(rules.find_unreachable_rules(), vec![0]);
+
+        rules.evaluation = RuleEvaluation::FirstMatch;
+        assert!(rules.find_unreachable_rules().is_empty());
+    }
+
+    fn state_with_rules(default_state: u32, rules: Vec<StateRule>) -> StateRules {
+        StateRules {
+            default_state,
+            evaluation: RuleEvaluation::FirstMatch,
+            rules,
+        }
+    }
+
+    fn rule_to(output: u32) -> StateRule {
+        StateRule {
+            output,
+            ..rule(3, 3, vec![1])
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Cell [7, 2] has no neighbors in state 1 to replace with 0")]
+    fn desynced_neighbor_counts_are_caught() {
+        let rules = vec![state_with_rules(0, vec![]), state_with_rules(1, vec![])];
+        let mut cell = SimulationCellState::new(0, 4, 2);
+        // All four neighbors are counted as being in state 0, so none can leave state 1.
+        cell.apply_change(IVec2::new(7, 2), None, 1, 0, &rules);
+    }
+
+    #[test]
+    fn isolated_state_is_unreachable() {
+        // 0 <-> 1 while 2 only ever leads back to 0.
+        let rules = vec![
+            state_with_rules(0, vec![rule_to(1)]),
+            state_with_rules(0, vec![rule_to(1)]),
+            state_with_rules(0, vec![rule_to(1)]),
+        ];
+        assert_eq!(find_unreachable_states(&rules), vec![2]);
+    }
+
+    #[test]
+    fn shadowed_rule_does_not_reach_its_output() {
+        let rules = vec![
+            state_with_rules(0, vec![rule(2, 4, vec![1]), rule_to(2)]),
+            state_with_rules(0, Vec::new()),
+            state_with_rules(0, Vec::new()),
+        ];
+        assert_eq!(find_unreachable_states(&rules), vec![2]);
+    }
+
+    #[test]
+    fn connected_states_are_all_reachable() {
+        // 0 -> 1 by rule, 1 -> 2 by default and 2 -> 0 by rule.
+        let rules = vec![
+            state_with_rules(0, vec![rule_to(1)]),
+            state_with_rules(2, vec![rule_to(1)]),
+            state_with_rules(2, vec![rule_to(0)]),
+        ];
+        assert!(find_unreachable_states(&rules).is_empty());
+        let defaults = square_simulation(2).clone_rules_for_shape(TileShape::Square);
+        assert!(find_unreachable_states(&defaults).is_empty());
+    }
+
+    // Run a blinker with the given number of frames between generations, feeding it the deltas,
+    // and count how many times it ran a generation.
+    fn count_generations(run_every: u32, deltas: impl IntoIterator<Item = Duration>) -> usize {
+        let mut sim = square_simulation(5);
+        for cell in [IVec2::new(1, 2), IVec2::new(2, 2), IVec2::new(3, 2)] {
+            sim.set_at(cell, 1);
+        }
+        sim.process();
+        sim.run_every = run_every;
+        // Every generation of a blinker flips it, so each one sets cells.
+        deltas
+            .into_iter()
+            .filter(|delta| !sim.advance(*delta).is_empty())
+            .count()
+    }
+
+    #[test]
+    fn generations_follow_elapsed_time() {
+        // One frame at a time matches counting frames: a generation every 6 calls.
+        assert_eq!(count_generations(6, vec![FRAME_TIME; 60]), 10);
+        // Frames twice as long run generations twice as often.
+        assert_eq!(count_generations(6, vec![FRAME_TIME * 2; 60]), 20);
+        // 20ms frames need 5 of them to cover the 100ms between generations.
+        assert_eq!(count_generations(6, vec![Duration::from_millis(20); 50]), 10);
+        // A frame longer than the wait still only runs one generation.
+        assert_eq!(count_generations(6, vec![Duration::from_secs(1); 7]), 7);
+        // Uneven frames: a generation on the first call, once 10 + 60 + 50ms have passed, and
+        // again after the 200ms frame.
+        let deltas = [10, 60, 50, 200, 30].map(Duration::from_millis);
+        assert_eq!(count_generations(6, deltas), 3);
+        // Not running means no generations however much time passes.
+        assert_eq!(count_generations(0, vec![Duration::from_secs(1); 5]), 0);
+    }
+}
@@ -1,6 +1,9 @@
-use std::f32::consts::FRAC_PI_3;
+use std::{collections::VecDeque, f32::consts::FRAC_PI_3};
 
-use bevy::math::{IVec2, Quat, Vec2, Vec3Swizzles};
+use bevy::{
+    math::{IVec2, Quat, Vec2, Vec3Swizzles},
+    utils::HashSet,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TilingKind {
@@ -106,6 +109,37 @@ impl TileShape {
         }
     }
 
+    /// Positions of the corners of this shape relative to its center, going counter clockwise.
+    /// These match the meshes used to draw the tiles.
+    pub fn get_corner_positions(&self) -> Vec<Vec2> {
+        match self {
+            TileShape::RightTriangle(rotation) => {
+                let half = OCTAGON_SQUARE_DIFFERENCE_OF_CENTER * 0.5;
+                [[-half, half, 0.0], [-half, -half, 0.0], [half, -half, 0.0]]
+                    .into_iter()
+                    .map(|corner| {
+                        let corner = rotation.rotate(corner);
+                        Vec2::new(corner[0], corner[1])
+                    })
+                    .collect()
+            }
+            _ => {
+                let num_sides = self.get_side_count();
+                let angle = std::f32::consts::TAU / num_sides as f32;
+                let start = match self {
+                    TileShape::EquilateralTriangle(direction) => direction.angle(),
+                    _ => 0.5 * angle,
+                };
+                (0..num_sides)
+                    .map(|i| {
+                        let corner_angle = start + angle * i as f32;
+                        self.get_radius() * Vec2::new(corner_angle.cos(), corner_angle.sin())
+                    })
+                    .collect()
+            }
+        }
+    }
+
     pub fn get_name(&self) -> String {
         match self {
             TileShape::Square => "Square".into(),
@@ -123,6 +157,9 @@ pub struct Tile {
     pub index: IVec2,
 }
 
+/// How many tiles along each axis a new board has.
+pub const DEFAULT_GRID_SIZE: i32 = 52;
+
 pub const HEXAGON_AXIS_RIGHT: f32 = std::f32::consts::FRAC_PI_3;
 pub const HEXAGON_AXIS_LEFT: f32 = -std::f32::consts::FRAC_PI_3;
 pub const OCTAGON_SQUARE_DIFFERENCE_OF_CENTER: f32 = (2.0 + std::f32::consts::SQRT_2) / 2.0;
@@ -179,6 +216,12 @@ impl Tiling {
         index.x >= 0 && index.x < self.max_index.x && index.y >= 0 && index.y < self.max_index.y
     }
 
+    /// Every index in the tiling once, all of which `adjust_index` leaves as they are.
+    pub fn iter_indices(&self) -> impl Iterator<Item = IVec2> {
+        let max_index = self.max_index;
+        (0..max_index.x).flat_map(move |x| (0..max_index.y).map(move |y| IVec2::new(x, y)))
+    }
+
     pub fn get_verticies(&self, index: IVec2, self_is_dual: bool) -> Vec<IVec2> {
         match self.kind {
             TilingKind::Square => {
@@ -200,8 +243,48 @@ impl Tiling {
             }
             TilingKind::Hexagonal => panic!("Not yet implemented"),
             TilingKind::OctagonAndSquare => panic!("Not yet implemented"),
-            TilingKind::EquilateralTriangular => panic!("Not yet implemented"),
-            TilingKind::RightTriangular => panic!("Not yet implemented"),
+            // Each corner of a triangle is the center of a hexagon. The dual of this tiling sits
+            // two hexagons right and one up from the hexagonal tiling this is the dual of.
+            TilingKind::EquilateralTriangular => {
+                let column = (index.x + index.y).div_euclid(2);
+                let corners = match self.get_tile_at_index(index).shape {
+                    TileShape::EquilateralTriangle(EquilateralDirection::Down) => [
+                        IVec2::new(column, index.y),
+                        IVec2::new(column - 1, index.y),
+                        IVec2::new(column - 1, index.y - 1),
+                    ],
+                    _ => [
+                        IVec2::new(column, index.y - 1),
+                        IVec2::new(column, index.y),
+                        IVec2::new(column - 1, index.y - 1),
+                    ],
+                };
+                let shift = if self_is_dual { IVec2::ZERO } else { IVec2::new(2, 1) };
+                corners.iter().map(|corner| *corner + shift).collect()
+            }
+            // Each pair of triangles splits a block whose corners are the centers of the
+            // octagons and squares. The dual of this tiling sits one block right and one up.
+            TilingKind::RightTriangular => {
+                let column = index.x.div_euclid(2);
+                let top_left = IVec2::new(column - 1, index.y);
+                let bottom_left = IVec2::new(column - 1, index.y - 1);
+                let bottom_right = IVec2::new(column, index.y - 1);
+                let top_right = IVec2::new(column, index.y);
+                let corners = match self.get_tile_at_index(index).shape {
+                    TileShape::RightTriangle(RightTriangleRotation::Zero) => {
+                        [top_left, bottom_left, bottom_right]
+                    }
+                    TileShape::RightTriangle(RightTriangleRotation::One) => {
+                        [top_right, top_left, bottom_left]
+                    }
+                    TileShape::RightTriangle(RightTriangleRotation::Two) => {
+                        [bottom_right, top_right, top_left]
+                    }
+                    _ => [bottom_left, bottom_right, top_right],
+                };
+                let shift = if self_is_dual { IVec2::ZERO } else { IVec2::ONE };
+                corners.iter().map(|corner| *corner + shift).collect()
+            }
         }
     }
 
@@ -211,6 +294,10 @@ impl Tiling {
                 //Hexagonal tilings are annoying because moving upwards also moves you sideways, but the way in
                 // which one moves sideways is non-obvious. Every 2 moved upwards moves you one to the left.
                 let target_y = index.y.rem_euclid(self.max_index.y);
+                // Wrapping below the bottom gives a positive difference, which this already
+                // rounds down. Above the top it rounds towards zero, and that is what we want:
+                // with an odd height rounding down instead would stop stepping back across the
+                // top seam from returning to the tile we started on.
                 let over_count = (target_y - index.y) / 2;
                 IVec2::new(
                     (index.x + over_count).rem_euclid(self.max_index.x),
@@ -380,6 +467,18 @@ impl Tiling {
         self.get_tile_at_index(self.get_index_for_position(position))
     }
 
+    /// The tile at the index, or `None` if the index is off the edge of the tiling.
+    pub fn try_get_tile_at_index(&self, index: IVec2) -> Option<Tile> {
+        if self.in_bounds(index) {
+            Some(self.get_tile_at_index(index))
+        } else {
+            None
+        }
+    }
+
+    /// The tile at the index. Indices off the edge of the tiling are not rejected: the position
+    /// is that of the tile they wrap around to while the index and shape come from the index as
+    /// given, so adjust the index first or use `try_get_tile_at_index` to catch them.
     pub fn get_tile_at_index(&self, index: IVec2) -> Tile {
         Tile {
             position: self.get_position_from_index(index),
@@ -416,6 +515,18 @@ impl Tiling {
         }
     }
 
+    /// The most neighbors any tile of the shape has in this tiling, so the most that a rule for
+    /// the shape can ever count. Shapes the tiling does not use have none.
+    pub fn max_neighbor_count(&self, shape: TileShape) -> u32 {
+        // Which neighbors a tile has repeats every four columns and two rows in every tiling.
+        (0..4)
+            .flat_map(|x| (0..2).map(move |y| IVec2::new(x, y)))
+            .filter(|index| self.get_tile_at_index(*index).shape == shape)
+            .map(|index| self.get_neighbors(index).len() as u32)
+            .max()
+            .unwrap_or(0)
+    }
+
     pub fn get_neighbors(&self, index: IVec2) -> &'static [(i32, i32)] {
         match self.kind {
             TilingKind::Square => &[
@@ -592,6 +703,53 @@ impl Tiling {
         }
     }
 
+    /// The fewest steps across edges it takes to get from one tile to the other, wrapping around
+    /// the edges of the tiling. Only square and hexagonal tilings know which tiles share an edge,
+    /// so every other tiling gives `None`.
+    pub fn grid_distance(&self, a: IVec2, b: IVec2) -> Option<u32> {
+        let start = self.adjust_index(a);
+        let target = self.adjust_index(b);
+        let mut visited = HashSet::default();
+        visited.insert(start);
+        let mut frontier = VecDeque::from([(start, 0)]);
+        while let Some((index, distance)) = frontier.pop_front() {
+            if index == target {
+                return Some(distance);
+            }
+            for step in self.edge_steps(index)? {
+                // Adjusting the index takes care of both the wrap and the hexagonal skew.
+                let next = self.adjust_index(index + step);
+                if visited.insert(next) {
+                    frontier.push_back((next, distance + 1));
+                }
+            }
+        }
+        None
+    }
+
+    // The offsets to the tiles sharing an edge with the tile at the index, if the tiling knows
+    // them.
+    fn edge_steps(&self, index: IVec2) -> Option<Vec<IVec2>> {
+        match self.kind {
+            // Every neighbor of a hexagon shares an edge with it.
+            TilingKind::Hexagonal => Some(
+                self.get_neighbors(index)
+                    .iter()
+                    .map(|(x, y)| IVec2::new(*x, *y))
+                    .collect(),
+            ),
+            TilingKind::Square => Some(
+                self.get_adjacent(index)
+                    .iter()
+                    .map(|(x, y, _)| IVec2::new(*x, *y))
+                    .collect(),
+            ),
+            TilingKind::OctagonAndSquare
+            | TilingKind::EquilateralTriangular
+            | TilingKind::RightTriangular => None,
+        }
+    }
+
     pub fn get_adjacent(&self, _index: IVec2) -> &'static [(i32, i32, usize)] {
         match self.kind {
             TilingKind::Square => &[(0, 1, 2), (1, 0, 3), (0, -1, 0), (-1, 0, 1),],
@@ -634,3 +792,300 @@ impl Tiling {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        math::{IVec2, Vec2},
+        utils::HashMap,
+    };
+
+    use super::{TileShape, Tiling, TilingKind};
+
+    fn tiling(kind: TilingKind, size: i32) -> Tiling {
+        Tiling {
+            kind,
+            max_index: IVec2::new(size, size),
+            offset: Vec2::ZERO,
+        }
+    }
+
+    #[test]
+    fn iter_indices_covers_every_index_once() {
+        for kind in [
+            TilingKind::Square,
+            TilingKind::Hexagonal,
+            TilingKind::OctagonAndSquare,
+            TilingKind::EquilateralTriangular,
+            TilingKind::RightTriangular,
+        ] {
+            let tiling = Tiling {
+                kind,
+                max_index: IVec2::new(5, 4),
+                offset: Vec2::ZERO,
+            };
+            let indices = tiling.iter_indices().collect::<Vec<_>>();
+            assert_eq!(indices.len(), 20);
+            for (i, index) in indices.iter().enumerate() {
+                assert!(tiling.in_bounds(*index));
+                assert_eq!(tiling.adjust_index(*index), *index);
+                assert!(!indices[..i].contains(index));
+            }
+        }
+    }
+    #[test]
+    fn try_get_tile_only_returns_tiles_in_bounds() {
+        for kind in [
+            TilingKind::Square,
+            TilingKind::Hexagonal,
+            TilingKind::OctagonAndSquare,
+            TilingKind::EquilateralTriangular,
+            TilingKind::RightTriangular,
+        ] {
+            let tiling = tiling(kind, 5);
+            for index in [IVec2::ZERO, IVec2::new(4, 0), IVec2::new(2, 3), IVec2::new(4, 4)] {
+                let tile = tiling.try_get_tile_at_index(index).unwrap();
+                let expected = tiling.get_tile_at_index(index);
+                assert_eq!(tile.index, index);
+                assert_eq!(tile.shape, expected.shape);
+                assert_eq!(tile.position, expected.position);
+            }
+            for index in [
+                IVec2::new(-1, 0),
+                IVec2::new(0, -1),
+                IVec2::new(5, 2),
+                IVec2::new(2, 5),
+                IVec2::new(7, 9),
+            ] {
+                assert!(tiling.try_get_tile_at_index(index).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn hexagonal_wrap_lands_next_to_the_seam() {
+        for height in [4, 5, 6, 7] {
+            let tiling = Tiling {
+                kind: TilingKind::Hexagonal,
+                max_index: IVec2::new(6, height),
+                offset: Vec2::ZERO,
+            };
+            for x in 0..6 {
+                // Below the bottom row is the tile that steps back up onto the bottom row.
+                let below = tiling.adjust_index(IVec2::new(x, -1));
+                assert_eq!(below.y, height - 1);
+                assert_eq!(tiling.adjust_index(below + IVec2::new(0, 1)), IVec2::new(x, 0));
+                // Above the top row is the tile that steps back down onto the top row.
+                let above = tiling.adjust_index(IVec2::new(x, height));
+                assert_eq!(above.y, 0);
+                assert_eq!(
+                    tiling.adjust_index(above - IVec2::new(0, 1)),
+                    IVec2::new(x, height - 1)
+                );
+            }
+            // Wrapping upwards moves a tile left for every two rows wrapped.
+            assert_eq!(
+                tiling.adjust_index(IVec2::new(3, height)),
+                IVec2::new(3 - height / 2, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn hexagonal_neighbors_are_adjacent_across_the_seams() {
+        let width = TileShape::Hexagon.get_width();
+        for height in [4, 5, 6, 7] {
+            let tiling = Tiling {
+                kind: TilingKind::Hexagonal,
+                max_index: IVec2::new(6, height),
+                offset: Vec2::ZERO,
+            };
+            // Wrapping up a whole board lands `height / 2` tiles left, which is half a tile short
+            // of straight up when the height is odd.
+            let across = Vec2::new(width * 6.0, 0.0);
+            let up = tiling.compute_offset_between_indicies(
+                IVec2::new(-(height / 2), 0),
+                IVec2::new(0, height),
+            );
+            let is_board_multiple = |offset: Vec2| {
+                (-1..=1).any(|times_up| {
+                    let rest = offset - times_up as f32 * up;
+                    let times_across = (rest.x / across.x).round();
+                    rest.y.abs() < 1e-3 && (rest.x - times_across * across.x).abs() < 1e-3
+                })
+            };
+            for index in tiling.iter_indices() {
+                let position = tiling.get_position_from_index(index);
+                let mut seen = Vec::new();
+                for (x, y) in tiling.get_neighbors(index) {
+                    let neighbor = tiling.adjust_index(index + IVec2::new(*x, *y));
+                    assert!(!seen.contains(&neighbor));
+                    seen.push(neighbor);
+                    // The wrapped neighbor is the tile drawn beside this one on a copy of the
+                    // board.
+                    let expected = position
+                        + tiling.compute_offset_between_indicies(IVec2::ZERO, IVec2::new(*x, *y));
+                    assert!(
+                        is_board_multiple(tiling.get_position_from_index(neighbor) - expected),
+                        "Neighbor ({}, {}) of {} wrapped to {} with height {}",
+                        x,
+                        y,
+                        index,
+                        neighbor,
+                        height
+                    );
+                    // And stepping back returns to where we started.
+                    assert_eq!(tiling.adjust_index(neighbor - IVec2::new(*x, *y)), index);
+                }
+            }
+        }
+
+        // On a 6x6 board the top row's upper neighbors wrap three tiles left onto the bottom row.
+        let tiling = tiling(TilingKind::Hexagonal, 6);
+        let top = IVec2::new(2, 5);
+        let neighbors = tiling
+            .get_neighbors(top)
+            .iter()
+            .map(|(x, y)| tiling.adjust_index(top + IVec2::new(*x, *y)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            neighbors,
+            [(5, 0), (0, 0), (1, 5), (3, 5), (1, 4), (2, 4)].map(|(x, y)| IVec2::new(x, y))
+        );
+    }
+
+    #[test]
+    fn square_distance_wraps_around_edges() {
+        let tiling = tiling(TilingKind::Square, 10);
+        assert_eq!(tiling.grid_distance(IVec2::new(3, 4), IVec2::new(3, 4)), Some(0));
+        assert_eq!(tiling.grid_distance(IVec2::new(0, 4), IVec2::new(9, 4)), Some(1));
+        assert_eq!(tiling.grid_distance(IVec2::new(4, 0), IVec2::new(4, 9)), Some(1));
+        assert_eq!(tiling.grid_distance(IVec2::ZERO, IVec2::new(9, 9)), Some(2));
+        assert_eq!(tiling.grid_distance(IVec2::new(1, 1), IVec2::new(4, 3)), Some(5));
+        // Halfway across the board is as far as you can get.
+        assert_eq!(tiling.grid_distance(IVec2::ZERO, IVec2::new(5, 5)), Some(10));
+        // Indices outside the board are wrapped onto it first.
+        assert_eq!(tiling.grid_distance(IVec2::new(-1, 0), IVec2::new(9, 1)), Some(1));
+    }
+
+    #[test]
+    fn distance_is_unknown_without_edge_adjacency() {
+        for kind in [
+            TilingKind::OctagonAndSquare,
+            TilingKind::EquilateralTriangular,
+            TilingKind::RightTriangular,
+        ] {
+            let tiling = tiling(kind, 6);
+            assert_eq!(tiling.grid_distance(IVec2::ZERO, IVec2::new(1, 1)), None);
+            assert_eq!(tiling.grid_distance(IVec2::ONE, IVec2::ONE), Some(0));
+        }
+    }
+
+    #[test]
+    fn hexagonal_distance_is_symmetric() {
+        let tiling = tiling(TilingKind::Hexagonal, 6);
+        assert_eq!(tiling.grid_distance(IVec2::ZERO, IVec2::new(1, 1)), Some(1));
+        assert_eq!(tiling.grid_distance(IVec2::ZERO, IVec2::new(1, -1)), Some(2));
+        let indices = tiling.iter_indices().collect::<Vec<_>>();
+        for a in &indices {
+            for b in &indices {
+                assert_eq!(tiling.grid_distance(*a, *b), tiling.grid_distance(*b, *a));
+            }
+        }
+    }
+
+    // Check that the corners of every triangle are at the centers of the tiles in `corners_in`
+    // and count how many triangles share each corner.
+    fn triangle_corners(
+        triangles: &Tiling,
+        corners_in: &Tiling,
+        self_is_dual: bool,
+    ) -> HashMap<IVec2, usize> {
+        let mut shared_by = HashMap::default();
+        for index in triangles.iter_indices() {
+            let tile = triangles.get_tile_at_index(index);
+            let corners = triangles.get_verticies(index, self_is_dual);
+            for (corner, position) in corners.iter().zip(tile.shape.get_corner_positions()) {
+                let center = corners_in.compute_offset_between_indicies(IVec2::ZERO, *corner)
+                    + corners_in.offset;
+                assert!(
+                    center.distance(tile.position + position) < 1e-4,
+                    "Corner {} of {} is not at {}",
+                    corner,
+                    index,
+                    tile.position + position
+                );
+                *shared_by.entry(*corner).or_insert(0) += 1;
+            }
+        }
+        shared_by
+    }
+
+    #[test]
+    fn triangle_corners_sit_on_the_centers_of_their_dual() {
+        let hexagons = tiling(TilingKind::Hexagonal, 6);
+        triangle_corners(&hexagons.get_dual(), &hexagons, true);
+        let triangles = tiling(TilingKind::EquilateralTriangular, 8);
+        triangle_corners(&triangles, &triangles.get_dual(), false);
+
+        let octagons = tiling(TilingKind::OctagonAndSquare, 6);
+        triangle_corners(&octagons.get_dual(), &octagons, true);
+        let triangles = tiling(TilingKind::RightTriangular, 8);
+        triangle_corners(&triangles, &triangles.get_dual(), false);
+    }
+
+    #[test]
+    fn triangle_corners_are_shared_by_their_neighbors() {
+        // Only corners away from the edge of the board have all of their triangles on it.
+        let interior = |triangles: &Tiling| {
+            let triangles = triangles.clone();
+            triangles
+                .iter_indices()
+                .filter(move |index| {
+                    index.cmpge(IVec2::splat(5)).all()
+                        && index.cmplt(triangles.max_index - 5).all()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Six triangles meet at the center of every hexagon.
+        let hexagons = tiling(TilingKind::Hexagonal, 12);
+        for (triangles, corners_in, self_is_dual) in [
+            (hexagons.get_dual(), hexagons.clone(), true),
+            (
+                tiling(TilingKind::EquilateralTriangular, 24),
+                tiling(TilingKind::EquilateralTriangular, 24).get_dual(),
+                false,
+            ),
+        ] {
+            let shared_by = triangle_corners(&triangles, &corners_in, self_is_dual);
+            for index in interior(&triangles) {
+                for corner in triangles.get_verticies(index, self_is_dual) {
+                    assert_eq!(shared_by[&corner], 6, "Corner {} of {}", corner, index);
+                }
+            }
+        }
+
+        // Eight meet at the center of an octagon and four at the center of a square.
+        for (triangles, self_is_dual) in [
+            (tiling(TilingKind::OctagonAndSquare, 12).get_dual(), true),
+            (tiling(TilingKind::RightTriangular, 24), false),
+        ] {
+            let octagons = if self_is_dual {
+                tiling(TilingKind::OctagonAndSquare, 12)
+            } else {
+                triangles.get_dual()
+            };
+            let shared_by = triangle_corners(&triangles, &octagons, self_is_dual);
+            for index in interior(&triangles) {
+                for corner in triangles.get_verticies(index, self_is_dual) {
+                    let expected = match octagons.get_tile_at_index(corner).shape {
+                        TileShape::Octagon => 8,
+                        _ => 4,
+                    };
+                    assert_eq!(shared_by[&corner], expected, "Corner {} of {}", corner, index);
+                }
+            }
+        }
+    }
+}
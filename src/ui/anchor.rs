@@ -1,10 +1,11 @@
 use bevy::{
-    math::Vec3,
-    prelude::{Component, EventReader, Query, Res, Transform, Changed},
+    hierarchy::Parent,
+    math::{Size, Vec3},
+    prelude::{Changed, Component, Entity, EventReader, Query, Res, ResMut, Transform, Visibility},
     window::{WindowResized, Windows},
 };
 
-use super::element::UiElement;
+use super::{element::UiElement, input::InputState};
 
 /// A component used for laying out elements within the screen.
 #[derive(Component)]
@@ -25,32 +26,110 @@ pub struct AnchoredUi {
     /// If given a value, this will cause the element to fill
     /// the set ratio of the screen's height.
     pub height_grow: Option<f32>,
+    /// How many pixels to move this element right of where
+    /// the percentages place it.
+    pub x_offset: f32,
+    /// How many pixels to move this element up from where
+    /// the percentages place it.
+    pub y_offset: f32,
+    /// Hidden elements are placed just off the right edge of
+    /// the screen with their visibility turned off. Nothing
+    /// under them can be focused or stay selected.
+    pub visible: bool,
+}
+
+impl Default for AnchoredUi {
+    fn default() -> Self {
+        Self {
+            x_percent: 0.5,
+            y_percent: 0.5,
+            width_grow: None,
+            height_grow: None,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            visible: true,
+        }
+    }
+}
+
+impl AnchoredUi {
+    /// Grow the element to fit the window, if we should, and find where it should
+    /// be placed within a window of the given size.
+    fn layout(&self, element: &mut UiElement, window: Size, z: f32) -> Vec3 {
+        if let Some(percent) = self.width_grow {
+            element.size.width = percent * window.width;
+        }
+        if let Some(percent) = self.height_grow {
+            element.size.height = percent * window.height;
+        }
+
+        let x = if self.visible {
+            (self.x_percent - 0.5) * (window.width - element.size.width) + self.x_offset
+        } else {
+            (window.width + element.size.width) / 2.0
+        };
+        Vec3::new(
+            x,
+            (self.y_percent - 0.5) * (window.height - element.size.height) + self.y_offset,
+            z,
+        )
+    }
+}
+
+/// Whether the element or any element it sits under has an anchor that is hidden.
+pub fn hidden_by_anchor(
+    mut entity: Entity,
+    parents: &Query<&Parent>,
+    anchors: &Query<&AnchoredUi>,
+) -> bool {
+    loop {
+        if anchors.get(entity).is_ok_and(|anchor| !anchor.visible) {
+            return true;
+        }
+        match parents.get(entity) {
+            Ok(parent) => entity = parent.0,
+            Err(_) => return false,
+        }
+    }
 }
 
 /// When adding an element with an anchor, adjust it's transform to be positioned
-/// correctly within the window.
+/// correctly within the window and show or hide it.
 pub fn position_on_added(
     windows: Res<Windows>,
-    mut transform_query: Query<(&mut Transform, &mut UiElement, &AnchoredUi), Changed<AnchoredUi>>,
+    mut transform_query: Query<
+        (&mut Transform, &mut UiElement, &AnchoredUi, Option<&mut Visibility>),
+        Changed<AnchoredUi>,
+    >,
 ) {
     if let Some(window) = windows.get_primary() {
-        transform_query.for_each_mut(|(mut transform, mut element, anchor)| {
-            if let Some(percent) = anchor.width_grow {
-                element.size.width = percent * window.width();
-            }
-            if let Some(percent) = anchor.height_grow {
-                element.size.height = percent * window.height();
+        let window = Size::new(window.width(), window.height());
+        transform_query.for_each_mut(|(mut transform, mut element, anchor, visibility)| {
+            transform.translation = anchor.layout(&mut element, window, transform.translation.z);
+            if let Some(mut visibility) = visibility {
+                if visibility.is_visible != anchor.visible {
+                    visibility.is_visible = anchor.visible;
+                }
             }
-
-            transform.translation = Vec3::new(
-                (anchor.x_percent - 0.5) * (window.width() - element.size.width),
-                (anchor.y_percent - 0.5) * (window.height() - element.size.height),
-                transform.translation.z,
-            );
         });
     }
 }
 
+/// Drop the selection when the panel it is in gets hidden, so keys stop going to an
+/// element that is offscreen.
+pub fn release_hidden_selection(
+    mut input_state: ResMut<InputState>,
+    mut elements: Query<&mut UiElement>,
+    parents: Query<&Parent>,
+    anchors: Query<&AnchoredUi>,
+) {
+    if let Some(selected) = input_state.selected() {
+        if hidden_by_anchor(selected, &parents, &anchors) {
+            input_state.deselect(&mut elements);
+        }
+    }
+}
+
 /// When the window's size changes, adjust the transform of anchored ui elements
 /// so they are correctly positioned within the window.
 pub fn position_on_window_changed(
@@ -58,19 +137,87 @@ pub fn position_on_window_changed(
     mut transform_query: Query<(&mut Transform, &mut UiElement, &AnchoredUi)>,
 ) {
     for resize in window_resize.iter() {
+        let window = Size::new(resize.width, resize.height);
         transform_query.for_each_mut(|(mut transform, mut element, anchor)| {
-            if let Some(percent) = anchor.width_grow {
-                element.size.width = percent * resize.width;
-            }
-            if let Some(percent) = anchor.height_grow {
-                element.size.height = percent * resize.height;
-            }
-
-            transform.translation = Vec3::new(
-                (anchor.x_percent - 0.5) * (resize.width - element.size.width),
-                (anchor.y_percent - 0.5) * (resize.height - element.size.height),
-                transform.translation.z,
-            );
+            transform.translation = anchor.layout(&mut element, window, transform.translation.z);
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::schedule::SystemStage,
+        hierarchy::BuildWorldChildren,
+        math::{Size, Vec3},
+        prelude::{Query, ResMut, Stage, World},
+    };
+
+    use crate::ui::{element::UiElement, input::InputState};
+
+    use super::{release_hidden_selection, AnchoredUi};
+
+    #[test]
+    fn offsets_apply_after_the_percent_anchor() {
+        let window = Size::new(800.0, 600.0);
+        let mut element = UiElement {
+            size: Size::new(100.0, 50.0),
+            ..Default::default()
+        };
+        // Inset 10 pixels from the top right corner.
+        let anchor = AnchoredUi {
+            x_percent: 1.0,
+            y_percent: 1.0,
+            x_offset: -10.0,
+            y_offset: -10.0,
+            ..Default::default()
+        };
+        assert_eq!(anchor.layout(&mut element, window, 3.0), Vec3::new(340.0, 265.0, 3.0));
+
+        let anchor = AnchoredUi {
+            x_percent: 0.0,
+            y_percent: 0.5,
+            height_grow: Some(0.5),
+            x_offset: 20.0,
+            ..Default::default()
+        };
+        assert_eq!(anchor.layout(&mut element, window, 0.0), Vec3::new(-330.0, 0.0, 0.0));
+        assert_eq!(element.size, Size::new(100.0, 300.0));
+
+        // Hidden elements sit just past the right edge of the window.
+        let anchor = AnchoredUi {
+            visible: false,
+            ..anchor
+        };
+        assert_eq!(anchor.layout(&mut element, window, 0.0).x, 450.0);
+    }
+
+    #[test]
+    fn hiding_a_panel_releases_its_selection() {
+        let mut world = World::new();
+        world.insert_resource(InputState::default());
+        let field = world.spawn().insert(UiElement::default()).id();
+        let panel = world
+            .spawn()
+            .insert(AnchoredUi::default())
+            .push_children(&[field])
+            .id();
+
+        let mut select = SystemStage::single_threaded();
+        select.add_system(
+            move |mut input_state: ResMut<InputState>, mut elements: Query<&mut UiElement>| {
+                input_state.select(field, &mut elements);
+            },
+        );
+        select.run(&mut world);
+        let mut release = SystemStage::single_threaded();
+        release.add_system(release_hidden_selection);
+        release.run(&mut world);
+        assert_eq!(world.get_resource::<InputState>().unwrap().selected(), Some(field));
+
+        world.get_mut::<AnchoredUi>(panel).unwrap().visible = false;
+        release.run(&mut world);
+        assert_eq!(world.get_resource::<InputState>().unwrap().selected(), None);
+        assert!(!world.get::<UiElement>(field).unwrap().selected_state.current);
+    }
+}
@@ -0,0 +1,219 @@
+use bevy::{
+    ecs::system::BoxedSystem,
+    input::Input,
+    prelude::{Color, Component, EventWriter, IntoSystem, KeyCode, Query, Res},
+    text::Text,
+};
+
+use super::{element::UiElement, widget::WidgetHandler};
+
+const HUE_STEP: f32 = 10.0;
+const SATURATION_VALUE_STEP: f32 = 0.05;
+
+/// Trait for structs that can generate an event given a color.
+pub trait ColorEventGenerator {
+    type Event: Component + Clone;
+    fn create_event(&self, color: Color) -> Self::Event;
+}
+
+/// The part of the color a picker is currently changing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HsvChannel {
+    Hue,
+    Saturation,
+    Value,
+}
+
+/// Component for UI Elements that allows for picking a color by its hue, saturation and value.
+/// While selected, the up and down arrows pick the channel and left and right change it.
+#[derive(Component)]
+pub struct ColorPicker<EventGenerator: Component + ColorEventGenerator> {
+    /// A generator used to create the events when the color changes.
+    pub event_generator: EventGenerator,
+    /// In degrees, from 0 up to 360.
+    pub hue: f32,
+    pub saturation: f32,
+    pub value: f32,
+    pub channel: HsvChannel,
+}
+
+impl<EventGenerator: Component + ColorEventGenerator> ColorPicker<EventGenerator> {
+    pub fn new(event_generator: EventGenerator, color: Color) -> Self {
+        let (hue, saturation, value) = color_to_hsv(color);
+        Self {
+            event_generator,
+            hue,
+            saturation,
+            value,
+            channel: HsvChannel::Hue,
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        hsv_to_color(self.hue, self.saturation, self.value)
+    }
+
+    /// The text shown for the picker, with the channel being changed in brackets.
+    pub fn label(&self) -> String {
+        [
+            (HsvChannel::Hue, format!("H {}", self.hue.round())),
+            (HsvChannel::Saturation, format!("S {}", (100.0 * self.saturation).round())),
+            (HsvChannel::Value, format!("V {}", (100.0 * self.value).round())),
+        ]
+        .into_iter()
+        .map(|(channel, text)| {
+            if channel == self.channel {
+                format!("[{}]", text)
+            } else {
+                text
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+    }
+
+    /// Move the current channel by the given number of steps. Hue wraps around while saturation
+    /// and value stop at their ends.
+    pub fn adjust(&mut self, steps: f32) {
+        match self.channel {
+            HsvChannel::Hue => self.hue = (self.hue + steps * HUE_STEP).rem_euclid(360.0),
+            HsvChannel::Saturation => {
+                self.saturation = (self.saturation + steps * SATURATION_VALUE_STEP).clamp(0.0, 1.0)
+            }
+            HsvChannel::Value => {
+                self.value = (self.value + steps * SATURATION_VALUE_STEP).clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+pub fn hsv_to_color(hue: f32, saturation: f32, value: f32) -> Color {
+    let chroma = value * saturation;
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let second = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (red, green, blue) = match sector as u32 {
+        0 => (chroma, second, 0.0),
+        1 => (second, chroma, 0.0),
+        2 => (0.0, chroma, second),
+        3 => (0.0, second, chroma),
+        4 => (second, 0.0, chroma),
+        _ => (chroma, 0.0, second),
+    };
+    let lightest = value - chroma;
+    Color::rgb(red + lightest, green + lightest, blue + lightest)
+}
+
+/// The hue in degrees, saturation and value of the color.
+pub fn color_to_hsv(color: Color) -> (f32, f32, f32) {
+    let [red, green, blue, _] = color.as_rgba_f32();
+    let max = red.max(green).max(blue);
+    let range = max - red.min(green).min(blue);
+    let hue = if range == 0.0 {
+        0.0
+    } else if max == red {
+        60.0 * ((green - blue) / range).rem_euclid(6.0)
+    } else if max == green {
+        60.0 * ((blue - red) / range + 2.0)
+    } else {
+        60.0 * ((red - green) / range + 4.0)
+    };
+    let saturation = if max > 0.0 { range / max } else { 0.0 };
+    (hue, saturation, max)
+}
+
+impl<EventGenerator: Component + ColorEventGenerator> WidgetHandler for ColorPicker<EventGenerator> {
+    type Event = EventGenerator::Event;
+
+    fn handler() -> BoxedSystem<(), ()> {
+        Box::new(IntoSystem::into_system(color_picker_handler::<EventGenerator>))
+    }
+}
+
+/// Detect arrow presses on selected color pickers to change their color.
+pub fn color_picker_handler<EventGenerator: Component + ColorEventGenerator>(
+    mut query: Query<(&mut Text, &mut ColorPicker<EventGenerator>, &UiElement)>,
+    mut events: EventWriter<EventGenerator::Event>,
+    keyboard: Res<Input<KeyCode>>,
+) {
+    query.for_each_mut(|(mut text, mut picker, element)| {
+        if !element.selected_state.current {
+            return;
+        }
+        let initial_channel = picker.channel;
+        if keyboard.just_pressed(KeyCode::Up) {
+            picker.channel = match picker.channel {
+                HsvChannel::Hue => HsvChannel::Value,
+                HsvChannel::Saturation => HsvChannel::Hue,
+                HsvChannel::Value => HsvChannel::Saturation,
+            };
+        } else if keyboard.just_pressed(KeyCode::Down) {
+            picker.channel = match picker.channel {
+                HsvChannel::Hue => HsvChannel::Saturation,
+                HsvChannel::Saturation => HsvChannel::Value,
+                HsvChannel::Value => HsvChannel::Hue,
+            };
+        }
+
+        let mut steps = 0.0;
+        if keyboard.just_pressed(KeyCode::Left) {
+            steps -= 1.0;
+        }
+        if keyboard.just_pressed(KeyCode::Right) {
+            steps += 1.0;
+        }
+        if steps != 0.0 {
+            picker.adjust(steps);
+            events.send(picker.event_generator.create_event(picker.color()));
+        }
+
+        if steps != 0.0 || initial_channel != picker.channel {
+            if let Some(section) = text.sections.first_mut() {
+                section.value = picker.label();
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::{Color, Component};
+
+    use super::{color_to_hsv, hsv_to_color, ColorEventGenerator, ColorPicker, HsvChannel};
+
+    #[derive(Component, Clone)]
+    struct Generator;
+
+    impl ColorEventGenerator for Generator {
+        type Event = Generator;
+
+        fn create_event(&self, _: Color) -> Self::Event {
+            Generator
+        }
+    }
+
+    #[test]
+    fn picker_round_trips_colors_and_wraps_hue() {
+        for color in [
+            Color::rgb(1.0, 0.0, 0.0),
+            Color::rgb(0.2, 0.6, 0.4),
+            Color::rgb(0.9, 0.8, 0.1),
+            Color::rgb(0.3, 0.1, 0.7),
+            Color::WHITE,
+            Color::BLACK,
+        ] {
+            let (hue, saturation, value) = color_to_hsv(color);
+            let round_trip = hsv_to_color(hue, saturation, value).as_rgba_f32();
+            for (channel, expected) in round_trip.iter().zip(color.as_rgba_f32()) {
+                assert!((channel - expected).abs() < 1e-5, "{:?}", color);
+            }
+        }
+
+        let mut picker = ColorPicker::new(Generator, hsv_to_color(5.0, 0.5, 1.0));
+        picker.adjust(-1.0);
+        assert!((picker.hue - 355.0).abs() < 1e-3);
+        picker.channel = HsvChannel::Value;
+        picker.adjust(3.0);
+        assert_eq!(picker.value, 1.0);
+        assert_eq!(picker.label(), "H 355 S 50 [V 100]");
+    }
+}
@@ -25,6 +25,9 @@ pub struct UiElement {
     /// A state representing how much the mouse-wheel has scrolled while
     /// over this element.
     pub scroll_state: UiStateDetails<Vec2>,
+    /// Text shown next to the cursor after this element has been hovered
+    /// for a moment. Only shown if the element accepts hover state.
+    pub tooltip: Option<String>,
 }
 
 impl Default for UiElement {
@@ -35,6 +38,7 @@ impl Default for UiElement {
             click_state: Default::default(),
             selected_state: Default::default(),
             scroll_state: Default::default(),
+            tooltip: None,
         }
     }
 }
@@ -0,0 +1,181 @@
+use bevy::{
+    hierarchy::Parent,
+    input::Input,
+    prelude::{Entity, GlobalTransform, KeyCode, Query, Res, ResMut, Visibility},
+};
+
+use super::{
+    anchor::{hidden_by_anchor, AnchoredUi},
+    element::UiElement,
+    input::InputState,
+};
+
+/// The visible UI elements that can be selected, in the order Tab moves focus through them:
+/// top to bottom, then left to right.
+#[derive(Default)]
+pub struct FocusOrder {
+    entities: Vec<Entity>,
+}
+
+impl FocusOrder {
+    /// The element after `current`, or before it when going `backwards`, wrapping around at the
+    /// ends. With nothing focused we start at the first element, or the last going backwards.
+    pub fn next(&self, current: Option<Entity>, backwards: bool) -> Option<Entity> {
+        let len = self.entities.len();
+        if len == 0 {
+            return None;
+        }
+        let index = match current.and_then(|entity| self.entities.iter().position(|e| *e == entity)) {
+            Some(index) if backwards => (index + len - 1) % len,
+            Some(index) => (index + 1) % len,
+            None if backwards => len - 1,
+            None => 0,
+        };
+        Some(self.entities[index])
+    }
+}
+
+/// Rebuild the focus order from the selectable elements on screen. Elements in a panel whose
+/// anchor is hidden are left out.
+pub fn update_focus_order(
+    mut focus_order: ResMut<FocusOrder>,
+    query: Query<(Entity, &GlobalTransform, &UiElement, Option<&Visibility>)>,
+    parents: Query<&Parent>,
+    anchors: Query<&AnchoredUi>,
+) {
+    let mut focusable = query
+        .iter()
+        .filter(|(entity, _, element, visibility)| {
+            element.selected_state.accepts_state
+                && visibility.is_none_or(|visibility| visibility.is_visible)
+                && !hidden_by_anchor(*entity, &parents, &anchors)
+        })
+        .map(|(entity, transform, _, _)| (entity, transform.translation))
+        .collect::<Vec<_>>();
+    focusable.sort_by(|(_, a), (_, b)| {
+        b.y.partial_cmp(&a.y)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.x.partial_cmp(&b.x).unwrap_or(std::cmp::Ordering::Equal))
+    });
+    focus_order.entities = focusable.into_iter().map(|(entity, _)| entity).collect();
+}
+
+/// Move the selection to the next element in the focus order on Tab, or the previous one on
+/// Shift-Tab.
+pub fn focus_traversal(
+    keyboard: Res<Input<KeyCode>>,
+    focus_order: Res<FocusOrder>,
+    mut input_state: ResMut<InputState>,
+    mut query: Query<&mut UiElement>,
+) {
+    if !keyboard.just_pressed(KeyCode::Tab) {
+        return;
+    }
+    let backwards = keyboard.pressed(KeyCode::LShift) || keyboard.pressed(KeyCode::RShift);
+    if let Some(next) = focus_order.next(input_state.selected(), backwards) {
+        input_state.select(next, &mut query);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::schedule::SystemStage,
+        hierarchy::BuildWorldChildren,
+        input::Input,
+        math::Vec3,
+        prelude::{Entity, GlobalTransform, KeyCode, ParallelSystemDescriptorCoercion, Stage, Visibility, World},
+    };
+
+    use crate::ui::{element::UiElement, input::InputState, AnchoredUi, UiStateDetails};
+
+    use super::{focus_traversal, update_focus_order, FocusOrder};
+
+    fn spawn_field(world: &mut World, y: f32, is_visible: bool) -> Entity {
+        world
+            .spawn()
+            .insert(GlobalTransform::from_translation(Vec3::new(0.0, y, 0.0)))
+            .insert(Visibility { is_visible })
+            .insert(UiElement {
+                selected_state: UiStateDetails {
+                    accepts_state: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .id()
+    }
+
+    fn press_tab(world: &mut World, shift: bool) -> Option<Entity> {
+        let mut keyboard = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+        keyboard.release(KeyCode::Tab);
+        keyboard.release(KeyCode::LShift);
+        keyboard.clear();
+        if shift {
+            keyboard.press(KeyCode::LShift);
+        }
+        keyboard.press(KeyCode::Tab);
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(update_focus_order);
+        stage.add_system(focus_traversal.after(update_focus_order));
+        stage.run(world);
+        world.get_resource::<InputState>().unwrap().selected()
+    }
+
+    fn focused(world: &World, fields: &[Entity]) -> Vec<Entity> {
+        fields
+            .iter()
+            .copied()
+            .filter(|entity| world.get::<UiElement>(*entity).unwrap().selected_state.current)
+            .collect()
+    }
+
+    #[test]
+    fn tab_moves_focus_through_fields_in_order() {
+        let mut world = World::new();
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(InputState::default());
+        world.insert_resource(FocusOrder::default());
+        // Spawned out of order, with a hidden field and one that cannot be selected.
+        let bottom = spawn_field(&mut world, -100.0, true);
+        let top = spawn_field(&mut world, 100.0, true);
+        spawn_field(&mut world, 50.0, false);
+        world
+            .spawn()
+            .insert(GlobalTransform::from_translation(Vec3::new(0.0, 75.0, 0.0)))
+            .insert(UiElement::default());
+        let middle = spawn_field(&mut world, 0.0, true);
+        let fields = [top, middle, bottom];
+
+        assert_eq!(press_tab(&mut world, false), Some(top));
+        assert_eq!(focused(&world, &fields), vec![top]);
+        assert_eq!(press_tab(&mut world, false), Some(middle));
+        assert_eq!(focused(&world, &fields), vec![middle]);
+        assert_eq!(press_tab(&mut world, false), Some(bottom));
+        assert_eq!(press_tab(&mut world, false), Some(top));
+        assert_eq!(press_tab(&mut world, true), Some(bottom));
+        assert_eq!(focused(&world, &fields), vec![bottom]);
+    }
+
+    #[test]
+    fn tab_skips_fields_in_hidden_panels() {
+        let mut world = World::new();
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(InputState::default());
+        world.insert_resource(FocusOrder::default());
+        let top = spawn_field(&mut world, 100.0, true);
+        let hidden = spawn_field(&mut world, 0.0, true);
+        let bottom = spawn_field(&mut world, -100.0, true);
+        world
+            .spawn()
+            .insert(AnchoredUi {
+                visible: false,
+                ..Default::default()
+            })
+            .push_children(&[hidden]);
+
+        assert_eq!(press_tab(&mut world, false), Some(top));
+        assert_eq!(press_tab(&mut world, false), Some(bottom));
+        assert_eq!(press_tab(&mut world, false), Some(top));
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use bevy::{
     hierarchy::{Children, Parent},
     input::{
@@ -5,7 +7,8 @@ use bevy::{
         Input,
     },
     math::{Vec2, Vec3Swizzles},
-    prelude::{Entity, EventReader, MouseButton, Query, Res, Transform, With, Without},
+    prelude::{Entity, EventReader, KeyCode, MouseButton, Query, Res, Transform, With, Without},
+    utils::HashMap,
     window::Windows,
 };
 
@@ -17,6 +20,9 @@ pub struct InputState {
     ui_element_selected: Option<Entity>,
     ui_element_selected_buffered: Option<Entity>,
     ui_element_scrolled: Option<Entity>,
+    // Where the mouse was when we last processed inputs, relative to the center of the window
+    // like our ui roots.
+    pub(super) mouse_position: Option<Vec2>,
 }
 
 impl Default for InputState {
@@ -27,12 +33,67 @@ impl Default for InputState {
             ui_element_selected: None,
             ui_element_selected_buffered: None,
             ui_element_scrolled: None,
+            mouse_position: None,
         }
     }
 }
 
 const SCROLL_SENSITIVITY: f32 = 0.5;
 
+/// Turns held keys into repeated presses. A key triggers when it is first pressed, again once it
+/// has been held for `delay` and then every `interval` for as long as it stays held.
+pub struct KeyRepeat {
+    pub delay: Duration,
+    pub interval: Duration,
+    // How long until each held key triggers again.
+    held: HashMap<KeyCode, Duration>,
+}
+
+impl Default for KeyRepeat {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(300), Duration::from_millis(50))
+    }
+}
+
+impl KeyRepeat {
+    pub fn new(delay: Duration, interval: Duration) -> Self {
+        Self {
+            delay,
+            interval,
+            held: Default::default(),
+        }
+    }
+
+    /// Whether the key triggers this frame, `delta` after the last. Call this once a frame for
+    /// each key being watched so it can keep track of how long the key has been held.
+    pub fn triggered(&mut self, key: KeyCode, keyboard: &Input<KeyCode>, delta: Duration) -> bool {
+        if keyboard.just_pressed(key) {
+            self.held.insert(key, self.delay);
+            return true;
+        }
+        if !keyboard.pressed(key) {
+            self.held.remove(&key);
+            return false;
+        }
+        let until_trigger = match self.held.get_mut(&key) {
+            Some(until_trigger) => until_trigger,
+            // We missed the press, so start waiting out the delay now.
+            None => {
+                self.held.insert(key, self.delay);
+                return false;
+            }
+        };
+        if *until_trigger > delta {
+            *until_trigger -= delta;
+            false
+        } else {
+            // Carry over the time past the trigger so the repeats keep a steady rate.
+            *until_trigger = (*until_trigger + self.interval).saturating_sub(delta);
+            true
+        }
+    }
+}
+
 fn update_hovers(ui_element_query: &mut Query<(&Transform, &mut UiElement, Option<&Children>)>) {
     ui_element_query.for_each_mut(|(_, mut element, _)| {
         if element.hover_state.accepts_state
@@ -121,6 +182,34 @@ impl InputState {
         self.ui_element_selected.is_some()
     }
 
+    pub fn selected(&self) -> Option<Entity> {
+        self.ui_element_selected
+    }
+
+    /// Select the given element as if it had been clicked, deselecting the current selection.
+    pub fn select(&mut self, entity: Entity, ui_element_query: &mut Query<&mut UiElement>) {
+        if self.ui_element_selected == Some(entity) {
+            return;
+        }
+        self.deselect(ui_element_query);
+        if let Ok(mut element) = ui_element_query.get_mut(entity) {
+            element.selected_state.previous = element.selected_state.current;
+            element.selected_state.current = true;
+        }
+        self.ui_element_selected = Some(entity);
+    }
+
+    /// Deselect the current selection as if somewhere else had been clicked.
+    pub fn deselect(&mut self, ui_element_query: &mut Query<&mut UiElement>) {
+        if let Some(previous) = self.ui_element_selected.take() {
+            if let Ok(mut element) = ui_element_query.get_mut(previous) {
+                element.selected_state.previous = element.selected_state.current;
+                element.selected_state.current = false;
+            }
+            self.ui_element_selected_buffered = Some(previous);
+        }
+    }
+
     pub fn process_inputs(
         &mut self,
         shift_down: bool,
@@ -205,13 +294,15 @@ impl InputState {
         update_hovers(&mut ui_element_query);
 
         let mut over_ui = false;
-        // If we have a mouse position, we are going to go issue hovers, clicks, selects and scrolls
-        if let Some(mouse_position) = windows
+        self.mouse_position = windows
             .get_primary()
             .and_then(|window| window.cursor_position())
-        {
-            let mouse_position = mouse_position
-                - Vec2::new(windows.primary().width(), windows.primary().height()) * 0.5;
+            .map(|mouse_position| {
+                mouse_position
+                    - Vec2::new(windows.primary().width(), windows.primary().height()) * 0.5
+            });
+        // If we have a mouse position, we are going to go issue hovers, clicks, selects and scrolls
+        if let Some(mouse_position) = self.mouse_position {
             let mut click_target = None;
             let mut scroll_target = None;
             let mut select_target = None;
@@ -258,3 +349,37 @@ impl InputState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{input::Input, prelude::KeyCode};
+
+    use super::KeyRepeat;
+
+    #[test]
+    fn held_key_repeats_at_interval() {
+        let mut repeat = KeyRepeat::new(Duration::from_millis(300), Duration::from_millis(100));
+        let mut keyboard = Input::<KeyCode>::default();
+        let frame = Duration::from_millis(50);
+
+        keyboard.press(KeyCode::Left);
+        let mut triggered_frames = Vec::new();
+        for frame_index in 0..14 {
+            if repeat.triggered(KeyCode::Left, &keyboard, frame) {
+                triggered_frames.push(frame_index);
+            }
+            // Only the first frame sees the key as just pressed.
+            keyboard.clear();
+        }
+        // Once on the press, after the 300ms delay and then every 100ms.
+        assert_eq!(triggered_frames, vec![0, 6, 8, 10, 12]);
+
+        keyboard.release(KeyCode::Left);
+        keyboard.clear();
+        assert!(!repeat.triggered(KeyCode::Left, &keyboard, frame));
+        keyboard.press(KeyCode::Left);
+        assert!(repeat.triggered(KeyCode::Left, &keyboard, frame));
+    }
+}
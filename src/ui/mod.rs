@@ -1,20 +1,45 @@
-use bevy::prelude::{App, Component, ParallelSystemDescriptorCoercion, Plugin};
+use bevy::{
+    ecs::event::Events,
+    prelude::{App, Component, ParallelSystemDescriptorCoercion, Plugin, SystemLabel},
+};
 
 pub mod anchor;
 pub mod button;
+pub mod color_picker;
 pub mod element;
+pub mod focus;
 pub mod input;
 pub mod number_field;
 pub mod text_field;
 pub mod scroll_view;
+pub mod slider;
+pub mod tooltip;
+pub mod widget;
 
 pub use anchor::AnchoredUi;
 pub use button::Button;
+pub use color_picker::{ColorEventGenerator, ColorPicker};
 pub use element::{UiElement, UiStateDetails};
-pub use input::InputState;
+pub use focus::FocusOrder;
+pub use input::{InputState, KeyRepeat};
 pub use number_field::{NumberField, NumberedEventGenerator};
 pub use scroll_view::{LayoutDirection, UiLinearScroll};
+pub use slider::{Slider, SliderFill, SliderValueText};
 pub use text_field::{TextEventGenerator, TextField};
+pub use tooltip::TooltipStyle;
+pub use widget::WidgetHandler;
+
+/// The order UI systems run in each frame. Elements react to input first,
+/// then are laid out, then have their sprites and text bounds synced to their
+/// layout so a click is fully shown by the end of the frame it happened in.
+/// Systems reacting to UI events by changing elements should run after
+/// `Input` and before `Layout`.
+#[derive(SystemLabel, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UiSystem {
+    Input,
+    Layout,
+    Sync,
+}
 
 pub struct UIPlugin {
     registry_functions: Vec<Box<dyn Fn(&mut App) + Sync + Send>>,
@@ -29,40 +54,184 @@ impl UIPlugin {
 
     pub fn register_event<Evt: Component + Clone>(mut self) -> Self {
         self.registry_functions.push(Box::new(|app: &mut App| {
-            app.add_event::<Evt>();
-            app.add_system(button::button_handler::<Evt>);
+            // Events shared by several widgets are only added once so buttons
+            // sending them don't send them twice.
+            if !app.world.contains_resource::<Events<Evt>>() {
+                app.add_event::<Evt>();
+                app.add_system(button::button_handler::<Evt>.label(UiSystem::Input));
+            }
         }));
         self
     }
 
-    pub fn register_number_event_generator<EvtGen: NumberedEventGenerator + Component>(mut self) -> Self {
+    /// Add the handler for a widget along with the event it sends.
+    pub fn register_widget<Widget: WidgetHandler>(mut self) -> Self {
         self.registry_functions.push(Box::new(|app: &mut App| {
-            app.add_system(number_field::number_field_handler::<EvtGen>);
+            app.add_system(Widget::handler().label(UiSystem::Input));
         }));
-        self.register_event::<EvtGen::Event>()
+        self.register_event::<Widget::Event>()
     }
 
-    pub fn register_text_event_generator<EvtGen: TextEventGenerator + Component>(mut self) -> Self {
-        self.registry_functions.push(Box::new(|app: &mut App| {
-            app.add_system(text_field::text_field_handler::<EvtGen>);
-        }));
-        self.register_event::<EvtGen::Event>()
+    pub fn register_number_event_generator<EvtGen: NumberedEventGenerator<Value = u32> + Component>(
+        self,
+    ) -> Self {
+        self.register_widget::<NumberField<EvtGen>>()
+            .register_widget::<Slider<EvtGen>>()
+    }
+
+    pub fn register_color_event_generator<EvtGen: ColorEventGenerator + Component>(self) -> Self {
+        self.register_widget::<ColorPicker<EvtGen>>()
+    }
+
+    pub fn register_text_event_generator<EvtGen: TextEventGenerator + Component>(self) -> Self {
+        self.register_widget::<TextField<EvtGen>>()
     }
 }
 
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(InputState::default());
-        app.add_system(element::update_text_to_match_layout);
-        app.add_system(element::update_sprite_to_match_layout);
-        app.add_system(anchor::position_on_added);
-        app.add_system(anchor::position_on_window_changed);
-        app.add_system(scroll_view::linear_scroll_children_changed);
+        app.insert_resource(KeyRepeat::default());
+        app.insert_resource(TooltipStyle::default());
+        app.insert_resource(FocusOrder::default());
+        app.add_system(focus::update_focus_order.label(UiSystem::Input));
+        app.add_system(
+            focus::focus_traversal
+                .label(UiSystem::Input)
+                .after(focus::update_focus_order),
+        );
+
+        app.add_system(
+            anchor::position_on_added
+                .label(UiSystem::Layout)
+                .after(UiSystem::Input),
+        );
+        app.add_system(
+            anchor::position_on_window_changed
+                .label(UiSystem::Layout)
+                .after(UiSystem::Input),
+        );
         app.add_system(
-            scroll_view::linear_scroll_handler.after(anchor::position_on_window_changed),
+            anchor::release_hidden_selection
+                .label(UiSystem::Layout)
+                .after(UiSystem::Input),
         );
+        app.add_system(
+            scroll_view::linear_scroll_children_changed
+                .label(UiSystem::Layout)
+                .after(UiSystem::Input),
+        );
+        app.add_system(
+            scroll_view::linear_scroll_handler
+                .label(UiSystem::Layout)
+                .after(UiSystem::Input)
+                .after(anchor::position_on_added)
+                .after(anchor::position_on_window_changed),
+        );
+        app.add_system(
+            scroll_view::update_scrollbars
+                .label(UiSystem::Layout)
+                .after(scroll_view::linear_scroll_children_changed)
+                .after(scroll_view::linear_scroll_handler),
+        );
+
+        app.add_system(
+            element::update_text_to_match_layout
+                .label(UiSystem::Sync)
+                .after(UiSystem::Layout),
+        );
+        app.add_system(
+            element::update_sprite_to_match_layout
+                .label(UiSystem::Sync)
+                .after(UiSystem::Layout),
+        );
+        app.add_system(tooltip::show_tooltips.label(UiSystem::Sync).after(UiSystem::Layout));
         for func in &self.registry_functions {
             func(app);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        asset::Handle,
+        core::Time,
+        hierarchy::BuildWorldChildren,
+        input::Input,
+        math::{Size, Vec2},
+        prelude::{
+            App, Component, EventReader, Image, KeyCode, ParallelSystemDescriptorCoercion, Query,
+            Transform, With,
+        },
+        sprite::Sprite,
+        window::{WindowResized, Windows},
+    };
+
+    use super::{Button, UIPlugin, UiElement, UiLinearScroll, UiSystem, UiStateDetails};
+
+    #[derive(Component, Clone)]
+    struct Grow;
+
+    #[derive(Component)]
+    struct Grows;
+
+    fn grow_on_click(mut events: EventReader<Grow>, mut query: Query<&mut UiElement, With<Grows>>) {
+        for _ in events.iter() {
+            query.for_each_mut(|mut element| element.size.height *= 2.0);
+        }
+    }
+
+    #[test]
+    fn clicks_are_laid_out_in_the_same_update() {
+        let mut app = App::new();
+        app.insert_resource(Windows::default());
+        app.insert_resource(Time::default());
+        app.insert_resource(Input::<KeyCode>::default());
+        app.add_event::<WindowResized>();
+        app.add_plugin(UIPlugin::new().register_event::<Grow>());
+        app.add_system(grow_on_click.after(UiSystem::Input).before(UiSystem::Layout));
+
+        app.world
+            .spawn()
+            .insert(Handle::<Image>::default())
+            .insert(Button::new(Handle::default(), Grow))
+            .insert(UiElement {
+                click_state: UiStateDetails {
+                    current: false,
+                    previous: true,
+                    accepts_state: true,
+                },
+                ..Default::default()
+            });
+        let child = app
+            .world
+            .spawn()
+            .insert(Transform::default())
+            .insert(UiElement {
+                size: Size::new(100.0, 50.0),
+                ..Default::default()
+            })
+            .id();
+        let scroll = app
+            .world
+            .spawn()
+            .insert(Transform::default())
+            .insert(Sprite::default())
+            .insert(UiElement {
+                size: Size::new(100.0, 100.0),
+                ..Default::default()
+            })
+            .insert(UiLinearScroll::default())
+            .insert(Grows)
+            .push_children(&[child])
+            .id();
+
+        app.update();
+        assert_eq!(
+            app.world.get::<Sprite>(scroll).unwrap().custom_size,
+            Some(Vec2::new(100.0, 200.0))
+        );
+        assert_eq!(app.world.get::<Transform>(child).unwrap().translation.y, 75.0);
+    }
+}
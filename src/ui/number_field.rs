@@ -1,89 +1,279 @@
+use std::{fmt::Display, str::FromStr};
+
 use bevy::{
+    ecs::system::BoxedSystem,
     input::Input,
-    prelude::{Color, Component, EventWriter, KeyCode, Query, Res},
+    prelude::{Color, Component, EventWriter, IntoSystem, KeyCode, Query, Res},
     text::{Text, TextSection, TextStyle},
 };
 
-use super::element::UiElement;
+use super::{element::UiElement, widget::WidgetHandler};
+
+/// Numbers that can be typed into a `NumberField`.
+pub trait FieldNumber: Copy + PartialOrd + Display + FromStr + Send + Sync + 'static {
+    /// Whether a leading minus sign can be typed.
+    const SIGNED: bool;
+}
+
+impl FieldNumber for u32 {
+    const SIGNED: bool = false;
+}
+
+impl FieldNumber for i32 {
+    const SIGNED: bool = true;
+}
 
 /// Trait for structs that can generate an event given a value.
 pub trait NumberedEventGenerator {
     type Event: Component + Clone;
-    fn create_event(&self, value: u32) -> Self::Event;
+    type Value: FieldNumber;
+    fn create_event(&self, value: Self::Value) -> Self::Event;
 }
 
-/// Component for UI Elements that allows for typing positive integers.
+/// Component for UI Elements that allows for typing integers. What is typed
+/// is only checked against the bounds and sent once it is committed.
 #[derive(Component)]
 pub struct NumberField<EventGenerator: Component + NumberedEventGenerator> {
     /// A generator used to create the events when the value of the field changes.
     pub event_generator: EventGenerator,
-    pub current_value: u32,
-    pub max_value: u32,
-    pub min_value: u32,
+    pub current_value: EventGenerator::Value,
+    pub max_value: EventGenerator::Value,
+    pub min_value: EventGenerator::Value,
+    /// What has been typed since the value was last committed, if anything.
+    pub typed: Option<String>,
+}
+
+impl<EventGenerator: Component + NumberedEventGenerator> WidgetHandler for NumberField<EventGenerator> {
+    type Event = EventGenerator::Event;
+
+    fn handler() -> BoxedSystem<(), ()> {
+        Box::new(IntoSystem::into_system(number_field_handler::<EventGenerator>))
+    }
+}
+
+const DIGIT_KEYS: [(KeyCode, char); 20] = [
+    (KeyCode::Key0, '0'),
+    (KeyCode::Key1, '1'),
+    (KeyCode::Key2, '2'),
+    (KeyCode::Key3, '3'),
+    (KeyCode::Key4, '4'),
+    (KeyCode::Key5, '5'),
+    (KeyCode::Key6, '6'),
+    (KeyCode::Key7, '7'),
+    (KeyCode::Key8, '8'),
+    (KeyCode::Key9, '9'),
+    (KeyCode::Numpad0, '0'),
+    (KeyCode::Numpad1, '1'),
+    (KeyCode::Numpad2, '2'),
+    (KeyCode::Numpad3, '3'),
+    (KeyCode::Numpad4, '4'),
+    (KeyCode::Numpad5, '5'),
+    (KeyCode::Numpad6, '6'),
+    (KeyCode::Numpad7, '7'),
+    (KeyCode::Numpad8, '8'),
+    (KeyCode::Numpad9, '9'),
+];
+
+impl<EventGenerator: Component + NumberedEventGenerator> NumberField<EventGenerator> {
+    /// Add the keys released this frame to what has been typed.
+    /// Returns whether anything changed.
+    fn type_keys(&mut self, keyboard: &Input<KeyCode>) -> bool {
+        let mut changed = false;
+        if keyboard.just_released(KeyCode::Delete) || keyboard.just_released(KeyCode::Back) {
+            // Deleting before typing anything edits the current value.
+            let current_value = self.current_value;
+            self.typed
+                .get_or_insert_with(|| current_value.to_string())
+                .pop();
+            changed = true;
+        }
+        if EventGenerator::Value::SIGNED
+            && (keyboard.just_released(KeyCode::Minus)
+                || keyboard.just_released(KeyCode::NumpadSubtract))
+            && self.typed.as_deref().unwrap_or_default().is_empty()
+        {
+            self.typed = Some("-".to_string());
+            changed = true;
+        }
+        for (key, digit) in DIGIT_KEYS.iter() {
+            if keyboard.just_released(*key) {
+                self.typed.get_or_insert_with(String::new).push(*digit);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Parse what was typed, only accepting numbers within our bounds.
+    fn parse_typed(&self, typed: &str) -> Option<EventGenerator::Value> {
+        typed
+            .parse()
+            .ok()
+            .filter(|value| *value >= self.min_value && *value <= self.max_value)
+    }
+}
+
+fn set_text(text: &mut Text, value: String) {
+    if text.sections.is_empty() {
+        text.sections.push(TextSection {
+            value,
+            style: TextStyle {
+                font: Default::default(),
+                font_size: 14.0,
+                color: Color::BLACK,
+            },
+        });
+    } else {
+        text.sections[0].value = value;
+    }
 }
 
 /// Detect button presses on selected number fields to type in numbers on them.
+/// Enter commits what was typed and escape reverts it. Deselecting the field
+/// with something typed also commits it. Values outside of the field's bounds
+/// are rejected and the field reverts to its previous value.
 pub fn number_field_handler<EventGenerator: Component + NumberedEventGenerator>(
     mut query: Query<(&mut Text, &mut NumberField<EventGenerator>, &UiElement)>,
     mut events: EventWriter<EventGenerator::Event>,
     keyboard: Res<Input<KeyCode>>,
 ) {
     query.for_each_mut(|(mut text, mut number_field, element)| {
-        if !element.selected_state.current {
+        let selected = element.selected_state.current;
+        if selected && keyboard.just_released(KeyCode::Escape) {
+            if number_field.typed.take().is_some() {
+                set_text(&mut text, number_field.current_value.to_string());
+            }
             return;
         }
-        let initial_value = number_field.current_value;
-        if keyboard.just_released(KeyCode::Delete) || keyboard.just_released(KeyCode::Back) {
-            number_field.current_value =
-                (number_field.current_value / 10).max(number_field.min_value);
-        }
-        for (key, value) in &[
-            (KeyCode::Key0, 0),
-            (KeyCode::Key1, 1),
-            (KeyCode::Key2, 2),
-            (KeyCode::Key3, 3),
-            (KeyCode::Key4, 4),
-            (KeyCode::Key5, 5),
-            (KeyCode::Key6, 6),
-            (KeyCode::Key7, 7),
-            (KeyCode::Key8, 8),
-            (KeyCode::Key9, 9),
-            (KeyCode::Numpad0, 0),
-            (KeyCode::Numpad1, 1),
-            (KeyCode::Numpad2, 2),
-            (KeyCode::Numpad3, 3),
-            (KeyCode::Numpad4, 4),
-            (KeyCode::Numpad5, 5),
-            (KeyCode::Numpad6, 6),
-            (KeyCode::Numpad7, 7),
-            (KeyCode::Numpad8, 8),
-            (KeyCode::Numpad9, 9),
-        ] {
-            if keyboard.just_released(*key) {
-                number_field.current_value =
-                    (number_field.current_value * 10 + value).min(number_field.max_value);
-                break;
+        if selected
+            && !keyboard.just_released(KeyCode::Return)
+            && !keyboard.just_released(KeyCode::NumpadEnter)
+        {
+            if number_field.type_keys(&keyboard) {
+                let typed = number_field.typed.clone().unwrap_or_default();
+                set_text(&mut text, typed);
             }
+            return;
         }
 
-        if initial_value != number_field.current_value {
-            if text.sections.len() == 0 {
-                text.sections.push(TextSection {
-                    value: format!("{}", number_field.current_value),
-                    style: TextStyle {
-                        font: Default::default(),
-                        font_size: 14.0,
-                        color: Color::BLACK,
-                    },
-                });
-            } else {
-                text.sections[0].value = format!("{}", number_field.current_value);
+        let typed = match number_field.typed.take() {
+            Some(typed) => typed,
+            None => return,
+        };
+        if let Some(value) = number_field.parse_typed(&typed) {
+            if value != number_field.current_value {
+                number_field.current_value = value;
+                events.send(number_field.event_generator.create_event(value));
             }
-            events.send(
-                number_field
-                    .event_generator
-                    .create_event(number_field.current_value),
-            );
         }
+        set_text(&mut text, number_field.current_value.to_string());
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::{event::Events, schedule::SystemStage},
+        input::Input,
+        prelude::{Component, Entity, KeyCode, Stage, World},
+        text::Text,
+    };
+
+    use crate::ui::element::UiElement;
+
+    use super::{number_field_handler, NumberField, NumberedEventGenerator};
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Offset(i32);
+
+    #[derive(Component)]
+    struct Generator;
+
+    impl NumberedEventGenerator for Generator {
+        type Event = Offset;
+        type Value = i32;
+
+        fn create_event(&self, value: i32) -> Self::Event {
+            Offset(value)
+        }
+    }
+
+    fn spawn_field(world: &mut World) -> Entity {
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(Events::<Offset>::default());
+        let mut element = UiElement::default();
+        element.selected_state.current = true;
+        world
+            .spawn()
+            .insert(Text::default())
+            .insert(element)
+            .insert(NumberField {
+                event_generator: Generator,
+                current_value: 3,
+                max_value: 20,
+                min_value: -20,
+                typed: None,
+            })
+            .id()
+    }
+
+    /// Release each key in its own frame, returning the events sent.
+    fn type_keys(world: &mut World, keys: &[KeyCode]) -> Vec<Offset> {
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(number_field_handler::<Generator>);
+        let mut sent = Vec::new();
+        for key in keys {
+            let mut keyboard = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+            keyboard.clear();
+            keyboard.press(*key);
+            keyboard.release(*key);
+            stage.run(world);
+            let mut events = world.get_resource_mut::<Events<Offset>>().unwrap();
+            sent.extend(events.iter_current_update_events().cloned());
+            events.update();
+        }
+        sent
+    }
+
+    fn field_text(world: &World, field: Entity) -> String {
+        world.get::<Text>(field).unwrap().sections[0].value.clone()
+    }
+
+    #[test]
+    fn typed_negative_values_are_sent_on_commit() {
+        let mut world = World::new();
+        let field = spawn_field(&mut world);
+
+        let sent = type_keys(&mut world, &[KeyCode::Minus, KeyCode::Key1, KeyCode::Numpad2]);
+        assert!(sent.is_empty());
+        assert_eq!(field_text(&world, field), "-12");
+        assert_eq!(world.get::<NumberField<Generator>>(field).unwrap().current_value, 3);
+
+        let sent = type_keys(&mut world, &[KeyCode::Return]);
+        assert_eq!(sent, vec![Offset(-12)]);
+        assert_eq!(field_text(&world, field), "-12");
+        assert_eq!(world.get::<NumberField<Generator>>(field).unwrap().current_value, -12);
+
+        // Escape throws away what was typed.
+        let sent = type_keys(&mut world, &[KeyCode::Key7, KeyCode::Escape, KeyCode::Return]);
+        assert!(sent.is_empty());
+        assert_eq!(field_text(&world, field), "-12");
+    }
+
+    #[test]
+    fn values_out_of_bounds_are_rejected() {
+        let mut world = World::new();
+        let field = spawn_field(&mut world);
+
+        let sent = type_keys(&mut world, &[KeyCode::Key2, KeyCode::Key1, KeyCode::Return]);
+        assert!(sent.is_empty());
+        assert_eq!(field_text(&world, field), "3");
+        let number_field = world.get::<NumberField<Generator>>(field).unwrap();
+        assert_eq!(number_field.current_value, 3);
+        assert_eq!(number_field.typed, None);
+
+        // A minus sign part way through a number is ignored.
+        let sent = type_keys(&mut world, &[KeyCode::Key1, KeyCode::Minus, KeyCode::Return]);
+        assert_eq!(sent, vec![Offset(1)]);
+    }
+}
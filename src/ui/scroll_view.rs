@@ -1,11 +1,16 @@
 use bevy::{
-    hierarchy::Children,
+    hierarchy::{BuildChildren, Children},
     math::{Size, Vec2, Vec3},
-    prelude::{Changed, Component, Entity, Query, Transform},
+    prelude::{Changed, Color, Commands, Component, Entity, Query, Transform, Visibility, With},
+    sprite::{Sprite, SpriteBundle},
 };
 
 use super::element::UiElement;
 
+// How wide the scrollbar along the right of a vertical scroll is, and the shortest it gets.
+const SCROLLBAR_WIDTH: f32 = 6.0;
+const SCROLLBAR_MIN_LENGTH: f32 = 12.0;
+
 /// What direction to layout the children of Scrollers
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum LayoutDirection {
@@ -19,6 +24,8 @@ pub enum LayoutDirection {
 pub struct UiLinearScroll {
     pub scroll_position: Vec2,
     pub layout_direction: LayoutDirection,
+    // The size of all of our children laid out, as of the last time they were positioned.
+    pub content_size: Size,
 }
 
 impl Default for UiLinearScroll {
@@ -26,10 +33,36 @@ impl Default for UiLinearScroll {
         Self {
             scroll_position: Vec2::ZERO,
             layout_direction: LayoutDirection::Vertical,
+            content_size: Size::new(0.0, 0.0),
         }
     }
 }
 
+/// Marks the sprite drawn as the scrollbar of the linear scroll it is a child of. It has no
+/// `UiElement`, so it is not laid out with the other children.
+#[derive(Component)]
+pub struct ScrollBar;
+
+/// Clamp a scroll position along one axis so the content can only be scrolled until its end
+/// meets the end of the viewport. Content that fits in the viewport does not scroll at all.
+fn clamp_scroll(position: f32, content: f32, viewport: f32) -> f32 {
+    position.min((content - viewport).max(0.0)).max(0.0)
+}
+
+/// How far from the start of the viewport the scrollbar starts and how long it is, or `None`
+/// if all of the content fits in the viewport.
+fn scrollbar_extent(position: f32, content: f32, viewport: f32) -> Option<(f32, f32)> {
+    if content <= viewport || viewport <= 0.0 {
+        return None;
+    }
+    let length = (viewport * viewport / content)
+        .max(SCROLLBAR_MIN_LENGTH)
+        .min(viewport);
+    let start =
+        clamp_scroll(position, content, viewport) / (content - viewport) * (viewport - length);
+    Some((start, length))
+}
+
 /// Helper function which takes in a list of children,
 /// calculates the bounds needed to fit them along the
 /// layout direction of the passed in scroll, and positions
@@ -59,16 +92,9 @@ fn position_scroll_children(
     }
 
     // Update scroll so that we cannot scroll past the bounds of our children.
-    scroll.scroll_position.y = scroll
-        .scroll_position
-        .y
-        .min((height - bounding_size.height).max(0.0))
-        .max(0.0);
-    scroll.scroll_position.x = scroll
-        .scroll_position
-        .x
-        .min((width - bounding_size.width).max(0.0))
-        .max(0.0);
+    scroll.content_size = Size::new(width, height);
+    scroll.scroll_position.y = clamp_scroll(scroll.scroll_position.y, height, bounding_size.height);
+    scroll.scroll_position.x = clamp_scroll(scroll.scroll_position.x, width, bounding_size.width);
 
     let mut position = Vec3::new(
         -bounding_size.width / 2.0 - scroll.scroll_position.x,
@@ -122,3 +148,141 @@ pub fn linear_scroll_handler(
         position_scroll_children(children, &mut transform_query, size, &mut scroll);
     });
 }
+
+/// Show a scrollbar along the right of vertical scrolls whose children do not fit, sized and
+/// placed to show which part of them is in view.
+pub fn update_scrollbars(
+    scroll_query: Query<
+        (Entity, &UiLinearScroll, &UiElement, Option<&Children>),
+        Changed<UiLinearScroll>,
+    >,
+    mut bar_query: Query<(&mut Transform, &mut Sprite, &mut Visibility), With<ScrollBar>>,
+    mut commands: Commands,
+) {
+    scroll_query.for_each(|(entity, scroll, element, children)| {
+        let extent = match scroll.layout_direction {
+            LayoutDirection::Vertical => scrollbar_extent(
+                scroll.scroll_position.y,
+                scroll.content_size.height,
+                element.size.height,
+            ),
+            LayoutDirection::Horizontal => None,
+        };
+        let (start, length) = extent.unwrap_or((0.0, 0.0));
+        let translation = Vec3::new(
+            (element.size.width - SCROLLBAR_WIDTH) / 2.0,
+            (element.size.height - length) / 2.0 - start,
+            2.0,
+        );
+        let size = Vec2::new(SCROLLBAR_WIDTH, length);
+
+        let bar = children
+            .and_then(|children| children.iter().find(|child| bar_query.get(**child).is_ok()));
+        match bar {
+            Some(bar) => {
+                if let Ok((mut transform, mut sprite, mut visibility)) = bar_query.get_mut(*bar) {
+                    transform.translation = translation;
+                    sprite.custom_size = Some(size);
+                    visibility.is_visible = extent.is_some();
+                }
+            }
+            None if extent.is_some() => {
+                commands.entity(entity).with_children(|parent| {
+                    parent
+                        .spawn_bundle(SpriteBundle {
+                            sprite: Sprite {
+                                color: Color::rgba(0.0, 0.0, 0.0, 0.4),
+                                custom_size: Some(size),
+                                ..Default::default()
+                            },
+                            transform: Transform::from_translation(translation),
+                            ..Default::default()
+                        })
+                        .insert(ScrollBar);
+                });
+            }
+            None => (),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::schedule::SystemStage,
+        hierarchy::BuildWorldChildren,
+        math::{Size, Vec2},
+        prelude::{Stage, Transform, World},
+    };
+
+    use crate::ui::element::UiElement;
+
+    use super::{
+        clamp_scroll, linear_scroll_children_changed, scrollbar_extent, UiLinearScroll,
+        SCROLLBAR_MIN_LENGTH,
+    };
+
+    #[test]
+    fn scroll_is_clamped_to_the_content() {
+        // Content taller than the viewport scrolls until its last child is at the bottom.
+        assert_eq!(clamp_scroll(-10.0, 300.0, 100.0), 0.0);
+        assert_eq!(clamp_scroll(50.0, 300.0, 100.0), 50.0);
+        assert_eq!(clamp_scroll(250.0, 300.0, 100.0), 200.0);
+        // Content that fits does not scroll.
+        assert_eq!(clamp_scroll(50.0, 80.0, 100.0), 0.0);
+        assert_eq!(clamp_scroll(-50.0, 80.0, 100.0), 0.0);
+
+        assert_eq!(scrollbar_extent(0.0, 80.0, 100.0), None);
+        assert_eq!(scrollbar_extent(0.0, 400.0, 100.0), Some((0.0, 25.0)));
+        assert_eq!(scrollbar_extent(300.0, 400.0, 100.0), Some((75.0, 25.0)));
+        assert_eq!(scrollbar_extent(900.0, 400.0, 100.0), Some((75.0, 25.0)));
+        assert_eq!(
+            scrollbar_extent(0.0, 100_000.0, 100.0),
+            Some((0.0, SCROLLBAR_MIN_LENGTH))
+        );
+    }
+
+    #[test]
+    fn removing_children_clamps_the_scroll_again() {
+        let mut world = World::new();
+        let children = (0..6)
+            .map(|_| {
+                world
+                    .spawn()
+                    .insert(Transform::default())
+                    .insert(UiElement {
+                        size: Size::new(10.0, 50.0),
+                        ..Default::default()
+                    })
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        let scroll = world
+            .spawn()
+            .insert(Transform::default())
+            .insert(UiElement {
+                size: Size::new(10.0, 100.0),
+                ..Default::default()
+            })
+            .insert(UiLinearScroll {
+                scroll_position: Vec2::new(0.0, 180.0),
+                ..Default::default()
+            })
+            .push_children(&children)
+            .id();
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(linear_scroll_children_changed);
+        stage.run(&mut world);
+        let position = |world: &World| world.get::<UiLinearScroll>(scroll).unwrap().scroll_position;
+        assert_eq!(position(&world), Vec2::new(0.0, 180.0));
+        assert_eq!(world.get::<UiLinearScroll>(scroll).unwrap().content_size.height, 300.0);
+
+        world.entity_mut(scroll).remove_children(&children[3..]);
+        stage.run(&mut world);
+        assert_eq!(position(&world), Vec2::new(0.0, 50.0));
+
+        world.entity_mut(scroll).remove_children(&children[1..3]);
+        stage.run(&mut world);
+        assert_eq!(position(&world), Vec2::ZERO);
+    }
+}
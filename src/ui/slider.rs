@@ -0,0 +1,202 @@
+use bevy::{
+    hierarchy::Children,
+    math::{Size, Vec2},
+    ecs::system::BoxedSystem,
+    prelude::{Component, EventWriter, GlobalTransform, IntoSystem, Query, Res, Transform, With},
+    sprite::Sprite,
+    text::Text,
+};
+
+use super::{
+    element::UiElement, input::InputState, number_field::NumberedEventGenerator,
+    widget::WidgetHandler,
+};
+
+/// Component for UI Elements that allows for picking a number by dragging across them. A child
+/// with `SliderFill` shows how far along the range the value is.
+#[derive(Component)]
+pub struct Slider<EventGenerator: Component + NumberedEventGenerator<Value = u32>> {
+    /// A generator used to create the events when the value of the slider changes.
+    pub event_generator: EventGenerator,
+    pub current: u32,
+    pub min: u32,
+    pub max: u32,
+}
+
+/// Marker for the sprite child of a slider that is sized to its value.
+#[derive(Component)]
+pub struct SliderFill;
+
+/// Marker for the child of a slider that shows its value.
+#[derive(Component)]
+pub struct SliderValueText;
+
+impl<EventGenerator: Component + NumberedEventGenerator<Value = u32>> WidgetHandler for Slider<EventGenerator> {
+    type Event = EventGenerator::Event;
+
+    fn handler() -> BoxedSystem<(), ()> {
+        Box::new(IntoSystem::into_system(slider_handler::<EventGenerator>))
+    }
+}
+
+impl<EventGenerator: Component + NumberedEventGenerator<Value = u32>> Slider<EventGenerator> {
+    /// How far along the range the value is, from 0 to 1.
+    pub fn fraction(&self) -> f32 {
+        if self.max <= self.min {
+            return 1.0;
+        }
+        (self.current.clamp(self.min, self.max) - self.min) as f32 / (self.max - self.min) as f32
+    }
+
+    /// The value under a cursor `offset` along x from the center of a slider `width` wide.
+    pub fn value_at(&self, offset: f32, width: f32) -> u32 {
+        let fraction = (offset / width.max(1.0) + 0.5).clamp(0.0, 1.0);
+        self.min + (fraction * self.max.saturating_sub(self.min) as f32).round() as u32
+    }
+}
+
+/// The x position and size of the fill for a slider of the given size that is `fraction` full.
+/// The fill grows from the slider's left edge.
+pub fn fill_layout(fraction: f32, size: Size) -> (f32, Size) {
+    let width = size.width * fraction;
+    ((width - size.width) / 2.0, Size::new(width, size.height))
+}
+
+/// While a slider is clicked, move its value to the mouse and resize its fill to match.
+pub fn slider_handler<EventGenerator: Component + NumberedEventGenerator<Value = u32>>(
+    mut slider_query: Query<(
+        &GlobalTransform,
+        &UiElement,
+        &mut Slider<EventGenerator>,
+        &Children,
+    )>,
+    mut fill_query: Query<(&mut Transform, &mut Sprite), With<SliderFill>>,
+    mut text_query: Query<&mut Text, With<SliderValueText>>,
+    mut events: EventWriter<EventGenerator::Event>,
+    input_state: Res<InputState>,
+) {
+    let mouse_position = match input_state.mouse_position {
+        Some(mouse_position) => mouse_position,
+        None => return,
+    };
+    slider_query.for_each_mut(|(transform, element, mut slider, children)| {
+        if !element.click_state.current {
+            return;
+        }
+        let value = slider.value_at(
+            mouse_position.x - transform.translation.x,
+            element.size.width,
+        );
+        if value == slider.current {
+            return;
+        }
+        slider.current = value;
+        events.send(slider.event_generator.create_event(value));
+        show_slider_value(&slider, element.size, children, &mut fill_query, &mut text_query);
+    });
+}
+
+/// Resize the fill and rewrite the value text among the slider's children to match its current
+/// value. Call this after setting `current` from somewhere other than the slider itself.
+pub fn show_slider_value<EventGenerator: Component + NumberedEventGenerator<Value = u32>>(
+    slider: &Slider<EventGenerator>,
+    size: Size,
+    children: &Children,
+    fill_query: &mut Query<(&mut Transform, &mut Sprite), With<SliderFill>>,
+    text_query: &mut Query<&mut Text, With<SliderValueText>>,
+) {
+    let (fill_x, fill_size) = fill_layout(slider.fraction(), size);
+    for child in children.iter() {
+        if let Ok((mut fill_transform, mut sprite)) = fill_query.get_mut(*child) {
+            fill_transform.translation.x = fill_x;
+            sprite.custom_size = Some(Vec2::new(fill_size.width, fill_size.height));
+        }
+        if let Ok(mut text) = text_query.get_mut(*child) {
+            if let Some(section) = text.sections.first_mut() {
+                section.value = slider.current.to_string();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::{event::Events, schedule::SystemStage},
+        hierarchy::BuildWorldChildren,
+        math::{Size, Vec2, Vec3},
+        prelude::{Component, GlobalTransform, Stage, Transform, World},
+        sprite::Sprite,
+    };
+
+    use crate::ui::{element::UiElement, input::InputState, number_field::NumberedEventGenerator};
+
+    use super::{slider_handler, Slider, SliderFill};
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Moved(u32);
+
+    #[derive(Component)]
+    struct Generator;
+
+    impl NumberedEventGenerator for Generator {
+        type Event = Moved;
+        type Value = u32;
+
+        fn create_event(&self, value: u32) -> Self::Event {
+            Moved(value)
+        }
+    }
+
+    fn drag_to(world: &mut World, mouse_x: f32) -> Vec<Moved> {
+        world.get_resource_mut::<InputState>().unwrap().mouse_position =
+            Some(Vec2::new(mouse_x, 3.0));
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(slider_handler::<Generator>);
+        stage.run(world);
+        let mut events = world.get_resource_mut::<Events<Moved>>().unwrap();
+        let sent = events.iter_current_update_events().cloned().collect();
+        events.update();
+        sent
+    }
+
+    #[test]
+    fn dragging_moves_value_and_fill() {
+        let mut world = World::new();
+        world.insert_resource(InputState::default());
+        world.insert_resource(Events::<Moved>::default());
+        let fill = world
+            .spawn()
+            .insert(Transform::default())
+            .insert(Sprite::default())
+            .insert(SliderFill)
+            .id();
+        let mut element = UiElement {
+            size: Size::new(200.0, 20.0),
+            ..Default::default()
+        };
+        element.click_state.current = true;
+        world
+            .spawn()
+            .insert(GlobalTransform::from_translation(Vec3::new(100.0, 0.0, 0.0)))
+            .insert(element)
+            .insert(Slider {
+                event_generator: Generator,
+                current: 50,
+                min: 0,
+                max: 100,
+            })
+            .push_children(&[fill]);
+
+        // Three quarters of the way along the slider.
+        assert_eq!(drag_to(&mut world, 150.0), vec![Moved(75)]);
+        let fill_size = world.get::<Sprite>(fill).unwrap().custom_size;
+        assert_eq!(fill_size, Some(Vec2::new(150.0, 20.0)));
+        assert_eq!(world.get::<Transform>(fill).unwrap().translation.x, -25.0);
+
+        // Staying put sends nothing and dragging past the end stops at the max.
+        assert!(drag_to(&mut world, 150.0).is_empty());
+        assert_eq!(drag_to(&mut world, 400.0), vec![Moved(100)]);
+        assert_eq!(drag_to(&mut world, -50.0), vec![Moved(0)]);
+    }
+}
@@ -1,7 +1,13 @@
+use std::collections::VecDeque;
 
-use bevy::{prelude::{Component, Query, EventWriter, Res, KeyCode, Color, EventReader}, text::{Text, TextSection, TextStyle}, input::Input, window::ReceivedCharacter};
+use bevy::{prelude::{Component, Query, EventWriter, Res, KeyCode, Color, EventReader, IntoSystem}, text::{Text, TextSection, TextStyle}, input::Input, window::ReceivedCharacter, ecs::system::BoxedSystem};
 
-use super::element::UiElement;
+use super::{element::UiElement, widget::WidgetHandler};
+
+/// How many confirmed values a text field remembers for recalling with up and down.
+const MAX_TEXT_HISTORY: usize = 32;
+/// Drawn where the cursor is in selected text fields.
+const CURSOR: char = '|';
 
 /// Trait for structs that can generate an event given a value.
 pub trait TextEventGenerator {
@@ -15,9 +21,146 @@ pub struct TextField<EventGenerator: Component + TextEventGenerator> {
     /// A generator used to create the events when the value of the field is confirmed.
     pub event_generator: EventGenerator,
     pub current_value: String,
+    /// The number of characters before the cursor.
+    cursor: usize,
+    /// Previously confirmed values, oldest first.
+    history: VecDeque<String>,
+    /// How far back in the history we are, along with what was being typed before recalling it.
+    recalled: Option<(usize, String)>,
 }
 
+impl<EventGenerator: Component + TextEventGenerator> TextField<EventGenerator> {
+    pub fn new(event_generator: EventGenerator, current_value: String) -> Self {
+        Self {
+            event_generator,
+            cursor: current_value.chars().count(),
+            current_value,
+            history: VecDeque::new(),
+            recalled: None,
+        }
+    }
+
+    fn byte_index(&self, cursor: usize) -> usize {
+        self.current_value
+            .char_indices()
+            .nth(cursor)
+            .map_or(self.current_value.len(), |(index, _)| index)
+    }
+
+    /// Replace the value of this field, moving the cursor to its end.
+    pub fn set_value(&mut self, value: String) {
+        self.cursor = value.chars().count();
+        self.current_value = value;
+    }
+
+    /// Type a string in at the cursor.
+    pub fn insert_str(&mut self, value: &str) {
+        let index = self.byte_index(self.cursor);
+        self.current_value.insert_str(index, value);
+        self.cursor += value.chars().count();
+    }
+
+    /// Remove the character before the cursor.
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.delete();
+        }
+    }
+
+    /// Remove the character after the cursor.
+    pub fn delete(&mut self) {
+        let index = self.byte_index(self.cursor);
+        if index < self.current_value.len() {
+            self.current_value.remove(index);
+        }
+    }
 
+    /// Move the cursor by `offset` characters, stopping at either end of the value.
+    pub fn move_cursor(&mut self, offset: isize) {
+        let length = self.current_value.chars().count() as isize;
+        self.cursor = (self.cursor as isize + offset).clamp(0, length) as usize;
+    }
+
+    /// Step back through the history to an older value.
+    pub fn recall_older(&mut self) {
+        let back = match &self.recalled {
+            Some((back, _)) => back + 1,
+            None => 1,
+        };
+        if back > self.history.len() {
+            return;
+        }
+        let value = self.history[self.history.len() - back].clone();
+        let typed = match self.recalled.take() {
+            Some((_, typed)) => typed,
+            None => self.current_value.clone(),
+        };
+        self.recalled = Some((back, typed));
+        self.set_value(value);
+    }
+
+    /// Step forward through the history to a newer value, returning to what
+    /// was being typed once we pass the newest one.
+    pub fn recall_newer(&mut self) {
+        match self.recalled.take() {
+            Some((1, typed)) => self.set_value(typed),
+            Some((back, typed)) => {
+                self.set_value(self.history[self.history.len() - back + 1].clone());
+                self.recalled = Some((back - 1, typed));
+            }
+            None => (),
+        }
+    }
+
+    /// Clear the field, remembering its value in the history, and return the value.
+    pub fn confirm(&mut self) -> String {
+        self.recalled = None;
+        self.cursor = 0;
+        let value = std::mem::take(&mut self.current_value);
+        if !value.is_empty() && self.history.back() != Some(&value) {
+            if self.history.len() == MAX_TEXT_HISTORY {
+                self.history.pop_front();
+            }
+            self.history.push_back(value.clone());
+        }
+        value
+    }
+
+    /// The text to show for this field, with the cursor drawn in when selected.
+    pub fn display(&self, selected: bool) -> String {
+        let mut value = self.current_value.clone();
+        if selected {
+            value.insert(self.byte_index(self.cursor), CURSOR);
+        }
+        value
+    }
+
+    /// Update `text` to show this field, if it is not already.
+    pub fn update_text(&self, text: &mut Text, selected: bool) {
+        let value = self.display(selected);
+        if text.sections.is_empty() {
+            text.sections.push(TextSection {
+                value,
+                style: TextStyle {
+                    font: Default::default(),
+                    font_size: 14.0,
+                    color: Color::BLACK,
+                },
+            });
+        } else if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+impl<EventGenerator: Component + TextEventGenerator> WidgetHandler for TextField<EventGenerator> {
+    type Event = EventGenerator::Event;
+
+    fn handler() -> BoxedSystem<(), ()> {
+        Box::new(IntoSystem::into_system(text_field_handler::<EventGenerator>))
+    }
+}
 
 /// Detect button presses on selected text fields to type in letters on them or confirm the value
 pub(super) fn text_field_handler<EventGenerator: Component + TextEventGenerator>(
@@ -26,38 +169,171 @@ pub(super) fn text_field_handler<EventGenerator: Component + TextEventGenerator>
     keyboard: Res<Input<KeyCode>>,
     mut char_event: EventReader<ReceivedCharacter>,
 ) {
+    let typed = char_event.iter().map(|event| event.char).collect::<Vec<_>>();
     query.for_each_mut(|(mut text, mut text_field, element)| {
         if !element.selected_state.current {
+            // Only check the text here so deselected fields drop their cursor without
+            // marking the text as changed every frame.
+            if text.sections.first().map(|section| &section.value) != Some(&text_field.current_value) {
+                text_field.update_text(&mut text, false);
+            }
             return;
         }
-        let initial_value = text_field.current_value.clone();
-        for char in char_event.iter() {
-            if char.char == '\u{7f}' || char.char == '\u{08}' {
-                text_field.current_value.pop();
-            } else if !char.char.is_control() {
-                text_field.current_value.push(char.char);
+        for char in typed.iter() {
+            if *char == '\u{7f}' || *char == '\u{08}' {
+                text_field.backspace();
+            } else if !char.is_control() {
+                text_field.insert_str(&char.to_string());
             }
         }
 
+        if keyboard.just_pressed(KeyCode::Delete) {
+            text_field.delete();
+        }
+        if keyboard.just_pressed(KeyCode::Left) {
+            text_field.move_cursor(-1);
+        }
+        if keyboard.just_pressed(KeyCode::Right) {
+            text_field.move_cursor(1);
+        }
+        if keyboard.just_pressed(KeyCode::Home) {
+            text_field.move_cursor(isize::MIN);
+        }
+        if keyboard.just_pressed(KeyCode::End) {
+            text_field.move_cursor(isize::MAX);
+        }
+        if keyboard.just_pressed(KeyCode::Up) {
+            text_field.recall_older();
+        }
+        if keyboard.just_pressed(KeyCode::Down) {
+            text_field.recall_newer();
+        }
+
         if keyboard.just_released(KeyCode::NumpadEnter) || keyboard.just_released(KeyCode::Return) {
-            let mut confirmed_string = String::new();
-            std::mem::swap(&mut confirmed_string, &mut text_field.current_value);
+            let confirmed_string = text_field.confirm();
             events.send(text_field.event_generator.create_event(confirmed_string));
         }
 
-        if initial_value != text_field.current_value {
-            if text.sections.len() == 0 {
-                text.sections.push(TextSection {
-                    value: text_field.current_value.clone(),
-                    style: TextStyle {
-                        font: Default::default(),
-                        font_size: 14.0,
-                        color: Color::BLACK,
-                    },
-                });
-            } else {
-                text.sections[0].value = text_field.current_value.clone();
-            }
-        }
+        text_field.update_text(&mut text, true);
     });
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::{event::Events, schedule::SystemStage},
+        input::Input,
+        prelude::{Component, KeyCode, Stage, World},
+        text::Text,
+        window::{ReceivedCharacter, WindowId},
+    };
+
+    use crate::ui::element::UiElement;
+
+    use super::{text_field_handler, TextEventGenerator, TextField};
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Command(String);
+
+    #[derive(Component)]
+    struct Generator;
+
+    impl TextEventGenerator for Generator {
+        type Event = Command;
+
+        fn create_event(&self, value: String) -> Self::Event {
+            Command(value)
+        }
+    }
+
+    #[test]
+    fn cursor_edits_in_the_middle() {
+        let mut field = TextField::new(Generator, "info 3 4".to_string());
+        assert_eq!(field.cursor, 8);
+        field.move_cursor(-2);
+        field.insert_str("9");
+        assert_eq!(field.display(true), "info 39| 4");
+        field.backspace();
+        field.backspace();
+        assert_eq!(field.display(true), "info | 4");
+        field.move_cursor(isize::MIN);
+        field.delete();
+        assert_eq!(field.display(true), "|nfo  4");
+        field.move_cursor(-1);
+        assert_eq!(field.cursor, 0);
+        field.move_cursor(isize::MAX);
+        field.move_cursor(1);
+        assert_eq!(field.display(true), "nfo  4|");
+        assert_eq!(field.display(false), "nfo  4");
+    }
+
+    #[test]
+    fn history_recalls_confirmed_values() {
+        let mut field = TextField::new(Generator, "info 3 4 0 meshes".to_string());
+        assert_eq!(field.confirm(), "info 3 4 0 meshes");
+        field.insert_str("help");
+        field.confirm();
+        field.insert_str("help");
+        field.confirm();
+        field.insert_str("dump");
+
+        field.recall_older();
+        assert_eq!(field.current_value, "help");
+        field.recall_older();
+        assert_eq!(field.current_value, "info 3 4 0 meshes");
+        assert_eq!(field.cursor, 17);
+        // There is nothing older to go back to.
+        field.recall_older();
+        assert_eq!(field.current_value, "info 3 4 0 meshes");
+        field.recall_newer();
+        assert_eq!(field.current_value, "help");
+        field.recall_newer();
+        assert_eq!(field.current_value, "dump");
+        field.recall_newer();
+        assert_eq!(field.current_value, "dump");
+    }
+
+    #[test]
+    fn handler_types_at_the_cursor_and_recalls_with_up() {
+        let mut world = World::new();
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(Events::<ReceivedCharacter>::default());
+        world.insert_resource(Events::<Command>::default());
+        let mut element = UiElement::default();
+        element.selected_state.current = true;
+        let field = world
+            .spawn()
+            .insert(Text::default())
+            .insert(element)
+            .insert(TextField::new(Generator, "ab".to_string()))
+            .id();
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(text_field_handler::<Generator>);
+        let mut frame = |world: &mut World, key: Option<KeyCode>, char: Option<char>| {
+            let mut keyboard = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+            keyboard.clear();
+            if let Some(key) = key {
+                keyboard.press(key);
+                keyboard.release(key);
+            }
+            if let Some(char) = char {
+                world
+                    .get_resource_mut::<Events<ReceivedCharacter>>()
+                    .unwrap()
+                    .send(ReceivedCharacter { id: WindowId::primary(), char });
+            }
+            stage.run(world);
+            world.get_resource_mut::<Events<ReceivedCharacter>>().unwrap().update();
+            world.get::<Text>(field).unwrap().sections[0].value.clone()
+        };
+
+        assert_eq!(frame(&mut world, Some(KeyCode::Left), None), "a|b");
+        assert_eq!(frame(&mut world, None, Some('x')), "ax|b");
+        assert_eq!(frame(&mut world, Some(KeyCode::Return), None), "|");
+        let sent = world.get_resource::<Events<Command>>().unwrap();
+        let sent = sent.iter_current_update_events().cloned().collect::<Vec<_>>();
+        assert_eq!(sent, vec![Command("axb".to_string())]);
+        assert_eq!(frame(&mut world, Some(KeyCode::Up), None), "axb|");
+        assert_eq!(frame(&mut world, Some(KeyCode::Home), None), "|axb");
+    }
+}
@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use bevy::{
+    core::Time,
+    hierarchy::{BuildChildren, DespawnRecursiveExt},
+    math::{Vec2, Vec3},
+    prelude::{Color, Commands, Entity, Handle, Local, Query, Res, Transform},
+    sprite::{Sprite, SpriteBundle},
+    text::{Font, Text, Text2dBundle, TextAlignment, TextStyle},
+};
+
+use super::{element::UiElement, input::InputState};
+
+// Space between the tooltip text and the edge of its bubble.
+const TOOLTIP_PADDING: f32 = 6.0;
+// How far from the cursor the corner of the bubble sits.
+const CURSOR_OFFSET: f32 = 12.0;
+
+/// How tooltips look and how long an element must be hovered before its tooltip shows.
+pub struct TooltipStyle {
+    pub font: Handle<Font>,
+    pub font_size: f32,
+    pub delay: Duration,
+}
+
+impl Default for TooltipStyle {
+    fn default() -> Self {
+        Self {
+            font: Default::default(),
+            font_size: 16.0,
+            delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Tracks how long the same element has been hovered.
+#[derive(Default)]
+pub struct HoverTimer {
+    target: Option<Entity>,
+    hovered_for: Duration,
+}
+
+impl HoverTimer {
+    /// Advance the timer with the element hovered this frame, starting over when it changes.
+    /// Returns whether the element has been hovered for at least `delay`.
+    pub fn tick(&mut self, target: Option<Entity>, delta: Duration, delay: Duration) -> bool {
+        if target != self.target {
+            self.target = target;
+            self.hovered_for = Duration::ZERO;
+        } else {
+            self.hovered_for += delta;
+        }
+        self.target.is_some() && self.hovered_for >= delay
+    }
+}
+
+#[derive(Default)]
+pub struct TooltipState {
+    timer: HoverTimer,
+    bubble: Option<Entity>,
+}
+
+/// Show the tooltip of the hovered element next to the cursor once it has been hovered for the
+/// style's delay, and remove it when the element is no longer hovered.
+pub fn show_tooltips(
+    mut commands: Commands,
+    mut state: Local<TooltipState>,
+    style: Res<TooltipStyle>,
+    time: Res<Time>,
+    input_state: Res<InputState>,
+    element_query: Query<(Entity, &UiElement)>,
+) {
+    let hovered = element_query
+        .iter()
+        .find(|(_, element)| element.hover_state.current && element.tooltip.is_some());
+    let show = state
+        .timer
+        .tick(hovered.map(|(entity, _)| entity), time.delta(), style.delay);
+    if !show {
+        if let Some(bubble) = state.bubble.take() {
+            commands.entity(bubble).despawn_recursive();
+        }
+        return;
+    }
+    if state.bubble.is_some() {
+        return;
+    }
+
+    let (text, mouse_position) = match (
+        hovered.and_then(|(_, element)| element.tooltip.clone()),
+        input_state.mouse_position,
+    ) {
+        (Some(text), Some(mouse_position)) => (text, mouse_position),
+        _ => return,
+    };
+    // Text2d does not tell us how wide the text is until it is laid out, so guess from the
+    // number of characters.
+    let size = Vec2::new(
+        text.chars().count() as f32 * style.font_size * 0.6,
+        style.font_size,
+    ) + Vec2::splat(2.0 * TOOLTIP_PADDING);
+    let position = mouse_position + Vec2::new(size.x, -size.y) / 2.0
+        + Vec2::new(CURSOR_OFFSET, -CURSOR_OFFSET);
+    let bubble = commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(0.1, 0.1, 0.1, 0.9),
+                custom_size: Some(size),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(position.extend(100.0)),
+            ..Default::default()
+        })
+        .with_children(|bubble| {
+            bubble.spawn_bundle(Text2dBundle {
+                text: Text::with_section(
+                    text,
+                    TextStyle {
+                        font: style.font.clone(),
+                        font_size: style.font_size,
+                        color: Color::WHITE,
+                    },
+                    TextAlignment {
+                        vertical: bevy::text::VerticalAlign::Center,
+                        horizontal: bevy::text::HorizontalAlign::Center,
+                    },
+                ),
+                transform: Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
+                ..Default::default()
+            });
+        })
+        .id();
+    state.bubble = Some(bubble);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::prelude::Entity;
+
+    use super::HoverTimer;
+
+    #[test]
+    fn hover_timer_shows_only_after_delay() {
+        let delay = Duration::from_millis(500);
+        let frame = Duration::from_millis(200);
+        let first = Some(Entity::from_raw(1));
+        let mut timer = HoverTimer::default();
+
+        assert!(!timer.tick(None, frame, delay));
+        // The frame the hover starts does not count towards the delay.
+        assert!(!timer.tick(first, frame, delay));
+        assert!(!timer.tick(first, frame, delay));
+        assert!(!timer.tick(first, frame, delay));
+        assert!(timer.tick(first, frame, delay));
+        assert!(timer.tick(first, frame, delay));
+
+        // Moving to another element starts the wait over.
+        let second = Some(Entity::from_raw(2));
+        assert!(!timer.tick(second, frame, delay));
+        assert!(!timer.tick(second, frame, delay));
+        assert!(!timer.tick(None, frame, delay));
+        assert!(!timer.tick(None, delay, delay));
+    }
+}
@@ -0,0 +1,89 @@
+use bevy::{ecs::system::BoxedSystem, prelude::Component};
+
+/// Implemented by widget components so they can be added with
+/// `UIPlugin::register_widget`, which adds the system handling them
+/// and the event they send.
+pub trait WidgetHandler: Component {
+    type Event: Component + Clone;
+
+    /// The system handling this widget. It runs with the other UI input systems.
+    fn handler() -> BoxedSystem<(), ()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        asset::Handle,
+        core::Time,
+        ecs::{event::Events, system::BoxedSystem},
+        input::Input,
+        prelude::{App, Component, EventWriter, Image, IntoSystem, KeyCode, Query},
+        window::{WindowResized, Windows},
+    };
+
+    use crate::ui::{Button, UIPlugin, UiElement, UiStateDetails};
+
+    use super::WidgetHandler;
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Pinged;
+
+    #[derive(Component)]
+    struct Pinger;
+
+    fn ping(query: Query<&Pinger>, mut events: EventWriter<Pinged>) {
+        query.for_each(|_| events.send(Pinged));
+    }
+
+    impl WidgetHandler for Pinger {
+        type Event = Pinged;
+
+        fn handler() -> BoxedSystem<(), ()> {
+            Box::new(IntoSystem::into_system(ping))
+        }
+    }
+
+    #[derive(Component)]
+    struct SecondPinger;
+
+    impl WidgetHandler for SecondPinger {
+        type Event = Pinged;
+
+        fn handler() -> BoxedSystem<(), ()> {
+            Box::new(IntoSystem::into_system(|| ()))
+        }
+    }
+
+    #[test]
+    fn registered_widgets_add_their_handler_and_event() {
+        let mut app = App::new();
+        app.insert_resource(Windows::default());
+        app.insert_resource(Time::default());
+        app.insert_resource(Input::<KeyCode>::default());
+        app.add_event::<WindowResized>();
+        app.add_plugin(
+            UIPlugin::new()
+                .register_widget::<Pinger>()
+                .register_widget::<SecondPinger>(),
+        );
+        assert!(app.world.contains_resource::<Events<Pinged>>());
+
+        app.world.spawn().insert(Pinger);
+        // Sharing an event between widgets only adds one button handler for it.
+        app.world
+            .spawn()
+            .insert(Handle::<Image>::default())
+            .insert(Button::new(Handle::default(), Pinged))
+            .insert(UiElement {
+                click_state: UiStateDetails {
+                    current: false,
+                    previous: true,
+                    accepts_state: true,
+                },
+                ..Default::default()
+            });
+        app.update();
+        let events = app.world.get_resource::<Events<Pinged>>().unwrap();
+        assert_eq!(events.iter_current_update_events().count(), 2);
+    }
+}
@@ -1,34 +1,67 @@
-use std::fmt::Display;
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    ops::Range,
+    time::{Duration, Instant},
+};
 
 use bevy::{
     hierarchy::DespawnRecursiveExt,
-    math::{IVec2, Vec2, Vec3Swizzles},
+    math::{IVec2, Vec2, Vec3},
     prelude::{
-        info, Assets, Color, Commands, Component, Entity, EventReader, Handle, Mut, Query, Res,
-        ResMut, Transform,
+        info, Assets, Color, Commands, Component, Entity, EventReader, EventWriter, Handle, Mut,
+        Query, Res, ResMut, Transform,
     },
-    utils::{HashMap, HashSet},
+    utils::{HashMap, HashSet, StableHashMap},
 };
 
 use crate::{
     hashmap_ext::HashMultiMapExt,
+    random::SeededRng,
     simulation::SimulationState,
     tiling::{Tiling, TilingKind}, visuals::geom::build_profiles::WallProfileIndex, menus::DebugState,
 };
 
 use super::{
     geom::{
-        handles::GeometryHandleSet, GeometryHandle, GeometryStorage, VerticalProfile,
+        handles::GeometryHandleSet,
+        intersection_cache::{IntersectionCache, IntersectionKey},
+        GeometryHandle, GeometryStorage, VerticalProfile,
     },
     render::{
         instanced_mesh::MeshInstance, instanced_pbr::InstancedStandardMaterial, InstancedPbrBundle,
     },
 };
 
+// How many mesh selections we remember for backtracking. Contradictions beyond this fall back
+// to clearing the contradicting entry's edge restrictions.
+const MAX_BACKTRACK_DEPTH: usize = 32;
+
+const DEFAULT_SEED: u64 = 0x5EED;
+
+// How many restriction intersections we remember while collapsing.
+const INTERSECTION_CACHE_CAPACITY: usize = 4096;
+
+// How many collapse iterations we run each frame unless told otherwise.
+const DEFAULT_ITERATIONS_PER_FRAME: usize = 1000;
+
+// How many changes an entry with history enabled remembers. The oldest are dropped first.
+const MAX_COLLAPSE_HISTORY: usize = 100;
+
 #[derive(Component)]
 pub enum SimulationStateChanged {
     NewTiling,
     StatesChanged(Vec<(IVec2, u32)>),
+    /// The board kept its tiling but changed size. `retained` are the cells still on the board
+    /// that are not empty, with their states.
+    Resized { retained: Vec<(IVec2, u32)> },
+}
+
+/// Sent whenever the number of collapse entries with a selected mesh changes.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CollapseProgress {
+    pub collapsed: usize,
+    pub total: usize,
 }
 
 pub struct CollapseState {
@@ -38,9 +71,74 @@ pub struct CollapseState {
     base_tiling: Tiling,
     collapsed_indicies: HashSet<(u32, IVec2)>,
     material: Handle<InstancedStandardMaterial>,
+    // Where the collapsed terrain sits in the world. Entries are placed at their dual tile
+    // position shifted by the origin with height 0 resting on the ground height.
+    ground_height: f32,
+    origin: Vec2,
+    placement_changed: bool,
+
+    // Updates are kept in stable maps so the order we process them in, and so the terrain we
+    // end up with, only depends on the seed and the changes we were given.
+    height_updates: StableHashMap<IVec2, Vec<(IVec2, u32)>>,
+    neighbor_restriction_updates: StableHashMap<CollapseEntryIndex, Vec<CollapseNeighborUpdate>>,
+    decision_stack: VecDeque<CollapseDecision>,
+    seed: u64,
+    rng: SeededRng,
+
+    // How many entries have a mesh selected, kept up to date as entries change so we do not
+    // have to scan every entry to report progress.
+    collapsed: usize,
+    // The most entries that have had a mesh at once since the entries were last built or
+    // resized. Neighbor updates can clear a selected mesh, but the progress we report only
+    // goes up.
+    most_collapsed: usize,
+    reported_progress: Option<CollapseProgress>,
+
+    // The range of dual tiling indices we spawn entries for. Every index is spawned without one.
+    region: Option<Range<IVec2>>,
+    // The first corner of a region being marked out.
+    region_corner: Option<IVec2>,
+
+    // When set, entries on the edge of the collapsed area are given a flat mesh before any
+    // other entry selects one, so the rest of the terrain has to fit a flat border.
+    flat_border: bool,
+    // Border entries waiting for their flat mesh.
+    border_to_fix: Vec<CollapseEntryIndex>,
+
+    // Restrictions we have already intersected. Cleared when the tiling is rebuilt since our
+    // geometry may have been reloaded with it.
+    intersection_cache: IntersectionCache,
+
+    // How much collapsing we do each frame. With a time budget we keep going until it has
+    // passed instead of counting iterations.
+    iterations_per_frame: usize,
+    time_budget: Option<Duration>,
+    // How many iterations the last call to `collapse_visuals` ran.
+    iterations_last_frame: usize,
+
+    // When set, rebuilding the tiling reuses the entities of the entries it replaces instead of
+    // despawning them all and spawning new ones.
+    pool_entities: bool,
+    // Entities left over from the last rebuild, waiting to be reused by an entry at their index.
+    entity_pool: HashMap<CollapseEntryIndex, Entity>,
+
+    // Set when the simulation changed before our geometry was ready. We rebuild from the
+    // simulation as it is once the geometry is ready.
+    rebuild_pending: bool,
+}
+
+// A mesh selection made while collapsing along with the options we have not tried yet.
+struct CollapseDecision {
+    index: CollapseEntryIndex,
+    remaining_options: Vec<GeometryHandle>,
+    // The state of each entry modified since this decision from before it was modified.
+    modified_entries: HashMap<CollapseEntryIndex, CollapseEntrySnapshot>,
+}
 
-    height_updates: HashMap<IVec2, Vec<(IVec2, u32)>>,
-    neighbor_restriction_updates: HashMap<CollapseEntryIndex, Vec<CollapseNeighborUpdate>>,
+struct CollapseEntrySnapshot {
+    current_mesh: Option<GeometryHandle>,
+    options: usize,
+    edge_restrictions: Vec<EdgeRestriction>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
@@ -75,7 +173,68 @@ pub enum CollapseHistory {
     Selected(GeometryHandle, usize),
     DownTo(GeometryHandle),
     SendingUpdates(IVec2, u32, u128),
-    Deselected(bool),
+    // Why the mesh was dropped and how many edge restrictions were cleared with it.
+    Deselected(DeselectReason, usize),
+    Contradiction(Box<ContradictionReport>),
+}
+
+// What an entry looked like when its restrictions left it with no options.
+#[derive(Debug, Clone)]
+pub struct ContradictionReport {
+    pub corner_data: Vec<(IVec2, u32)>,
+    pub bottom_indicator: usize,
+    pub top_indicator: usize,
+    pub edge_restrictions: Vec<(usize, Option<u128>)>,
+}
+
+impl ContradictionReport {
+    /// Describe the contradiction a line at a time, labelling walls with their names.
+    pub fn describe(&self, wall_names: &HashMap<WallProfileIndex, String>) -> Vec<String> {
+        let corners = self
+            .corner_data
+            .iter()
+            .map(|(corner, height)| format!("{}={}", corner, height))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut lines = vec![
+            format!("Contradiction with corners {}", corners),
+            format!(
+                "  Indicators: {} {}",
+                VerticalProfile::create_label_string(self.bottom_indicator),
+                VerticalProfile::create_label_string(self.top_indicator)
+            ),
+        ];
+        for (edge, walls) in &self.edge_restrictions {
+            let walls = match walls {
+                Some(walls) => wall_labels(*walls, wall_names),
+                None => "any".to_string(),
+            };
+            lines.push(format!("  Edge {}: {}", edge, walls));
+        }
+        lines
+    }
+}
+
+/// The walls set in the given bits, by name where they have one.
+pub fn wall_labels(walls: u128, wall_names: &HashMap<WallProfileIndex, String>) -> String {
+    WallProfileIndex::from_bits(walls)
+        .iter()
+        .map(|wall| {
+            wall_names
+                .get(wall)
+                .cloned()
+                .unwrap_or_else(|| wall.index().to_string())
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeselectReason {
+    // Our restrictions left no options at all.
+    EmptyIntersection,
+    // The current mesh is no longer one of the options our restrictions allow.
+    MeshNoLongerMatches,
 }
 
 impl Default for CollapseState {
@@ -95,8 +254,451 @@ impl Default for CollapseState {
             },
             collapsed_indicies: Default::default(),
             material: Default::default(),
+            ground_height: 0.0,
+            origin: Vec2::ZERO,
+            placement_changed: false,
             height_updates: Default::default(),
             neighbor_restriction_updates: Default::default(),
+            decision_stack: Default::default(),
+            seed: DEFAULT_SEED,
+            rng: SeededRng::new(DEFAULT_SEED),
+            collapsed: 0,
+            most_collapsed: 0,
+            reported_progress: None,
+            region: None,
+            region_corner: None,
+            flat_border: false,
+            border_to_fix: Vec::new(),
+            intersection_cache: IntersectionCache::new(INTERSECTION_CACHE_CAPACITY),
+            iterations_per_frame: DEFAULT_ITERATIONS_PER_FRAME,
+            iterations_last_frame: 0,
+            time_budget: None,
+            pool_entities: true,
+            entity_pool: Default::default(),
+            rebuild_pending: false,
+        }
+    }
+}
+
+impl CollapseState {
+    pub fn origin(&self) -> Vec2 {
+        self.origin
+    }
+
+    pub fn set_ground_height(&mut self, ground_height: f32) {
+        if self.ground_height != ground_height {
+            self.ground_height = ground_height;
+            self.placement_changed = true;
+        }
+    }
+
+    pub fn set_origin(&mut self, origin: Vec2) {
+        if self.origin != origin {
+            self.origin = origin;
+            self.placement_changed = true;
+        }
+    }
+
+    /// Seed the choices made while collapsing. The collapse is restarted from the seed the
+    /// next time the tiling is rebuilt.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = SeededRng::new(seed);
+    }
+
+    /// Only spawn entries for the given range of dual tiling indices, or for all of them when
+    /// given `None`. Takes effect the next time the tiling is rebuilt.
+    pub fn set_region(&mut self, region: Option<Range<IVec2>>) {
+        self.region = region;
+        self.region_corner = None;
+    }
+
+    /// Mark a corner of the rectangle to use as our region. Every second corner completes the
+    /// rectangle between the two and returns true.
+    pub fn mark_region_corner(&mut self, index: IVec2) -> bool {
+        match self.region_corner.take() {
+            Some(corner) => {
+                self.region = Some(corner.min(index)..corner.max(index) + IVec2::ONE);
+                true
+            }
+            None => {
+                self.region_corner = Some(index);
+                false
+            }
+        }
+    }
+
+    pub fn intersection_cache(&self) -> &IntersectionCache {
+        &self.intersection_cache
+    }
+
+    /// Change how many restriction intersections we remember. 0 turns the cache off.
+    pub fn set_intersection_cache_capacity(&mut self, capacity: usize) {
+        self.intersection_cache.set_capacity(capacity);
+    }
+
+    /// How many vertices are waiting on height updates and how many entries are waiting on
+    /// neighbor updates.
+    pub fn pending_updates(&self) -> (usize, usize) {
+        (self.height_updates.len(), self.neighbor_restriction_updates.len())
+    }
+
+    pub fn iterations_per_frame(&self) -> usize {
+        self.iterations_per_frame
+    }
+
+    pub fn time_budget(&self) -> Option<Duration> {
+        self.time_budget
+    }
+
+    /// How many collapse iterations ran last frame, including any debug step.
+    pub fn iterations_last_frame(&self) -> usize {
+        self.iterations_last_frame
+    }
+
+    /// Run at most this many collapse iterations each frame. Clears any time budget.
+    pub fn set_iterations_per_frame(&mut self, iterations_per_frame: usize) {
+        self.iterations_per_frame = iterations_per_frame;
+        self.time_budget = None;
+    }
+
+    /// Keep collapsing each frame until the given time has passed.
+    pub fn set_time_budget(&mut self, time_budget: Duration) {
+        self.time_budget = Some(time_budget);
+    }
+
+    // Whether we can run another iteration this frame after running `iterations` since `start`.
+    fn within_budget(&self, iterations: usize, start: Instant) -> bool {
+        match self.time_budget {
+            Some(time_budget) => start.elapsed() < time_budget,
+            None => iterations < self.iterations_per_frame,
+        }
+    }
+
+    /// Reuse the entities of existing entries when the tiling is rebuilt.
+    pub fn set_pool_entities(&mut self, pool_entities: bool) {
+        self.pool_entities = pool_entities;
+    }
+
+    // Despawn the pooled entities no entry in the rebuilt tiling took.
+    fn despawn_unused_pool(&mut self, commands: &mut Commands) {
+        for (_, entity) in self.entity_pool.drain() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    /// Fix the entries on the edge of the collapsed area to flat meshes before collapsing the
+    /// rest. Applies to the entries we have now and any spawned later.
+    pub fn set_flat_border(&mut self, flat_border: bool) {
+        self.flat_border = flat_border;
+        self.border_to_fix.clear();
+        if flat_border {
+            let mut border = self
+                .position_to_entry
+                .keys()
+                .filter(|index| self.on_border(index.index))
+                .cloned()
+                .collect::<Vec<_>>();
+            // Fix the border in the same order every time so the collapse stays seeded.
+            border.sort_unstable_by_key(|index| (index.height, index.index.x, index.index.y));
+            self.border_to_fix = border;
+        }
+    }
+
+    // Whether the index is on the outermost ring of the indices we spawn entries for.
+    fn on_border(&self, index: IVec2) -> bool {
+        let (start, end) = match &self.region {
+            Some(region) => (region.start, region.end),
+            None => (IVec2::ZERO, self.dual_tiling.max_index),
+        };
+        index.x == start.x || index.y == start.y || index.x == end.x - 1 || index.y == end.y - 1
+    }
+
+    fn queue_border_fix(&mut self, index: CollapseEntryIndex) {
+        if self.flat_border && self.on_border(index.index) && !self.border_to_fix.contains(&index) {
+            self.border_to_fix.push(index);
+        }
+    }
+
+    fn in_region(&self, index: IVec2) -> bool {
+        match &self.region {
+            Some(region) => index.cmpge(region.start).all() && index.cmplt(region.end).all(),
+            None => true,
+        }
+    }
+
+    fn push_decision(&mut self, decision: CollapseDecision) {
+        self.decision_stack.push_back(decision);
+        if self.decision_stack.len() > MAX_BACKTRACK_DEPTH {
+            self.decision_stack.pop_front();
+        }
+    }
+
+    /// How many of our entries have selected a mesh out of all of them. This is the most that
+    /// have had one at once, so it never goes down until the entries are rebuilt or resized.
+    pub fn progress(&self) -> CollapseProgress {
+        CollapseProgress {
+            collapsed: self.most_collapsed,
+            total: self.position_to_entry.len(),
+        }
+    }
+
+    // Update our collapsed count after an entry that was or was not collapsed has been modified.
+    fn track_collapsed(&mut self, was_collapsed: bool, entry: &CollapseEntry) {
+        match (was_collapsed, entry.current_mesh.is_some()) {
+            (false, true) => self.collapsed += 1,
+            (true, false) => self.collapsed -= 1,
+            _ => {}
+        }
+        self.most_collapsed = self.most_collapsed.max(self.collapsed);
+    }
+
+    // Remember the state of the entry before it gets modified so the latest decision can be undone.
+    fn record_modification(&mut self, entry: &CollapseEntry) {
+        if let Some(decision) = self.decision_stack.back_mut() {
+            decision
+                .modified_entries
+                .entry(CollapseEntryIndex::new(entry.index_in_tiling, entry.height))
+                .or_insert_with(|| entry.snapshot());
+        }
+    }
+
+    /// World space translation of the collapse entry at the given index.
+    pub fn get_entry_translation(&self, index: CollapseEntryIndex) -> Vec3 {
+        let position = self.dual_tiling.get_tile_at_index(index.index).position + self.origin;
+        Vec3::new(
+            position.x,
+            self.ground_height + index.height as f32,
+            position.y,
+        )
+    }
+
+    // Spawn a collapse entry at the given height for every tile in our dual tiling within our region.
+    fn spawn_layer(
+        &mut self,
+        commands: &mut Commands,
+        sim_state: &SimulationState,
+        geom_data: &GeometryStorage,
+        height: u32,
+    ) {
+        for index in self.dual_tiling.iter_indices() {
+            if self.in_region(index) {
+                self.spawn_entry(commands, sim_state, geom_data, CollapseEntryIndex::new(index, height));
+            }
+        }
+    }
+
+    fn spawn_entry(
+        &mut self,
+        commands: &mut Commands,
+        sim_state: &SimulationState,
+        geom_data: &GeometryStorage,
+        index: CollapseEntryIndex,
+    ) {
+        let bundle = InstancedPbrBundle {
+            transform: Transform::from_translation(self.get_entry_translation(index)),
+            material: self.material.clone(),
+            ..Default::default()
+        };
+        let entry = CollapseEntry::new(
+            &self.dual_tiling,
+            sim_state,
+            geom_data,
+            index.index,
+            index.height,
+            false,
+        );
+        let entity = match self.entity_pool.remove(&index) {
+            // Overwriting every component leaves the entity as if it were just spawned.
+            Some(entity) => {
+                commands.entity(entity).insert_bundle(bundle).insert(entry);
+                entity
+            }
+            None => commands.spawn_bundle(bundle).insert(entry).id(),
+        };
+        self.position_to_entry.insert(index, entity);
+        self.queue_border_fix(index);
+    }
+
+    // Throw away every entry and build a new set for the simulation's tiling.
+    fn rebuild(
+        &mut self,
+        commands: &mut Commands,
+        materials: &mut Assets<InstancedStandardMaterial>,
+        sim_state: &SimulationState,
+        geom_data: &GeometryStorage,
+    ) {
+        let old_entries = std::mem::take(&mut self.position_to_entry);
+        if self.pool_entities {
+            self.entity_pool.extend(old_entries);
+        } else {
+            for entity in old_entries.values() {
+                commands.entity(*entity).despawn_recursive();
+            }
+        }
+
+        self.collapsed = 0;
+        self.most_collapsed = 0;
+        self.max_height = 0;
+        self.base_tiling = sim_state.tiling.clone();
+        self.dual_tiling = sim_state.tiling.get_dual();
+        self.collapsed_indicies = HashSet::new();
+
+        self.height_updates.clear();
+        self.neighbor_restriction_updates.clear();
+        self.decision_stack.clear();
+        self.rng = SeededRng::new(self.seed);
+        self.intersection_cache.clear();
+        self.border_to_fix.clear();
+
+        if self.dual_tiling.kind != TilingKind::Square {
+            self.despawn_unused_pool(commands);
+            return;
+        }
+
+        if self.material == Default::default() {
+            self.material = materials.add(InstancedStandardMaterial {
+                base_color: Color::INDIGO,
+                perceptual_roughness: 1.0,
+                double_sided: false,
+                cull_mode: None,
+                ..Default::default()
+            });
+        }
+
+        // Stack a layer of entries for every height a cell's state raises it to.
+        let mut max_state = 0;
+        for index in sim_state.tiling.iter_indices() {
+            let state = sim_state.get_at(index);
+            max_state = max_state.max(state);
+            for vertex in sim_state.tiling.get_verticies(index, false) {
+                self.height_updates.add_element(vertex, (index, state));
+            }
+        }
+        self.grow_to_fit(commands, sim_state, geom_data, max_state);
+        self.despawn_unused_pool(commands);
+    }
+
+    // Follow the board changing size without changing its tiling. Entries past the new edge are
+    // despawned and entries are spawned for the indices the board grew into, leaving every
+    // entry both sizes share, and the terrain it collapsed to, alone.
+    fn resize(
+        &mut self,
+        commands: &mut Commands,
+        sim_state: &SimulationState,
+        geom_data: &GeometryStorage,
+        retained: &[(IVec2, u32)],
+        is_collapsed: impl Fn(Entity) -> bool,
+    ) {
+        let old_border = self
+            .position_to_entry
+            .keys()
+            .filter(|index| self.flat_border && self.on_border(index.index))
+            .cloned()
+            .collect::<HashSet<_>>();
+        let old_base = std::mem::replace(&mut self.base_tiling, sim_state.tiling.clone());
+        let old_dual = std::mem::replace(&mut self.dual_tiling, sim_state.tiling.get_dual());
+        let in_dual = |index: IVec2, dual: &Tiling| index.cmplt(dual.max_index).all();
+        // The decisions we could go back to may have been made for entries we remove.
+        self.decision_stack.clear();
+
+        let removed = self
+            .position_to_entry
+            .keys()
+            .filter(|index| !in_dual(index.index, &self.dual_tiling))
+            .cloned()
+            .collect::<Vec<_>>();
+        for index in removed {
+            if let Some(entity) = self.position_to_entry.remove(&index) {
+                if is_collapsed(entity) {
+                    self.collapsed -= 1;
+                }
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        let dual_tiling = &self.dual_tiling;
+        self.height_updates.retain(|index, _| in_dual(*index, dual_tiling));
+        self.neighbor_restriction_updates
+            .retain(|index, _| in_dual(index.index, dual_tiling));
+        self.border_to_fix.retain(|index| in_dual(index.index, dual_tiling));
+
+        // Entries the old edge ran along that are inside the board now lose their flat mesh
+        // and collapse again with the rest of the terrain.
+        let unfixed = old_border
+            .iter()
+            .filter(|index| !self.on_border(index.index))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.border_to_fix.retain(|index| !unfixed.contains(index));
+        for index in unfixed {
+            if let Some(entity) = self.position_to_entry.remove(&index) {
+                if is_collapsed(entity) {
+                    self.collapsed -= 1;
+                }
+                // Spawning over a pooled entity gives it a new entry with nothing selected.
+                self.entity_pool.insert(index, entity);
+                self.spawn_entry(commands, sim_state, geom_data, index);
+            }
+        }
+
+        for height in 0..self.max_height {
+            for index in self.dual_tiling.iter_indices() {
+                if !in_dual(index, &old_dual) && self.in_region(index) {
+                    self.spawn_entry(commands, sim_state, geom_data, CollapseEntryIndex::new(index, height));
+                }
+            }
+        }
+
+        // The cells that fell off the board are empty to the entries still next to them.
+        for cell in old_base.iter_indices() {
+            if sim_state.tiling.try_get_tile_at_index(cell).is_some() {
+                continue;
+            }
+            for vertex in old_base.get_verticies(cell, false) {
+                if in_dual(vertex, &self.dual_tiling) {
+                    self.height_updates.add_element(vertex, (cell, 0));
+                }
+            }
+        }
+        // The simulation only sets the kept cells again on its next step, so the new entries
+        // next to them need their states now.
+        for (cell, state) in retained {
+            for vertex in sim_state.tiling.get_verticies(*cell, false) {
+                if in_dual(vertex, &self.dual_tiling) && !in_dual(vertex, &old_dual) {
+                    self.height_updates.add_element(vertex, (*cell, *state));
+                }
+            }
+        }
+
+        // Entries the new edge runs along are fixed to a flat mesh like the rest of the border.
+        let mut new_border = self
+            .position_to_entry
+            .keys()
+            .filter(|index| {
+                self.flat_border && self.on_border(index.index) && !old_border.contains(*index)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        new_border.sort_unstable_by_key(|index| (index.height, index.index.x, index.index.y));
+        for index in new_border {
+            self.queue_border_fix(index);
+        }
+        // Progress starts over from the entries that are still collapsed.
+        self.most_collapsed = self.collapsed;
+    }
+
+    // Add layers until we have one for every height a cell in the simulation can reach.
+    fn grow_to_fit(
+        &mut self,
+        commands: &mut Commands,
+        sim_state: &SimulationState,
+        geom_data: &GeometryStorage,
+        max_state: u32,
+    ) {
+        while self.max_height <= max_state {
+            let height = self.max_height;
+            self.spawn_layer(commands, sim_state, geom_data, height);
+            self.max_height += 1;
         }
     }
 }
@@ -114,10 +716,14 @@ pub struct CollapseEntry {
     // Store the possible set of geometry handles from our corner handles alone. This get's modified
     // only when our corner data updates.
     pub possible_geometry_entries_from_corner_data: GeometryHandleSet,
-    pub history: Vec<CollapseHistory>,
-    pub history_enabled: bool,
+    // The most recent changes to this entry, only recorded while history is enabled.
+    history: VecDeque<CollapseHistory>,
+    history_enabled: bool,
+    // Set when our restrictions left us with no options. Cleared by `take_contradiction`.
+    contradiction: bool,
 }
 
+#[derive(Clone)]
 pub struct EdgeRestriction {
     pub edge: usize,
     pub restruction: Option<u128>,
@@ -136,14 +742,12 @@ impl CollapseEntry {
             .get_verticies(index, true)
             .iter()
             .map(|index| {
-                (
-                    *index,
-                    if sim_state.tiling.in_bounds(*index) {
-                        sim_state.get_at(*index)
-                    } else {
-                        0
-                    },
-                )
+                // Corners off the edge of the board are treated as empty rather than wrapped.
+                let state = sim_state
+                    .tiling
+                    .try_get_tile_at_index(*index)
+                    .map_or(0, |tile| sim_state.get_at(tile.index));
+                (*index, state)
             })
             .collect::<Vec<_>>();
 
@@ -192,15 +796,66 @@ impl CollapseEntry {
             edge_restrictions: Vec::new(),
             possible_geometry_entries_from_corner_data: possible_geometry_entries_from_corner_data
                 .clone(),
-            history: Vec::new(),
+            history: VecDeque::new(),
             history_enabled,
+            contradiction: false,
         }
     }
 
+    fn snapshot(&self) -> CollapseEntrySnapshot {
+        CollapseEntrySnapshot {
+            current_mesh: self.current_mesh,
+            options: self.options,
+            edge_restrictions: self.edge_restrictions.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: CollapseEntrySnapshot) {
+        self.current_mesh = snapshot.current_mesh;
+        self.options = snapshot.options;
+        self.edge_restrictions = snapshot.edge_restrictions;
+        self.debug_assert_edges_sorted();
+    }
+
+    // `merge_neighbor_updates` binary searches the edge restrictions, so they must stay sorted
+    // by edge with at most one restriction per edge.
+    fn debug_assert_edges_sorted(&self) {
+        debug_assert!(
+            self.edge_restrictions
+                .windows(2)
+                .all(|pair| pair[0].edge < pair[1].edge),
+            "Edge restrictions of {:?} are out of order: {:?}",
+            self.index_in_tiling,
+            self.edge_restrictions
+                .iter()
+                .map(|edge| edge.edge)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    /// Returns true if we ran into a contradiction since the last time this was called.
+    pub fn take_contradiction(&mut self) -> bool {
+        std::mem::take(&mut self.contradiction)
+    }
+
     fn write_to_history(&mut self, history: CollapseHistory) {
-        if self.history_enabled && self.history.len() < 100 {
-            self.history.push(history);
+        if !self.history_enabled {
+            return;
+        }
+        if self.history.len() >= MAX_COLLAPSE_HISTORY {
+            self.history.pop_front();
         }
+        self.history.push_back(history);
+    }
+
+    /// Start recording changes to this entry. Returns false if we were already recording.
+    pub fn enable_history(&mut self) -> bool {
+        !std::mem::replace(&mut self.history_enabled, true)
+    }
+
+    /// The changes recorded for this entry, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &CollapseHistory> {
+        self.history.iter()
     }
 
     pub fn compute_edge_restrictions(&self, geom_data: &GeometryStorage) -> Vec<GeometryHandleSet> {
@@ -225,19 +880,43 @@ impl CollapseEntry {
         GeometryHandleSet::intersection(main_restriction.into_iter().chain(&edge_restrictions))
     }
 
+    // The key covers our edge restrictions and the corner indicators our corner options come
+    // from, so the cached intersection is only reused while neither has changed.
+    fn intersection_key(&self) -> IntersectionKey {
+        IntersectionKey {
+            side_count: self.corner_data.len(),
+            bottom_indicator: self.current_bottom_indicator,
+            top_indicator: self.current_top_indicator,
+            edges: self
+                .edge_restrictions
+                .iter()
+                .map(|edge| (edge.edge, edge.restruction))
+                .collect(),
+        }
+    }
+
+    /// Same as `compute_current_total_restriction` but reuses intersections we have seen before.
+    pub fn cached_total_restriction(
+        &self,
+        geom_data: &GeometryStorage,
+        cache: &mut IntersectionCache,
+    ) -> GeometryHandleSet {
+        cache.get_or_insert_with(self.intersection_key(), || {
+            self.compute_current_total_restriction(geom_data)
+        })
+    }
+
     fn recompute_from_restrictions(
         &mut self,
         log_total_restrictions: bool,
-        mut select: bool,
+        mut select: Option<&mut SeededRng>,
         tiling: &Tiling,
         #[allow(unused)]
         max_height: u32,
         geom_data: &GeometryStorage,
+        cache: &mut IntersectionCache,
     ) -> Vec<(CollapseEntryIndex, CollapseNeighborUpdate)> {
-        let edge_restrictions = self.compute_edge_restrictions(geom_data);
-        let main_restriction = [&self.possible_geometry_entries_from_corner_data];
-        let mut current_total_restrictions =
-            GeometryHandleSet::intersection(main_restriction.into_iter().chain(&edge_restrictions));
+        let mut current_total_restrictions = self.cached_total_restriction(geom_data, cache);
 
         if log_total_restrictions {
             info!("  Total: {}", current_total_restrictions.data_string());
@@ -264,13 +943,38 @@ impl CollapseEntry {
                     }
                     info!("    Walls: {}", data);
                 }
+                info!(
+                    "    Forbidden: {}",
+                    self.possible_geometry_entries_from_corner_data
+                        .difference(&walls)
+                        .data_string()
+                );
             }
         }
 
         if current_total_restrictions.empty() {
+            self.contradiction = true;
+            if self.history_enabled {
+                self.write_to_history(CollapseHistory::Contradiction(Box::new(
+                    ContradictionReport {
+                        corner_data: self.corner_data.clone(),
+                        bottom_indicator: self.current_bottom_indicator,
+                        top_indicator: self.current_top_indicator,
+                        edge_restrictions: self
+                            .edge_restrictions
+                            .iter()
+                            .map(|edge| (edge.edge, edge.restruction))
+                            .collect(),
+                    },
+                )));
+            }
+            let cleared_edges = self.edge_restrictions.len();
             self.edge_restrictions.clear();
             current_total_restrictions = self.possible_geometry_entries_from_corner_data.clone();
-            self.write_to_history(CollapseHistory::Deselected(true));
+            self.write_to_history(CollapseHistory::Deselected(
+                DeselectReason::EmptyIntersection,
+                cleared_edges,
+            ));
             self.current_mesh = None;
         }
 
@@ -286,16 +990,23 @@ impl CollapseEntry {
             if current_total_restrictions.contains(current) {
                 current_total_restrictions = GeometryHandleSet::new(self.corner_data.len());
                 current_total_restrictions.insert(current);
-                select = false;
+                select = None;
             } else {
-                self.write_to_history(CollapseHistory::Deselected(false));
+                self.write_to_history(CollapseHistory::Deselected(
+                    DeselectReason::MeshNoLongerMatches,
+                    0,
+                ));
                 self.current_mesh = None;
             }
         }
 
-        // If we need to select a mesh, then select one.
-        if select {
-            self.current_mesh = current_total_restrictions.into_iter().next();
+        // If we need to select a mesh, then draw one weighted by how likely its profile is.
+        if let Some(rng) = select {
+            self.current_mesh = rng.choose_weighted(
+                current_total_restrictions
+                    .into_iter()
+                    .map(|handle| (handle, geom_data.weight(handle))),
+            );
             if let Some(current) = self.current_mesh {
                 self.write_to_history(CollapseHistory::Selected(
                     current,
@@ -341,17 +1052,79 @@ impl CollapseEntry {
         updates
     }
 
+    /// Select the given mesh, which must be one of our current options, and return the
+    /// updates this sends to our neighbors.
+    pub fn select_mesh(
+        &mut self,
+        log_total_restrictions: bool,
+        handle: GeometryHandle,
+        tiling: &Tiling,
+        max_height: u32,
+        geom_data: &GeometryStorage,
+        cache: &mut IntersectionCache,
+    ) -> Vec<(CollapseEntryIndex, CollapseNeighborUpdate)> {
+        let options = self.cached_total_restriction(geom_data, cache).length();
+        self.write_to_history(CollapseHistory::Selected(handle, options));
+        self.current_mesh = Some(handle);
+        self.recompute_from_restrictions(
+            log_total_restrictions,
+            None,
+            tiling,
+            max_height,
+            geom_data,
+            cache,
+        )
+    }
+
     /// Update the state of this entry given a set of corner-height pair changes and return
     /// a list of neighbor updates that need to be propagated out to our neighbors. This is
-    /// what will initiate the collapse and ensure we are in a "collapsable" state
+    /// what will initiate the collapse and ensure we are in a "collapsable" state.
+    ///
+    /// Any neighbor updates that were queued for this entry before the corner change arrived
+    /// should be passed in as well. They are folded into our restrictions before we recompute
+    /// so that they are validated against our new corners rather than applied on top of a
+    /// stale state.
+    #[allow(clippy::too_many_arguments)]
     pub fn vertex_set_to(
         &mut self,
         log_total_restrictions: bool,
         corner_value_pairs: &[(IVec2, u32)],
+        pending_neighbor_updates: Vec<CollapseNeighborUpdate>,
         tiling: &Tiling,
         max_height: u32,
         geom_data: &GeometryStorage,
+        cache: &mut IntersectionCache,
     ) -> Vec<(CollapseEntryIndex, CollapseNeighborUpdate)> {
+        let has_edge_updates = self.merge_neighbor_updates(pending_neighbor_updates);
+        let has_corner_updates = self.update_corner_data(corner_value_pairs, geom_data);
+        if !has_edge_updates && !has_corner_updates {
+            return Vec::new();
+        }
+        if has_edge_updates {
+            self.current_mesh = None;
+        }
+
+        // Then we are going to determine if we are compatible with the restrictions we have
+        // on us from our current neighbors. And if not, we will clear those restrictions and
+        // publish restrictions to our neighbors based on either the combined restriction
+        // or only the restrictions from our corner heights.
+        self.recompute_from_restrictions(
+            log_total_restrictions,
+            None,
+            tiling,
+            max_height,
+            geom_data,
+            cache,
+        )
+    }
+
+    // Update our corner storage and the options our corners allow for. Returns false if
+    // nothing about our corners changed.
+    fn update_corner_data(
+        &mut self,
+        corner_value_pairs: &[(IVec2, u32)],
+        geom_data: &GeometryStorage,
+    ) -> bool {
         // First we are going to update our corner storage. If we already have set what
         // is passed into us then we will return and do nothing.
         let mut did_an_update_happen = false;
@@ -368,7 +1141,7 @@ impl CollapseEntry {
             }
         }
         if !did_an_update_happen {
-            return Vec::new();
+            return false;
         }
 
         // Next, we are going to recompute what our corners allow for in
@@ -415,19 +1188,8 @@ impl CollapseEntry {
             self.current_bottom_indicator,
             self.current_top_indicator,
         );
-
-        // Then we are going to determine if we are compatible with the restrictions we have
-        // on us from our current neighbors. And if not, we will clear those restrictions and
-        // publish restrictions to our neighbors based on either the combined restriction
-        // or only the restrictions from our corner heights.
-        self.recompute_from_restrictions(
-            log_total_restrictions,
-            false,
-            tiling,
-            max_height,
-            geom_data,
-        )
-    }
+        true
+    }
 
     pub fn process_neighbor_updates(
         &mut self,
@@ -436,7 +1198,29 @@ impl CollapseEntry {
         tiling: &Tiling,
         max_height: u32,
         geom_data: &GeometryStorage,
+        cache: &mut IntersectionCache,
     ) -> Vec<(CollapseEntryIndex, CollapseNeighborUpdate)> {
+        // If we have not modified our restrictions, then do not send back out updates. This protects
+        // against our neighbors selecting their final meshes, causing us to recognize that and selecting
+        // our final mesh and sending them updates, etc.
+        if self.merge_neighbor_updates(updates) {
+            self.current_mesh = None;
+            self.recompute_from_restrictions(
+                log_total_restrictions,
+                None,
+                tiling,
+                max_height,
+                geom_data,
+                cache,
+            )
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Store the restrictions our neighbors have placed on our edges. Returns true if any
+    // of our edge restrictions changed.
+    fn merge_neighbor_updates(&mut self, updates: Vec<CollapseNeighborUpdate>) -> bool {
         let mut has_some_updates = false;
         for update in updates {
             match self
@@ -478,22 +1262,8 @@ impl CollapseEntry {
                 }
             }
         }
-
-        // If we have not modified our restrictions, then do not send back out updates. This protects
-        // against our neighbors selecting their final meshes, causing us to recognize that and selecting
-        // our final mesh and sending them updates, etc.
-        if has_some_updates {
-            self.current_mesh = None;
-            self.recompute_from_restrictions(
-                log_total_restrictions,
-                false,
-                tiling,
-                max_height,
-                geom_data,
-            )
-        } else {
-            Vec::new()
-        }
+        self.debug_assert_edges_sorted();
+        has_some_updates
     }
 }
 
@@ -503,84 +1273,52 @@ pub fn rebuild_visuals(
     mut materials: ResMut<Assets<InstancedStandardMaterial>>,
     sim_state: Res<SimulationState>,
     geom_data: Res<GeometryStorage>,
+    entry_query: Query<&CollapseEntry>,
     mut commands: Commands,
 ) {
+    // Entries built now could select meshes that have not loaded yet. Everything we are told
+    // until the geometry is ready is covered by rebuilding from the simulation afterwards.
+    if !geom_data.ready {
+        if events.iter().count() > 0 {
+            collapse_state.rebuild_pending = true;
+        }
+        return;
+    }
+    if std::mem::take(&mut collapse_state.rebuild_pending) {
+        collapse_state.rebuild(&mut commands, &mut materials, &sim_state, &geom_data);
+    }
+
     for evt in events.iter() {
         match evt {
             // In the case of a new tiling, build out an entirely new set of collapse entries to handle the tiling.
             SimulationStateChanged::NewTiling => {
-                for entity in collapse_state.position_to_entry.values() {
-                    commands.entity(*entity).despawn_recursive();
-                }
-
-                collapse_state.position_to_entry.clear();
-                collapse_state.max_height = 1; //sim_state.num_states as u32;
-                collapse_state.base_tiling = sim_state.tiling.clone();
-                collapse_state.dual_tiling = sim_state.tiling.get_dual();
-                collapse_state.collapsed_indicies = HashSet::new();
-
-                collapse_state.height_updates.clear();
-                collapse_state.neighbor_restriction_updates.clear();
-
-                if collapse_state.dual_tiling.kind != TilingKind::Square {
+                collapse_state.rebuild(&mut commands, &mut materials, &sim_state, &geom_data);
+            }
+            SimulationStateChanged::Resized { retained } => {
+                // Only a square board we have already built entries for can be resized in place.
+                if collapse_state.base_tiling.kind != sim_state.tiling.kind
+                    || collapse_state.dual_tiling.kind != TilingKind::Square
+                    || collapse_state.material == Default::default()
+                {
+                    collapse_state.rebuild(&mut commands, &mut materials, &sim_state, &geom_data);
                     continue;
                 }
-
-                if collapse_state.material == Default::default() {
-                    collapse_state.material = materials.add(InstancedStandardMaterial {
-                        base_color: Color::INDIGO,
-                        perceptual_roughness: 1.0,
-                        double_sided: false,
-                        cull_mode: None,
-                        ..Default::default()
-                    });
-                }
-
-                for x in 0..collapse_state.dual_tiling.max_index.x {
-                    for y in 0..collapse_state.dual_tiling.max_index.y {
-                        let tile = collapse_state
-                            .dual_tiling
-                            .get_tile_at_index(IVec2::new(x, y));
-                        let entity = commands
-                            .spawn_bundle(InstancedPbrBundle {
-                                transform: Transform::from_translation(
-                                    tile.position.extend(0.0).xzy(),
-                                ),
-                                material: collapse_state.material.clone(),
-                                ..Default::default()
-                            })
-                            .insert(CollapseEntry::new(
-                                &collapse_state.dual_tiling,
-                                &sim_state,
-                                &geom_data,
-                                tile.index,
-                                0u32,
-                                false,
-                            ))
-                            .id();
-                        collapse_state
-                            .position_to_entry
-                            .insert(CollapseEntryIndex::new(tile.index, 0), entity);
-                    }
-                }
-
-                for x in 0..sim_state.tiling.max_index.x {
-                    for y in 0..sim_state.tiling.max_index.y {
-                        let index = IVec2::new(x, y);
-                        let state = sim_state.get_at(IVec2::new(x, y));
-                        for vertex in sim_state.tiling.get_verticies(index, false) {
-                            collapse_state
-                                .height_updates
-                                .add_element(vertex, (index, state));
-                        }
-                    }
-                }
+                collapse_state.resize(&mut commands, &sim_state, &geom_data, retained, |entity| {
+                    entry_query
+                        .get(entity)
+                        .is_ok_and(|entry| entry.current_mesh.is_some())
+                });
             }
             SimulationStateChanged::StatesChanged(changes) => {
                 if collapse_state.dual_tiling.kind != TilingKind::Square {
                     continue;
                 }
 
+                // New layers are built from the current states, so they only need the height
+                // updates below to keep the layers we already had in sync.
+                let max_state = changes.iter().map(|(_, state)| *state).max().unwrap_or(0);
+                collapse_state.grow_to_fit(&mut commands, &sim_state, &geom_data, max_state);
+
                 for (corner, new_value) in changes {
                     for vertex in sim_state.tiling.get_verticies(*corner, false) {
                         collapse_state
@@ -593,21 +1331,46 @@ pub fn rebuild_visuals(
     }
 }
 
+// Move all of our collapse entries if the ground height or origin has changed.
+pub fn update_collapse_placement(
+    mut collapse_state: ResMut<CollapseState>,
+    mut entry_query: Query<(&CollapseEntry, &mut Transform)>,
+) {
+    if !collapse_state.placement_changed {
+        return;
+    }
+    collapse_state.placement_changed = false;
+
+    for (entry, mut transform) in entry_query.iter_mut() {
+        transform.translation = collapse_state
+            .get_entry_translation(CollapseEntryIndex::new(entry.index_in_tiling, entry.height));
+    }
+}
+
 pub fn collapse_visuals(
     mut collapse_state: ResMut<CollapseState>,
     mut entry_query: Query<(
         Entity,
         &mut CollapseEntry,
         &mut MeshInstance,
+        &mut Handle<InstancedStandardMaterial>,
         &mut Transform,
     )>,
     geom_data: Res<GeometryStorage>,
     mut debug: ResMut<DebugState>,
+    mut progress_events: EventWriter<CollapseProgress>,
 ) {
-    for _ in 0..1000 {
+    let start = Instant::now();
+    let mut iterations = 0;
+    'collapse: loop {
         if debug.breaking && !debug.step {
-            return;
+            break;
         }
+        // A debug step always gets its one iteration.
+        if !debug.step && !collapse_state.within_budget(iterations, start) {
+            break;
+        }
+        iterations += 1;
         let was_step = debug.step;
         debug.step = false;
 
@@ -619,25 +1382,41 @@ pub fn collapse_visuals(
                     if debug.break_on.contains(&CollapseEntryIndex::new(index, h)) {
                         debug.breaking = true;
                         info!("Height Update: {} {:?}", index, collapse_state.height_updates.get(&index));
-                        return;
+                        break 'collapse;
                     }
                 }
             }
             if let Some(updates) = collapse_state.height_updates.remove(&index) {
+                // Our corners changing invalidates the decisions we could return to.
+                collapse_state.decision_stack.clear();
                 for entry_height in 0..collapse_state.max_height {
-                    if let Some(entity) = collapse_state
-                        .position_to_entry
-                        .get(&CollapseEntryIndex::new(index, entry_height))
-                    {
-                        if let Ok((_, entry, _, _)) = entry_query.get_mut(*entity) {
+                    let entry_index = CollapseEntryIndex::new(index, entry_height);
+                    if let Some(entity) = collapse_state.position_to_entry.get(&entry_index).cloned() {
+                        if let Ok((_, entry, _, _, _)) = entry_query.get_mut(entity) {
                             let mut entry: Mut<CollapseEntry> = entry;
+                            let was_collapsed = entry.current_mesh.is_some();
+                            // Any neighbor updates still queued for this entry were computed
+                            // against our old corners. Fold them in with the height change so
+                            // they are validated against the new corner data.
+                            let pending_neighbor_updates = collapse_state
+                                .neighbor_restriction_updates
+                                .remove(&entry_index)
+                                .unwrap_or_default();
+                            let state = &mut *collapse_state;
                             let neighbor_updates = entry.vertex_set_to(
                                 was_step,
                                 &updates,
-                                &collapse_state.dual_tiling,
-                                collapse_state.max_height,
+                                pending_neighbor_updates,
+                                &state.dual_tiling,
+                                state.max_height,
                                 &geom_data,
+                                &mut state.intersection_cache,
                             );
+                            collapse_state.track_collapsed(was_collapsed, &entry);
+                            // A border entry whose corners no longer fit its mesh gets fixed again.
+                            if entry.current_mesh.is_none() {
+                                collapse_state.queue_border_fix(entry_index);
+                            }
                             collapse_state
                                 .neighbor_restriction_updates
                                 .extend_elements(neighbor_updates);
@@ -658,19 +1437,31 @@ pub fn collapse_visuals(
             if !was_step && debug.break_on.contains(&index) {
                 debug.breaking = true;
                 info!("Neighbor Update: {:?} {:?}", index, collapse_state.neighbor_restriction_updates.get(&index));
-                return;
+                break;
             }
 
             if let Some(updates) = collapse_state.neighbor_restriction_updates.remove(&index) {
-                if let Some(entity) = collapse_state.position_to_entry.get(&index) {
-                    if let Ok((_, mut entry, _, _)) = entry_query.get_mut(*entity) {
+                if let Some(entity) = collapse_state.position_to_entry.get(&index).cloned() {
+                    if let Ok((_, mut entry, _, _, _)) = entry_query.get_mut(entity) {
+                        collapse_state.record_modification(&entry);
+                        let was_collapsed = entry.current_mesh.is_some();
+                        let state = &mut *collapse_state;
                         let neighbor_updates = entry.process_neighbor_updates(
                             was_step,
                             updates,
-                            &collapse_state.dual_tiling,
-                            collapse_state.max_height,
+                            &state.dual_tiling,
+                            state.max_height,
                             &geom_data,
+                            &mut state.intersection_cache,
                         );
+                        collapse_state.track_collapsed(was_collapsed, &entry);
+                        // If we can, undo the selection that led us here instead of keeping
+                        // the entry with its edge restrictions cleared.
+                        if entry.take_contradiction()
+                            && backtrack(&mut collapse_state, &mut entry_query, &geom_data, was_step)
+                        {
+                            continue;
+                        }
                         collapse_state
                             .neighbor_restriction_updates
                             .extend_elements(neighbor_updates);
@@ -680,67 +1471,281 @@ pub fn collapse_visuals(
             continue;
         }
 
+        // Fix the border before anything else selects a mesh.
+        if let Some(index) = collapse_state.border_to_fix.pop() {
+            if let Some(entity) = collapse_state.position_to_entry.get(&index).cloned() {
+                if let Ok((_, entry, mesh_instance, material, transform)) = entry_query.get_mut(entity) {
+                    fix_border_entry(
+                        &mut collapse_state,
+                        entry,
+                        mesh_instance,
+                        material,
+                        transform,
+                        &geom_data,
+                        was_step,
+                    );
+                }
+            }
+            continue;
+        }
+
         // Now check elements that we need to select.
         let mut smallest_num = usize::MAX;
-        let mut index = (0, IVec2::new(-1, -1));
-        let mut entity_to_collapse = None;
-        entry_query.for_each(|(entity, entry, _, _)| {
+        let mut candidates = Vec::new();
+        entry_query.for_each(|(entity, entry, _, _, _)| {
             if entry.current_mesh.is_some() {
                 return;
             }
             if entry.options < smallest_num {
                 smallest_num = entry.options;
-                index = (entry.height, entry.index_in_tiling);
-                entity_to_collapse = Some(entity);
+                candidates.clear();
+            }
+            if entry.options == smallest_num {
+                candidates.push(((entry.height, entry.index_in_tiling), entity));
             }
         });
 
         // Sanity check.
         if smallest_num == usize::MAX {
-            return;
+            break;
         }
 
+        // Break ties between the entries with the fewest options using the seed. Sort first
+        // so the choice does not depend on the order the query visits entries in.
+        candidates.sort_unstable_by_key(|((height, index), _)| (*height, index.x, index.y));
+        let candidate = collapse_state.rng.next_below(candidates.len());
+        let (index, entity_to_collapse) = candidates[candidate];
+
         if !was_step && debug.break_on.contains(&CollapseEntryIndex::new(index.1, index.0)) {
             debug.breaking = true;
             info!("Select: {:?}", index);
-            return;
+            break;
         }
 
-        let entity_to_collapse = entity_to_collapse
-            .expect("Somehow we had more indicies to collapse but did not find one to");
-        if let Ok((_, mut entry, mut mesh_instance, mut transform)) =
+        let contradiction = if let Ok((_, mut entry, mut mesh_instance, mut material, mut transform)) =
             entry_query.get_mut(entity_to_collapse)
         {
+            collapse_state.record_modification(&entry);
+            let state = &mut *collapse_state;
+            let mut options = entry.cached_total_restriction(&geom_data, &mut state.intersection_cache);
+            let snapshot = entry.snapshot();
             let new_restrictions = entry.recompute_from_restrictions(
                 was_step,
-                true,
-                &collapse_state.dual_tiling,
-                collapse_state.max_height,
+                Some(&mut state.rng),
+                &state.dual_tiling,
+                state.max_height,
                 &geom_data,
+                &mut state.intersection_cache,
             );
+            collapse_state.track_collapsed(false, &entry);
+            let contradiction = entry.take_contradiction();
+            if let (false, Some(handle)) = (contradiction, entry.current_mesh) {
+                let index = CollapseEntryIndex::new(entry.index_in_tiling, entry.height);
+                let mut modified_entries = HashMap::default();
+                modified_entries.insert(index, snapshot);
+                options.remove(handle);
+                collapse_state.push_decision(CollapseDecision {
+                    index,
+                    remaining_options: options.into_iter().collect(),
+                    modified_entries,
+                });
+            }
             collapse_state
                 .neighbor_restriction_updates
                 .extend_elements(new_restrictions);
-            if let Some(current_mesh) = entry.current_mesh {
-                if let Some(new_handle) = &geom_data.mesh_handles[current_mesh.index] {
-                    if new_handle.clone() != mesh_instance.mesh.clone() {
-                        mesh_instance.mesh = new_handle.clone();
-                    }
-                }
+            show_selected_mesh(
+                &entry,
+                &collapse_state,
+                &geom_data,
+                &mut mesh_instance,
+                &mut material,
+                &mut transform,
+            );
+            contradiction
+        } else {
+            false
+        };
+        // Backtracking throws away the updates we just queued if it finds a decision to undo.
+        if contradiction {
+            backtrack(&mut collapse_state, &mut entry_query, &geom_data, was_step);
+        }
+    }
+    collapse_state.iterations_last_frame = iterations;
 
-                let new_transform = current_mesh.orientation.get_transform(
-                    collapse_state
-                        .dual_tiling
-                        .get_tile_at_index(entry.index_in_tiling)
-                        .shape
-                        .get_side_count() as usize,
-                );
+    let progress = collapse_state.progress();
+    if collapse_state.reported_progress != Some(progress) {
+        collapse_state.reported_progress = Some(progress);
+        progress_events.send(progress);
+    }
+}
 
-                transform.rotation = new_transform.rotation;
-                transform.scale = new_transform.scale;
+// Display the mesh the entry has selected, if it has one, with the material of its profile.
+// Profiles without their own material use the collapse's material.
+fn show_selected_mesh(
+    entry: &CollapseEntry,
+    collapse_state: &CollapseState,
+    geom_data: &GeometryStorage,
+    mesh_instance: &mut MeshInstance,
+    material: &mut Handle<InstancedStandardMaterial>,
+    transform: &mut Transform,
+) {
+    if let Some(current_mesh) = entry.current_mesh {
+        if let Some(new_handle) = &geom_data.mesh_handles[current_mesh.index] {
+            if new_handle.clone() != mesh_instance.mesh.clone() {
+                mesh_instance.mesh = new_handle.clone();
             }
         }
+        let new_material = geom_data.material(current_mesh).unwrap_or(&collapse_state.material);
+        if *new_material != *material {
+            *material = new_material.clone();
+        }
+
+        let new_transform = current_mesh.orientation.get_transform(
+            collapse_state
+                .dual_tiling
+                .get_tile_at_index(entry.index_in_tiling)
+                .shape
+                .get_side_count() as usize,
+        );
+
+        transform.rotation = new_transform.rotation;
+        transform.scale = new_transform.scale;
+    }
+}
+
+// Select a flat mesh for a border entry and send its walls to its neighbors. Entries that have
+// no flat option are left for the regular collapse.
+fn fix_border_entry(
+    collapse_state: &mut CollapseState,
+    mut entry: Mut<CollapseEntry>,
+    mut mesh_instance: Mut<MeshInstance>,
+    mut material: Mut<Handle<InstancedStandardMaterial>>,
+    mut transform: Mut<Transform>,
+    geom_data: &GeometryStorage,
+    log_total_restrictions: bool,
+) {
+    let options = entry.cached_total_restriction(geom_data, &mut collapse_state.intersection_cache);
+    // Prefer the most common flat mesh, then the first one, so the choice does not use the seed.
+    let mut flat = None;
+    for handle in options.into_iter().filter(|handle| geom_data.is_flat(*handle)) {
+        let better = match flat {
+            Some(current) => geom_data.weight(handle) > geom_data.weight(current),
+            None => true,
+        };
+        if better {
+            flat = Some(handle);
+        }
+    }
+    let handle = match flat {
+        Some(handle) => handle,
+        None => return,
+    };
+
+    // Selections made before this one could not have accounted for the fixed border.
+    collapse_state.decision_stack.clear();
+    let was_collapsed = entry.current_mesh.is_some();
+    let neighbor_updates = entry.select_mesh(
+        log_total_restrictions,
+        handle,
+        &collapse_state.dual_tiling,
+        collapse_state.max_height,
+        geom_data,
+        &mut collapse_state.intersection_cache,
+    );
+    collapse_state.track_collapsed(was_collapsed, &entry);
+    collapse_state
+        .neighbor_restriction_updates
+        .extend_elements(neighbor_updates);
+    show_selected_mesh(
+        &entry,
+        collapse_state,
+        geom_data,
+        &mut mesh_instance,
+        &mut material,
+        &mut transform,
+    );
+}
+
+// Undo decisions until we reach one with an option we have not tried yet and select that
+// option instead. Returns false if there was no decision left to return to.
+fn backtrack(
+    collapse_state: &mut CollapseState,
+    entry_query: &mut Query<(
+        Entity,
+        &mut CollapseEntry,
+        &mut MeshInstance,
+        &mut Handle<InstancedStandardMaterial>,
+        &mut Transform,
+    )>,
+    geom_data: &GeometryStorage,
+    log_total_restrictions: bool,
+) -> bool {
+    while let Some(decision) = collapse_state.decision_stack.pop_back() {
+        // Decisions are only made once all updates have been processed, so anything still
+        // queued came from the choices we are undoing.
+        collapse_state.neighbor_restriction_updates.clear();
+        for (index, snapshot) in decision.modified_entries {
+            if let Some(entity) = collapse_state.position_to_entry.get(&index) {
+                if let Ok((_, mut entry, mut mesh_instance, mut material, mut transform)) =
+                    entry_query.get_mut(*entity)
+                {
+                    let was_collapsed = entry.current_mesh.is_some();
+                    entry.restore(snapshot);
+                    collapse_state.track_collapsed(was_collapsed, &entry);
+                    show_selected_mesh(
+                        &entry,
+                        collapse_state,
+                        geom_data,
+                        &mut mesh_instance,
+                        &mut material,
+                        &mut transform,
+                    );
+                }
+            }
+        }
+
+        let mut remaining_options = decision.remaining_options;
+        if remaining_options.is_empty() {
+            continue;
+        }
+        let handle = remaining_options.remove(0);
+        let entity = match collapse_state.position_to_entry.get(&decision.index) {
+            Some(entity) => *entity,
+            None => continue,
+        };
+        if let Ok((_, mut entry, mut mesh_instance, mut material, mut transform)) = entry_query.get_mut(entity) {
+            let mut modified_entries = HashMap::default();
+            modified_entries.insert(decision.index, entry.snapshot());
+            collapse_state.push_decision(CollapseDecision {
+                index: decision.index,
+                remaining_options,
+                modified_entries,
+            });
+            let was_collapsed = entry.current_mesh.is_some();
+            let neighbor_updates = entry.select_mesh(
+                log_total_restrictions,
+                handle,
+                &collapse_state.dual_tiling,
+                collapse_state.max_height,
+                geom_data,
+                &mut collapse_state.intersection_cache,
+            );
+            collapse_state.track_collapsed(was_collapsed, &entry);
+            collapse_state
+                .neighbor_restriction_updates
+                .extend_elements(neighbor_updates);
+            show_selected_mesh(
+                &entry,
+                collapse_state,
+                geom_data,
+                &mut mesh_instance,
+                &mut material,
+                &mut transform,
+            );
+            return true;
+        }
     }
+    false
 }
 
 impl Display for CollapseHistory {
@@ -780,15 +1785,23 @@ impl Display for CollapseHistory {
             CollapseHistory::DownTo(handle) => {
                 write!(f, "Down to {}", handle)
             }
-            CollapseHistory::Deselected(cleared_edges) => write!(
-                f,
-                "Deselected{}",
-                if *cleared_edges {
-                    " and cleared dges"
-                } else {
-                    ""
+            CollapseHistory::Deselected(reason, cleared_edges) => {
+                match reason {
+                    DeselectReason::EmptyIntersection => {
+                        write!(f, "Deselected as no options were left")?
+                    }
+                    DeselectReason::MeshNoLongerMatches => {
+                        write!(f, "Deselected as the mesh no longer matched")?
+                    }
                 }
-            ),
+                if *cleared_edges > 0 {
+                    write!(f, " and cleared {} edges", cleared_edges)?;
+                }
+                Ok(())
+            }
+            CollapseHistory::Contradiction(report) => {
+                write!(f, "{}", report.describe(&HashMap::default()).join(";"))
+            }
             CollapseHistory::SendingUpdates(adjacent_index, height, walls) => {
                 write!(f, "Sending Update ")?;
                 for wall in WallProfileIndex::from_bits(*walls) {
@@ -800,3 +1813,1078 @@ impl Display for CollapseHistory {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        asset::{AddAsset, AssetPlugin},
+        core::CorePlugin,
+        ecs::{
+            event::Events,
+            schedule::{Stage, SystemStage},
+            system::CommandQueue,
+        },
+        math::{IVec2, Vec2},
+        prelude::{App, Commands, Entity, Handle, Transform, World},
+        utils::{HashMap, HashSet},
+    };
+
+    use crate::{
+        hashmap_ext::HashMultiMapExt,
+        menus::DebugState,
+        simulation::SimulationState,
+        tiling::{Tiling, TilingKind},
+        visuals::geom::{
+            build_profiles::{MeshProfile, ProfileDefinition, WallProfileDefinition},
+            handles::GeometryHandleSet,
+            intersection_cache::IntersectionCache,
+            GeomOrientation, GeometryStorage, LayerProfileIndex, VerticalProfile, WallProfileIndex,
+        },
+        visuals::render::{instanced_mesh::MeshInstance, instanced_pbr::InstancedStandardMaterial},
+    };
+
+    use super::{
+        collapse_visuals, rebuild_visuals, update_collapse_placement, SimulationStateChanged, GeometryHandle, CollapseEntry, CollapseEntryIndex,
+        CollapseHistory, CollapseNeighborUpdate, CollapseProgress, CollapseState, DeselectReason,
+        MAX_COLLAPSE_HISTORY,
+    };
+
+    // A small set of square profiles. Wall 0 is flat and wall 1 is a (symmetric) cliff.
+    // Each vertical configuration has a profile with all flat walls and one with all cliffs.
+    fn test_geometry() -> GeometryStorage {
+        let mut geom_data = GeometryStorage::new();
+        for wall in 0..2 {
+            geom_data.wall_profiles.push(WallProfileDefinition {
+                definition: ProfileDefinition {
+                    verticies: Vec::new(),
+                    edges: Vec::new(),
+                },
+                reverse_profile: WallProfileIndex::new(wall),
+            });
+        }
+        for (bottom, top) in [("ffff", "eeee"), ("fsff", "efee")] {
+            for wall in 0..2 {
+                geom_data.store(
+                    MeshProfile {
+                        sides: 4,
+                        walls: vec![WallProfileIndex::new(wall); 4],
+                        top: LayerProfileIndex::new(0),
+                        bottom: LayerProfileIndex::new(0),
+                        orientations: vec![GeomOrientation::Standard { rotations: 0 }],
+                        weight: 1.0,
+                    },
+                    &VerticalProfile::parse_from(top.to_string()).unwrap(),
+                    &VerticalProfile::parse_from(bottom.to_string()).unwrap(),
+                    None,
+                );
+            }
+        }
+        geom_data.ready = true;
+        geom_data
+    }
+
+    // Flat profiles where the first one has a wall on side 1 that no profile can match on side 3.
+    // Picking the first option for an entry with a neighbor on side 1 is a dead end.
+    fn dead_end_geometry() -> GeometryStorage {
+        let mut geom_data = GeometryStorage::new();
+        for wall in 0..3 {
+            geom_data.wall_profiles.push(WallProfileDefinition {
+                definition: ProfileDefinition {
+                    verticies: Vec::new(),
+                    edges: Vec::new(),
+                },
+                reverse_profile: WallProfileIndex::new(wall),
+            });
+        }
+        for walls in [[0, 2, 0, 0], [0, 0, 0, 0]] {
+            geom_data.store(
+                MeshProfile {
+                    sides: 4,
+                    walls: walls.iter().map(|wall| WallProfileIndex::new(*wall)).collect(),
+                    top: LayerProfileIndex::new(0),
+                    bottom: LayerProfileIndex::new(0),
+                    orientations: vec![GeomOrientation::Standard { rotations: 0 }],
+                    weight: 1.0,
+                },
+                &VerticalProfile::parse_from("eeee".to_string()).unwrap(),
+                &VerticalProfile::parse_from("ffff".to_string()).unwrap(),
+                None,
+            );
+        }
+        geom_data.ready = true;
+        geom_data
+    }
+
+    // Flat profiles that all share the same walls, so every entry is free to pick any of them.
+    fn free_geometry(weights: &[f32]) -> GeometryStorage {
+        let mut geom_data = GeometryStorage::new();
+        geom_data.wall_profiles.push(WallProfileDefinition {
+            definition: ProfileDefinition {
+                verticies: Vec::new(),
+                edges: Vec::new(),
+            },
+            reverse_profile: WallProfileIndex::new(0),
+        });
+        for (layer, &weight) in weights.iter().enumerate() {
+            geom_data.store(
+                MeshProfile {
+                    sides: 4,
+                    walls: vec![WallProfileIndex::new(0); 4],
+                    top: LayerProfileIndex::new(layer),
+                    bottom: LayerProfileIndex::new(0),
+                    orientations: vec![GeomOrientation::Standard { rotations: 0 }],
+                    weight,
+                },
+                &VerticalProfile::parse_from("eeee".to_string()).unwrap(),
+                &VerticalProfile::parse_from("ffff".to_string()).unwrap(),
+                None,
+            );
+        }
+        geom_data.ready = true;
+        geom_data
+    }
+
+    fn test_tilings() -> (SimulationState, Tiling) {
+        let sim_state = SimulationState::new(Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(4, 4),
+            offset: Vec2::ZERO,
+        });
+        let dual_tiling = sim_state.tiling.get_dual();
+        (sim_state, dual_tiling)
+    }
+
+    fn test_entry(index: IVec2, height: u32) -> CollapseEntry {
+        CollapseEntry {
+            index_in_tiling: index,
+            height,
+            options: 0,
+            current_mesh: None,
+            corner_data: Vec::new(),
+            current_bottom_indicator: 0,
+            current_top_indicator: 0,
+            edge_restrictions: Vec::new(),
+            possible_geometry_entries_from_corner_data: GeometryHandleSet::new(4),
+            history: Default::default(),
+            history_enabled: false,
+            contradiction: false,
+        }
+    }
+
+    #[test]
+    fn ground_height_shifts_entries() {
+        let mut world = World::new();
+        let mut collapse_state = CollapseState::default();
+        collapse_state.dual_tiling = Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(4, 4),
+            offset: Vec2::ZERO,
+        };
+
+        let indicies = [
+            CollapseEntryIndex::new(IVec2::new(0, 0), 0),
+            CollapseEntryIndex::new(IVec2::new(3, 1), 0),
+            CollapseEntryIndex::new(IVec2::new(2, 2), 1),
+        ];
+        let entities = indicies
+            .iter()
+            .map(|index| {
+                world
+                    .spawn()
+                    .insert(test_entry(index.index, index.height))
+                    .insert(Transform::from_translation(
+                        collapse_state.get_entry_translation(*index),
+                    ))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        let before = entities
+            .iter()
+            .map(|entity| world.get::<Transform>(*entity).unwrap().translation)
+            .collect::<Vec<_>>();
+
+        collapse_state.set_ground_height(2.5);
+        world.insert_resource(collapse_state);
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(update_collapse_placement);
+        stage.run(&mut world);
+
+        for (entity, before) in entities.iter().zip(before) {
+            let after = world.get::<Transform>(*entity).unwrap().translation;
+            assert_eq!(after.y - before.y, 2.5);
+            assert_eq!(after.x, before.x);
+            assert_eq!(after.z, before.z);
+        }
+    }
+
+    #[test]
+    fn out_of_order_neighbor_updates_stay_sorted_by_edge() {
+        let update = |side: usize, wall: usize| CollapseNeighborUpdate {
+            side,
+            walls: WallProfileIndex::new(wall).to_bits(),
+            #[cfg(debug_assertions)]
+            from_neighbor: IVec2::ZERO,
+        };
+        let mut entry = test_entry(IVec2::new(1, 1), 0);
+        let edges = |entry: &CollapseEntry| {
+            entry
+                .edge_restrictions
+                .iter()
+                .map(|edge| (edge.edge, edge.restruction))
+                .collect::<Vec<_>>()
+        };
+        let walls = |wall: usize| Some(WallProfileIndex::new(wall).to_bits());
+
+        assert!(entry.merge_neighbor_updates(vec![update(3, 0), update(1, 1), update(2, 0)]));
+        assert_eq!(edges(&entry), vec![(1, walls(1)), (2, walls(0)), (3, walls(0))]);
+
+        // New edges in front of, between and behind the existing ones, with repeats of an edge
+        // in the same batch where the last update wins.
+        assert!(entry.merge_neighbor_updates(vec![
+            update(0, 1),
+            update(3, 2),
+            update(0, 2),
+            update(1, 1),
+        ]));
+        assert_eq!(
+            edges(&entry),
+            vec![(0, walls(2)), (1, walls(1)), (2, walls(0)), (3, walls(2))]
+        );
+
+        // Nothing changes when the neighbors repeat themselves, in any order.
+        assert!(!entry.merge_neighbor_updates(vec![update(2, 0), update(0, 2), update(3, 2)]));
+
+        // After a contradiction clears them the edges are filled back in order.
+        entry.edge_restrictions.clear();
+        assert!(entry.merge_neighbor_updates(vec![update(2, 1), update(0, 0)]));
+        assert_eq!(edges(&entry), vec![(0, walls(0)), (2, walls(1))]);
+    }
+
+    #[test]
+    fn history_records_changes_only_once_enabled() {
+        let geom_data = test_geometry();
+        let (sim_state, dual_tiling) = test_tilings();
+        let index = IVec2::new(1, 1);
+        let mut cache = IntersectionCache::new(0);
+        let mut entry = CollapseEntry::new(&dual_tiling, &sim_state, &geom_data, index, 0, false);
+        let neighbor_update = |walls: usize| CollapseNeighborUpdate {
+            side: 0,
+            walls: WallProfileIndex::new(walls).to_bits(),
+            #[cfg(debug_assertions)]
+            from_neighbor: IVec2::new(1, 2),
+        };
+        let kinds = |entry: &CollapseEntry| {
+            entry
+                .history()
+                .map(|history| match history {
+                    CollapseHistory::SetCorner(..) => "SetCorner",
+                    CollapseHistory::SetEdge(..) => "SetEdge",
+                    CollapseHistory::Selected(..) => "Selected",
+                    CollapseHistory::DownTo(..) => "DownTo",
+                    CollapseHistory::SendingUpdates(..) => "SendingUpdates",
+                    CollapseHistory::Deselected(..) => "Deselected",
+                    CollapseHistory::Contradiction(..) => "Contradiction",
+                })
+                .collect::<Vec<_>>()
+        };
+
+        entry.merge_neighbor_updates(vec![neighbor_update(1)]);
+        assert_eq!(kinds(&entry), Vec::<&str>::new());
+
+        assert!(entry.enable_history());
+        assert!(!entry.enable_history());
+        entry.vertex_set_to(
+            false,
+            &[(index, 1)],
+            vec![neighbor_update(0)],
+            &dual_tiling,
+            1,
+            &geom_data,
+            &mut cache,
+        );
+        // The queued edge is merged before the corner, leaving one option whose walls are sent
+        // to each neighbor.
+        assert_eq!(
+            kinds(&entry),
+            vec![
+                "SetEdge",
+                "SetCorner",
+                "DownTo",
+                "SendingUpdates",
+                "SendingUpdates",
+                "SendingUpdates",
+                "SendingUpdates",
+            ]
+        );
+
+        // Only the most recent changes are kept.
+        for height in 0..MAX_COLLAPSE_HISTORY as u32 {
+            entry.update_corner_data(&[(index, height)], &geom_data);
+        }
+        assert_eq!(entry.history().count(), MAX_COLLAPSE_HISTORY);
+        assert!(matches!(
+            entry.history().next(),
+            Some(CollapseHistory::SetCorner(_, 0))
+        ));
+        entry.update_corner_data(&[(index, 1)], &geom_data);
+        assert_eq!(entry.history().count(), MAX_COLLAPSE_HISTORY);
+        assert!(matches!(
+            entry.history().next(),
+            Some(CollapseHistory::SetCorner(_, 1))
+        ));
+    }
+
+    #[test]
+    fn contradictions_report_corners_and_conflicting_edges() {
+        let geom_data = test_geometry();
+        let (sim_state, dual_tiling) = test_tilings();
+        let index = IVec2::new(1, 1);
+        let mut cache = IntersectionCache::new(0);
+        let mut entry = CollapseEntry::new(&dual_tiling, &sim_state, &geom_data, index, 0, true);
+        // Every mesh has the same wall all the way around, so no mesh has both walls.
+        let update = |side: usize, wall: usize| CollapseNeighborUpdate {
+            side,
+            walls: WallProfileIndex::new(wall).to_bits(),
+            #[cfg(debug_assertions)]
+            from_neighbor: IVec2::ZERO,
+        };
+        entry.vertex_set_to(
+            false,
+            &[(index, 1)],
+            vec![update(0, 0), update(1, 1)],
+            &dual_tiling,
+            1,
+            &geom_data,
+            &mut cache,
+        );
+        assert!(entry.take_contradiction());
+
+        let report = entry
+            .history()
+            .find_map(|history| match history {
+                CollapseHistory::Contradiction(report) => Some(report.clone()),
+                _ => None,
+            })
+            .unwrap();
+        assert!(report.corner_data.contains(&(index, 1)));
+        assert_eq!(report.edge_restrictions.len(), 2);
+
+        let mut wall_names = HashMap::default();
+        wall_names.insert(WallProfileIndex::new(0), "floor".to_string());
+        let lines = report.describe(&wall_names);
+        assert!(lines[0].contains(&format!("{}=1", index)));
+        assert!(lines.contains(&"  Edge 0: floor".to_string()));
+        assert!(lines.contains(&"  Edge 1: 1".to_string()));
+    }
+
+    #[test]
+    fn deselection_causes_are_told_apart() {
+        let emptied = CollapseHistory::Deselected(DeselectReason::EmptyIntersection, 3).to_string();
+        let mismatched =
+            CollapseHistory::Deselected(DeselectReason::MeshNoLongerMatches, 0).to_string();
+        assert_eq!(emptied, "Deselected as no options were left and cleared 3 edges");
+        assert_eq!(mismatched, "Deselected as the mesh no longer matched");
+        assert_ne!(
+            CollapseHistory::Deselected(DeselectReason::EmptyIntersection, 0).to_string(),
+            mismatched
+        );
+    }
+
+    #[test]
+    fn height_update_reconciles_queued_neighbor_updates() {
+        let geom_data = test_geometry();
+        let (sim_state, dual_tiling) = test_tilings();
+        let index = IVec2::new(1, 1);
+        let corner_changes = [(index, 1)];
+        let neighbor_update = CollapseNeighborUpdate {
+            side: 0,
+            walls: WallProfileIndex::new(0).to_bits(),
+            #[cfg(debug_assertions)]
+            from_neighbor: IVec2::new(1, 2),
+        };
+
+        // Height change arriving while a neighbor update is still queued.
+        let mut reconciled = CollapseEntry::new(&dual_tiling, &sim_state, &geom_data, index, 0, false);
+        let mut cache = IntersectionCache::new(0);
+        let reconciled_updates = reconciled.vertex_set_to(
+            false,
+            &corner_changes,
+            vec![neighbor_update],
+            &dual_tiling,
+            1,
+            &geom_data,
+            &mut cache,
+        );
+
+        // The same changes processed one after another.
+        let mut sequential = CollapseEntry::new(&dual_tiling, &sim_state, &geom_data, index, 0, false);
+        sequential.vertex_set_to(
+            false,
+            &corner_changes,
+            Vec::new(),
+            &dual_tiling,
+            1,
+            &geom_data,
+            &mut cache,
+        );
+        let sequential_updates = sequential.process_neighbor_updates(
+            false,
+            vec![neighbor_update],
+            &dual_tiling,
+            1,
+            &geom_data,
+            &mut cache,
+        );
+
+        assert_eq!(reconciled.options, 1);
+        assert_eq!(reconciled.options, sequential.options);
+        assert_eq!(reconciled.current_mesh, sequential.current_mesh);
+        assert_eq!(reconciled.corner_data, sequential.corner_data);
+        assert_eq!(
+            reconciled.current_bottom_indicator,
+            sequential.current_bottom_indicator
+        );
+        assert_eq!(
+            reconciled
+                .edge_restrictions
+                .iter()
+                .map(|edge| (edge.edge, edge.restruction))
+                .collect::<Vec<_>>(),
+            sequential
+                .edge_restrictions
+                .iter()
+                .map(|edge| (edge.edge, edge.restruction))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(reconciled_updates, sequential_updates);
+    }
+
+    #[test]
+    fn backtracking_recovers_from_dead_end_selection() {
+        let geom_data = dead_end_geometry();
+        let sim_state = SimulationState::new(Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(1, 1),
+            offset: Vec2::ZERO,
+        });
+        let mut collapse_state = CollapseState {
+            dual_tiling: Tiling {
+                kind: TilingKind::Square,
+                max_index: IVec2::new(2, 1),
+                offset: Vec2::ZERO,
+            },
+            max_height: 1,
+            ..Default::default()
+        };
+
+        // The left entry is selected first and has its neighbor on side 1.
+        let mut world = World::new();
+        let entities = [IVec2::new(0, 0), IVec2::new(1, 0)].map(|index| {
+            let entity = world
+                .spawn()
+                .insert(CollapseEntry::new(
+                    &collapse_state.dual_tiling,
+                    &sim_state,
+                    &geom_data,
+                    index,
+                    0,
+                    true,
+                ))
+                .insert(MeshInstance {
+                    mesh: Default::default(),
+                    lods: Vec::new(),
+                })
+                .insert(Handle::<InstancedStandardMaterial>::default())
+                .insert(Transform::default())
+                .id();
+            collapse_state
+                .position_to_entry
+                .insert(CollapseEntryIndex::new(index, 0), entity);
+            entity
+        });
+
+        world.insert_resource(collapse_state);
+        world.insert_resource(geom_data);
+        world.insert_resource(DebugState {
+            breaking: true,
+            ..Default::default()
+        });
+        world.insert_resource(Events::<CollapseProgress>::default());
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(collapse_visuals);
+
+        // Step through selecting the left entry and the right entry processing the dead end it
+        // was sent. Instead of the right entry clearing its restrictions and leaving the left
+        // entry's selection in place, the selection is undone and the next option is taken.
+        for _ in 0..2 {
+            world.get_resource_mut::<DebugState>().unwrap().step = true;
+            stage.run(&mut world);
+        }
+        let left = world.get::<CollapseEntry>(entities[0]).unwrap();
+        assert!(left.history.iter().any(|history| matches!(
+            history,
+            CollapseHistory::Selected(handle, _) if handle.index == 0
+        )));
+        assert_eq!(left.current_mesh.map(|handle| handle.index), Some(1));
+        assert!(world
+            .get::<CollapseEntry>(entities[1])
+            .unwrap()
+            .edge_restrictions
+            .is_empty());
+
+        // Then let the collapse finish.
+        world.get_resource_mut::<DebugState>().unwrap().breaking = false;
+        stage.run(&mut world);
+        let left = world.get::<CollapseEntry>(entities[0]).unwrap();
+        let right = world.get::<CollapseEntry>(entities[1]).unwrap();
+        assert_eq!(left.current_mesh.map(|handle| handle.index), Some(1));
+        assert!(right.current_mesh.is_some());
+        assert!(world
+            .get_resource::<CollapseState>()
+            .unwrap()
+            .neighbor_restriction_updates
+            .is_empty());
+    }
+
+    // Build a world holding a flat board that has not been collapsed yet.
+    fn flat_collapse_world(geom_data: GeometryStorage, seed: u64) -> (World, Vec<(IVec2, Entity)>) {
+        let (sim_state, dual_tiling) = test_tilings();
+        let mut collapse_state = CollapseState {
+            dual_tiling: dual_tiling.clone(),
+            max_height: 1,
+            ..Default::default()
+        };
+        collapse_state.set_seed(seed);
+
+        let mut world = World::new();
+        let mut entities = Vec::new();
+        for index in dual_tiling.iter_indices() {
+            let entity = world
+                .spawn()
+                .insert(CollapseEntry::new(
+                    &dual_tiling,
+                    &sim_state,
+                    &geom_data,
+                    index,
+                    0,
+                    false,
+                ))
+                .insert(MeshInstance {
+                    mesh: Default::default(),
+                    lods: Vec::new(),
+                })
+                .insert(Handle::<InstancedStandardMaterial>::default())
+                .insert(Transform::default())
+                .id();
+            collapse_state
+                .position_to_entry
+                .insert(CollapseEntryIndex::new(index, 0), entity);
+            entities.push((index, entity));
+        }
+
+        world.insert_resource(collapse_state);
+        world.insert_resource(geom_data);
+        world.insert_resource(DebugState::default());
+        world.insert_resource(Events::<CollapseProgress>::default());
+        (world, entities)
+    }
+
+    // Collapse a flat board from scratch and return the mesh each entry ended up with.
+    fn collapse_with_seed(
+        geom_data: GeometryStorage,
+        seed: u64,
+    ) -> Vec<(IVec2, Option<GeometryHandle>)> {
+        let (mut world, entities) = flat_collapse_world(geom_data, seed);
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(collapse_visuals);
+        stage.run(&mut world);
+
+        entities
+            .into_iter()
+            .map(|(index, entity)| (index, world.get::<CollapseEntry>(entity).unwrap().current_mesh))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_collapses_the_same_way() {
+        let first = collapse_with_seed(free_geometry(&[1.0; 3]), 7);
+        assert!(first.iter().all(|(_, mesh)| mesh.is_some()));
+        assert_eq!(first, collapse_with_seed(free_geometry(&[1.0; 3]), 7));
+        assert_ne!(first, collapse_with_seed(free_geometry(&[1.0; 3]), 8));
+    }
+
+    #[test]
+    fn heavier_profiles_are_selected_more_often() {
+        let mut counts = [0; 3];
+        for seed in 0..50 {
+            for (_, mesh) in collapse_with_seed(free_geometry(&[20.0, 1.0, 1.0]), seed) {
+                counts[mesh.unwrap().index] += 1;
+            }
+        }
+        // The heavy profile should win about 10 out of every 11 draws.
+        assert!(counts[0] > 4 * (counts[1] + counts[2]), "{:?}", counts);
+        assert!(counts[1] > 0 && counts[2] > 0, "{:?}", counts);
+    }
+
+    // The mesh and number of options of every entry after each step of a script.
+    type Recording = Vec<Vec<(Option<GeometryHandle>, usize)>>;
+
+    // Collapse a board and then raise and lower a few cells, recording every entry's state
+    // after each step. Returns the recording along with how many intersections were reused.
+    fn run_scripted_updates(cache_capacity: usize) -> (Recording, usize) {
+        let (sim_state, _) = test_tilings();
+        let (mut world, entities) = flat_collapse_world(test_geometry(), 3);
+        world
+            .get_resource_mut::<CollapseState>()
+            .unwrap()
+            .set_intersection_cache_capacity(cache_capacity);
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(collapse_visuals);
+
+        let script = [
+            (IVec2::new(1, 1), 1),
+            (IVec2::new(2, 1), 1),
+            (IVec2::new(1, 1), 0),
+            (IVec2::new(2, 2), 1),
+            (IVec2::new(2, 1), 0),
+            (IVec2::new(2, 2), 0),
+        ];
+        let mut recording = Vec::new();
+        for step in 0..=script.len() {
+            if step > 0 {
+                let (index, state) = script[step - 1];
+                let mut collapse_state = world.get_resource_mut::<CollapseState>().unwrap();
+                for vertex in sim_state.tiling.get_verticies(index, false) {
+                    collapse_state.height_updates.add_element(vertex, (index, state));
+                }
+            }
+            stage.run(&mut world);
+            recording.push(
+                entities
+                    .iter()
+                    .map(|(_, entity)| {
+                        let entry = world.get::<CollapseEntry>(*entity).unwrap();
+                        (entry.current_mesh, entry.options)
+                    })
+                    .collect(),
+            );
+        }
+        let hits = world
+            .get_resource::<CollapseState>()
+            .unwrap()
+            .intersection_cache
+            .hits();
+        (recording, hits)
+    }
+
+    #[test]
+    fn cached_intersections_match_uncached() {
+        let (uncached, uncached_hits) = run_scripted_updates(0);
+        let (cached, cached_hits) = run_scripted_updates(64);
+        assert_eq!(uncached_hits, 0);
+        assert!(cached_hits > 0);
+        assert_eq!(cached, uncached);
+
+        // A cache too small to hold what we revisit still gives the same results.
+        let (tiny, _) = run_scripted_updates(1);
+        assert_eq!(tiny, uncached);
+    }
+
+    #[test]
+    fn flat_border_is_kept_and_restricts_interior() {
+        let flat_walls = Some(WallProfileIndex::new(0).to_bits());
+        for seed in 0..5 {
+            let (mut world, entities) = flat_collapse_world(test_geometry(), seed);
+            let mut collapse_state = world.get_resource_mut::<CollapseState>().unwrap();
+            collapse_state.set_flat_border(true);
+            let dual_tiling = collapse_state.dual_tiling.clone();
+            let border = entities
+                .iter()
+                .filter(|(index, _)| collapse_state.on_border(*index))
+                .cloned()
+                .collect::<Vec<_>>();
+            assert_eq!(collapse_state.border_to_fix.len(), border.len());
+            let mut stage = SystemStage::single_threaded();
+            stage.add_system(collapse_visuals);
+            stage.run(&mut world);
+
+            // Both profiles have the same wall on every side, so the first (flat) one is forced.
+            for (_, entity) in &border {
+                let entry = world.get::<CollapseEntry>(*entity).unwrap();
+                assert_eq!(entry.current_mesh.map(|handle| handle.index), Some(0));
+            }
+            // Each interior neighbor of the border was told to use flat walls facing it.
+            for (index, _) in &border {
+                for &(x_offset, y_offset, neighbor_side) in dual_tiling.get_adjacent(*index) {
+                    let neighbor = *index + IVec2::new(x_offset, y_offset);
+                    let entity = match entities.iter().find(|(other, _)| *other == neighbor) {
+                        Some((_, entity)) => *entity,
+                        None => continue,
+                    };
+                    let entry = world.get::<CollapseEntry>(entity).unwrap();
+                    let restriction = entry
+                        .edge_restrictions
+                        .iter()
+                        .find(|edge| edge.edge == neighbor_side)
+                        .and_then(|edge| edge.restruction);
+                    assert_eq!(restriction, flat_walls, "{} from {}", neighbor, index);
+                }
+            }
+            // And so the interior, which could otherwise pick the cliff, stays flat as well.
+            for (_, entity) in &entities {
+                let entry = world.get::<CollapseEntry>(*entity).unwrap();
+                assert_eq!(entry.current_mesh.map(|handle| handle.index), Some(0));
+            }
+        }
+    }
+
+    #[test]
+    fn progress_increases_to_total() {
+        let (mut world, entities) = flat_collapse_world(free_geometry(&[1.0]), 0);
+        world.get_resource_mut::<DebugState>().unwrap().breaking = true;
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(collapse_visuals);
+
+        // Step through the collapse one iteration at a time, reading the progress as it is sent.
+        let mut reader = world
+            .get_resource::<Events<CollapseProgress>>()
+            .unwrap()
+            .get_reader();
+        let mut reported = Vec::new();
+        for _ in 0..10 * entities.len() {
+            world.get_resource_mut::<DebugState>().unwrap().step = true;
+            stage.run(&mut world);
+            let events = world.get_resource::<Events<CollapseProgress>>().unwrap();
+            reported.extend(reader.iter(events).cloned());
+        }
+
+        assert!(reported.iter().all(|progress| progress.total == entities.len()));
+        assert!(reported
+            .windows(2)
+            .all(|pair| pair[0].collapsed < pair[1].collapsed));
+        assert_eq!(reported.first().map(|progress| progress.collapsed), Some(1));
+        assert_eq!(
+            reported.last().map(|progress| progress.collapsed),
+            Some(entities.len())
+        );
+    }
+
+    #[test]
+    fn iteration_budget_limits_collapse_per_call() {
+        let (mut world, entities) = flat_collapse_world(free_geometry(&[1.0]), 0);
+        world
+            .get_resource_mut::<CollapseState>()
+            .unwrap()
+            .set_iterations_per_frame(1);
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(collapse_visuals);
+
+        let run = |world: &mut World, stage: &mut SystemStage| {
+            stage.run(world);
+            let collapse_state = world.get_resource::<CollapseState>().unwrap();
+            (collapse_state.progress(), collapse_state.iterations_last_frame())
+        };
+        let mut collapsed = 0;
+        for _ in 0..10 * entities.len() {
+            let (progress, iterations) = run(&mut world, &mut stage);
+            assert_eq!(iterations, 1);
+            assert!(progress.collapsed >= collapsed);
+            collapsed = progress.collapsed;
+        }
+        assert_eq!(collapsed, entities.len());
+
+        // A larger budget runs that many iterations each call while there is still work left.
+        let (mut world, entities) = flat_collapse_world(free_geometry(&[1.0]), 0);
+        world
+            .get_resource_mut::<CollapseState>()
+            .unwrap()
+            .set_iterations_per_frame(3);
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(collapse_visuals);
+        for _ in 0..10 * entities.len() {
+            let (progress, iterations) = run(&mut world, &mut stage);
+            if progress.collapsed < entities.len() {
+                assert_eq!(iterations, 3);
+            } else {
+                assert!(iterations <= 3);
+            }
+        }
+        assert_eq!(run(&mut world, &mut stage).0.collapsed, entities.len());
+
+        // Stepping still runs a single iteration when the budget allows none.
+        let (mut world, _) = flat_collapse_world(free_geometry(&[1.0]), 0);
+        world
+            .get_resource_mut::<CollapseState>()
+            .unwrap()
+            .set_iterations_per_frame(0);
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(collapse_visuals);
+        assert_eq!(run(&mut world, &mut stage).1, 0);
+        assert_eq!(world.get_resource::<CollapseState>().unwrap().progress().collapsed, 0);
+        world.get_resource_mut::<DebugState>().unwrap().step = true;
+        assert_eq!(run(&mut world, &mut stage).1, 1);
+        assert_eq!(world.get_resource::<CollapseState>().unwrap().progress().collapsed, 1);
+    }
+
+    // Rebuild the collapse entries for the simulation in the app and return their entities.
+    fn rebuild_entries(app: &mut App) -> HashSet<Entity> {
+        app.world
+            .get_resource_mut::<Events<SimulationStateChanged>>()
+            .unwrap()
+            .send(SimulationStateChanged::NewTiling);
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(rebuild_visuals);
+        stage.run(&mut app.world);
+        app.world
+            .get_resource::<CollapseState>()
+            .unwrap()
+            .position_to_entry
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn rebuilding_same_size_reuses_entities() {
+        let mut app = App::new();
+        app.add_plugin(CorePlugin)
+            .add_plugin(AssetPlugin)
+            .add_asset::<InstancedStandardMaterial>()
+            .add_event::<SimulationStateChanged>()
+            .insert_resource(CollapseState::default())
+            .insert_resource(test_tilings().0)
+            .insert_resource(test_geometry());
+
+        let first = rebuild_entries(&mut app);
+        let collapsed = *first.iter().next().unwrap();
+        app.world.get_mut::<CollapseEntry>(collapsed).unwrap().current_mesh = Some(GeometryHandle {
+            index: 0,
+            orientation: GeomOrientation::Standard { rotations: 0 },
+        });
+
+        let second = rebuild_entries(&mut app);
+        assert_eq!(first, second);
+        assert_eq!(
+            app.world.query::<&CollapseEntry>().iter(&app.world).count(),
+            second.len()
+        );
+        // Reused entries start over.
+        assert!(app.world.get::<CollapseEntry>(collapsed).unwrap().current_mesh.is_none());
+
+        // Without pooling every entry is replaced.
+        app.world
+            .get_resource_mut::<CollapseState>()
+            .unwrap()
+            .set_pool_entities(false);
+        let third = rebuild_entries(&mut app);
+        assert!(third.is_disjoint(&second));
+        assert_eq!(
+            app.world.query::<&CollapseEntry>().iter(&app.world).count(),
+            third.len()
+        );
+    }
+
+    #[test]
+    fn entries_wait_for_geometry_to_be_ready() {
+        let mut geom_data = test_geometry();
+        geom_data.ready = false;
+        let mut app = App::new();
+        app.add_plugin(CorePlugin)
+            .add_plugin(AssetPlugin)
+            .add_asset::<InstancedStandardMaterial>()
+            .add_event::<SimulationStateChanged>()
+            .insert_resource(CollapseState::default())
+            .insert_resource(test_tilings().0)
+            .insert_resource(geom_data);
+
+        assert!(rebuild_entries(&mut app).is_empty());
+        assert_eq!(app.world.query::<&CollapseEntry>().iter(&app.world).count(), 0);
+
+        // Once ready the deferred rebuild happens without being told again.
+        app.world.get_resource_mut::<GeometryStorage>().unwrap().ready = true;
+        app.world
+            .get_resource_mut::<Events<SimulationStateChanged>>()
+            .unwrap()
+            .clear();
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(rebuild_visuals);
+        stage.run(&mut app.world);
+        let entries = app.world.get_resource::<CollapseState>().unwrap().position_to_entry.len();
+        assert!(entries > 0);
+        assert_eq!(app.world.query::<&CollapseEntry>().iter(&app.world).count(), entries);
+    }
+
+    // Tell the collapse state the simulation in the app was resized and return its entries.
+    fn resize_entries(app: &mut App, max_index: IVec2) -> HashMap<CollapseEntryIndex, Entity> {
+        let retained = app
+            .world
+            .get_resource_mut::<SimulationState>()
+            .unwrap()
+            .resize(max_index);
+        let mut events = app
+            .world
+            .get_resource_mut::<Events<SimulationStateChanged>>()
+            .unwrap();
+        // A new system would read the rebuild we sent before again.
+        events.clear();
+        events.send(SimulationStateChanged::Resized { retained });
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(rebuild_visuals);
+        stage.run(&mut app.world);
+        app.world.get_resource::<CollapseState>().unwrap().position_to_entry.clone()
+    }
+
+    #[test]
+    fn resizing_by_a_row_only_touches_that_row() {
+        let mut app = App::new();
+        app.add_plugin(CorePlugin)
+            .add_plugin(AssetPlugin)
+            .add_asset::<InstancedStandardMaterial>()
+            .add_event::<SimulationStateChanged>()
+            .insert_resource(CollapseState::default())
+            .insert_resource(test_tilings().0)
+            .insert_resource(test_geometry());
+        rebuild_entries(&mut app);
+        let before = app.world.get_resource::<CollapseState>().unwrap().position_to_entry.clone();
+        let collapsed = before[&CollapseEntryIndex::new(IVec2::new(2, 2), 0)];
+        app.world.get_mut::<CollapseEntry>(collapsed).unwrap().current_mesh = Some(GeometryHandle {
+            index: 0,
+            orientation: GeomOrientation::Standard { rotations: 0 },
+        });
+        let mut collapse_state = app.world.get_resource_mut::<CollapseState>().unwrap();
+        collapse_state.collapsed = 1;
+        collapse_state.height_updates.clear();
+
+        // Growing by a row keeps every entry and spawns one more row of the dual tiling.
+        let grown = resize_entries(&mut app, IVec2::new(4, 5));
+        for (index, entity) in &before {
+            assert_eq!(grown.get(index), Some(entity));
+        }
+        let added = grown
+            .keys()
+            .filter(|index| !before.contains_key(index))
+            .collect::<Vec<_>>();
+        assert_eq!(added.len(), 5);
+        assert!(added.iter().all(|index| index.index.y == 5 && index.height == 0));
+        assert!(app.world.get::<CollapseEntry>(collapsed).unwrap().current_mesh.is_some());
+        let collapse_state = app.world.get_resource::<CollapseState>().unwrap();
+        assert!(collapse_state.height_updates.is_empty());
+        assert_eq!(collapse_state.progress().collapsed, 1);
+        assert_eq!(collapse_state.progress().total, 30);
+
+        // Shrinking back removes that row, and the entries along the new edge only hear that
+        // the cells past it are gone.
+        let mut sim_state = app.world.get_resource_mut::<SimulationState>().unwrap();
+        sim_state.set_at(IVec2::new(1, 4), 1);
+        sim_state.process();
+        let shrunk = resize_entries(&mut app, IVec2::new(4, 4));
+        assert_eq!(shrunk, before);
+        for entity in grown.values().filter(|entity| !before.values().any(|e| e == *entity)) {
+            assert!(app.world.get_entity(*entity).is_none());
+        }
+        let collapse_state = app.world.get_resource::<CollapseState>().unwrap();
+        assert!(collapse_state.height_updates.keys().all(|index| index.y == 4));
+        assert!(collapse_state.height_updates[&IVec2::new(1, 4)].contains(&(IVec2::new(1, 4), 0)));
+    }
+
+    #[test]
+    fn growing_moves_the_flat_border_to_the_new_edge() {
+        let mut app = App::new();
+        app.add_plugin(CorePlugin)
+            .add_plugin(AssetPlugin)
+            .add_asset::<InstancedStandardMaterial>()
+            .add_event::<SimulationStateChanged>()
+            .insert_resource(CollapseState::default())
+            .insert_resource(test_tilings().0)
+            .insert_resource(test_geometry());
+        rebuild_entries(&mut app);
+        let old_edge = app.world.get_resource::<CollapseState>().unwrap().position_to_entry
+            [&CollapseEntryIndex::new(IVec2::new(2, 4), 0)];
+        // Stand in for the flat mesh the old edge was fixed to.
+        app.world.get_mut::<CollapseEntry>(old_edge).unwrap().current_mesh = Some(GeometryHandle {
+            index: 0,
+            orientation: GeomOrientation::Standard { rotations: 0 },
+        });
+        let mut collapse_state = app.world.get_resource_mut::<CollapseState>().unwrap();
+        collapse_state.set_flat_border(true);
+        collapse_state.collapsed = 1;
+
+        let grown = resize_entries(&mut app, IVec2::new(4, 5));
+        assert_eq!(grown[&CollapseEntryIndex::new(IVec2::new(2, 4), 0)], old_edge);
+        assert!(app.world.get::<CollapseEntry>(old_edge).unwrap().current_mesh.is_none());
+        let collapse_state = app.world.get_resource::<CollapseState>().unwrap();
+        assert_eq!(collapse_state.progress().collapsed, 0);
+        // Along the top only the new row waits to be fixed, besides the corners of the sides.
+        let top = collapse_state
+            .border_to_fix
+            .iter()
+            .map(|index| index.index)
+            .filter(|index| index.y >= 4)
+            .collect::<Vec<_>>();
+        assert!(top.iter().all(|index| index.y == 5 || index.x == 0 || index.x == 4));
+        assert!((0..5).all(|x| top.contains(&IVec2::new(x, 5))));
+    }
+
+    #[test]
+    fn region_limits_spawned_entries() {
+        let geom_data = test_geometry();
+        let (sim_state, dual_tiling) = test_tilings();
+        let mut collapse_state = CollapseState {
+            dual_tiling,
+            ..Default::default()
+        };
+        assert!(!collapse_state.mark_region_corner(IVec2::new(3, 1)));
+        assert!(collapse_state.mark_region_corner(IVec2::new(1, 2)));
+        let region = IVec2::new(1, 1)..IVec2::new(4, 3);
+        assert_eq!(collapse_state.region, Some(region));
+
+        let mut world = World::new();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        collapse_state.grow_to_fit(&mut commands, &sim_state, &geom_data, 0);
+        queue.apply(&mut world);
+
+        assert_eq!(collapse_state.position_to_entry.len(), 6);
+        assert_eq!(world.query::<&CollapseEntry>().iter(&world).count(), 6);
+        for index in collapse_state.position_to_entry.keys() {
+            assert!((1..4).contains(&index.index.x) && (1..3).contains(&index.index.y));
+        }
+    }
+
+    #[test]
+    fn raised_cell_stacks_two_layers() {
+        let geom_data = test_geometry();
+        let (mut sim_state, dual_tiling) = test_tilings();
+        let raised = IVec2::new(1, 1);
+        sim_state.set_at(raised, 1);
+        sim_state.process();
+        let mut collapse_state = CollapseState {
+            dual_tiling: dual_tiling.clone(),
+            ..Default::default()
+        };
+
+        let mut world = World::new();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        collapse_state.grow_to_fit(&mut commands, &sim_state, &geom_data, 1);
+        queue.apply(&mut world);
+
+        assert_eq!(collapse_state.max_height, 2);
+        assert_eq!(
+            collapse_state.position_to_entry.len(),
+            2 * (dual_tiling.max_index.x * dual_tiling.max_index.y) as usize
+        );
+        for vertex in sim_state.tiling.get_verticies(raised, false) {
+            let entry = |height| {
+                let entity = collapse_state.position_to_entry[&CollapseEntryIndex::new(vertex, height)];
+                world.get::<CollapseEntry>(entity).unwrap()
+            };
+            let (lower, upper) = (entry(0), entry(1));
+            // The top of the ground layer is the bottom of the layer stacked on it.
+            assert_eq!(lower.current_top_indicator, upper.current_bottom_indicator);
+            assert_ne!(lower.current_top_indicator, lower.current_bottom_indicator);
+            assert!(VerticalProfile::from_bits(upper.current_bottom_indicator)
+                .contains(&VerticalProfile::Full));
+        }
+    }
+}
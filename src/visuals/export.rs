@@ -0,0 +1,276 @@
+use std::io::Write;
+
+use bevy::{
+    math::Vec3,
+    prelude::{info, warn, Assets, Component, EventReader, Mesh, Query, Res, Transform},
+    render::{
+        mesh::{Indices, VertexAttributeValues},
+        render_resource::PrimitiveTopology,
+    },
+};
+
+use super::{
+    collapse::{CollapseEntry, CollapseEntryIndex, CollapseState},
+    geom::GeometryStorage,
+};
+
+/// Write every collapsed terrain mesh to a single OBJ file at the given path.
+#[derive(Component)]
+pub struct ExportTerrainObj {
+    pub path: String,
+}
+
+impl CollapseState {
+    /// Write the meshes selected by the given entries as one triangulated OBJ, placing each mesh
+    /// where its entry displays it. Entries whose meshes are still loading are skipped.
+    /// Returns how many meshes were written.
+    pub fn write_obj<'a, W: Write>(
+        &self,
+        entries: impl IntoIterator<Item = &'a CollapseEntry>,
+        geom_data: &GeometryStorage,
+        meshes: &Assets<Mesh>,
+        writer: &mut W,
+    ) -> std::io::Result<usize> {
+        writeln!(writer, "# Collapsed terrain")?;
+        let mut written = 0;
+        let mut skipped = 0;
+        let mut vertex_offset = 0;
+        for entry in entries {
+            let handle = match entry.current_mesh {
+                Some(handle) => handle,
+                None => continue,
+            };
+            let mesh = match geom_data.mesh_handles[handle.index]
+                .as_ref()
+                .and_then(|mesh_handle| meshes.get(mesh_handle))
+            {
+                Some(mesh) => mesh,
+                None => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+
+            let sides = self
+                .dual_tiling
+                .get_tile_at_index(entry.index_in_tiling)
+                .shape
+                .get_side_count() as usize;
+            let mut transform = handle.orientation.get_transform(sides);
+            transform.translation = self
+                .get_entry_translation(CollapseEntryIndex::new(entry.index_in_tiling, entry.height));
+            match write_mesh(mesh, &transform, vertex_offset, writer)? {
+                Some(vertex_count) => {
+                    vertex_offset += vertex_count;
+                    written += 1;
+                }
+                None => skipped += 1,
+            }
+        }
+        if skipped > 0 {
+            warn!("Skipped {} terrain meshes that are still loading or not triangles", skipped);
+        }
+        Ok(written)
+    }
+
+    pub fn export_obj<'a>(
+        &self,
+        entries: impl IntoIterator<Item = &'a CollapseEntry>,
+        geom_data: &GeometryStorage,
+        meshes: &Assets<Mesh>,
+        path: impl AsRef<std::path::Path>,
+    ) -> std::io::Result<usize> {
+        let file = std::fs::File::create(path)?;
+        let mut writer = std::io::BufWriter::new(file);
+        let written = self.write_obj(entries, geom_data, meshes, &mut writer)?;
+        writer.flush()?;
+        Ok(written)
+    }
+}
+
+// Write the vertices of the mesh moved by the transform followed by its triangles. OBJ indices
+// are global to the file, so the triangles are offset by the vertices written before this mesh.
+// Returns the number of vertices written or None if the mesh is not a list of triangles.
+fn write_mesh<W: Write>(
+    mesh: &Mesh,
+    transform: &Transform,
+    vertex_offset: usize,
+    writer: &mut W,
+) -> std::io::Result<Option<usize>> {
+    if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+        return Ok(None);
+    }
+    let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(VertexAttributeValues::Float32x3(positions)) => positions,
+        _ => return Ok(None),
+    };
+    for position in positions {
+        let position = transform.mul_vec3(Vec3::from(*position));
+        writeln!(writer, "v {} {} {}", position.x, position.y, position.z)?;
+    }
+
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(Indices::U16(indices)) => indices.iter().map(|index| *index as usize).collect(),
+        Some(Indices::U32(indices)) => indices.iter().map(|index| *index as usize).collect(),
+        None => (0..positions.len()).collect(),
+    };
+    // Mirroring the mesh turns its triangles inside out, so wind them the other way.
+    let mirrored = transform.scale.x * transform.scale.y * transform.scale.z < 0.0;
+    for triangle in indices.chunks_exact(3) {
+        let (a, b, c) = if mirrored {
+            (triangle[0], triangle[2], triangle[1])
+        } else {
+            (triangle[0], triangle[1], triangle[2])
+        };
+        let base = vertex_offset + 1;
+        writeln!(writer, "f {} {} {}", base + a, base + b, base + c)?;
+    }
+    Ok(Some(positions.len()))
+}
+
+pub fn export_terrain_obj(
+    mut events: EventReader<ExportTerrainObj>,
+    collapse_state: Res<CollapseState>,
+    entry_query: Query<&CollapseEntry>,
+    geom_data: Res<GeometryStorage>,
+    meshes: Res<Assets<Mesh>>,
+) {
+    for event in events.iter() {
+        match collapse_state.export_obj(entry_query.iter(), &geom_data, &meshes, &event.path) {
+            Ok(written) => info!("Exported {} terrain meshes to {}", written, event.path),
+            Err(err) => warn!("Failed to export terrain to {}: {}", event.path, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        asset::{AddAsset, AssetPlugin, Assets, Handle, HandleId},
+        core::CorePlugin,
+        math::{IVec2, Vec2, Vec3},
+        prelude::{App, Mesh},
+        render::{mesh::Indices, render_resource::PrimitiveTopology},
+    };
+
+    use crate::{
+        simulation::SimulationState,
+        tiling::{Tiling, TilingKind},
+        visuals::{
+            collapse::{CollapseEntry, CollapseState},
+            geom::{
+                build_profiles::MeshProfile, GeomOrientation, GeometryHandle, GeometryStorage,
+                LayerProfileIndex, VerticalProfile, WallProfileIndex,
+            },
+        },
+    };
+
+    // A unit square in the xz plane made of two triangles.
+    fn quad() -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            vec![
+                [-0.5, 0.0, -0.5],
+                [0.5, 0.0, -0.5],
+                [0.5, 0.0, 0.5],
+                [-0.5, 0.0, 0.5],
+            ],
+        );
+        mesh.set_indices(Some(Indices::U16(vec![0, 1, 2, 0, 2, 3])));
+        mesh
+    }
+
+    fn store_flat(geom_data: &mut GeometryStorage, mesh: Handle<Mesh>) {
+        geom_data.store(
+            MeshProfile {
+                sides: 4,
+                walls: vec![WallProfileIndex::new(0); 4],
+                top: LayerProfileIndex::new(0),
+                bottom: LayerProfileIndex::new(0),
+                orientations: vec![GeomOrientation::Standard { rotations: 0 }],
+                weight: 1.0,
+            },
+            &VerticalProfile::parse_from("eeee".to_string()).unwrap(),
+            &VerticalProfile::parse_from("ffff".to_string()).unwrap(),
+            Some(mesh),
+        );
+    }
+
+    #[test]
+    fn obj_holds_every_loaded_mesh() {
+        let mut app = App::new();
+        app.add_plugin(CorePlugin)
+            .add_plugin(AssetPlugin)
+            .add_asset::<Mesh>();
+        let mut meshes = app.world.get_resource_mut::<Assets<Mesh>>().unwrap();
+        let loaded = meshes.add(quad());
+
+        // The second profile's mesh has not finished loading.
+        let mut geom_data = GeometryStorage::new();
+        store_flat(&mut geom_data, loaded);
+        store_flat(&mut geom_data, Handle::weak(HandleId::random::<Mesh>()));
+
+        let sim_state = SimulationState::new(Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(2, 1),
+            offset: Vec2::ZERO,
+        });
+        let mut collapse_state = CollapseState::default();
+        collapse_state.dual_tiling = sim_state.tiling.get_dual();
+        let mut entries = Vec::new();
+        for (x, mesh_index) in [(0, Some(0)), (1, Some(0)), (2, Some(1)), (0, None)] {
+            let mut entry = CollapseEntry::new(
+                &collapse_state.dual_tiling,
+                &sim_state,
+                &geom_data,
+                IVec2::new(x, if mesh_index.is_some() { 0 } else { 1 }),
+                0,
+                false,
+            );
+            entry.current_mesh = mesh_index.map(|index| GeometryHandle {
+                index,
+                orientation: GeomOrientation::Standard { rotations: 0 },
+            });
+            entries.push(entry);
+        }
+
+        let mut obj = Vec::new();
+        let meshes = app.world.get_resource::<Assets<Mesh>>().unwrap();
+        let written = collapse_state
+            .write_obj(&entries, &geom_data, meshes, &mut obj)
+            .unwrap();
+        assert_eq!(written, 2);
+
+        // Parse the positions and faces back out of the file.
+        let mut vertices = Vec::new();
+        let mut faces = Vec::new();
+        for line in String::from_utf8(obj).unwrap().lines() {
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let values = tokens.map(|value| value.parse::<f32>().unwrap()).collect::<Vec<_>>();
+                    vertices.push(Vec3::new(values[0], values[1], values[2]));
+                }
+                Some("f") => faces.push(
+                    tokens
+                        .map(|value| value.parse::<usize>().unwrap())
+                        .collect::<Vec<_>>(),
+                ),
+                _ => {}
+            }
+        }
+        assert_eq!(vertices.len(), 8);
+        assert_eq!(faces.len(), 4);
+        assert!(faces
+            .iter()
+            .all(|face| face.len() == 3 && face.iter().all(|index| (1..=8).contains(index))));
+        // The second quad's faces point at its own vertices, which sit one tile over.
+        assert!(faces[2..].iter().flatten().all(|index| *index > 4));
+        let offset = collapse_state.dual_tiling.get_tile_at_index(IVec2::new(1, 0)).position
+            - collapse_state.dual_tiling.get_tile_at_index(IVec2::new(0, 0)).position;
+        for (first, second) in vertices[..4].iter().zip(&vertices[4..]) {
+            assert!((*second - *first - Vec3::new(offset.x, 0.0, offset.y)).length() < 1e-5);
+        }
+    }
+}
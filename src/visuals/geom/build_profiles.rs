@@ -110,6 +110,8 @@ pub struct MeshProfile {
     pub top: LayerProfileIndex,
     pub bottom: LayerProfileIndex,
     pub orientations: Vec<GeomOrientation>,
+    // How likely this profile is to be picked relative to the others it is allowed next to.
+    pub weight: f32,
 }
 
 const TOLERANCE: f32 = 0.0001;
@@ -433,5 +435,5 @@ pub fn generate_profiles_for_mesh(
         layer_profiles
     );
 
-    MeshProfile { sides: num_sides, walls, bottom, top, orientations }
+    MeshProfile { sides: num_sides, walls, bottom, top, orientations, weight: 1.0 }
 }
\ No newline at end of file
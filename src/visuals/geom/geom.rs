@@ -1,10 +1,18 @@
+use std::{fmt::Display, path::Path};
+
 use bevy::{
-    prelude::{AssetServer, Assets, Handle, Local, Mesh, Res, ResMut, Color, Visibility, Component, Query, KeyCode, With, EventWriter, info},
+    prelude::{AssetServer, Assets, Handle, Image, Mesh, Res, ResMut, Color, Visibility, Component, Query, KeyCode, With, EventWriter, error, info, warn},
     render::mesh::Indices,
-    utils::HashMap, asset::LoadState, pbr::StandardMaterial, input::Input,
+    utils::HashMap, asset::{FileAssetIo, LoadState}, input::Input,
 };
+use serde::Deserialize;
 
-use crate::{ui::InputState, menus::CommandEvent};
+use crate::{
+    menus::CommandEvent,
+    tiling::{EquilateralDirection, TileShape},
+    ui::InputState,
+    visuals::render::instanced_pbr::InstancedStandardMaterial,
+};
 
 use super::{
     build_profiles::{generate_profiles_for_mesh, WallProfileDefinition, MeshProfile, WallProfileIndex, LayerProfileDefinition},
@@ -54,8 +62,20 @@ pub struct GeometryStorage {
     pub wall_profiles: Vec<WallProfileDefinition>,
     pub layer_profiles: Vec<LayerProfileDefinition>,
 
-    pub base_material: Handle<StandardMaterial>,
-    pub side_materials: Vec<Handle<StandardMaterial>>,
+    pub base_material: Handle<InstancedStandardMaterial>,
+    // Shared by every wall preview, which are told apart by their instance color. It is white
+    // and unlit so the instanced PBR shader draws each preview in exactly its color.
+    pub side_material: Handle<InstancedStandardMaterial>,
+    pub side_colors: Vec<Color>,
+    // Materials for the profiles that have their own texture, by index into `profiles`. Every
+    // other profile is drawn with whatever material its user falls back to.
+    profile_materials: HashMap<usize, Handle<InstancedStandardMaterial>>,
+
+    // The profiles whose meshes are loading. Taken once they have all loaded.
+    object_profiles: Vec<ObjectProfile>,
+    // Set once every mesh has loaded and had its profiles extracted. Nothing should be
+    // collapsed before then since the meshes it would select may not exist yet.
+    pub ready: bool,
 }
 
 impl GeometryStorage {
@@ -69,10 +89,42 @@ impl GeometryStorage {
             wall_profiles: Vec::new(),
             layer_profiles: Vec::new(),
             base_material: Handle::default(),
-            side_materials: Vec::new(),
+            side_material: Handle::default(),
+            side_colors: Vec::new(),
+            profile_materials: HashMap::new(),
+            object_profiles: Vec::new(),
+            ready: false,
         }
     }
 
+    /// How likely the profile of the given handle is to be selected while collapsing.
+    pub fn weight(&self, handle: GeometryHandle) -> f32 {
+        self.profiles
+            .get(handle.index)
+            .map(|profile| profile.weight)
+            .unwrap_or(1.0)
+    }
+
+    /// A mesh is flat when it has the same wall on every side.
+    pub fn is_flat(&self, handle: GeometryHandle) -> bool {
+        let profile = match self.profiles.get(handle.index) {
+            Some(profile) => profile,
+            None => return false,
+        };
+        let first = self.get_wall(profile, 0, &handle.orientation);
+        (1..profile.sides).all(|side| self.get_wall(profile, side, &handle.orientation) == first)
+    }
+
+    /// The material the mesh of the given handle should be drawn with, if its profile has one.
+    pub fn material(&self, handle: GeometryHandle) -> Option<&Handle<InstancedStandardMaterial>> {
+        self.profile_materials.get(&handle.index)
+    }
+
+    /// Draw every mesh of the profile stored at `index` with `material`.
+    pub fn set_profile_material(&mut self, index: usize, material: Handle<InstancedStandardMaterial>) {
+        self.profile_materials.insert(index, material);
+    }
+
     pub fn get_wall(&self, profile: &MeshProfile, side: usize, orientation: &GeomOrientation) -> WallProfileIndex {
         let wall =  profile.walls[orientation.get_index_in_sequence(side, profile.sides, false)];
         if orientation.is_reversed() {
@@ -82,7 +134,8 @@ impl GeometryStorage {
         }
     }
 
-    pub fn store(&mut self, profile: MeshProfile, top_descriptor: &Vec<VerticalProfile>, bottom_descriptor: &Vec<VerticalProfile>, mesh: Option<Handle<Mesh>>) {
+    /// Add a profile and the mesh drawn for it, returning the index its handles refer to.
+    pub fn store(&mut self, profile: MeshProfile, top_descriptor: &Vec<VerticalProfile>, bottom_descriptor: &Vec<VerticalProfile>, mesh: Option<Handle<Mesh>>) -> usize {
         let index = self.mesh_handles.len();
         self.mesh_handles.push(mesh);
         let profile_side_count = profile.sides;
@@ -119,6 +172,7 @@ impl GeometryStorage {
         }
 
         self.profiles.push(profile);
+        index
     }
 
     pub fn get_vertical_matching(
@@ -171,12 +225,189 @@ impl GeometryStorage {
     }
 }
 
-pub fn load_geometry(mut geom_data: ResMut<GeometryStorage>, asset_server: Res<AssetServer>) {
-    // Load the mesh for every profile we have
-    let profiles = get_rect_profiles();
-    for profile in profiles {
+pub fn load_geometry(
+    mut geom_data: ResMut<GeometryStorage>,
+    asset_server: Res<AssetServer>,
+    mut events: EventWriter<CommandEvent>,
+) {
+    // Load the mesh for every profile we have a mesh for. Not every tile shape has meshes yet.
+    let assets = FileAssetIo::get_root_path().join("assets");
+    let mut profiles = load_profiles();
+    for resource_location in remove_missing_profiles(&mut profiles, &assets) {
+        report_unusable_mesh(&resource_location, &mut events);
+    }
+    for profile in &mut profiles {
         let resource_location = profile.get_resource_location();
         geom_data.mesh_handles.push(Some(asset_server.load::<Mesh, _>(&resource_location)));
+        // Textures are not waited on. The mesh is drawn with its texture once the image loads.
+        if let Some(texture) = profile.texture.take() {
+            if assets.join(&texture).exists() {
+                let _: Handle<Image> = asset_server.load(texture.as_str());
+                profile.texture = Some(texture);
+            } else {
+                warn!("Texture {} for {} does not exist", texture, resource_location);
+            }
+        }
+    }
+    geom_data.object_profiles = profiles;
+}
+
+// The file in the assets folder that profiles are read from instead of the built-in list.
+const PROFILES_FILE: &str = "profiles.ron";
+
+// Read our profiles from the profiles file, falling back to the built-in profiles if it is
+// missing or has any invalid entries.
+fn load_profiles() -> Vec<ObjectProfile> {
+    let path = FileAssetIo::get_root_path().join("assets").join(PROFILES_FILE);
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(err) => {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to read {}: {}", path.display(), err);
+            }
+            return get_builtin_profiles();
+        }
+    };
+    match parse_profiles(&source) {
+        Ok(profiles) => {
+            info!("Loaded {} profiles from {}", profiles.len(), path.display());
+            profiles
+        }
+        Err(ProfilesLoadError::InvalidEntries(errors)) => {
+            for (index, err) in errors {
+                warn!("Profile {} in {} is invalid: {}", index, path.display(), err);
+            }
+            warn!("Using the built-in profiles instead");
+            get_builtin_profiles()
+        }
+        Err(err) => {
+            warn!("Failed to parse {}: {}. Using the built-in profiles instead", path.display(), err);
+            get_builtin_profiles()
+        }
+    }
+}
+
+// Parse a list of profile definitions, reporting every entry that is invalid.
+fn parse_profiles(source: &str) -> Result<Vec<ObjectProfile>, ProfilesLoadError> {
+    let definitions: Vec<ObjectProfileDefinition> =
+        ron::from_str(source).map_err(ProfilesLoadError::Parse)?;
+    let mut profiles = Vec::with_capacity(definitions.len());
+    let mut errors = Vec::new();
+    for (index, definition) in definitions.into_iter().enumerate() {
+        match ObjectProfile::try_from(definition) {
+            Ok(profile) => profiles.push(profile),
+            Err(err) => errors.push((index, err)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(profiles)
+    } else {
+        Err(ProfilesLoadError::InvalidEntries(errors))
+    }
+}
+
+#[derive(Debug)]
+enum ProfilesLoadError {
+    Parse(ron::Error),
+    InvalidEntries(Vec<(usize, ProfileDefinitionError)>),
+}
+
+impl Display for ProfilesLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfilesLoadError::Parse(err) => write!(f, "{}", err),
+            ProfilesLoadError::InvalidEntries(errors) => {
+                for (index, err) in errors {
+                    write!(f, "Profile {}: {}. ", index, err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ProfileDefinitionError {
+    InvalidBottom(VerticalProfileParseError),
+    InvalidTop(VerticalProfileParseError),
+    MismatchedTop { bottom: usize, top: usize },
+    MismatchedWalls { sides: usize, walls: usize },
+}
+
+impl Display for ProfileDefinitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileDefinitionError::InvalidBottom(err) => write!(f, "Invalid bottom pattern {:?}", err),
+            ProfileDefinitionError::InvalidTop(err) => write!(f, "Invalid top pattern {:?}", err),
+            ProfileDefinitionError::MismatchedTop { bottom, top } => {
+                write!(f, "Bottom has {} corners but top has {}", bottom, top)
+            }
+            ProfileDefinitionError::MismatchedWalls { sides, walls } => {
+                write!(f, "Profile has {} sides but {} wall labels", sides, walls)
+            }
+        }
+    }
+}
+
+/// A profile as written in the profiles file. The patterns use the same letters as
+/// `VerticalProfile::parse_from` and there must be one wall label for every corner.
+#[derive(Deserialize)]
+struct ObjectProfileDefinition {
+    bottom: String,
+    walls: Vec<String>,
+    top: String,
+    // Appended to the mesh name to tell apart meshes that share patterns and walls.
+    #[serde(default)]
+    variant: Option<String>,
+    #[serde(default)]
+    transforms: Vec<GeomOrientation>,
+    #[serde(default = "default_profile_weight")]
+    weight: f32,
+    // An image in the assets folder to draw the mesh with instead of the default material.
+    #[serde(default)]
+    texture: Option<String>,
+}
+
+fn default_profile_weight() -> f32 {
+    1.0
+}
+
+impl TryFrom<ObjectProfileDefinition> for ObjectProfile {
+    type Error = ProfileDefinitionError;
+
+    fn try_from(definition: ObjectProfileDefinition) -> Result<Self, Self::Error> {
+        let bottom = VerticalProfile::parse_from(definition.bottom)
+            .map_err(ProfileDefinitionError::InvalidBottom)?;
+        let top = VerticalProfile::parse_from(definition.top)
+            .map_err(ProfileDefinitionError::InvalidTop)?;
+        if bottom.len() != top.len() {
+            return Err(ProfileDefinitionError::MismatchedTop {
+                bottom: bottom.len(),
+                top: top.len(),
+            });
+        }
+        if definition.walls.len() != bottom.len() {
+            return Err(ProfileDefinitionError::MismatchedWalls {
+                sides: bottom.len(),
+                walls: definition.walls.len(),
+            });
+        }
+
+        let mut edge_labels = definition.walls;
+        edge_labels.extend(definition.variant);
+        let transforms = if definition.transforms.is_empty() {
+            vec![GeomOrientation::Standard { rotations: 0 }]
+        } else {
+            definition.transforms
+        };
+        Ok(ObjectProfile {
+            top,
+            bottom,
+            edge_labels,
+            transforms,
+            weight: definition.weight,
+            texture: definition.texture,
+        })
     }
 }
 
@@ -185,6 +416,8 @@ struct ObjectProfile {
     bottom: Vec<VerticalProfile>,
     edge_labels: Vec<String>,
     transforms: Vec<GeomOrientation>,
+    weight: f32,
+    texture: Option<String>,
 }
 
 impl ObjectProfile {
@@ -194,9 +427,18 @@ impl ObjectProfile {
             bottom: VerticalProfile::parse_from(bottom)?,
             edge_labels: labels.into_iter().map(|x| x.to_string()).collect(),
             transforms: vec!(GeomOrientation::Standard { rotations: 0 }),
+            weight: 1.0,
+            texture: None,
         })
     }
 
+    fn with_weight(self, weight: f32) -> Self {
+        Self {
+            weight,
+            ..self
+        }
+    }
+
     fn with_transforms(self, transforms: Vec<GeomOrientation>) -> Self {
         Self {
             transforms,
@@ -204,8 +446,17 @@ impl ObjectProfile {
         }
     }
 
+    fn sides(&self) -> usize {
+        self.bottom.len()
+    }
+
     fn get_resource_location(&self) -> String {
-        let mut data = String::from("rect/");
+        let mut data = match self.sides() {
+            3 => String::from("tri/"),
+            4 => String::from("rect/"),
+            6 => String::from("hex/"),
+            sides => format!("{}_sided/", sides),
+        };
         for p in &self.bottom {
             data.push_str(p.label());
         }
@@ -231,11 +482,10 @@ pub fn log_geometry(
     mut geom_storage: ResMut<GeometryStorage>,
     asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut has_extracted: Local<bool>,
-    mut colors: ResMut<Assets<StandardMaterial>>,
+    mut colors: ResMut<Assets<InstancedStandardMaterial>>,
     mut events: EventWriter<CommandEvent>,
 ) {
-    if !*has_extracted {
+    if !geom_storage.ready {
         events.send(CommandEvent("n w 00 empty".to_string()));
         events.send(CommandEvent("n w 01 floor".to_string()));
         events.send(CommandEvent("n w 02 ciel".to_string()));
@@ -244,56 +494,74 @@ pub fn log_geometry(
         events.send(CommandEvent("n w 05 wall".to_string()));
         events.send(CommandEvent("n w 06 llaw".to_string()));
 
-        for handle in &geom_storage.mesh_handles {
-            if let Some(handle) = handle {
-                if asset_server.get_load_state(handle) == LoadState::Loading {
-                    return;
-                }
-            }
+        let load_states = geom_storage
+            .mesh_handles
+            .iter()
+            .map(|handle| match handle {
+                Some(handle) => asset_server.get_load_state(handle),
+                None => LoadState::Loaded,
+            })
+            .collect::<Vec<_>>();
+        if load_states.contains(&LoadState::Loading) {
+            return;
         }
         let mut tmp_handles = Vec::new();
         std::mem::swap(&mut tmp_handles, &mut geom_storage.mesh_handles);
 
+        let mut profiles = std::mem::take(&mut geom_storage.object_profiles);
+        for resource_location in remove_failed_profiles(&mut profiles, &load_states) {
+            report_unusable_mesh(&resource_location, &mut events);
+        }
+        let mut side_counts = profiles.iter().map(|profile| profile.sides()).collect::<Vec<_>>();
+        side_counts.sort_unstable();
+        side_counts.dedup();
+
         let mut empty_mesh = Mesh::new(bevy::render::mesh::PrimitiveTopology::TriangleList);
         empty_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, Vec::<[f32; 3]>::new());
         empty_mesh.set_indices(Some(Indices::U16(Vec::new())));
-        let empty_mesh_profile = {
-            let &mut GeometryStorage {
-                ref mut wall_profiles,
-                ref mut layer_profiles,
-                ..
-            } = geom_storage.as_mut();
+        // Every tile shape needs entries for the space above and below the terrain.
+        for sides in side_counts {
+            let empty_mesh_profile = {
+                let &mut GeometryStorage {
+                    ref mut wall_profiles,
+                    ref mut layer_profiles,
+                    ..
+                } = geom_storage.as_mut();
 
-            generate_profiles_for_mesh(
-                &empty_mesh,
-                vec![GeomOrientation::Standard { rotations: 0 }],
-                0.0,
-                4,
-                wall_profiles,
-                layer_profiles
-            )
-        };
-        let all_stackable = VerticalProfile::parse_from("ssss".to_string()).unwrap();
-        let all_empty = VerticalProfile::parse_from("eeee".to_string()).unwrap();
-        geom_storage.store(
-            empty_mesh_profile.clone(),
-            &all_stackable,
-            &all_stackable,
-            None
-        );
-        geom_storage.store(
-            empty_mesh_profile,
-            &all_empty,
-            &all_empty,
-            None
-        );
+                generate_profiles_for_mesh(
+                    &empty_mesh,
+                    vec![GeomOrientation::Standard { rotations: 0 }],
+                    0.0,
+                    sides,
+                    wall_profiles,
+                    layer_profiles
+                )
+            };
+            let all_stackable = VerticalProfile::parse_from("s".repeat(sides)).unwrap();
+            let all_empty = VerticalProfile::parse_from("e".repeat(sides)).unwrap();
+            geom_storage.store(
+                empty_mesh_profile.clone(),
+                &all_stackable,
+                &all_stackable,
+                None
+            );
+            geom_storage.store(
+                empty_mesh_profile,
+                &all_empty,
+                &all_empty,
+                None
+            );
+        }
 
-        for profile in get_rect_profiles() {
+        // Profiles that share a texture share its material so they are still drawn together.
+        let mut texture_materials: HashMap<String, Handle<InstancedStandardMaterial>> = HashMap::new();
+        for profile in profiles {
             let resource_location = profile.get_resource_location();
             let mesh_handle: Handle<Mesh> = asset_server.get_handle(&resource_location);
 
 
             if let Some(mesh) = meshes.get(&mesh_handle) {
+                let weight = profile.weight;
                 let mesh_profile = {
                     let &mut GeometryStorage {
                         ref mut wall_profiles,
@@ -301,21 +569,34 @@ pub fn log_geometry(
                         ..
                     } = geom_storage.as_mut();
 
-                    generate_profiles_for_mesh(
-                        mesh,
-                        profile.transforms,
-                        0.5,
-                        4,
-                        wall_profiles,
-                        layer_profiles,
-                    )
+                    let sides = profile.sides();
+                    MeshProfile {
+                        weight,
+                        ..generate_profiles_for_mesh(
+                            mesh,
+                            profile.transforms,
+                            side_distance(sides),
+                            sides,
+                            wall_profiles,
+                            layer_profiles,
+                        )
+                    }
                 };
-                geom_storage.store(
+                let index = geom_storage.store(
                     mesh_profile,
                     &profile.top,
                     &profile.bottom,
                     Some(mesh_handle.clone())
                 );
+                if let Some(texture) = profile.texture {
+                    let material = texture_materials.entry(texture).or_insert_with_key(|texture| {
+                        colors.add(InstancedStandardMaterial {
+                            cull_mode: None,
+                            ..asset_server.load::<Image, _>(texture.as_str()).into()
+                        })
+                    });
+                    geom_storage.set_profile_material(index, material.clone());
+                }
             }
         }
 
@@ -355,22 +636,26 @@ pub fn log_geometry(
             }
         }
 
-        let base_color = colors.add(StandardMaterial {
+        let base_color = colors.add(InstancedStandardMaterial {
             cull_mode: None,
             ..Color::WHITE.into()
         });
-        let num_walls = geom_storage.wall_profiles.len();
-        let side_colors = (0..num_walls).into_iter().map(|index| colors.add(StandardMaterial {
+        let side_material = colors.add(InstancedStandardMaterial {
             cull_mode: None,
             unlit: true,
             double_sided: true,
-            ..Color::hsl(360.0 * index as f32 / num_walls as f32, 1.0, 0.5).into()
-        })).collect::<Vec<_>>();
+            ..Color::WHITE.into()
+        });
+        let num_walls = geom_storage.wall_profiles.len();
+        let side_colors = (0..num_walls).into_iter().map(|index| {
+            Color::hsl(360.0 * index as f32 / num_walls as f32, 1.0, 0.5)
+        }).collect::<Vec<_>>();
 
         geom_storage.base_material = base_color;
-        geom_storage.side_materials = side_colors;
+        geom_storage.side_material = side_material;
+        geom_storage.side_colors = side_colors;
 
-        *has_extracted = true;
+        geom_storage.ready = true;
     }
 }
 
@@ -386,22 +671,81 @@ pub fn geometry_input(
     }
 }
 
+// Drop the profiles whose mesh failed to load so nothing collapses into a tile we can't draw.
+// `load_states` has the load state of each profile's mesh, in the same order as `profiles`.
+// Returns where each dropped profile's mesh was meant to be loaded from.
+fn remove_failed_profiles(profiles: &mut Vec<ObjectProfile>, load_states: &[LoadState]) -> Vec<String> {
+    let mut failed = Vec::new();
+    let mut load_states = load_states.iter();
+    profiles.retain(|profile| {
+        if load_states.next() == Some(&LoadState::Failed) {
+            failed.push(profile.get_resource_location());
+            false
+        } else {
+            true
+        }
+    });
+    failed
+}
+
+// Drop the profiles whose mesh file is not in `assets`, returning where each one was expected.
+fn remove_missing_profiles(profiles: &mut Vec<ObjectProfile>, assets: &Path) -> Vec<String> {
+    let mut missing = Vec::new();
+    profiles.retain(|profile| {
+        let resource_location = profile.get_resource_location();
+        let exists = assets.join(&resource_location).exists();
+        if !exists {
+            missing.push(resource_location);
+        }
+        exists
+    });
+    missing
+}
+
+// Log a mesh that could not be loaded, and show it in the console too.
+fn report_unusable_mesh(resource_location: &str, events: &mut EventWriter<CommandEvent>) {
+    error!("Failed to load mesh {}. Its profile will not be used", resource_location);
+    events.send(CommandEvent(format!(
+        "echo Error: failed to load mesh {}. Its profile will not be used",
+        resource_location
+    )));
+}
+
+// How far the sides of a tile with the given number of sides are from its center.
+fn side_distance(sides: usize) -> f32 {
+    let shape = match sides {
+        3 => TileShape::EquilateralTriangle(EquilateralDirection::Up),
+        6 => TileShape::Hexagon,
+        8 => TileShape::Octagon,
+        _ => TileShape::Square,
+    };
+    shape.get_radius() * (std::f32::consts::PI / sides as f32).cos()
+}
+
+fn get_builtin_profiles() -> Vec<ObjectProfile> {
+    let mut profiles = get_rect_profiles();
+    profiles.extend(get_tri_profiles());
+    profiles
+}
+
 fn get_rect_profiles() -> Vec<ObjectProfile> {
     use super::GeomOrientation::*;
     vec![
-        // Flats
+        // Flats. These are favored so open ground is not broken up by ramps everywhere.
         ObjectProfile::new(
             "ffff".to_string(),
             vec!["bottom", "bottom", "bottom", "bottom"],
             "eeee".to_string(),
         )
-        .unwrap(),
+        .unwrap()
+        .with_weight(4.0),
         ObjectProfile::new(
             "ssss".to_string(),
             vec!["top", "top", "top", "top"],
             "ffff".to_string(),
         )
-        .unwrap(),
+        .unwrap()
+        .with_weight(4.0),
         // Ramps
         ObjectProfile::new(
             "ffss".to_string(),
@@ -681,3 +1025,295 @@ fn get_rect_profiles() -> Vec<ObjectProfile> {
         ]),
     ]
 }
+
+// Profiles for the triangles in the dual of a hexagonal tiling.
+fn get_tri_profiles() -> Vec<ObjectProfile> {
+    use super::GeomOrientation::*;
+    let rotations = || vec![
+        Standard { rotations: 0 },
+        Standard { rotations: 1 },
+        Standard { rotations: 2 },
+    ];
+    vec![
+        // Flats
+        ObjectProfile::new(
+            "fff".to_string(),
+            vec!["bottom", "bottom", "bottom"],
+            "eee".to_string(),
+        )
+        .unwrap()
+        .with_weight(4.0),
+        ObjectProfile::new(
+            "sss".to_string(),
+            vec!["top", "top", "top"],
+            "fff".to_string(),
+        )
+        .unwrap()
+        .with_weight(4.0),
+        // Ramps
+        ObjectProfile::new(
+            "ffs".to_string(),
+            vec!["bottom", "ramp", "pmar"],
+            "eef".to_string(),
+        )
+        .unwrap()
+        .with_transforms(rotations()),
+        ObjectProfile::new(
+            "fss".to_string(),
+            vec!["ramp", "top", "pmar"],
+            "eff".to_string(),
+        )
+        .unwrap()
+        .with_transforms(rotations()),
+        // Cliffs
+        ObjectProfile::new(
+            "ffs".to_string(),
+            vec!["bottom", "wall", "llaw"],
+            "eef".to_string(),
+        )
+        .unwrap()
+        .with_transforms(rotations()),
+        ObjectProfile::new(
+            "fss".to_string(),
+            vec!["wall", "top", "llaw"],
+            "eff".to_string(),
+        )
+        .unwrap()
+        .with_transforms(rotations()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        asset::{HandleId, LoadState},
+        prelude::Handle,
+    };
+
+    use crate::visuals::{
+        geom::{
+            build_profiles::{MeshProfile, WallProfileDefinition, ProfileDefinition},
+            GeomOrientation, GeometryHandle, GeometryStorage, LayerProfileIndex, VerticalProfile,
+            WallProfileIndex,
+        },
+        render::instanced_pbr::InstancedStandardMaterial,
+    };
+
+    use super::{
+        get_rect_profiles, get_tri_profiles, parse_profiles, remove_failed_profiles,
+        remove_missing_profiles, side_distance, ProfileDefinitionError, ProfilesLoadError,
+    };
+
+    #[test]
+    fn profiles_parse_from_ron() {
+        let profiles = parse_profiles(
+            r#"[
+                (bottom: "ffff", walls: ["bottom", "bottom", "bottom", "bottom"], top: "eeee", weight: 4.0),
+                (
+                    bottom: "fffs",
+                    walls: ["bottom", "bottom", "wall", "pmar"],
+                    top: "eeef",
+                    variant: Some("2"),
+                    transforms: [Standard(rotations: 1), Flipped(rotations: 3)],
+                    texture: Some("textures/cliff.png"),
+                ),
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[0].weight, 4.0);
+        assert_eq!(profiles[0].texture, None);
+        assert_eq!(profiles[1].texture.as_deref(), Some("textures/cliff.png"));
+        assert_eq!(profiles[0].transforms, vec![GeomOrientation::Standard { rotations: 0 }]);
+        assert_eq!(profiles[0].get_resource_location(), "rect/ffff_bottom_bottom_bottom_bottom_eeee.obj");
+        assert_eq!(profiles[1].weight, 1.0);
+        assert_eq!(profiles[1].top, VerticalProfile::parse_from("eeef".to_string()).unwrap());
+        assert_eq!(
+            profiles[1].transforms,
+            vec![
+                GeomOrientation::Standard { rotations: 1 },
+                GeomOrientation::Flipped { rotations: 3 },
+            ]
+        );
+        assert_eq!(profiles[1].get_resource_location(), "rect/fffs_bottom_bottom_wall_pmar_2_eeef.obj");
+    }
+
+    #[test]
+    fn invalid_profiles_are_reported_per_entry() {
+        let result = parse_profiles(
+            r#"[
+                (bottom: "ffff", walls: ["bottom", "bottom", "bottom"], top: "eeee"),
+                (bottom: "ffff", walls: ["bottom", "bottom", "bottom", "bottom"], top: "eeee"),
+                (bottom: "ffff", walls: ["bottom", "bottom", "bottom", "bottom"], top: "eee"),
+            ]"#,
+        );
+        match result {
+            Err(ProfilesLoadError::InvalidEntries(errors)) => {
+                assert_eq!(errors.len(), 2);
+                assert!(matches!(
+                    errors[0],
+                    (0, ProfileDefinitionError::MismatchedWalls { sides: 4, walls: 3 })
+                ));
+                assert!(matches!(
+                    errors[1],
+                    (2, ProfileDefinitionError::MismatchedTop { bottom: 4, top: 3 })
+                ));
+            }
+            _ => panic!("Expected invalid entries"),
+        }
+    }
+
+    #[test]
+    fn builtin_profiles_match_their_side_counts() {
+        for (profiles, sides, folder) in [(get_rect_profiles(), 4, "rect/"), (get_tri_profiles(), 3, "tri/")] {
+            for profile in profiles {
+                assert_eq!(profile.sides(), sides);
+                assert_eq!(profile.top.len(), sides);
+                assert!(profile.edge_labels.len() >= sides);
+                assert!(profile.get_resource_location().starts_with(folder));
+            }
+        }
+        assert!((side_distance(4) - 0.5).abs() < 1e-6);
+        assert!((side_distance(3) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn triangle_profile_is_stored_by_side_count() {
+        let mut geom_data = GeometryStorage::new();
+        geom_data.wall_profiles.push(WallProfileDefinition {
+            definition: ProfileDefinition {
+                verticies: Vec::new(),
+                edges: Vec::new(),
+            },
+            reverse_profile: WallProfileIndex::new(0),
+        });
+        let orientations = (0..3)
+            .map(|rotations| GeomOrientation::Standard { rotations })
+            .collect::<Vec<_>>();
+        let bottom = VerticalProfile::parse_from("ffs".to_string()).unwrap();
+        let top = VerticalProfile::parse_from("eef".to_string()).unwrap();
+        geom_data.store(
+            MeshProfile {
+                sides: 3,
+                walls: vec![WallProfileIndex::new(0); 3],
+                top: LayerProfileIndex::new(0),
+                bottom: LayerProfileIndex::new(0),
+                orientations: orientations.clone(),
+                weight: 1.0,
+            },
+            &top,
+            &bottom,
+            None,
+        );
+
+        for orientation in orientations {
+            let bottom_indicator = VerticalProfile::compute_indicator(&bottom, orientation);
+            let top_indicator = VerticalProfile::compute_indicator(&top, orientation);
+            let matching = geom_data.get_vertical_matching(3, bottom_indicator, top_indicator);
+            assert_eq!(matching.get_max_rotations(), 3);
+            assert!(matching.contains(GeometryHandle { index: 0, orientation }));
+            assert_eq!(matching.length(), 1);
+            // A square with the same corner pattern would be a different key.
+            assert_eq!(geom_data.get_vertical_matching(4, bottom_indicator, top_indicator).length(), 0);
+        }
+    }
+
+    #[test]
+    fn profiles_map_to_their_materials() {
+        let mut geom_data = GeometryStorage::new();
+        geom_data.wall_profiles.push(WallProfileDefinition {
+            definition: ProfileDefinition {
+                verticies: Vec::new(),
+                edges: Vec::new(),
+            },
+            reverse_profile: WallProfileIndex::new(0),
+        });
+        let orientations = (0..4)
+            .map(|rotations| GeomOrientation::Standard { rotations })
+            .collect::<Vec<_>>();
+        let mut store = |bottom: &str, top: &str| {
+            geom_data.store(
+                MeshProfile {
+                    sides: 4,
+                    walls: vec![WallProfileIndex::new(0); 4],
+                    top: LayerProfileIndex::new(0),
+                    bottom: LayerProfileIndex::new(0),
+                    orientations: orientations.clone(),
+                    weight: 1.0,
+                },
+                &VerticalProfile::parse_from(top.to_string()).unwrap(),
+                &VerticalProfile::parse_from(bottom.to_string()).unwrap(),
+                None,
+            )
+        };
+        let plain = store("ffff", "eeee");
+        let grass = store("ssss", "ffff");
+        let cliff = store("ffss", "eeff");
+        assert_eq!((plain, grass, cliff), (0, 1, 2));
+
+        let grass_material =
+            Handle::<InstancedStandardMaterial>::weak(HandleId::random::<InstancedStandardMaterial>());
+        let cliff_material =
+            Handle::<InstancedStandardMaterial>::weak(HandleId::random::<InstancedStandardMaterial>());
+        geom_data.set_profile_material(grass, grass_material.clone());
+        geom_data.set_profile_material(cliff, cliff_material.clone());
+
+        // Every orientation of a profile is drawn with the same material.
+        for orientation in orientations {
+            let handle = |index| GeometryHandle { index, orientation };
+            assert_eq!(geom_data.material(handle(plain)), None);
+            assert_eq!(geom_data.material(handle(grass)), Some(&grass_material));
+            assert_eq!(geom_data.material(handle(cliff)), Some(&cliff_material));
+        }
+    }
+
+    #[test]
+    fn profiles_with_failed_meshes_are_removed() {
+        let mut profiles = get_rect_profiles();
+        let locations = profiles
+            .iter()
+            .map(|profile| profile.get_resource_location())
+            .collect::<Vec<_>>();
+        let mut load_states = vec![LoadState::Loaded; profiles.len()];
+        load_states[0] = LoadState::Failed;
+        load_states[2] = LoadState::Failed;
+
+        let failed = remove_failed_profiles(&mut profiles, &load_states);
+        assert_eq!(failed, vec![locations[0].clone(), locations[2].clone()]);
+        assert_eq!(profiles.len(), locations.len() - 2);
+        let remaining = profiles
+            .iter()
+            .map(|profile| profile.get_resource_location())
+            .collect::<Vec<_>>();
+        assert!(!remaining.contains(&locations[0]));
+        assert!(!remaining.contains(&locations[2]));
+        assert!(remaining.contains(&locations[1]));
+
+        // Nothing is removed when every mesh loaded.
+        let mut profiles = get_rect_profiles();
+        let load_states = vec![LoadState::Loaded; profiles.len()];
+        assert!(remove_failed_profiles(&mut profiles, &load_states).is_empty());
+        assert_eq!(profiles.len(), locations.len());
+    }
+
+    #[test]
+    fn profiles_with_missing_meshes_are_removed() {
+        let assets = std::env::temp_dir().join(format!("missing_meshes_{}", std::process::id()));
+        let mut profiles = get_rect_profiles();
+        let locations = profiles
+            .iter()
+            .map(|profile| profile.get_resource_location())
+            .collect::<Vec<_>>();
+        let present = assets.join(&locations[1]);
+        std::fs::create_dir_all(present.parent().unwrap()).unwrap();
+        std::fs::write(&present, "").unwrap();
+
+        let missing = remove_missing_profiles(&mut profiles, &assets);
+        std::fs::remove_dir_all(&assets).unwrap();
+        assert_eq!(missing.len(), locations.len() - 1);
+        assert!(!missing.contains(&locations[1]));
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].get_resource_location(), locations[1]);
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
     fmt::Display,
-    ops::{BitAnd, BitOr},
+    ops::{BitAnd, BitOr, Sub},
 };
 
 use super::orientations::GeomOrientation;
@@ -16,6 +16,7 @@ impl Display for GeometryHandle {
         match self.orientation {
             GeomOrientation::Standard { rotations } => write!(f, "[{}@{}]", self.index, rotations),
             GeomOrientation::Flipped { rotations } => write!(f, "[{}@r{}]", self.index, rotations),
+            GeomOrientation::Mirrored { rotations } => write!(f, "[{}@m{}]", self.index, rotations),
         }
     }
 }
@@ -81,6 +82,30 @@ impl GeometryHandleSet {
         }
     }
 
+    /// Remove the handle from the set. Returns false if it was not in the set.
+    pub fn remove(&mut self, handle: GeometryHandle) -> bool {
+        match self
+            .entries
+            .binary_search_by(|entry| entry.index.cmp(&handle.index))
+        {
+            Ok(entry_index) => {
+                let orientation = handle.orientation.to_bits();
+                let entry = &mut self.entries[entry_index];
+                if entry.orientations & orientation == 0 {
+                    return false;
+                }
+                entry.orientations &= !orientation;
+                self.length -= 1;
+                // Removing the entry keeps the rest in order.
+                if entry.orientations == 0 {
+                    self.entries.remove(entry_index);
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
     pub fn contains(&self, handle: GeometryHandle) -> bool {
         match self
             .entries
@@ -145,11 +170,13 @@ impl GeometryHandleSet {
             }
         }
 
-        Self {
+        let set = Self {
             entries: new_entries,
             max_rotations,
             length,
-        }
+        };
+        set.debug_check_length();
+        set
     }
 
     /// Compute the intersection of a number of geometry handle sets.
@@ -178,6 +205,9 @@ impl GeometryHandleSet {
 
             let mut locations = vec![0; sets.len()];
 
+            // `at` cycles through the sets. `last_incremented_at` is the set that last moved
+            // `current_index` forward, so once we are back around to it every set holds
+            // `current_index` and the orientations left are the ones they all share.
             loop {
                 max_rotations = max_rotations.max(sets[at].max_rotations);
                 if last_incremented_at == at {
@@ -228,11 +258,54 @@ impl GeometryHandleSet {
             }
         }
 
-        Self {
+        let set = Self {
             entries: new_entries,
             max_rotations,
             length,
+        };
+        set.debug_check_length();
+        set
+    }
+
+    /// Compute the handles in this set that are not in `other`.
+    pub fn difference(&self, other: &GeometryHandleSet) -> GeometryHandleSet {
+        let mut new_entries = Vec::new();
+        let mut length = 0;
+        let mut other_at = 0;
+        for entry in &self.entries {
+            while other_at < other.entries.len() && other.entries[other_at].index < entry.index {
+                other_at += 1;
+            }
+            let mut orientations = entry.orientations;
+            if other_at < other.entries.len() && other.entries[other_at].index == entry.index {
+                orientations &= !other.entries[other_at].orientations;
+            }
+            if orientations != 0 {
+                new_entries.push(GeometryHandleSetEntry {
+                    index: entry.index,
+                    orientations,
+                });
+                length += orientations.count_ones() as usize;
+            }
         }
+
+        let set = Self {
+            entries: new_entries,
+            max_rotations: self.max_rotations,
+            length,
+        };
+        set.debug_check_length();
+        set
+    }
+
+    // We keep our length alongside our entries, so check it still counts what we iterate over.
+    fn debug_check_length(&self) {
+        debug_assert_eq!(
+            self.length,
+            self.into_iter().count(),
+            "Length does not match the handles in {}",
+            self.data_string()
+        );
     }
 
     pub fn data_string(&self) -> String {
@@ -257,6 +330,9 @@ impl GeometryHandleSet {
                     GeomOrientation::Flipped { rotations } => {
                         data.push_str(&format!("r{}", rotations))
                     }
+                    GeomOrientation::Mirrored { rotations } => {
+                        data.push_str(&format!("m{}", rotations))
+                    }
                 }
             }
             data.push(']');
@@ -289,6 +365,14 @@ impl BitAnd for &GeometryHandleSet {
     }
 }
 
+impl Sub for &GeometryHandleSet {
+    type Output = GeometryHandleSet;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.difference(rhs)
+    }
+}
+
 pub struct GeometryHandleSetIterator<'a> {
     set: &'a GeometryHandleSet,
     location: usize,
@@ -344,6 +428,21 @@ impl<'a> Iterator for GeometryHandleSetIterator<'a> {
                 }
             }
 
+            while self.orientation < 3 * self.set.max_rotations {
+                self.orientation += 1;
+                let expected_orientation = GeomOrientation::Mirrored {
+                    rotations: self.orientation - 1 - 2 * self.set.max_rotations,
+                };
+                if self.set.entries[self.location].orientations & expected_orientation.to_bits()
+                    != 0
+                {
+                    return Some(GeometryHandle {
+                        index: self.set.entries[self.location].index,
+                        orientation: expected_orientation,
+                    });
+                }
+            }
+
             self.orientation = 0;
             self.location += 1;
         }
@@ -353,7 +452,7 @@ impl<'a> Iterator for GeometryHandleSetIterator<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::visuals::geom::GeomOrientation;
+    use crate::{random::SeededRng, visuals::geom::GeomOrientation};
 
     use super::{GeometryHandle, GeometryHandleSet, GeometryHandleSetEntry};
 
@@ -494,6 +593,113 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn remove() {
+        let mut set = GeometryHandleSet::new(5);
+        for (index, rotations) in [(0, 1), (2, 0), (2, 3), (4, 2)] {
+            set.insert(GeometryHandle {
+                index,
+                orientation: GeomOrientation::Standard { rotations },
+            });
+        }
+
+        assert!(set.remove(GeometryHandle {
+            index: 2,
+            orientation: GeomOrientation::Standard { rotations: 3 },
+        }));
+        assert_eq!(
+            set.entries,
+            vec![
+                GeometryHandleSetEntry {
+                    index: 0,
+                    orientations: 0x2
+                },
+                GeometryHandleSetEntry {
+                    index: 2,
+                    orientations: 0x1
+                },
+                GeometryHandleSetEntry {
+                    index: 4,
+                    orientations: 0x4
+                },
+            ]
+        );
+        assert_eq!(set.length, 3);
+
+        // Removing the last orientation of an index removes its entry.
+        assert!(set.remove(GeometryHandle {
+            index: 2,
+            orientation: GeomOrientation::Standard { rotations: 0 },
+        }));
+        assert_eq!(
+            set.entries,
+            vec![
+                GeometryHandleSetEntry {
+                    index: 0,
+                    orientations: 0x2
+                },
+                GeometryHandleSetEntry {
+                    index: 4,
+                    orientations: 0x4
+                },
+            ]
+        );
+        assert_eq!(set.length, 2);
+        assert_eq!(set.into_iter().count(), 2);
+
+        // The remaining entries can still be found and added to.
+        assert!(set.contains(GeometryHandle {
+            index: 4,
+            orientation: GeomOrientation::Standard { rotations: 2 },
+        }));
+        set.insert(GeometryHandle {
+            index: 3,
+            orientation: GeomOrientation::Standard { rotations: 0 },
+        });
+        assert_eq!(
+            set.entries.iter().map(|entry| entry.index).collect::<Vec<_>>(),
+            vec![0, 3, 4]
+        );
+    }
+
+    #[test]
+    fn remove_missing() {
+        let mut set = GeometryHandleSet::new(5);
+        set.insert(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Standard { rotations: 1 },
+        });
+
+        // Neither a missing index nor a missing orientation of a present index changes the set.
+        assert!(!set.remove(GeometryHandle {
+            index: 0,
+            orientation: GeomOrientation::Standard { rotations: 1 },
+        }));
+        assert!(!set.remove(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Flipped { rotations: 1 },
+        }));
+        assert_eq!(
+            set.entries,
+            vec![GeometryHandleSetEntry {
+                index: 1,
+                orientations: 0x2
+            }]
+        );
+        assert_eq!(set.length, 1);
+
+        assert!(set.remove(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Standard { rotations: 1 },
+        }));
+        assert!(set.empty());
+        assert!(!set.remove(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Standard { rotations: 1 },
+        }));
+        assert_eq!(set.length, 0);
+    }
+
     #[test]
     fn union_disjoint() {
         let mut set0 = GeometryHandleSet::new(3);
@@ -858,4 +1064,226 @@ mod tests {
         assert_eq!(empty.max_rotations, 0);
         assert_eq!(empty.length, 0);
     }
+
+    #[test]
+    fn difference() {
+        let mut set0 = GeometryHandleSet::new(3);
+        let mut set1 = GeometryHandleSet::new(5);
+
+        set0.insert(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Standard { rotations: 1 },
+        });
+        set0.insert(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Standard { rotations: 2 },
+        });
+        set0.insert(GeometryHandle {
+            index: 2,
+            orientation: GeomOrientation::Standard { rotations: 2 },
+        });
+        set0.insert(GeometryHandle {
+            index: 2,
+            orientation: GeomOrientation::Standard { rotations: 0 },
+        });
+        set0.insert(GeometryHandle {
+            index: 4,
+            orientation: GeomOrientation::Standard { rotations: 0 },
+        });
+
+        set1.insert(GeometryHandle {
+            index: 0,
+            orientation: GeomOrientation::Standard { rotations: 1 },
+        });
+        set1.insert(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Standard { rotations: 1 },
+        });
+        set1.insert(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Standard { rotations: 2 },
+        });
+        set1.insert(GeometryHandle {
+            index: 2,
+            orientation: GeomOrientation::Standard { rotations: 1 },
+        });
+        set1.insert(GeometryHandle {
+            index: 2,
+            orientation: GeomOrientation::Standard { rotations: 0 },
+        });
+
+        let difference = set0.difference(&set1);
+        assert_eq!(
+            difference.entries,
+            vec![
+                GeometryHandleSetEntry {
+                    index: 2,
+                    orientations: 0x4
+                },
+                GeometryHandleSetEntry {
+                    index: 4,
+                    orientations: 0x1
+                },
+            ]
+        );
+        assert_eq!(difference.max_rotations, 3);
+        assert_eq!(difference.length, 2);
+
+        // Iterating the difference gives the handles of the first set missing from the second.
+        let expected = set0
+            .into_iter()
+            .filter(|handle| !set1.contains(*handle))
+            .collect::<Vec<_>>();
+        assert_eq!(difference.into_iter().collect::<Vec<_>>(), expected);
+        assert_eq!((&set0 - &set1).entries, difference.entries);
+    }
+
+    #[test]
+    fn difference_disjoint() {
+        let mut set0 = GeometryHandleSet::new(3);
+        let mut set1 = GeometryHandleSet::new(5);
+
+        set0.insert(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Standard { rotations: 1 },
+        });
+        set0.insert(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Standard { rotations: 2 },
+        });
+        set0.insert(GeometryHandle {
+            index: 2,
+            orientation: GeomOrientation::Standard { rotations: 2 },
+        });
+
+        set1.insert(GeometryHandle {
+            index: 2,
+            orientation: GeomOrientation::Standard { rotations: 1 },
+        });
+        set1.insert(GeometryHandle {
+            index: 3,
+            orientation: GeomOrientation::Standard { rotations: 1 },
+        });
+
+        let difference = set0.difference(&set1);
+        assert_eq!(difference.entries, set0.entries);
+        assert_eq!(difference.max_rotations, 3);
+        assert_eq!(difference.length, 3);
+    }
+
+    #[test]
+    fn difference_fully_overlapping() {
+        let mut set0 = GeometryHandleSet::new(5);
+        let mut set1 = GeometryHandleSet::new(5);
+
+        set0.insert(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Standard { rotations: 2 },
+        });
+        set0.insert(GeometryHandle {
+            index: 3,
+            orientation: GeomOrientation::Flipped { rotations: 1 },
+        });
+
+        set1.insert(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Standard { rotations: 1 },
+        });
+        set1.insert(GeometryHandle {
+            index: 1,
+            orientation: GeomOrientation::Standard { rotations: 2 },
+        });
+        set1.insert(GeometryHandle {
+            index: 3,
+            orientation: GeomOrientation::Flipped { rotations: 1 },
+        });
+
+        let difference = set0.difference(&set1);
+        assert_eq!(difference.entries, vec![]);
+        assert!(difference.empty());
+        assert_eq!(difference.length, 0);
+        assert_eq!(difference.into_iter().count(), 0);
+
+        // Removing the smaller set from the larger leaves only what the smaller did not have.
+        let remaining = set1.difference(&set0);
+        assert_eq!(
+            remaining.entries,
+            vec![GeometryHandleSetEntry {
+                index: 1,
+                orientations: 0x2
+            },]
+        );
+        assert_eq!(remaining.length, 1);
+    }
+
+    #[test]
+    fn difference_empty() {
+        let mut set = GeometryHandleSet::new(5);
+        set.insert(GeometryHandle {
+            index: 0,
+            orientation: GeomOrientation::Flipped { rotations: 1 },
+        });
+        let empty = GeometryHandleSet::new(5);
+        assert_eq!(set.difference(&empty).entries, set.entries);
+        assert_eq!(set.difference(&empty).length, 1);
+        assert_eq!(empty.difference(&set).entries, vec![]);
+        assert_eq!(empty.difference(&set).length, 0);
+    }
+
+    // Build a random set of handles with small indices, so sets overlap often, along with the
+    // handles it holds.
+    fn random_set(rng: &mut SeededRng, max_rotations: usize) -> (GeometryHandleSet, Vec<GeometryHandle>) {
+        let mut set = GeometryHandleSet::new(max_rotations);
+        let mut handles = Vec::new();
+        for _ in 0..rng.next_below(12) {
+            let rotations = rng.next_below(max_rotations);
+            let handle = GeometryHandle {
+                index: rng.next_below(4),
+                orientation: if rng.next_below(4) == 0 {
+                    GeomOrientation::Flipped { rotations }
+                } else {
+                    GeomOrientation::Standard { rotations }
+                },
+            };
+            set.insert(handle);
+            if !handles.contains(&handle) {
+                handles.push(handle);
+            }
+        }
+        (set, handles)
+    }
+
+    #[test]
+    fn length_matches_iteration() {
+        let mut rng = SeededRng::new(0);
+        for _ in 0..2000 {
+            // Sets can disagree on how many rotations they have, as they do in the other tests.
+            let sets = (0..1 + rng.next_below(5))
+                .map(|_| {
+                    let max_rotations = 1 + rng.next_below(6);
+                    random_set(&mut rng, max_rotations)
+                })
+                .collect::<Vec<_>>();
+            for (set, handles) in &sets {
+                assert_eq!(set.length(), handles.len());
+                assert_eq!(set.into_iter().count(), handles.len());
+            }
+
+            let intersection = GeometryHandleSet::intersection(sets.iter().map(|(set, _)| set));
+            let expected = sets[0]
+                .1
+                .iter()
+                .filter(|handle| sets.iter().all(|(_, handles)| handles.contains(handle)))
+                .count();
+            assert_eq!(intersection.into_iter().count(), expected, "{}", intersection.data_string());
+            assert_eq!(intersection.length(), expected, "{}", intersection.data_string());
+
+            let union = GeometryHandleSet::union(sets.iter().map(|(set, _)| set));
+            let mut expected = sets.iter().flat_map(|(_, handles)| handles.clone()).collect::<Vec<_>>();
+            expected.sort_unstable_by_key(|handle| (handle.index, handle.orientation.to_bits()));
+            expected.dedup();
+            assert_eq!(union.into_iter().count(), expected.len(), "{}", union.data_string());
+            assert_eq!(union.length(), expected.len(), "{}", union.data_string());
+        }
+    }
 }
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+
+use bevy::utils::HashMap;
+
+use super::handles::GeometryHandleSet;
+
+/// Everything the restriction on a collapse entry is computed from: the vertical profile its
+/// corners give it and the walls its neighbors allow on each of its edges.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct IntersectionKey {
+    pub side_count: usize,
+    pub bottom_indicator: usize,
+    pub top_indicator: usize,
+    pub edges: Vec<(usize, Option<u128>)>,
+}
+
+/// Remembers the intersections of `GeometryHandleSet`s we have computed so entries that end up
+/// with the same restrictions do not rebuild them. Holds at most `capacity` intersections,
+/// dropping the least recently used one to make room.
+pub struct IntersectionCache {
+    capacity: usize,
+    entries: HashMap<IntersectionKey, (GeometryHandleSet, u64)>,
+    // Our keys ordered by when they were last used, oldest first.
+    recency: BTreeMap<u64, IntersectionKey>,
+    clock: u64,
+    hits: usize,
+}
+
+impl IntersectionCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::default(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// How many lookups were answered without computing the intersection.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Change how many intersections we hold on to. A capacity of 0 turns the cache off.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > self.capacity {
+            self.evict_oldest();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    /// Return the intersection stored for the key, computing and storing it if we do not have it.
+    pub fn get_or_insert_with(
+        &mut self,
+        key: IntersectionKey,
+        compute: impl FnOnce() -> GeometryHandleSet,
+    ) -> GeometryHandleSet {
+        if self.capacity == 0 {
+            return compute();
+        }
+
+        self.clock += 1;
+        if let Some((set, last_used)) = self.entries.get_mut(&key) {
+            self.recency.remove(last_used);
+            *last_used = self.clock;
+            self.recency.insert(self.clock, key);
+            self.hits += 1;
+            return set.clone();
+        }
+
+        if self.entries.len() >= self.capacity {
+            self.evict_oldest();
+        }
+        let set = compute();
+        self.recency.insert(self.clock, key.clone());
+        self.entries.insert(key, (set.clone(), self.clock));
+        set
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(oldest) = self.recency.keys().next().cloned() {
+            if let Some(key) = self.recency.remove(&oldest) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::visuals::geom::{handles::GeometryHandleSet, GeomOrientation, GeometryHandle};
+
+    use super::{IntersectionCache, IntersectionKey};
+
+    fn key(bottom_indicator: usize) -> IntersectionKey {
+        IntersectionKey {
+            side_count: 4,
+            bottom_indicator,
+            top_indicator: 0,
+            edges: Vec::new(),
+        }
+    }
+
+    fn set_of(index: usize) -> GeometryHandleSet {
+        let mut set = GeometryHandleSet::new(4);
+        set.insert(GeometryHandle {
+            index,
+            orientation: GeomOrientation::Standard { rotations: 0 },
+        });
+        set
+    }
+
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let mut cache = IntersectionCache::new(2);
+        cache.get_or_insert_with(key(0), || set_of(0));
+        cache.get_or_insert_with(key(1), || set_of(1));
+        // Using the first key makes the second the oldest.
+        cache.get_or_insert_with(key(0), || unreachable!());
+        cache.get_or_insert_with(key(2), || set_of(2));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.hits(), 1);
+
+        let mut recomputed = false;
+        let set = cache.get_or_insert_with(key(1), || {
+            recomputed = true;
+            set_of(1)
+        });
+        assert!(recomputed);
+        assert_eq!(set.length(), 1);
+        assert!(cache.get_or_insert_with(key(2), || unreachable!()).contains(GeometryHandle {
+            index: 2,
+            orientation: GeomOrientation::Standard { rotations: 0 },
+        }));
+    }
+}
@@ -1,6 +1,7 @@
 pub mod build_profiles;
 pub mod geom;
 pub mod handles;
+pub mod intersection_cache;
 pub mod orientations;
 pub mod vertical;
 
@@ -2,11 +2,18 @@ use bevy::{
     math::{Quat, Vec3},
     prelude::Transform,
 };
+use serde::Deserialize;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Deserialize)]
 pub enum GeomOrientation {
     Standard { rotations: usize },
+    /// Reflected across the z axis, then rotated.
     Flipped { rotations: usize },
+    /// Reflected across the x axis, then rotated. This is a flip followed by a half turn, which
+    /// for odd side counts is not one of the rotations: the tile ends up pointing the other way,
+    /// and its corners are read in the frame of a cell facing that way, where they line up with
+    /// the same `Flipped` orientation.
+    Mirrored { rotations: usize },
 }
 
 impl Default for GeomOrientation {
@@ -15,6 +22,19 @@ impl Default for GeomOrientation {
     }
 }
 
+// The bit the first mirrored orientation is stored at. Standard orientations count up from the
+// lowest bit and flipped ones down from the highest, so each kind has room for 16 rotations.
+const MIRRORED_BITS_START: usize = usize::BITS as usize / 2;
+
+// How many rotations a half turn is, or none when it is not a rotation of the shape.
+const fn half_turn(max_sides: usize) -> usize {
+    if max_sides % 2 == 0 {
+        max_sides / 2
+    } else {
+        0
+    }
+}
+
 impl GeomOrientation {
     pub fn get_index_in_sequence(
         &self,
@@ -28,6 +48,10 @@ impl GeomOrientation {
                 (4 * max_index - if is_corner { 1 } else { 2 } - in_index + *rotations)
                     .rem_euclid(max_index)
             }
+            GeomOrientation::Mirrored { rotations } => GeomOrientation::Flipped {
+                rotations: *rotations + half_turn(max_index),
+            }
+            .get_index_in_sequence(in_index, max_index, is_corner),
         }
     }
 
@@ -35,6 +59,7 @@ impl GeomOrientation {
         match self {
             GeomOrientation::Standard { .. } => false,
             GeomOrientation::Flipped { .. } => true,
+            GeomOrientation::Mirrored { .. } => true,
         }
     }
 
@@ -42,19 +67,24 @@ impl GeomOrientation {
         match self {
             GeomOrientation::Standard { rotations } => 1 << rotations,
             GeomOrientation::Flipped { rotations } => 1 << (usize::BITS as usize - 1 - rotations),
+            GeomOrientation::Mirrored { rotations } => 1 << (MIRRORED_BITS_START + rotations),
         }
     }
 
     pub fn from_bits(bits: usize, max_rotations: usize) -> impl Iterator<Item = GeomOrientation> {
-        (0..2 * max_rotations).filter_map(move |rotation| {
+        (0..3 * max_rotations).filter_map(move |rotation| {
             let transform = if rotation < max_rotations {
                 GeomOrientation::Standard {
                     rotations: rotation,
                 }
-            } else {
+            } else if rotation < 2 * max_rotations {
                 GeomOrientation::Flipped {
                     rotations: rotation - max_rotations,
                 }
+            } else {
+                GeomOrientation::Mirrored {
+                    rotations: rotation - 2 * max_rotations,
+                }
             };
             if transform.to_bits() & bits != 0 {
                 Some(transform)
@@ -75,6 +105,10 @@ impl GeomOrientation {
                 Quat::from_rotation_y(std::f32::consts::TAU * *rotations as f32 / max_sides as f32),
             )
             .with_scale(Vec3::new(1.0, 1.0, -1.0)),
+            GeomOrientation::Mirrored { rotations } => Transform::from_rotation(
+                Quat::from_rotation_y(std::f32::consts::TAU * *rotations as f32 / max_sides as f32),
+            )
+            .with_scale(Vec3::new(-1.0, 1.0, 1.0)),
         }
     }
 
@@ -82,6 +116,7 @@ impl GeomOrientation {
         match self {
             GeomOrientation::Standard { rotations } => GeomOrientation::Standard { rotations: (max_sides - rotations) % max_sides },
             GeomOrientation::Flipped { rotations } => GeomOrientation::Flipped { rotations: (max_sides - rotations) % max_sides },
+            GeomOrientation::Mirrored { rotations } => GeomOrientation::Mirrored { rotations: (max_sides - rotations) % max_sides },
         }
     }
 
@@ -95,6 +130,118 @@ impl GeomOrientation {
                 GeomOrientation::Standard { rotations: (*rot1 + rot2) % max_sides },
             (GeomOrientation::Flipped { rotations: rot1 }, GeomOrientation::Standard { rotations: rot2 }) => 
                 GeomOrientation::Flipped { rotations: (*rot1 + rot2) % max_sides },
+            (GeomOrientation::Standard { rotations: rot1 }, GeomOrientation::Mirrored { rotations: rot2 }) |
+            (GeomOrientation::Mirrored { rotations: rot1 }, GeomOrientation::Standard { rotations: rot2 }) =>
+                GeomOrientation::Mirrored { rotations: (*rot1 + rot2) % max_sides },
+            (GeomOrientation::Mirrored { rotations: rot1 }, GeomOrientation::Mirrored { rotations: rot2 }) =>
+                GeomOrientation::Standard { rotations: (*rot1 + rot2) % max_sides },
+            // The two reflections differ by a half turn.
+            (GeomOrientation::Flipped { rotations: rot1 }, GeomOrientation::Mirrored { rotations: rot2 }) |
+            (GeomOrientation::Mirrored { rotations: rot1 }, GeomOrientation::Flipped { rotations: rot2 }) =>
+                GeomOrientation::Standard { rotations: (*rot1 + rot2 + half_turn(max_sides)) % max_sides },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{Mat4, Quat, Vec3};
+
+    use super::GeomOrientation;
+
+    const TRIANGLE: usize = 3;
+
+    fn triangle_orientations() -> Vec<GeomOrientation> {
+        (0..TRIANGLE)
+            .flat_map(|rotations| {
+                [
+                    GeomOrientation::Standard { rotations },
+                    GeomOrientation::Flipped { rotations },
+                    GeomOrientation::Mirrored { rotations },
+                ]
+            })
+            .collect()
+    }
+
+    fn matrix(orientation: GeomOrientation) -> Mat4 {
+        orientation.get_transform(TRIANGLE).compute_matrix()
+    }
+
+    fn assert_same_transform(a: GeomOrientation, b: Mat4) {
+        let a = matrix(a);
+        assert!(a.abs_diff_eq(b, 1e-5), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn mirrored_composes_with_rotation_on_a_triangle() {
+        for mirrored in 0..TRIANGLE {
+            let mirror = GeomOrientation::Mirrored { rotations: mirrored };
+            for rotated in 0..TRIANGLE {
+                let rotation = GeomOrientation::Standard { rotations: rotated };
+                let expected = GeomOrientation::Mirrored {
+                    rotations: (mirrored + rotated) % TRIANGLE,
+                };
+                assert_eq!(mirror.compose(rotation, TRIANGLE), expected);
+                assert_eq!(rotation.compose(mirror, TRIANGLE), expected);
+
+                // Mirroring then rotating moves the corners the same way as the composition.
+                for corner in 0..TRIANGLE {
+                    let mirrored_corner = mirror.get_index_in_sequence(corner, TRIANGLE, true);
+                    assert_eq!(
+                        rotation.get_index_in_sequence(mirrored_corner, TRIANGLE, true),
+                        expected.get_index_in_sequence(corner, TRIANGLE, true),
+                    );
+                }
+                assert_same_transform(
+                    expected,
+                    matrix(mirror) * matrix(rotation),
+                );
+            }
+            assert_eq!(
+                mirror.compose(mirror.inverse(TRIANGLE), TRIANGLE),
+                GeomOrientation::Standard { rotations: 0 }
+            );
+        }
+    }
+
+    #[test]
+    fn mirrored_is_a_flip_turned_half_way_round() {
+        for rotations in 0..TRIANGLE {
+            let flipped = GeomOrientation::Flipped { rotations };
+            let mirrored = GeomOrientation::Mirrored { rotations };
+            let half_turn = Mat4::from_quat(Quat::from_rotation_y(std::f32::consts::PI));
+            assert_same_transform(mirrored, half_turn * matrix(flipped));
+            assert_eq!(
+                mirrored.get_transform(TRIANGLE).scale,
+                Vec3::new(-1.0, 1.0, 1.0)
+            );
+            assert!(mirrored.is_reversed());
         }
+
+        // On a square the half turn is a rotation, so the corners follow it.
+        let mirrored = GeomOrientation::Mirrored { rotations: 1 };
+        let flipped = GeomOrientation::Flipped { rotations: 3 };
+        for corner in 0..4 {
+            assert_eq!(
+                mirrored.get_index_in_sequence(corner, 4, true),
+                flipped.get_index_in_sequence(corner, 4, true)
+            );
+        }
+    }
+
+    #[test]
+    fn every_orientation_has_its_own_bit() {
+        let orientations = triangle_orientations();
+        let bits = orientations
+            .iter()
+            .fold(0, |bits, orientation| {
+                assert_eq!(bits & orientation.to_bits(), 0, "{:?}", orientation);
+                bits | orientation.to_bits()
+            });
+        let mut decoded = GeomOrientation::from_bits(bits, TRIANGLE).collect::<Vec<_>>();
+        let mut expected = orientations;
+        decoded.sort_by_key(|orientation| orientation.to_bits());
+        expected.sort_by_key(|orientation| orientation.to_bits());
+        assert_eq!(decoded, expected);
     }
 }
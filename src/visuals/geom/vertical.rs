@@ -12,7 +12,13 @@ pub enum VerticalProfileParseError {
     InvalidVerticalPattern
 }
 
+// Bits each corner takes up in an indicator.
 const VERTICAL_PROFILE_LEN: usize = 2;
+const VERTICAL_PROFILE_MASK: usize = (1 << VERTICAL_PROFILE_LEN) - 1;
+
+/// The most corners an indicator can hold: 32 on 64 bit targets, enough for octagons with room
+/// to spare.
+pub const MAX_VERTICAL_CORNERS: usize = usize::BITS as usize / VERTICAL_PROFILE_LEN;
 
 impl VerticalProfile {
 
@@ -35,13 +41,13 @@ impl VerticalProfile {
     pub fn from_bits(mut indicator: usize) -> Vec<VerticalProfile> {
         let mut profiles = Vec::new();
         while indicator > 0 {
-            match indicator & 3 {
+            match indicator & VERTICAL_PROFILE_MASK {
                 1 => profiles.push(VerticalProfile::Empty),
                 2 => profiles.push(VerticalProfile::Stackable),
                 3 => profiles.push(VerticalProfile::Full),
                 _ => (),
             }
-            indicator >>= 2;
+            indicator >>= VERTICAL_PROFILE_LEN;
         }
         profiles
     }
@@ -71,7 +77,16 @@ impl VerticalProfile {
         Ok(sequence)
     }
 
+    /// Pack the corners of `sequence`, as seen through `rotation`, into one value. Every corner
+    /// value is non-zero, so two sequences of the same length only share an indicator when they
+    /// are equal; sequences of different lengths are kept apart by the side count stored next to
+    /// the indicator in `GeometryStorageVerticalKey`. At most `MAX_VERTICAL_CORNERS` corners fit.
     pub fn compute_indicator(sequence: &Vec<VerticalProfile>, rotation: GeomOrientation) -> usize {
+        debug_assert!(
+            sequence.len() <= MAX_VERTICAL_CORNERS,
+            "{} corners do not fit in a vertical profile indicator",
+            sequence.len()
+        );
         let mut indicator = 0;
         for i in 0..sequence.len() {
             indicator |= sequence[rotation.get_index_in_sequence(i, sequence.len(), true)].value()
@@ -80,3 +95,44 @@ impl VerticalProfile {
         indicator
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::HashSet;
+
+    use super::{GeomOrientation, VerticalProfile, MAX_VERTICAL_CORNERS};
+
+    const PROFILES: [VerticalProfile; 3] = [
+        VerticalProfile::Empty,
+        VerticalProfile::Stackable,
+        VerticalProfile::Full,
+    ];
+
+    #[test]
+    fn octagon_sequences_have_distinct_indicators() {
+        let unrotated = GeomOrientation::Standard { rotations: 0 };
+        let mut indicators = HashSet::default();
+        for mut combination in 0..3usize.pow(8) {
+            let sequence = (0..8)
+                .map(|_| {
+                    let profile = PROFILES[combination % 3];
+                    combination /= 3;
+                    profile
+                })
+                .collect::<Vec<_>>();
+            let indicator = VerticalProfile::compute_indicator(&sequence, unrotated);
+            assert!(indicator < 1 << 16);
+            assert_eq!(VerticalProfile::from_bits(indicator), sequence);
+            assert!(indicators.insert(indicator));
+        }
+    }
+
+    #[test]
+    fn longest_supported_sequence_fits() {
+        let sequence = vec![VerticalProfile::Full; MAX_VERTICAL_CORNERS];
+        let indicator =
+            VerticalProfile::compute_indicator(&sequence, GeomOrientation::Standard { rotations: 0 });
+        assert_eq!(indicator, usize::MAX);
+        assert_eq!(VerticalProfile::from_bits(indicator), sequence);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod collapse;
 pub mod descriptor;
+pub mod export;
 pub mod geom;
 pub mod render;
@@ -6,15 +6,15 @@ use bevy::{
         lifetimeless::{Read, SQuery, SRes},
         SystemParamItem,
     },
-    math::{Mat4, Size, Vec4},
+    math::{Mat4, Size, Vec3, Vec4},
     pbr::{
-        GlobalLightMeta, GpuLights, LightMeta, MeshPipelineKey, MeshUniform, MeshViewBindGroup,
-        NotShadowCaster, NotShadowReceiver, SetMeshBindGroup, SetShadowViewBindGroup, Shadow,
+        AlphaMode, GlobalLightMeta, GpuLights, LightMeta, MeshPipelineKey, MeshUniform, MeshViewBindGroup,
+        NotShadowCaster, SetMeshBindGroup, SetShadowViewBindGroup, Shadow,
         ShadowPipeline, ViewClusterBindings, ViewShadowBindings,
         CLUSTERED_FORWARD_STORAGE_BUFFER_COUNT,
     },
     prelude::{
-        Assets, Commands, Component, ComputedVisibility, Entity, FromWorld, GlobalTransform,
+        Assets, Color, Commands, Component, ComputedVisibility, Entity, FromWorld, GlobalTransform,
         Handle, HandleUntyped, Image, Local, Mesh, Plugin, Query, Res, Transform, Visibility, With,
         Without, World,
     },
@@ -27,6 +27,7 @@ use bevy::{
         },
         render_resource::*,
         renderer::{RenderDevice, RenderQueue},
+        camera::{ActiveCamera, Camera3d},
         texture::{BevyDefault, GpuImage, TextureFormatPixelInfo},
         view::{NoFrustumCulling, ViewUniform, ViewUniforms},
         RenderApp, RenderStage,
@@ -35,7 +36,7 @@ use bevy::{
 };
 use bytemuck::Pod;
 
-use super::instanced_pbr::InstancedStandardMaterial;
+use super::instanced_pbr::{InstancedStandardMaterial, INSTANCED_PBR_SHADER_HANDLE};
 
 #[derive(Default)]
 pub struct InstanceMeshRenderPlugin;
@@ -50,6 +51,10 @@ impl Plugin for InstanceMeshRenderPlugin {
             INSTANCE_MESH_SHADER_HANDLE,
             Shader::from_wgsl(include_str!("instanced_mesh.wgsl")),
         );
+        assets.set_untracked(
+            INSTANCED_PBR_SHADER_HANDLE,
+            Shader::from_wgsl(include_str!("instanced_pbr.wgsl")),
+        );
 
         if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
             render_app
@@ -65,6 +70,32 @@ impl Plugin for InstanceMeshRenderPlugin {
 #[derive(Component)]
 pub struct MeshInstance {
     pub mesh: Handle<Mesh>,
+    /// Lower detail meshes drawn instead of `mesh` once the instance is at least the paired
+    /// distance from the camera.
+    pub lods: Vec<(f32, Handle<Mesh>)>,
+}
+
+impl MeshInstance {
+    /// The mesh to draw when the instance is `distance` from the camera: the LOD with the
+    /// largest distance no further than it, or the full mesh when it is closer than every LOD.
+    pub fn mesh_at_distance(&self, distance: f32) -> &Handle<Mesh> {
+        self.lods
+            .iter()
+            .filter(|(min_distance, _)| *min_distance <= distance)
+            .max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(_, mesh)| mesh)
+            .unwrap_or(&self.mesh)
+    }
+}
+
+/// Tints every vertex of an instance. Instances without one are drawn white.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct InstanceColor(pub Color);
+
+impl Default for InstanceColor {
+    fn default() -> Self {
+        Self(Color::WHITE)
+    }
 }
 
 #[derive(Component, Clone, Copy, Pod, bytemuck::Zeroable)]
@@ -78,10 +109,12 @@ pub struct InstanceTransforms {
     inverse_transpose_transform_1: Vec4,
     inverse_transpose_transform_2: Vec4,
     inverse_transpose_transform_3: Vec4,
+    // In linear space, as the shaders work with it.
+    color: Vec4,
 }
 
 impl InstanceTransforms {
-    fn new(transform: Mat4) -> Self {
+    fn new(transform: Mat4, color: Color) -> Self {
         let inverse_transpose = transform.inverse().transpose();
         Self {
             transform_0: transform.col(0),
@@ -92,6 +125,7 @@ impl InstanceTransforms {
             inverse_transpose_transform_1: inverse_transpose.col(1),
             inverse_transpose_transform_2: inverse_transpose.col(2),
             inverse_transpose_transform_3: inverse_transpose.col(3),
+            color: Vec4::from(color.as_linear_rgba_f32()),
         }
     }
 }
@@ -101,6 +135,29 @@ pub(crate) struct InstancedMeshTransforms {
     pub transforms: Vec<InstanceTransforms>,
 }
 
+impl InstancedMeshTransforms {
+    /// The average translation of the instances, used to place the whole batch in a render phase.
+    pub fn center(&self) -> Vec3 {
+        if self.transforms.is_empty() {
+            return Vec3::ZERO;
+        }
+        self.transforms
+            .iter()
+            .fold(Vec3::ZERO, |sum, instance| sum + instance.transform_3.truncate())
+            / self.transforms.len() as f32
+    }
+
+    /// Order the instances so the ones furthest from `view_translation` are drawn first, which
+    /// blended instances need to be composited correctly.
+    pub fn sort_back_to_front(&mut self, view_translation: Vec3) {
+        self.transforms.sort_by(|a, b| {
+            let a = view_translation.distance_squared(a.transform_3.truncate());
+            let b = view_translation.distance_squared(b.transform_3.truncate());
+            b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal)
+        });
+    }
+}
+
 // NOTE: These must match the bit flags in bevy_pbr2/src/render/mesh.wgsl!
 bitflags::bitflags! {
     #[repr(transparent)]
@@ -111,17 +168,21 @@ bitflags::bitflags! {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn extract_meshes(
     mut commands: Commands,
     mut previous_caster_len: Local<usize>,
     mut previous_not_caster_len: Local<usize>,
+    active_camera: Res<ActiveCamera<Camera3d>>,
+    camera_query: Query<&GlobalTransform, With<Camera3d>>,
+    materials: Res<Assets<InstancedStandardMaterial>>,
     caster_query: Query<
         (
             &ComputedVisibility,
             &GlobalTransform,
             &MeshInstance,
             &Handle<InstancedStandardMaterial>,
-            Option<&NotShadowReceiver>,
+            Option<&InstanceColor>,
         ),
         Without<NotShadowCaster>,
     >,
@@ -130,7 +191,7 @@ pub fn extract_meshes(
             &ComputedVisibility,
             &GlobalTransform,
             &MeshInstance,
-            Option<&NotShadowReceiver>,
+            Option<&InstanceColor>,
         ),
         With<NotShadowCaster>,
     >,
@@ -139,27 +200,47 @@ pub fn extract_meshes(
         (Handle<Mesh>, Handle<InstancedStandardMaterial>),
         InstancedMeshTransforms,
     >::with_capacity(*previous_caster_len);
-    for (computed_visibility, transform, instance, material, _) in caster_query.iter() {
+    // Without a camera to measure from, every instance gets its full mesh.
+    let view_translation = active_camera
+        .get()
+        .and_then(|camera| camera_query.get(camera).ok())
+        .map(|camera_transform| camera_transform.translation);
+    let mesh_for = |instance: &MeshInstance, transform: &GlobalTransform| {
+        let distance = view_translation
+            .map(|view_translation| view_translation.distance(transform.translation))
+            .unwrap_or(0.0);
+        instance.mesh_at_distance(distance).clone_weak()
+    };
+    for (computed_visibility, transform, instance, material, color) in caster_query.iter() {
         if !computed_visibility.is_visible {
             continue;
         }
+        let mesh = mesh_for(instance, transform);
         let transform = transform.compute_matrix();
-        if let Some(instance_data) =
-            caster_map.get_mut(&(instance.mesh.clone_weak(), material.clone_weak()))
+        let color = color.copied().unwrap_or_default().0;
+        if let Some(instance_data) = caster_map.get_mut(&(mesh.clone_weak(), material.clone_weak()))
         {
             instance_data
                 .transforms
-                .push(InstanceTransforms::new(transform));
+                .push(InstanceTransforms::new(transform, color));
         } else {
             caster_map.insert(
-                (instance.mesh.clone_weak(), material.clone_weak()),
+                (mesh, material.clone_weak()),
                 InstancedMeshTransforms {
-                    transforms: vec![InstanceTransforms::new(transform)],
+                    transforms: vec![InstanceTransforms::new(transform, color)],
                 },
             );
         }
     }
     *previous_caster_len = caster_map.len();
+    if let Some(view_translation) = view_translation {
+        for ((_, material), instance_data) in caster_map.iter_mut() {
+            let alpha_mode = materials.get(material).map(|material| material.alpha_mode);
+            if let Some(AlphaMode::Blend) = alpha_mode {
+                instance_data.sort_back_to_front(view_translation);
+            }
+        }
+    }
     commands.spawn_batch(caster_map.into_iter().map(|((a, b), c)| {
         (
             a,
@@ -180,20 +261,22 @@ pub fn extract_meshes(
 
     let mut not_caster_map =
         HashMap::<Handle<Mesh>, InstancedMeshTransforms>::with_capacity(*previous_not_caster_len);
-    for (computed_visibility, transform, instance, _) in not_caster_query.iter() {
+    for (computed_visibility, transform, instance, color) in not_caster_query.iter() {
         if !computed_visibility.is_visible {
             continue;
         }
+        let mesh = mesh_for(instance, transform);
         let transform = transform.compute_matrix();
-        if let Some(instance_data) = not_caster_map.get_mut(&instance.mesh) {
+        let color = color.copied().unwrap_or_default().0;
+        if let Some(instance_data) = not_caster_map.get_mut(&mesh) {
             instance_data
                 .transforms
-                .push(InstanceTransforms::new(transform));
+                .push(InstanceTransforms::new(transform, color));
         } else {
             not_caster_map.insert(
-                instance.mesh.clone_weak(),
+                mesh,
                 InstancedMeshTransforms {
-                    transforms: vec![InstanceTransforms::new(transform)],
+                    transforms: vec![InstanceTransforms::new(transform, color)],
                 },
             );
         }
@@ -610,6 +693,11 @@ impl SpecializedMeshPipeline for InstancedMeshPipeline {
                     offset: VertexFormat::Float32x4.size() * 7,
                     shader_location: 13, // shader locations 0-2 are taken up by Position, Normal and UV attributes
                 },
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: VertexFormat::Float32x4.size() * 8,
+                    shader_location: 14,
+                },
             ],
         };
 
@@ -756,3 +844,79 @@ impl EntityRenderCommand for DrawInstancedMesh {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        asset::HandleId,
+        math::{Mat4, Vec3},
+        prelude::{Color, Handle, Mesh},
+    };
+
+    use super::{InstanceTransforms, InstancedMeshTransforms, MeshInstance};
+
+    #[test]
+    fn instances_pick_lod_by_distance() {
+        let [full, near, far] = [(); 3].map(|_| Handle::<Mesh>::weak(HandleId::random::<Mesh>()));
+        // The LODs do not have to be in order.
+        let instance = MeshInstance {
+            mesh: full.clone(),
+            lods: vec![(50.0, far.clone()), (20.0, near.clone())],
+        };
+        for (distance, expected) in [
+            (0.0, &full),
+            (19.9, &full),
+            (20.0, &near),
+            (49.0, &near),
+            (50.0, &far),
+            (1000.0, &far),
+        ] {
+            assert_eq!(instance.mesh_at_distance(distance), expected, "{}", distance);
+        }
+
+        let no_lods = MeshInstance {
+            mesh: full.clone(),
+            lods: Vec::new(),
+        };
+        assert_eq!(no_lods.mesh_at_distance(1000.0), &full);
+    }
+
+    #[test]
+    fn blended_instances_sort_back_to_front() {
+        let at = |x: f32| {
+            InstanceTransforms::new(Mat4::from_translation(Vec3::new(x, 0.0, 0.0)), Color::WHITE)
+        };
+        let mut batch = InstancedMeshTransforms {
+            transforms: vec![at(1.0), at(9.0), at(-4.0), at(3.0)],
+        };
+        assert_eq!(batch.center(), Vec3::new(2.25, 0.0, 0.0));
+
+        batch.sort_back_to_front(Vec3::new(10.0, 0.0, 0.0));
+        let order = batch
+            .transforms
+            .iter()
+            .map(|instance| instance.transform_3.x)
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![-4.0, 1.0, 3.0, 9.0]);
+    }
+
+    #[test]
+    fn instance_color_round_trips_through_buffer() {
+        let color = Color::rgba(0.2, 0.4, 0.8, 0.5);
+        let instances = [
+            InstanceTransforms::new(Mat4::IDENTITY, color),
+            InstanceTransforms::new(Mat4::IDENTITY, Color::WHITE),
+        ];
+        let floats: &[f32] = bytemuck::cast_slice(&instances);
+        // Two matrices followed by the color for each instance.
+        assert_eq!(floats.len(), 2 * (32 + 4));
+        assert_eq!(&floats[32..36], &color.as_linear_rgba_f32());
+        assert_eq!(&floats[68..72], &[1.0, 1.0, 1.0, 1.0]);
+
+        let [r, g, b, a] =
+            Color::rgba_linear(floats[32], floats[33], floats[34], floats[35]).as_rgba_f32();
+        for (channel, expected) in [r, g, b, a].into_iter().zip([0.2, 0.4, 0.8, 0.5]) {
+            assert!((channel - expected).abs() < 1e-5);
+        }
+    }
+}
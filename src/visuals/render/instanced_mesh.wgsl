@@ -23,6 +23,7 @@ struct Vertex {
     [[location(11)]] instance_inverse_transpose_model_1: vec4<f32>;
     [[location(12)]] instance_inverse_transpose_model_2: vec4<f32>;
     [[location(13)]] instance_inverse_transpose_model_3: vec4<f32>;
+    [[location(14)]] instance_color: vec4<f32>;
 };
 
 struct VertexOutput {
@@ -33,6 +34,7 @@ struct VertexOutput {
 #ifdef VERTEX_TANGENTS
     [[location(3)]] world_tangent: vec4<f32>;
 #endif
+    [[location(4)]] color: vec4<f32>;
 };
 
 [[group(2), binding(0)]]
@@ -79,6 +81,7 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 #endif
 
     out.uv = vertex.uv;
+    out.color = vertex.instance_color;
     out.clip_position = view.view_proj * out.world_position;
     return out;
 }
@@ -91,9 +94,10 @@ struct FragmentInput {
 #ifdef VERTEX_TANGENTS
     [[location(3)]] world_tangent: vec4<f32>;
 #endif
+    [[location(4)]] color: vec4<f32>;
 };
 
 [[stage(fragment)]]
 fn fragment(in: FragmentInput) -> [[location(0)]] vec4<f32> {
-    return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+    return vec4<f32>(1.0, 0.0, 1.0, 1.0) * in.color;
 }
\ No newline at end of file
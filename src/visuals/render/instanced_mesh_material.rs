@@ -37,6 +37,25 @@ use super::instanced_mesh::{DrawInstancedMesh, InstancedMeshPipeline, InstancedM
 /// asset type (which includes [`Material`] types).
 pub struct InstancedMaterialPlugin<M: InstancedSpecializedMaterial>(PhantomData<M>);
 
+/// The render phase a batch of instances is queued in.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InstancedPhase {
+    Opaque,
+    AlphaMask,
+    /// Drawn after the other phases, back to front, with blending.
+    Transparent,
+}
+
+impl From<AlphaMode> for InstancedPhase {
+    fn from(alpha_mode: AlphaMode) -> Self {
+        match alpha_mode {
+            AlphaMode::Opaque => InstancedPhase::Opaque,
+            AlphaMode::Mask(_) => InstancedPhase::AlphaMask,
+            AlphaMode::Blend => InstancedPhase::Transparent,
+        }
+    }
+}
+
 impl<M: InstancedSpecializedMaterial> Default for InstancedMaterialPlugin<M> {
     fn default() -> Self {
         Self(Default::default())
@@ -317,7 +336,7 @@ fn queue_instanced_material_meshes<M: InstancedSpecializedMaterial>(
     msaa: Res<Msaa>,
     render_meshes: Res<RenderAssets<Mesh>>,
     render_materials: Res<RenderAssets<M>>,
-    material_meshes: Query<(Entity, &Handle<M>, &Handle<Mesh>), With<InstancedMeshTransforms>>,
+    material_meshes: Query<(Entity, &Handle<M>, &Handle<Mesh>, &InstancedMeshTransforms)>,
     mut views: Query<(
         &ExtractedView,
         &VisibleEntities,
@@ -326,7 +345,7 @@ fn queue_instanced_material_meshes<M: InstancedSpecializedMaterial>(
         &mut RenderPhase<Transparent3d>,
     )>,
 ) {
-    for (_view, _visible_entities, mut opaque_phase, mut alpha_mask_phase, mut transparent_phase) in
+    for (view, _visible_entities, mut opaque_phase, mut alpha_mask_phase, mut transparent_phase) in
         views.iter_mut()
     {
         let draw_opaque_pbr = opaque_draw_functions
@@ -344,14 +363,17 @@ fn queue_instanced_material_meshes<M: InstancedSpecializedMaterial>(
 
         let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples);
 
-        for (visible_entity, material_handle, mesh_handle) in material_meshes.iter() {
+        let inverse_view_matrix = view.transform.compute_matrix().inverse();
+        let inverse_view_row_2 = inverse_view_matrix.row(2);
+
+        for (visible_entity, material_handle, mesh_handle, instances) in material_meshes.iter() {
             if let Some(material) = render_materials.get(material_handle) {
                 if let Some(mesh) = render_meshes.get(mesh_handle) {
                     let mut mesh_key =
                         MeshPipelineKey::from_primitive_topology(mesh.primitive_topology)
                             | msaa_key;
-                    let alpha_mode = M::alpha_mode(material);
-                    if let AlphaMode::Blend = alpha_mode {
+                    let phase = InstancedPhase::from(M::alpha_mode(material));
+                    if phase == InstancedPhase::Transparent {
                         mesh_key |= MeshPipelineKey::TRANSPARENT_MAIN_PASS;
                     }
 
@@ -374,11 +396,11 @@ fn queue_instanced_material_meshes<M: InstancedSpecializedMaterial>(
                         }
                     };
 
-                    // NOTE: row 2 of the inverse view matrix dotted with column 3 of the model matrix
-                    // gives the z component of translation of the mesh in view space
-                    let mesh_z = 0.0; //inverse_view_row_2.dot(mesh_uniform.transform.col(3));
-                    match alpha_mode {
-                        AlphaMode::Opaque => {
+                    // NOTE: row 2 of the inverse view matrix dotted with the center of the instances
+                    // gives the z component of translation of the batch in view space
+                    let mesh_z = inverse_view_row_2.dot(instances.center().extend(1.0));
+                    match phase {
+                        InstancedPhase::Opaque => {
                             opaque_phase.add(Opaque3d {
                                 entity: visible_entity,
                                 draw_function: draw_opaque_pbr,
@@ -390,7 +412,7 @@ fn queue_instanced_material_meshes<M: InstancedSpecializedMaterial>(
                                 distance: -mesh_z,
                             });
                         }
-                        AlphaMode::Mask(_) => {
+                        InstancedPhase::AlphaMask => {
                             alpha_mask_phase.add(AlphaMask3d {
                                 entity: visible_entity,
                                 draw_function: draw_alpha_mask_pbr,
@@ -402,7 +424,7 @@ fn queue_instanced_material_meshes<M: InstancedSpecializedMaterial>(
                                 distance: -mesh_z,
                             });
                         }
-                        AlphaMode::Blend => {
+                        InstancedPhase::Transparent => {
                             transparent_phase.add(Transparent3d {
                                 entity: visible_entity,
                                 draw_function: draw_transparent_pbr,
@@ -420,3 +442,17 @@ fn queue_instanced_material_meshes<M: InstancedSpecializedMaterial>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::pbr::AlphaMode;
+
+    use super::InstancedPhase;
+
+    #[test]
+    fn alpha_modes_route_to_their_phase() {
+        assert_eq!(InstancedPhase::from(AlphaMode::Opaque), InstancedPhase::Opaque);
+        assert_eq!(InstancedPhase::from(AlphaMode::Mask(0.5)), InstancedPhase::AlphaMask);
+        assert_eq!(InstancedPhase::from(AlphaMode::Blend), InstancedPhase::Transparent);
+    }
+}
@@ -5,7 +5,7 @@ use bevy::{
     ecs::system::{lifetimeless::SRes, SystemParamItem},
     math::Vec4,
     pbr::AlphaMode,
-    prelude::{AssetServer, Color, Handle, Image, Shader},
+    prelude::{AssetServer, Color, Handle, HandleUntyped, Image, Shader},
     reflect::TypeUuid,
     render::{
         mesh::MeshVertexBufferLayout,
@@ -22,6 +22,10 @@ use bevy::{
     },
 };
 
+/// The bevy_pbr fragment shader with the instance color multiplied into the base color.
+pub const INSTANCED_PBR_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 9430156183529874311);
+
 /// A material with "standard" properties used in PBR lighting
 /// Standard property values with pictures here
 /// <https://google.github.io/filament/Material%20Properties.pdf>.
@@ -405,7 +409,7 @@ impl InstancedSpecializedMaterial for InstancedStandardMaterial {
     }
 
     fn fragment_shader(_asset_server: &AssetServer) -> Option<Handle<Shader>> {
-        Some(bevy::pbr::PBR_SHADER_HANDLE.typed())
+        Some(INSTANCED_PBR_SHADER_HANDLE.typed())
     }
 
     #[inline]
@@ -530,3 +534,37 @@ impl InstancedSpecializedMaterial for InstancedStandardMaterial {
         render_asset.alpha_mode
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        asset::AssetPlugin,
+        core::CorePlugin,
+        prelude::{App, AssetServer},
+    };
+
+    use super::{InstancedStandardMaterial, INSTANCED_PBR_SHADER_HANDLE};
+    use crate::visuals::render::instanced_mesh_material::InstancedSpecializedMaterial;
+
+    #[test]
+    fn standard_material_is_tinted_by_instance_color() {
+        let mut app = App::new();
+        app.add_plugin(CorePlugin).add_plugin(AssetPlugin);
+        let asset_server = app.world.resource::<AssetServer>();
+        assert_eq!(
+            InstancedStandardMaterial::fragment_shader(asset_server),
+            Some(INSTANCED_PBR_SHADER_HANDLE.typed())
+        );
+
+        // The instance color the vertex stage writes has to be read back and applied.
+        let vertex = include_str!("instanced_mesh.wgsl");
+        let fragment = include_str!("instanced_pbr.wgsl");
+        let color_input = "[[location(4)]] color: vec4<f32>;";
+        assert!(vertex.contains("out.color = vertex.instance_color;"));
+        let vertex_output = &vertex[vertex.find("struct VertexOutput").unwrap()..];
+        assert!(vertex_output[..vertex_output.find("};").unwrap()].contains(color_input));
+        let fragment_input = &fragment[fragment.find("struct FragmentInput").unwrap()..];
+        assert!(fragment_input[..fragment_input.find("};").unwrap()].contains(color_input));
+        assert!(fragment.contains("output_color = output_color * in.color;"));
+    }
+}
@@ -1,6 +1,9 @@
 use bevy::prelude::{Bundle, ComputedVisibility, GlobalTransform, Handle, Transform, Visibility};
 
-use self::{instanced_mesh::MeshInstance, instanced_mesh_material::InstancedSpecializedMaterial};
+use self::{
+    instanced_mesh::{InstanceColor, MeshInstance},
+    instanced_mesh_material::InstancedSpecializedMaterial,
+};
 
 pub mod instanced_mesh;
 pub mod instanced_mesh_material;
@@ -13,6 +16,8 @@ pub struct InstancedPbrBundle<M: InstancedSpecializedMaterial> {
     pub material: Handle<M>,
     pub transform: Transform,
     pub global_transform: GlobalTransform,
+    /// Tint for this instance, white unless set
+    pub color: InstanceColor,
     /// User indication of whether an entity is visible
     pub visibility: Visibility,
     /// Algorithmically-computed indication of whether an entity is visible and should be extracted for rendering
@@ -24,10 +29,12 @@ impl<M: InstancedSpecializedMaterial> Default for InstancedPbrBundle<M> {
         Self {
             mesh: MeshInstance {
                 mesh: Default::default(),
+                lods: Vec::new(),
             },
             material: Default::default(),
             transform: Default::default(),
             global_transform: Default::default(),
+            color: Default::default(),
             visibility: Default::default(),
             computed_visibility: Default::default(),
         }
@@ -0,0 +1,213 @@
+//! Runs the generations of a `SimulationState` on a background thread so large boards don't
+//! hold up drawing. The main thread keeps its own state as the snapshot it draws from and
+//! takes in the changes the worker sends back once each batch of generations is done.
+
+use std::{
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+    thread,
+};
+
+use bevy::{math::IVec2, utils::HashMap};
+
+use crate::simulation::SimulationState;
+
+enum WorkerCommand {
+    Run { epoch: u64, generations: u32 },
+    Replace { epoch: u64, state: Box<SimulationState> },
+}
+
+struct WorkerResult {
+    epoch: u64,
+    generations: u32,
+    changes: Vec<(IVec2, u32)>,
+}
+
+/// The cells changed by a batch of generations on the worker, with the state each ended in.
+pub struct WorkerChanges {
+    pub generations: u32,
+    pub changes: Vec<(IVec2, u32)>,
+}
+
+pub struct SimulationWorker {
+    commands: Sender<WorkerCommand>,
+    results: Mutex<Receiver<WorkerResult>>,
+    // Bumped each time the worker's state is replaced so batches run on the old state are
+    // dropped instead of being applied to the new one.
+    epoch: u64,
+    // Whether a batch has been sent that hasn't come back yet.
+    running: bool,
+    // Generations that came due while a batch was running. They go out as one batch once it
+    // comes back, so a worker that falls behind catches up instead of queueing a batch per
+    // frame.
+    backlog: u32,
+}
+
+impl SimulationWorker {
+    /// Start a worker thread running its own copy of `state`. The thread stops once the worker
+    /// is dropped.
+    pub fn spawn(state: SimulationState) -> Self {
+        let (commands, command_receiver) = channel();
+        let (result_sender, results) = channel();
+        thread::spawn(move || run_worker(state, command_receiver, result_sender));
+        Self {
+            commands,
+            results: Mutex::new(results),
+            epoch: 0,
+            running: false,
+            backlog: 0,
+        }
+    }
+
+    /// Drop any generations running or waiting to run, because the board they would be
+    /// applied to was edited. Nothing runs again until the worker's state is replaced.
+    pub fn discard(&mut self) {
+        self.epoch += 1;
+        self.running = false;
+        self.backlog = 0;
+    }
+
+    /// Hand the worker a new copy of the state after the board was edited. Any generations
+    /// still running on the old state are dropped.
+    pub fn replace(&mut self, state: SimulationState) {
+        self.discard();
+        let _ = self.commands.send(WorkerCommand::Replace {
+            epoch: self.epoch,
+            state: Box::new(state),
+        });
+    }
+
+    /// Ask for more generations. They are sent straight away unless a batch is still running.
+    pub fn request(&mut self, generations: u32) {
+        self.backlog += generations;
+        self.send_backlog();
+    }
+
+    /// Whether there are generations sent or waiting to be sent that haven't come back yet.
+    pub fn is_busy(&self) -> bool {
+        self.running || self.backlog > 0
+    }
+
+    /// Take the changes from any batch that has come back, without waiting for one.
+    pub fn poll(&mut self) -> Option<WorkerChanges> {
+        let mut generations = 0;
+        let mut merged = HashMap::default();
+        for result in self.results.get_mut().unwrap().try_iter() {
+            if result.epoch != self.epoch {
+                continue;
+            }
+            self.running = false;
+            generations += result.generations;
+            merged.extend(result.changes);
+        }
+        self.send_backlog();
+        if generations == 0 {
+            return None;
+        }
+        Some(WorkerChanges {
+            generations,
+            changes: sorted(merged),
+        })
+    }
+
+    fn send_backlog(&mut self) {
+        if !self.running && self.backlog > 0 {
+            self.running = self
+                .commands
+                .send(WorkerCommand::Run {
+                    epoch: self.epoch,
+                    generations: self.backlog,
+                })
+                .is_ok();
+            self.backlog = 0;
+        }
+    }
+}
+
+fn run_worker(
+    mut state: SimulationState,
+    commands: Receiver<WorkerCommand>,
+    results: Sender<WorkerResult>,
+) {
+    let mut current_epoch = 0;
+    for command in commands.iter() {
+        match command {
+            WorkerCommand::Replace { epoch, state: new_state } => {
+                current_epoch = epoch;
+                state = *new_state;
+            }
+            WorkerCommand::Run { epoch, generations } => {
+                if epoch != current_epoch {
+                    continue;
+                }
+                let mut changes = HashMap::default();
+                for _ in 0..generations {
+                    changes.extend(state.run_generation());
+                }
+                let result = WorkerResult {
+                    epoch,
+                    generations,
+                    changes: sorted(changes),
+                };
+                if results.send(result).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Keep the order changes are applied in the same from run to run.
+fn sorted(changes: HashMap<IVec2, u32>) -> Vec<(IVec2, u32)> {
+    let mut changes = changes.into_iter().collect::<Vec<_>>();
+    changes.sort_unstable_by_key(|(index, _)| (index.y, index.x));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy::math::Vec2;
+
+    use super::*;
+    use crate::tiling::{Tiling, TilingKind};
+
+    #[test]
+    fn worker_matches_running_in_place() {
+        let tiling = Tiling {
+            kind: TilingKind::Square,
+            max_index: IVec2::new(24, 24),
+            offset: Vec2::ZERO,
+        };
+        let start = SimulationState::new_with_random(tiling.clone(), 1, 0.4, 11);
+        let mut in_place = start.clone();
+        for _ in 0..6 {
+            in_place.run_generation();
+        }
+
+        // Requests made while a batch is running are run together as the next batch.
+        let mut snapshot = start.clone();
+        let mut worker = SimulationWorker::spawn(start);
+        for _ in 0..6 {
+            worker.request(1);
+        }
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while worker.is_busy() {
+            assert!(Instant::now() < deadline, "worker never finished");
+            match worker.poll() {
+                Some(batch) => {
+                    snapshot.apply_generations(batch.generations, &batch.changes);
+                }
+                None => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+
+        assert_eq!(snapshot.generation(), 6);
+        for index in tiling.iter_indices() {
+            assert_eq!(snapshot.get_at(index), in_place.get_at(index), "{:?}", index);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use bevy::math::{IVec2, Vec2};
+use game_of_life::{
+    patterns::place_rle,
+    simulation::SimulationState,
+    tiling::{Tiling, TilingKind},
+};
+
+const GLIDER: &str = "#N Glider\nx = 3, y = 3, rule = B3/S23\nbo$2bo$3o!";
+
+fn live_cells(sim_state: &SimulationState) -> Vec<IVec2> {
+    let mut cells = sim_state
+        .tiling
+        .iter_indices()
+        .filter(|index| sim_state.get_at(*index) == 1)
+        .collect::<Vec<_>>();
+    cells.sort_unstable_by_key(|index| (index.y, index.x));
+    cells
+}
+
+#[test]
+fn glider_travels_headless() {
+    let mut sim_state = SimulationState::new(Tiling {
+        kind: TilingKind::Square,
+        max_index: IVec2::new(20, 20),
+        offset: Vec2::ZERO,
+    });
+    place_rle(&mut sim_state, GLIDER, IVec2::new(4, 14)).unwrap();
+    sim_state.process();
+    let start = live_cells(&sim_state);
+    assert_eq!(start.len(), 5);
+
+    for _ in 0..40 {
+        sim_state.run_generation();
+        assert_eq!(sim_state.population(), vec![395, 5]);
+    }
+
+    // Every four generations the glider moves one cell right and one down.
+    let mut expected = start
+        .iter()
+        .map(|index| sim_state.tiling.adjust_index(*index + IVec2::new(10, -10)))
+        .collect::<Vec<_>>();
+    expected.sort_unstable_by_key(|index| (index.y, index.x));
+    assert_eq!(live_cells(&sim_state), expected);
+}